@@ -13,7 +13,7 @@ use std::sync::Arc;
 use crate::catcodes::{CategoryCode, CategoryCodeScheme};
 use crate::interpreter::dimensions::{dimtostr, Numeric};
 use crate::utils::{TeXError, TeXString,TeXStr};
-use crate::{FileEnd, log};
+use crate::FileEnd;
 use crate::commands::ProvidesWhatsit::Exec;
 
 pub struct PrimitiveExecutable {
@@ -33,7 +33,7 @@ pub struct Conditional {
 impl Conditional {
     pub fn expand(&self,int:&mut Interpreter) -> Result<(),TeXError> {
         let i = int.state.conditions.len();
-        int.push_condition(None);
+        int.push_condition(None,self.name,false);
         (self._apply)(int,i,false)
     }
 }
@@ -418,6 +418,7 @@ impl PrimitiveTeXCommand {
     pub fn as_command(self) -> TeXCommand {
         TeXCommand {
             orig: Arc::new(self),
+            defined_at:None
             //rf: None
         }
     }
@@ -574,7 +575,26 @@ impl PrimitiveTeXCommand {
                 let ret : TeXString = if catcodes.escapechar != 255 {catcodes.escapechar.into()} else {"".into()};
                 ret + e.name.into()
             },
-            _ => todo!("{}",self)
+            AV(AssignableValue::MuSkip(i)) => {
+                let ret : TeXString = if catcodes.escapechar != 255 {catcodes.escapechar.into()} else {"".into()};
+                ret + "muskip".into() + i.to_string().into()
+            },
+            AV(AssignableValue::Font(p)) => {
+                let ret : TeXString = if catcodes.escapechar != 255 {catcodes.escapechar.into()} else {"".into()};
+                ret + p.name.into()
+            },
+            AV(AssignableValue::Tok(p)) => {
+                let ret : TeXString = if catcodes.escapechar != 255 {catcodes.escapechar.into()} else {"".into()};
+                ret + p.name.into()
+            },
+            AV(AssignableValue::PrimMuSkip(p)) => {
+                let ret : TeXString = if catcodes.escapechar != 255 {catcodes.escapechar.into()} else {"".into()};
+                ret + p.name.into()
+            },
+            Ext(e) => {
+                let ret : TeXString = if catcodes.escapechar != 255 {catcodes.escapechar.into()} else {"".into()};
+                ret + e.name().as_str().into()
+            }
         };
         ret
     }
@@ -661,20 +681,36 @@ impl PrimitiveTeXCommand {
     }
     pub fn get_expansion(&self,tk:Token,int:&mut Interpreter,cmd:Arc<TeXCommand>) -> Result<Option<Expansion>,TeXError> {
         use PrimitiveTeXCommand::*;
-        log!("Expanding {}",tk);
+        int.check_limits(&tk)?;
+        int.profile_hook(&tk,crate::interpreter::profiler::ProfileStep::Expansion);
+        int.debug_hook(&tk)?;
+        int.trace_expansion(&tk,self);
+        int.expansion_depth += 1;
+        let ret = self.expand_i(tk,int,cmd);
+        int.expansion_depth -= 1;
+        ret
+    }
+    fn expand_i(&self,tk:Token,int:&mut Interpreter,cmd:Arc<TeXCommand>) -> Result<Option<Expansion>,TeXError> {
+        use PrimitiveTeXCommand::*;
         match self {
             Cond(c) => {c.expand(int)?; Ok(None)},
             Primitive(p) => {
                 let mut exp = Expansion::new(tk,cmd.orig.clone());
                 (p._apply)(&mut exp,int)?;
+                int.check_expansion_size(&exp.0,exp.2.len())?;
                 Ok(Some(exp))
             },
             Ext(p) => {
                 let mut exp = Expansion::new(tk,cmd.orig.clone());
                 p.expand(&mut exp,int)?;
+                int.check_expansion_size(&exp.0,exp.2.len())?;
+                Ok(Some(exp))
+            },
+            Def(d) => {
+                let exp = self.do_def(tk, int, d,cmd)?;
+                int.check_expansion_size(&exp.0,exp.2.len())?;
                 Ok(Some(exp))
             },
-            Def(d) => Ok(Some(self.do_def(tk, int, d,cmd)?)),
             _ => TeXErr!("Should be unreachable!")
         }
     }
@@ -696,12 +732,7 @@ impl PrimitiveTeXCommand {
             }
             print!("");
             //TeXErr!(tk => "temp");
-            //unsafe { crate::LOG = true }
         }*/
-        log!("{}",d);
-        //if unsafe{crate::LOG} {
-            log!("    >>{}",int.preview());
-        //}
         let in_halign = int.in_halign();
         let mut args : Vec<Vec<Token>> = Vec::with_capacity(d.sig.arity as usize);
         let mut iter = d.sig.elems.iter().peekable();
@@ -756,6 +787,11 @@ impl PrimitiveTeXCommand {
                             let mut totalgroups = 0;
                             while int.has_next() {
                                 let next = if in_halign {int.next_token_halign()} else {int.next_token()};
+                                if !d.long && next.catcode == CategoryCode::Escape && &next.cmdname() == "par" {
+                                    let e = TeXError::new(std::format!("Paragraph ended before \\{} was complete",tk.name()),Some(next.clone()));
+                                    // only \nonstopmode etc. read on after a runaway argument
+                                    return Err(if int.state.interaction_mode() >= 3 { e.fatal() } else { e.recover_with(vec!(next)) })
+                                }
                                 match next.catcode {
                                     CategoryCode::BeginGroup if groups == 0 => {
                                         groups += 1;
@@ -786,12 +822,7 @@ impl PrimitiveTeXCommand {
         }
 
 
-        if unsafe{crate::LOG} {
-            log!("    args:");
-            for (i, a) in args.iter().enumerate() {
-                log!("    {}:{}",i+1,TokenList(a));
-            }
-        }
+        if int.tracing(&crate::commands::registers::TRACINGMACROS,0) { int.trace_macro(&tk,self,&args) }
         let mut exp = Expansion::with_capacity(tk,cmd.orig.clone(),50);
         let mut rf = exp.get_ref();
         let mut iter = d.ret.iter();
@@ -817,7 +848,7 @@ impl PrimitiveTeXCommand {
         Ok(exp)
     }
     pub fn assign(&self,tk:Token,int:&mut Interpreter,globally:bool,cmd:Arc<TeXCommand>) -> Result<(),TeXError> {
-        use crate::commands::registers::GLOBALDEFS;
+        use crate::commands::registers::{GLOBALDEFS, TRACINGASSIGNS};
         use PrimitiveTeXCommand::*;
 
         let globals = int.state.registers_prim.get(&(GLOBALDEFS.index - 1));
@@ -833,7 +864,9 @@ impl PrimitiveTeXCommand {
                 AssignableValue::Register(i) => {
                     int.read_eq();
                     let num = int.read_number()?;
-                    log!("Assign register {} to {}",i,num);
+                    if int.tracing(&TRACINGASSIGNS,0) {
+                        int.trace_assign(&format!("count{}",i),int.state.registers.get(i).to_string(),num.to_string(),global)
+                    }
                     int.state.registers.set(*i, num, global);
                     Ok(())
                 }
@@ -853,37 +886,46 @@ impl PrimitiveTeXCommand {
                 },
                 AssignableValue::Dim(i) => {
                     int.read_eq();
-                    log!("Assigning dimen {}",i);
                     let num = int.read_dimension()?;
-                    log!("Assign dimen register {} to {}",i,dimtostr(num));
+                    if int.tracing(&TRACINGASSIGNS,0) {
+                        int.trace_assign(&format!("dimen{}",i),dimtostr(int.state.dimensions.get(i)),dimtostr(num),global)
+                    }
                     int.state.dimensions.set(*i, num, global);
                     Ok(())
                 }
                 AssignableValue::Skip(i) => {
                     int.read_eq();
                     let num = int.read_skip()?;
-                    log!("Assign skip register {} to {}",i,num);
+                    if int.tracing(&TRACINGASSIGNS,0) {
+                        int.trace_assign(&format!("skip{}",i),int.state.skips.get(i).to_string(),num.to_string(),global)
+                    }
                     int.state.skips.set(*i, num, global);
                     Ok(())
                 }
                 AssignableValue::MuSkip(i) => {
                     int.read_eq();
                     let num = int.read_muskip()?;
-                    log!("Assign muskip register {} to {}",i,num);
+                    if int.tracing(&TRACINGASSIGNS,0) {
+                        int.trace_assign(&format!("muskip{}",i),int.state.muskips.get(i).to_string(),num.to_string(),global)
+                    }
                     int.state.muskips.set(*i, num, global);
                     Ok(())
                 },
                 AssignableValue::PrimSkip(r) => {
                     int.read_eq();
                     let num = int.read_skip()?;
-                    log!("Assign {} to {}",r.name,num);
+                    if int.tracing(&TRACINGASSIGNS,0) {
+                        int.trace_assign(r.name,int.state.skips_prim.get(&(r.index - 1)).to_string(),num.to_string(),global)
+                    }
                     int.state.skips_prim.set(r.index - 1, num, global);
                     Ok(())
                 },
                 AssignableValue::PrimMuSkip(r) => {
                     int.read_eq();
                     let num = int.read_muskip()?;
-                    log!("Assign {} to {}",r.name,num);
+                    if int.tracing(&TRACINGASSIGNS,0) {
+                        int.trace_assign(r.name,int.state.muskips_prim.get(&(r.index - 1)).to_string(),num.to_string(),global)
+                    }
                     int.state.muskips_prim.set(r.index - 1, num, global);
                     Ok(())
                 },
@@ -906,6 +948,11 @@ impl PrimitiveTeXCommand {
                         }
                         _ => TeXErr!("Expected balanced argument or token register in token assignment")
                     };
+                    if int.tracing(&TRACINGASSIGNS,0) {
+                        let scheme = int.state.catcodes.get_scheme();
+                        int.trace_assign(&format!("toks{}",i),crate::interpreter::tokens_to_string(&int.state.toks.get(i),scheme).to_string(),
+                            crate::interpreter::tokens_to_string(&toks,scheme).to_string(),global)
+                    }
                     int.state.toks.set(*i, toks.iter().map(|x| x.cloned()).collect(), global);
                     Ok(())
                 },
@@ -928,20 +975,33 @@ impl PrimitiveTeXCommand {
                         }
                         _ => TeXErr!("Expected balanced argument or token register in token assignment")
                     };
+                    if int.tracing(&TRACINGASSIGNS,0) {
+                        let scheme = int.state.catcodes.get_scheme();
+                        int.trace_assign(r.name,crate::interpreter::tokens_to_string(&int.state.toks_prim.get(&(r.index - 1)),scheme).to_string(),
+                            crate::interpreter::tokens_to_string(&toks,scheme).to_string(),global)
+                    }
                     int.state.toks_prim.set(r.index - 1, toks.iter().map(|x| x.cloned()).collect(), global);
                     Ok(())
                 },
                 AssignableValue::PrimReg(r) => {
                     int.read_eq();
                     let num = int.read_number()?;
-                    log!("Assign {} to {}",r.name,num);
+                    let traced = int.tracing(&TRACINGASSIGNS,0);
+                    if traced {
+                        int.trace_assign(r.name,int.state.registers_prim.get(&(r.index - 1)).to_string(),num.to_string(),global)
+                    }
                     int.state.registers_prim.set(r.index - 1, num, global);
+                    if !traced && int.tracing(&TRACINGASSIGNS,0) {
+                        int.trace_assign_into(r.name,num.to_string())
+                    }
                     Ok(())
                 },
                 AssignableValue::PrimDim(r) => {
                     int.read_eq();
                     let num = int.read_dimension()?;
-                    log!("Assign {} to {}",r.name,dimtostr(num));
+                    if int.tracing(&TRACINGASSIGNS,0) {
+                        int.trace_assign(r.name,dimtostr(int.state.dimensions_prim.get(&(r.index - 1))),dimtostr(num),global)
+                    }
                     int.state.dimensions_prim.set(r.index - 1, num, global);
                     Ok(())
                 }
@@ -954,9 +1014,33 @@ impl PrimitiveTeXCommand {
     }
 }
 
+/// Where a command was last (re)defined, and by which primitive
+#[derive(Clone,PartialEq)]
+pub struct DefinitionSite {
+    pub file:TeXStr,
+    pub line:usize,
+    pub column:usize,
+    pub primitive:Option<TeXStr>
+}
+
+impl fmt::Display for DefinitionSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.primitive {
+            Some(p) => write!(f,"\\{} in ",p)?,
+            None => ()
+        }
+        if self.file.len() == 0 {
+            write!(f,"<unknown file>")
+        } else {
+            write!(f,"{} (line {}, column {})",self.file,self.line,self.column)
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct TeXCommand {
     pub orig:Arc<PrimitiveTeXCommand>,
+    pub defined_at:Option<Arc<DefinitionSite>>
     //pub rf:Option<ExpansionRef>
 }
 
@@ -967,7 +1051,26 @@ impl TeXCommand {
             orig:match *self.orig {
                 PrimitiveTeXCommand::Def(ref d) => Arc::new(PrimitiveTeXCommand::Def(d.clean())),
                 _ => self.orig.clone()
-            }
+            },
+            defined_at:self.defined_at.clone()
+        }
+    }
+    pub fn with_site(self,site:DefinitionSite) -> TeXCommand {
+        TeXCommand {
+            orig:self.orig,
+            defined_at:Some(Arc::new(site))
+        }
+    }
+    /// The meaning of this command followed by its definition site, if known (as shown by `\show`)
+    pub fn describe(&self,catcodes:&CategoryCodeScheme) -> String {
+        let mut meaning = self.meaning(catcodes).to_string();
+        // like TeX, break the line between `macro:` and the parameter text
+        if let (PrimitiveTeXCommand::Def(_),Some(i)) = (&*self.orig,meaning.find("macro:")) {
+            meaning.insert(i + 6,'\n')
+        }
+        match &self.defined_at {
+            Some(site) => format!("{}.\n(defined by {})",meaning,site),
+            None => meaning + "."
         }
     }
 }
@@ -1053,6 +1156,10 @@ impl TeXCommand {
         self.orig.clone().expand(tk,int,Arc::new(self))
     }
     pub fn assign(self,tk:Token,int:&mut Interpreter,globally:bool) -> Result<(),TeXError> {
-        self.orig.clone().assign(tk,int,globally,Arc::new(self))
+        let site = if int.state.store_sites { Some(int.definition_site(self.orig.name())) } else { None };
+        let prev = std::mem::replace(&mut int.assigning,site);
+        let ret = self.orig.clone().assign(tk,int,globally,Arc::new(self));
+        int.assigning = prev;
+        ret
     }
 }
\ No newline at end of file
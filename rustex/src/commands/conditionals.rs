@@ -3,8 +3,8 @@ use crate::commands::{Conditional, PrimitiveExecutable, PrimitiveTeXCommand};
 use crate::utils::TeXError;
 use crate::catcodes::CategoryCode;
 use crate::commands::primitives::read_font;
-use crate::log;
 use crate::stomach::boxes::TeXBox;
+use crate::commands::registers::TRACINGIFS;
 
 
 pub fn dotrue(int: &mut Interpreter,cond:usize,unless:bool) -> Result<(),TeXError> {
@@ -24,7 +24,6 @@ use crate::FileEnd;
 pub fn false_loop(int:&mut Interpreter,initifs:usize,allowelse : bool) -> Result<(),TeXError> {
     use PrimitiveTeXCommand::*;
     let mut inifs = initifs;
-    //log!("false loop: {}",inifs);
     while int.has_next() {
         let next = int.next_token();
         match next.catcode {
@@ -34,10 +33,12 @@ pub fn false_loop(int:&mut Interpreter,initifs:usize,allowelse : bool) -> Result
                     Some(p) => {
                         match *p.orig {
                             Primitive(x) if inifs == 0 && *x == FI => {
+                                if int.tracing(&TRACINGIFS,0) { int.trace_command(&next,Some(&p.orig)) }
                                 int.pop_condition();
                                 return Ok(())
                             }
                             Primitive(x) if allowelse && inifs == 0 && *x == ELSE => {
+                                if int.tracing(&TRACINGIFS,0) { int.trace_command(&next,Some(&p.orig)) }
                                 return Ok(())
                             }
                             Primitive(x) if *x == FI => inifs -=1,
@@ -93,8 +94,9 @@ pub static UNLESS: PrimitiveExecutable = PrimitiveExecutable {
                 let cmd = int.get_command(&cnd.cmdname())?;
                 match *cmd.orig {
                     PrimitiveTeXCommand::Cond(c) => {
+                        int.trace_if(c.name,true);
                         let i = int.state.conditions.len();
-                        int.push_condition(None);
+                        int.push_condition(None,c.name,true);
                         (c._apply)(int,i,true)
                     }
                     _ => TeXErr!(cnd => "Expected conditional after \\unless")
@@ -178,7 +180,6 @@ pub static IFX : Conditional = Conditional {
                    (None,_) => false,
                    (_,None) => false,
                    (Some(c1),Some(c2)) => {
-                       log!("     Compare:\n    {}\n    {}",c1.meaning(int.state.catcodes.get_scheme()),c2.meaning(int.state.catcodes.get_scheme()));
                        match (tka.expand,tkb.expand) {
                            (true,true) | (false,false) => c1 == c2,
                            (true,false) | (false,true) => {
@@ -193,7 +194,6 @@ pub static IFX : Conditional = Conditional {
             (a,b) if a == b => tka.char == tkb.char,
             _ => false
         };
-        log!("\\ifx {}{}: {}",tka,tkb,istrue);
         if istrue {dotrue(int,cond,unless)} else {dofalse(int,cond,unless)}
     }
 };
@@ -209,7 +209,6 @@ pub static IFNUM : Conditional = Conditional {
             Some(ref s) if s == ">" => i1 > i2,
             _ =>  TeXErr!("Expected '<','=' or '>' in \\ifnum")
         };
-        log!("\\ifnum {}{}{}: {}",i1,rel.as_ref().unwrap(),i2,istrue);
         if istrue {dotrue(int,cond,unless)} else {dofalse(int,cond,unless)}
     },
     name:"ifnum"
@@ -222,7 +221,6 @@ pub static IFEOF : Conditional = Conditional {
             18 => dofalse(int,cond,unless),
             i => {
                 let ret = int.state.file_eof(i)?;
-                log!("\\ifeof {}: {}",i,ret);
                 if ret {dotrue(int,cond,unless)} else {dofalse(int,cond,unless)}
             }
         }
@@ -261,14 +259,6 @@ pub static IF : Conditional = Conditional {
     _apply: |int,cond,unless| {
         let first = get_if_token(cond,int)?;
         let second = get_if_token(cond,int)?;
-        if unsafe{crate::LOG} {
-            match (&first,&second) {
-                (Some(ref a),Some(ref b)) => log!("   {}=={}",a,b),
-                (None,Some(ref b)) => log!("   NONE=={}",b),
-                (Some(ref a),None) => log!("   {}==NONE",a),
-                (None,None) => log!("   NONE==NONE"),
-            }
-        }
         let istrue = match (first,second) {
             (None,_) | (_,None) => false,
             (Some(a),Some(b)) => {
@@ -306,7 +296,6 @@ pub static IFDEFINED : Conditional = Conditional {
                 int.state.commands.get(&next.cmdname()).is_some(),
             _ => TeXErr!(next.clone() => "Expected command after \\ifdefined; got: {}",next)
         };
-        log!("\\ifdefined {}: {}",next,istrue);
         if istrue { dotrue(int,cond,unless) } else { dofalse(int,cond,unless) }
     }
 };
@@ -317,7 +306,6 @@ pub static IFCSNAME : Conditional = Conditional {
         use crate::commands::primitives::csname;
         let cmdname = csname(int)?.into();
         let istrue = int.state.commands.get(&cmdname).is_some();
-        log!("\\ifcsname {}: {}",cmdname,istrue);
         if istrue { dotrue(int,cond,unless) } else { dofalse(int,cond,unless) }
     }
 };
@@ -359,7 +347,6 @@ pub static IFCAT : Conditional = Conditional {
             }
             o => o.toint()
         };
-        log!("\\ifcat {}=={}: {}",cc1,cc2,cc1==cc2);
         if cc1 == cc2 { dotrue(int,cond,unless) } else { dofalse(int,cond,unless) }
     }
 };
@@ -369,7 +356,6 @@ pub static IFODD : Conditional = Conditional {
     _apply: |int,cond,unless| {
         let num = int.read_number()?;
         let istrue = num % 2 != 0;
-        log!("\\ifodd {}: {}",num,istrue);
         if istrue { dotrue(int,cond,unless) } else { dofalse(int,cond,unless) }
     }
 };
@@ -391,7 +377,6 @@ pub static IFCASE : Conditional = Conditional {
             let mut inifs = int.state.conditions.len() - (cond + 1);
             //let mut inifs = cond.0 as u8;
             let mut currnum = 1 as u8;
-            //log!("false loop: {}",inifs);
             while int.has_next() {
                 let next = int.next_token();
                 match next.catcode {
@@ -399,6 +384,10 @@ pub static IFCASE : Conditional = Conditional {
                         match int.state.commands.get(&next.cmdname()) {
                             None => {}
                             Some(p) => {
+                                if inifs == 0 && int.tracing(&TRACINGIFS,0) && match *p.orig {
+                                    Primitive(x) => *x == FI || *x == ELSE || *x == OR,
+                                    _ => false
+                                } { int.trace_command(&next,Some(&p.orig)) }
                                 match *p.orig {
                                     Primitive(x) if inifs == 0 && *x == FI => {
                                         int.pop_condition();
@@ -437,7 +426,6 @@ pub static IFDIM : Conditional = Conditional {
             Some(s) if s == ">" => dim1 > dim2,
             _ => TeXErr!("Expected <,= or > after \\ifdim")
         };
-        log!("\\ifdim {}{}{}: {}",dim1,rel.unwrap(),dim2,istrue);
         if istrue { dotrue(int,cond,unless) } else { dofalse(int,cond,unless) }
     }
 };
@@ -5,7 +5,7 @@ use itertools::Itertools;
 use crate::commands::rustex_specials::{AnnotateBegin, HTMLLiteral};
 use crate::commands::{ParamToken, PrimitiveExecutable, PrimitiveTeXCommand, ProvidesWhatsit, SimpleWhatsit, TeXCommand};
 use crate::stomach::whatsits::WhatsitTrait;
-use crate::{interpreter, log, TeXErr, TeXString, Token};
+use crate::{interpreter, TeXErr, TeXString, Token};
 use crate::catcodes::CategoryCode;
 use crate::commands::primitives::RELAX;
 use crate::interpreter::params::CommandListener;
@@ -23,7 +23,6 @@ pub static URL: SimpleWhatsit = SimpleWhatsit {
     name: "Url@FormatString",
     modes: |_| { true },
     _get: |_, int| {
-        log!("Url: {}",int.preview());
         if let Some(tc) = int.state.commands.get(&("Url@String".into())) {
             match &*tc.orig {
                 PrimitiveTeXCommand::Def(dm) => {
@@ -1,8 +1,10 @@
+use std::sync::Arc;
 use chrono::{Datelike, Timelike, TimeZone};
 use crate::commands::{AssignableValue, PrimitiveExecutable, Conditional, DimenReference, RegisterReference, NumericCommand, PrimitiveTeXCommand, TokReference, SimpleWhatsit, ProvidesWhatsit, TokenList, PrimitiveAssignment};
 use crate::interpreter::{string_to_tokens, TeXMode, tokenize};
 use crate::{Interpreter, pdf_to_img, Token, VERSION_INFO};
-use crate::{log,TeXErr};
+use crate::pdfsvg::{bp_to_sp, pdf_to_svg, svg_size};
+use crate::TeXErr;
 use crate::catcodes::CategoryCode;
 use crate::interpreter::dimensions::{dimtostr, Numeric};
 use crate::commands::conditionals::{dotrue,dofalse};
@@ -165,8 +167,9 @@ pub static PDFMAJORVERSION: NumericCommand = NumericCommand {
 
 pub static PDFSHELLESCAPE: NumericCommand = NumericCommand {
     name:"pdfshellescape",
-    _getvalue:|_int| {
-        Ok(Numeric::Int(2))
+    _getvalue:|int| {
+        // restricted (2) unless enabled, like a default TeX Live installation
+        Ok(Numeric::Int(if int.params.shell_escape() {1} else {2}))
     }
 };
 
@@ -195,9 +198,7 @@ pub static PDFSTRCMP: PrimitiveExecutable = PrimitiveExecutable {
         let first = int.tokens_to_string(&tks);
         tks = int.read_balanced_argument(true,false,false,true)?;
         let second = int.tokens_to_string(&tks);
-        log!("\\pdfstrcmp: \"{}\" == \"{}\"?",first,second);
         if first == second {
-            log!("true");
             rf.2 = tokenize("0".into(),&crate::catcodes::OTHER_SCHEME)
         } else if first.to_string() < second.to_string() {
             rf.2 = tokenize("-1".into(),&crate::catcodes::OTHER_SCHEME)
@@ -214,9 +215,6 @@ pub static PDFFILESIZE: PrimitiveExecutable = PrimitiveExecutable {
     _apply:|rf,int| {
         let strtks = int.read_balanced_argument(true,false,false,true)?;
         let str = int.tokens_to_string(&strtks);
-        /*if str.to_string() == "hyphen.cfg" {
-            unsafe { crate::LOG = true };
-        }*/
         let file = int.get_file(&str.to_utf8())?;
         match &*file.string.read().unwrap() {
             None => (),
@@ -240,7 +238,6 @@ pub static IFPDFABSNUM : Conditional = Conditional {
             Some(ref s) if s == ">" => i1.abs() > i2.abs(),
             _ => TeXErr!("Expected '<','=' or '>' in \\ifpdfabsnum")
         };
-        log!("\\ifpdfabsnum {}{}{}: {}",i1,rel.as_ref().unwrap(),i2,istrue);
         if istrue { dotrue(int, cond, unless) } else { dofalse(int, cond, unless) }
     },
 };
@@ -584,7 +581,6 @@ pub static PDFREFXIMAGE: SimpleWhatsit = SimpleWhatsit {
             Some(i) => i.clone(),
             None => TeXErr!(tk.clone() => "No image as index {}",num)
         };
-        //unsafe {crate::LOG = true}
         Ok(Whatsit::Simple(SimpleWI::PDFXImage(img)))
     }
 };
@@ -594,7 +590,6 @@ pub static PDFXIMAGE: PrimitiveExecutable = PrimitiveExecutable {
     expandable:false,
     _apply:|tk,int| {
         //println!("Here! >>{}",int.preview());
-        //unsafe {crate::LOG = true}
         let rule = read_rule_spec(int)?;
         let attr = read_attrspec(int)?;
         let pagespec = match int.read_keyword(vec!("page"))? {
@@ -611,37 +606,56 @@ pub static PDFXIMAGE: PrimitiveExecutable = PrimitiveExecutable {
         };
         let tks = int.read_balanced_argument(true,false,false,true)?;
         let filename = int.tokens_to_string(&tks);
+        let fs = int.params.filesystem();
         let file = match int.kpsewhich(filename.to_string().as_str()) {
-            Some((p,_)) if p.exists() => p,
+            Some((p,_)) if fs.is_file(&p) => p,
             _ => TeXErr!("No image file by name {} found",filename)
         };
-        let image = match match image::io::Reader::open(file.clone()) {
-            Ok(x) => x,
+        let bytes = match fs.read(&file) {
+            Ok(b) => b,
             _ => TeXErr!("Error reading image {}",filename)
-        }.with_guessed_format().unwrap().decode() {
-            Ok(x) => Some(x),
-            Err(e) => {
-                match file.extension() {
-                    Some(s) if s == "pdf" => pdf_to_img(file.to_str().unwrap()),
-                    _ => TeXErr!("Error decoding image {} - {}",filename,e)
+        };
+        int.state.add_dependency_file(&file,&bytes);
+        let is_svg = file.extension().map(|e| e.eq_ignore_ascii_case("svg")).unwrap_or(false);
+        let mut svg = None;
+        let image = if is_svg { None } else {
+            match image::io::Reader::new(std::io::Cursor::new(&bytes)).with_guessed_format().unwrap().decode() {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    match file.extension() {
+                        Some(s) if s == "pdf" => {
+                            let page = pagespec.unwrap_or(1);
+                            let bx = boxspec.as_ref().map(|b| b.to_string());
+                            svg = pdf_to_svg(&bytes,page,bx.as_deref());
+                            if svg.is_some() { None } else { pdf_to_img(&bytes,page,bx.as_deref()) }
+                        }
+                        _ => TeXErr!("Error decoding image {} - {}",filename,e)
+                    }
                 }
             }
         };
+        let size = match (&svg,&image) {
+            (Some(svg),_) => Some((svg.width,svg.height)),
+            (_,Some(img)) => Some((img.width() as f32,img.height() as f32)),
+            _ if is_svg => Some(svg_size(&bytes)),
+            _ => None
+        };
         let mut _width = rule.width.clone();
         let mut _height = rule.height.clone();
-        match (_width,_height,&image) {
-            (Some(w),None,Some(img)) => {
-                let ow = img.width() as f32;
-                let oh = img.height() as f32;
-                _height = Some(((oh as f32) / (ow / (w as f32))).round() as i32);
+        match (_width,_height,size) {
+            (Some(w),None,Some((ow,oh))) => {
+                _height = Some((oh / (ow / (w as f32))).round() as i32);
+            }
+            (None,Some(h),Some((ow,oh))) => {
+                _width = Some((ow / (oh / (h as f32))).round() as i32);
             }
-            (None,Some(h),Some(img)) => {
-                let ow = img.width() as f32;
-                let oh = img.height() as f32;
-                _width = Some(((ow as f32) / (oh / (h as f32))).round() as i32);
+            (None,None,Some((ow,oh))) if is_svg => {
+                _width = Some(bp_to_sp(ow));
+                _height = Some(bp_to_sp(oh));
             }
             _ => {}
         }
+        let original = if image.is_some() || is_svg { Some(Arc::new(bytes)) } else { None };
         int.state.pdfximages.push(
             PDFXImage {
                 rule,
@@ -651,6 +665,8 @@ pub static PDFXIMAGE: PrimitiveExecutable = PrimitiveExecutable {
                 boxspec,
                 filename: file,
                 image,
+                svg,
+                original,
                 sourceref: int.update_reference(&tk.0),
                 _width,_height
             }
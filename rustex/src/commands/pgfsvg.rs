@@ -1,5 +1,5 @@
-use crate::commands::{PrimitiveExecutable, PrimitiveTeXCommand, ProvidesWhatsit, SimpleWhatsit, TokenList};
-use crate::{Interpreter, htmlliteral, htmlnode, TeXErr, htmlparent, withwidth, INSERT_RUSTEX_ATTRS, log};
+use crate::commands::{PrimitiveExecutable, PrimitiveTeXCommand, ProvidesWhatsit, SimpleWhatsit};
+use crate::{Interpreter, htmlliteral, htmlnode, TeXErr, htmlparent, withwidth};
 use crate::interpreter::dimensions::numtostr;
 use crate::references::SourceFileReference;
 use crate::stomach::boxes::{HBox, TeXBox};
@@ -66,7 +66,7 @@ impl WhatsitTrait for PGFEscape {
         match mode {
             ColonMode::External(s) if s.to_string() == "svg" => {
                 htmlnode!(colon,foreignObject,self.sourceref,"",node_top,fo => {
-                    if INSERT_RUSTEX_ATTRS {
+                    if colon.rustex_attrs {
                         fo.attr("rustex:width".into(),dimtohtml(self.bx.width()));
                         fo.attr("rustex:height".into(),dimtohtml(self.bx.height()));
                         fo.attr("rustex:depth".into(),dimtohtml(self.bx.depth()));
@@ -373,7 +373,6 @@ pub static PGF_FLUSH : PrimitiveExecutable = PrimitiveExecutable {
             }
             _ => TeXErr!("\\pgf@sys@svgpath wrongly defined")
         }
-        log!("PGF FLUSH: {}",TokenList(&rf.2));
         Ok(())
     }
 };
@@ -648,7 +647,6 @@ pub static PGF_G_BEGIN: SimpleWhatsit = SimpleWhatsit {
             sourceref: int.update_reference(tk),
             attrs,tag:keystr.into()
         };
-        log!("PGFG: {:?}   =   {:?}",bg.attrs.keys().collect::<Vec<_>>(),bg.attrs.values().collect::<Vec<_>>());
         Ok(Whatsit::GroupOpen(WIGroup::External(Arc::new(bg),vec!())))
     },
 };
@@ -5,7 +5,7 @@ use crate::ontology::{Token, ExpansionRef};
 use crate::catcodes::CategoryCode;
 use crate::interpreter::state::{FontStyle, GroupType, State};
 use crate::utils::{TeXError, TeXStr, TeXString};
-use crate::{log,TeXErr,FileEnd};
+use crate::{TeXErr,FileEnd};
 use crate::VERSION_INFO;
 use crate::stomach::whatsits::{Accent, lineheight, PrintChar, SpaceChar, WhatsitTrait};
 
@@ -100,14 +100,15 @@ pub static COUNT : NumAssValue = NumAssValue {
         let index = int.read_number()? as u16;
         int.read_eq();
         let val = int.read_number()?;
-        log!("\\count sets {} to {}",index,val);
+        if int.tracing(&TRACINGASSIGNS,0) {
+            int.trace_assign(&format!("count{}",index),int.state.registers.get(&index).to_string(),val.to_string(),global)
+        }
         int.state.registers.set(index as u16,val,global);
         Ok(())
     },
     _getvalue: |int| {
         let index = int.read_number()? as u16;
         let num = int.state.registers.get(&index);
-        log!("\\count {} = {}",index,num);
         Ok(Numeric::Int(num))
     }
 };
@@ -118,14 +119,15 @@ pub static DIMEN : NumAssValue = NumAssValue {
         let index = int.read_number()? as u16;
         int.read_eq();
         let val = int.read_dimension()?;
-        log!("\\dimen sets {} to {}",index,val);
+        if int.tracing(&TRACINGASSIGNS,0) {
+            int.trace_assign(&format!("dimen{}",index),dimtostr(int.state.dimensions.get(&index)),dimtostr(val),global)
+        }
         int.state.dimensions.set(index,val,global);
         Ok(())
     },
     _getvalue: |int| {
         let index = int.read_number()? as u16;
         let dim = int.state.dimensions.get(&index);
-        log!("\\dimen {} = {}",index,dim);
         Ok(Numeric::Dim(dim))
     }
 };
@@ -136,14 +138,15 @@ pub static SKIP : NumAssValue = NumAssValue {
         let index = int.read_number()? as u16;
         int.read_eq();
         let val = int.read_skip()?;
-        log!("\\skip sets {} to {}",index,val);
+        if int.tracing(&TRACINGASSIGNS,0) {
+            int.trace_assign(&format!("skip{}",index),int.state.skips.get(&index).to_string(),val.to_string(),global)
+        }
         int.state.skips.set(index,val,global);
         Ok(())
     },
     _getvalue: |int| {
         let index = int.read_number()? as u16;
         let dim = int.state.skips.get(&index);
-        log!("\\skip {} = {}",index,dim);
         Ok(Numeric::Skip(dim))
     }
 };
@@ -348,7 +351,6 @@ fn do_def(int:&mut Interpreter, global:bool, protected:bool, long:bool,edef:bool
     }
     let sig = read_sig(int)?;
     let ret = int.read_token_list(edef,true,edef,false)?;
-    log!("\\def {}{}{}{}{}",command,sig,"{",TokenList(&ret),"}");
     let dm = PrimitiveTeXCommand::Def(DefMacro {
         protected,
         long,
@@ -359,7 +361,7 @@ fn do_def(int:&mut Interpreter, global:bool, protected:bool, long:bool,edef:bool
     Ok(())
 }
 
-use crate::interpreter::dimensions::{dimtostr, MuSkip, Numeric, round_f, Skip, SkipDim};
+use crate::interpreter::dimensions::{dimtostr, MuSkip, Numeric, round_f, Skip, SkipDim, IGNORE_DEPTH};
 use crate::stomach::whatsits::{ExecutableWhatsit, Whatsit};
 use crate::stomach::math::{Above, Delimiter, GroupedMath, MathAccent, MathBin, MathChar, MathClose, MathGroup, MathInner, MathKernel, MathOp, MathOpen, MathOrd, MathPunct, MathRel, MKern, Overline, Radical, Underline};
 use crate::stomach::boxes::{BoxMode, TeXBox, HBox, VBox, VBoxType};
@@ -420,7 +422,6 @@ pub static LET: PrimitiveAssignment = PrimitiveAssignment {
         }
         int.read_eq();
         let def = int.next_token();
-        log!("\\let {}={}",cmd,def);
         let ch = match def.catcode {
             CategoryCode::Escape | CategoryCode::Active => {
                 int.state.commands.get(&def.cmdname())//.map(|x| x)
@@ -459,7 +460,6 @@ pub static NEWLINECHAR : NumAssValue = NumAssValue {
     _assign: |_,int,global| {
         int.read_eq();
         let num = int.read_number()? as u8;
-        log!("\\newlinechar: {}",num);
         int.state.catcodes.set_newline(num,global);
         Ok(())
     },
@@ -473,7 +473,6 @@ pub static ENDLINECHAR : NumAssValue = NumAssValue {
     _assign: |_,int,global| {
         int.read_eq();
         let num = int.read_number()? as u8;
-        log!("\\endlinechar: {}",num);
         int.state.catcodes.set_endline(num,global);
         Ok(())
     },
@@ -487,7 +486,6 @@ pub static ESCAPECHAR: NumAssValue = NumAssValue {
     _assign: |_,int,global| {
         int.read_eq();
         let num = int.read_number()? as u8;
-        log!("\\escapechar: {}",num);
         int.state.catcodes.set_escape(num,global);
         Ok(())
     },
@@ -500,22 +498,10 @@ pub static INPUT: PrimitiveExecutable = PrimitiveExecutable {
     name:"input",
     expandable:true,
     _apply:|rf,int| {
-        use std::process::Command;
-        use std::str;
         let filename = int.read_string()?;
         if filename.starts_with("|kpsewhich ") {
-            let pwd = int.jobinfo.in_file().display().to_string();
-            let args = filename[11..].split(" ");
-            let out = if cfg!(target_os = "windows") {
-                Command::new("cmd").current_dir(&pwd).env("PWD",&pwd).env("CD",&pwd).args(&["/C",&filename[1..]])//args.collect::<Vec<&str>>())
-                    .output().expect("kpsewhich not found!")
-                    .stdout
-            } else {
-                Command::new("kpsewhich").current_dir(&pwd).env("PWD",&pwd).env("CD",&pwd).args(args.collect::<Vec<&str>>())
-                    .output().expect("kpsewhich not found!")
-                    .stdout
-            };
-            let ret = std::str::from_utf8(out.as_slice()).unwrap().trim();
+            let ret = crate::kpathsea::kpsewhich_command(&filename[11..],int.jobinfo.in_file(),int.params.filesystem());
+            let ret = ret.trim();
             int.requeue(int.eof_token());
             int.insert_every(&EVERYEOF);
             rf.2 = crate::interpreter::string_to_tokens(ret.into());
@@ -534,7 +520,7 @@ pub static BEGINGROUP : PrimitiveExecutable = PrimitiveExecutable {
     name:"begingroup",
     expandable:false,
     _apply:|_rf,int| {
-        int.state.push(int.stomach,GroupType::Begingroup);
+        int.push_group(GroupType::Begingroup)?;
         Ok(())
     }
 };
@@ -587,62 +573,80 @@ pub static NUMBER : PrimitiveExecutable = PrimitiveExecutable {
     name: "number"
 };
 
-fn get_inrv(int:&mut Interpreter,withint:bool) -> Result<(u16,Numeric,Numeric,bool),TeXError> {
+/// The register to be modified by `\advance`, `\multiply` or `\divide`: its index, current value,
+/// the operand, whether it is a primitive register and its name (for `\tracingassigns`)
+fn get_inrv(int:&mut Interpreter,withint:bool) -> Result<(u16,Numeric,Numeric,bool,String),TeXError> {
     use crate::commands::PrimitiveTeXCommand::*;
     int.expand_until(true)?;
     let cmd = int.read_command_token()?;
-    let (index,num,val,prim) = match *int.get_command(&cmd.cmdname())?.orig {
+    let (index,num,val,prim,name) = match *int.get_command(&cmd.cmdname())?.orig {
         AV(AssignableValue::Register(i)) => {
             int.read_keyword(vec!("by"))?;
-            (i as u16,Numeric::Int(int.state.registers.get(&(i as u16))),int.read_number_i(false)?,false)
+            (i as u16,Numeric::Int(int.state.registers.get(&(i as u16))),int.read_number_i(false)?,false,format!("count{}",i))
         }
         AV(AssignableValue::PrimReg(r)) => {
             int.read_keyword(vec!("by"))?;
-            ((r.index -1) as u16, Numeric::Int(int.state.registers_prim.get(&((r.index -1) as usize))), int.read_number_i(false)?,true)
+            ((r.index -1) as u16, Numeric::Int(int.state.registers_prim.get(&((r.index -1) as usize))), int.read_number_i(false)?,true,r.name.to_string())
         }
         AV(AssignableValue::Int(c)) if *c == COUNT => {
             let i = int.read_number()? as u16;
             int.read_keyword(vec!("by"))?;
-            (i,Numeric::Int(int.state.registers.get(&i)),int.read_number_i(false)?,false)
+            (i,Numeric::Int(int.state.registers.get(&i)),int.read_number_i(false)?,false,format!("count{}",i))
         }
         AV(AssignableValue::Int(c)) if *c == DIMEN => {
             let i = int.read_number()? as u16;
             int.read_keyword(vec!("by"))?;
-            (i,Numeric::Dim(int.state.dimensions.get(&i)), if withint {int.read_number_i(false)?} else {Numeric::Dim(int.read_dimension()?)},false)
+            (i,Numeric::Dim(int.state.dimensions.get(&i)), if withint {int.read_number_i(false)?} else {Numeric::Dim(int.read_dimension()?)},false,format!("dimen{}",i))
         }
         AV(AssignableValue::Int(c)) if *c == SKIP => {
             let i = int.read_number()? as u16;
             int.read_keyword(vec!("by"))?;
-            (i,Numeric::Skip(int.state.skips.get(&i)), if withint {int.read_number_i(false)?} else {Numeric::Skip(int.read_skip()?)},false)
+            (i,Numeric::Skip(int.state.skips.get(&i)), if withint {int.read_number_i(false)?} else {Numeric::Skip(int.read_skip()?)},false,format!("skip{}",i))
         }
         AV(AssignableValue::Dim(i)) => {
             int.read_keyword(vec!("by"))?;
-            (i,Numeric::Dim(int.state.dimensions.get(&i)), if withint {int.read_number_i(false)?} else {Numeric::Dim(int.read_dimension()?)},false)
+            (i,Numeric::Dim(int.state.dimensions.get(&i)), if withint {int.read_number_i(false)?} else {Numeric::Dim(int.read_dimension()?)},false,format!("dimen{}",i))
         }
         AV(AssignableValue::PrimDim(r)) => {
             int.read_keyword(vec!("by"))?;
-            ((r.index -1) as u16, Numeric::Dim(int.state.dimensions_prim.get(&((r.index - 1) as usize))),if withint {int.read_number_i(false)?} else {Numeric::Dim(int.read_dimension()?)},true)
+            ((r.index -1) as u16, Numeric::Dim(int.state.dimensions_prim.get(&((r.index - 1) as usize))),if withint {int.read_number_i(false)?} else {Numeric::Dim(int.read_dimension()?)},true,r.name.to_string())
         }
         AV(AssignableValue::Skip(i)) => {
             int.read_keyword(vec!("by"))?;
-            (i, Numeric::Skip(int.state.skips.get(&i)),if withint {int.read_number_i(false)?} else {Numeric::Skip(int.read_skip()?)},false)
+            (i, Numeric::Skip(int.state.skips.get(&i)),if withint {int.read_number_i(false)?} else {Numeric::Skip(int.read_skip()?)},false,format!("skip{}",i))
         }
         AV(AssignableValue::PrimSkip(r)) => {
             int.read_keyword(vec!("by"))?;
-            ((r.index -1) as u16, Numeric::Skip(int.state.skips_prim.get(&((r.index -1) as usize))),if withint {int.read_number_i(false)?} else {Numeric::Skip(int.read_skip()?)},true)
+            ((r.index -1) as u16, Numeric::Skip(int.state.skips_prim.get(&((r.index -1) as usize))),if withint {int.read_number_i(false)?} else {Numeric::Skip(int.read_skip()?)},true,r.name.to_string())
         }
         ref p =>{
             todo!("{}",p)
         }
         //_ => return Err(TeXError::new("Expected register after \\divide; got: ".to_owned() + &cmd.as_string()))
     };
-    Ok((index,num,val,prim))
+    Ok((index,num,val,prim,name))
+}
+/// `\tracingassigns` for `\advance`, `\multiply` and `\divide`, given the value `old` before the operation
+fn trace_arith(int:&Interpreter,name:&str,old:Numeric,index:u16,primitive:bool,global:bool) {
+    if !int.tracing(&TRACINGASSIGNS,0) { return }
+    let i = index as usize;
+    let new = match (old,primitive) {
+        (Numeric::Int(_),true) => Numeric::Int(int.state.registers_prim.get(&i)),
+        (Numeric::Dim(_),true) => Numeric::Dim(int.state.dimensions_prim.get(&i)),
+        (Numeric::Skip(_),true) => Numeric::Skip(int.state.skips_prim.get(&i)),
+        (Numeric::MuSkip(_),true) => Numeric::MuSkip(int.state.muskips_prim.get(&i)),
+        (Numeric::Int(_),false) => Numeric::Int(int.state.registers.get(&index)),
+        (Numeric::Dim(_),false) => Numeric::Dim(int.state.dimensions.get(&index)),
+        (Numeric::Skip(_),false) => Numeric::Skip(int.state.skips.get(&index)),
+        (Numeric::MuSkip(_),false) => Numeric::MuSkip(int.state.muskips.get(&index)),
+        _ => return
+    };
+    int.trace_assign(name,old.to_string(),new.to_string(),global)
 }
 pub static DIVIDE : PrimitiveAssignment = PrimitiveAssignment {
     name: "divide",
     _assign: |_,int,global| {
-        let (index,num,div,primitive) = get_inrv(int,true)?;
-        log!("\\divide sets {} to {}",index,num/div);
+        let (index,num,div,primitive,name) = get_inrv(int,true)?;
         match num {
             Numeric::Int(i) if primitive => int.state.registers_prim.set(index as usize, i / div.get_i32(), global),
             Numeric::Dim(i) if primitive => int.state.dimensions_prim.set(index as usize, i / div.get_i32(),global),
@@ -654,14 +658,14 @@ pub static DIVIDE : PrimitiveAssignment = PrimitiveAssignment {
             Numeric::MuSkip(i) => int.state.muskips.set(index, i / div.get_i32(),global),
             _ => TeXErr!("Should be unreachable!")
         };
+        trace_arith(int,&name,num,index,primitive,global);
         Ok(())
     }
 };
 pub static MULTIPLY : PrimitiveAssignment = PrimitiveAssignment {
     name: "multiply",
     _assign: |_,int,global| {
-        let (index,num,fac,primitive) = get_inrv(int,true)?;
-        log!("\\multiply sets {} to {}",index,num*fac);
+        let (index,num,fac,primitive,name) = get_inrv(int,true)?;
         match num {
             Numeric::Int(_) if primitive => int.state.registers_prim.set(index as usize,match num * fac.as_int() {
                 Numeric::Int(i) => i,
@@ -697,14 +701,14 @@ pub static MULTIPLY : PrimitiveAssignment = PrimitiveAssignment {
             },global),
             _ => TeXErr!("Should be unreachable!")
         };
+        trace_arith(int,&name,num,index,primitive,global);
         Ok(())
     }
 };
 pub static ADVANCE : PrimitiveAssignment = PrimitiveAssignment {
     name: "advance",
     _assign: |_,int,global| {
-        let (index,num,sum,primitive) = get_inrv(int,false)?;
-        log!("\\advance sets {} to {}",index,num + sum);
+        let (index,num,sum,primitive,name) = get_inrv(int,false)?;
         match (num,sum) {
             (Numeric::Int(num),Numeric::Int(sum)) if primitive => int.state.registers_prim.set(index as usize,num + sum,global),
             (Numeric::Int(num),Numeric::Dim(sum)) if primitive => int.state.registers_prim.set(index as usize,num+sum,global),
@@ -718,6 +722,7 @@ pub static ADVANCE : PrimitiveAssignment = PrimitiveAssignment {
             (Numeric::MuSkip(num),Numeric::MuSkip(sum)) => int.state.muskips.set(index,num + sum,global),
             _ => TeXErr!("Should be unreachable!")
         };
+        trace_arith(int,&name,num,index,primitive,global);
         Ok(())
     }
 };
@@ -730,11 +735,9 @@ pub static THE: PrimitiveExecutable = PrimitiveExecutable {
         use crate::commands::PrimitiveTeXCommand::*;
         int.expand_until(false)?;
         let reg = int.read_command_token()?;
-        log!("\\the {}",reg);
         rf.2 = match &*int.get_command(&reg.cmdname())?.orig {
             Num(ic) => {
                 let ret = (ic._getvalue)(int)?;
-                log!("\\the{} = {}",reg,ret);
                 stt(ret.to_string().into())
             },
             AV(AssignableValue::Int(i)) => stt((i._getvalue)(int)?.to_string().into()),
@@ -800,10 +803,10 @@ pub static OPENOUT: ProvidesExecutableWhatsit = ProvidesExecutableWhatsit {
         let num = int.read_number()? as u8;
         int.read_eq();
         let filename = int.read_string()?;
-        let file = int.get_file(&filename)?;
+        let (file,target) = int.get_outfile(&filename)?;
         Ok(ExecutableWhatsit {
-            _apply: Box::new(move |state: &mut State,_| {
-                state.file_openout(num,file.clone())
+            _apply: Box::new(move |state: &mut State,params| {
+                state.file_openout(num,file.clone(),target.clone(),params)
             })
         })
     }
@@ -814,8 +817,11 @@ pub static OPENIN: PrimitiveExecutable = PrimitiveExecutable {
         let num = int.read_number()? as u8;
         int.read_eq();
         let filename = int.read_string()?;
+        if int.kpsewhich(&filename).is_none() {
+            // like in TeX, the stream is then closed, i.e. \ifeof is true
+            return int.state.file_closein(num)
+        }
         let file = int.get_file(&filename)?;
-        log!("\\openin {}",num);
         int.state.file_openin(num,file)?;
         Ok(())
     },
@@ -829,8 +835,8 @@ pub static CLOSEOUT: ProvidesExecutableWhatsit = ProvidesExecutableWhatsit {
         let num = int.read_number()? as u8;
 
         Ok(ExecutableWhatsit {
-            _apply: Box::new(move |state: &mut State,_| {
-                state.file_closeout(num);Ok(())
+            _apply: Box::new(move |state: &mut State,params| {
+                state.file_closeout(num,params)
             })
         })
     }
@@ -839,7 +845,6 @@ pub static CLOSEOUT: ProvidesExecutableWhatsit = ProvidesExecutableWhatsit {
 pub static CLOSEIN: PrimitiveExecutable = PrimitiveExecutable {
     _apply: |_,int| {
         let num = int.read_number()? as u8;
-        log!("\\closein {}",num);
         int.state.file_closein(num)?;
         Ok(())
     },
@@ -882,9 +887,6 @@ pub static READLINE: PrimitiveAssignment = PrimitiveAssignment {
             None => TeXErr!("\"to\" expected in \\read")
         }
         let newcmd = int.read_command_token()?;
-        /*if int.current_line() == "/home/jazzpirate/work/Software/ext/sTeX/doc/manual.tex (157, 1)" {
-            unsafe { crate::LOG = true }
-        }*/
         let toks = int.state.file_read_line(index)?;
 
         let cmd = PrimitiveTeXCommand::Def(DefMacro {
@@ -913,9 +915,10 @@ pub static WRITE: ProvidesExecutableWhatsit = ProvidesExecutableWhatsit {
         }
         let ret = int.read_token_list(true,true,true,true)?;
         let string = int.tokens_to_string(&ret) + "\n".into();
+        let jobdir = int.jobinfo.in_file().to_path_buf();
         return Ok(ExecutableWhatsit {
             _apply: Box::new(move |state,params| {
-                state.file_write(num,string.clone(),params)
+                state.file_write(num,string.clone(),params,&jobdir)
             })
         });
     }
@@ -1013,7 +1016,6 @@ pub static STRING: PrimitiveExecutable = PrimitiveExecutable {
     _apply:|rf,int| {
         //int.assert_has_next()?;
         let next = int.next_token();
-        log!("\\string: {}",next);
         rf.2 = match next.catcode {
             CategoryCode::Escape => {
                 let esc = int.state.catcodes.get_scheme().escapechar;
@@ -1043,7 +1045,6 @@ pub fn csname(int : &mut Interpreter) -> Result<TeXString,TeXError> {
     int.state.incs += 1;
     let incs = int.state.incs;
     let mut cmdname : TeXString = "".into();
-    log!("\\csname: {}",int.preview());
     while incs == int.state.incs && int.has_next() {
         let next = int.next_token();
         match next.catcode {
@@ -1074,7 +1075,6 @@ pub fn csname(int : &mut Interpreter) -> Result<TeXString,TeXError> {
             _ => cmdname += next.char
         }
     }
-    log!("\\csname return: {}",cmdname);
     return Ok(cmdname)
 }
 
@@ -1090,7 +1090,10 @@ pub static CSNAME: PrimitiveExecutable = PrimitiveExecutable {
             Some(_) => (),
             None => {
                 let cmd = PrimitiveTeXCommand::Primitive(&RELAX).as_command();
-                int.change_command(cmdname,Some(cmd),false)
+                let site = if int.state.store_sites { Some(int.definition_site(Some("csname".into()))) } else { None };
+                let prev = std::mem::replace(&mut int.assigning,site);
+                int.change_command(cmdname,Some(cmd),false);
+                int.assigning = prev
             }
         }
         rf.2.push(ret);
@@ -1131,7 +1134,6 @@ pub static ERRMESSAGE: PrimitiveExecutable = PrimitiveExecutable {
         let rethelp : TeXString = /*if !eh.is_empty() {
            /* eh.push(Token::new(0,CategoryCode::EndGroup,None,None,false));
             int.push_tokens(eh);
-            unsafe {crate::LOG = true};
             let rethelp = int.read_token_list(true,false,false,true)?;
             int.tokens_to_string(&rethelp) */ "".into()
         } else */{"".into()};
@@ -1165,7 +1167,6 @@ fn expr_loop(int: &mut Interpreter,getnum : fn(&mut Interpreter) -> Result<Numer
 
 fn expr_loop_main(int: &mut Interpreter,getnum : fn(&mut Interpreter) -> Result<Numeric,TeXError>) -> Result<Numeric,TeXError> {
     int.skip_ws();
-    log!("expr_loop: >{}",int.preview());
     let mut first = expr_loop_inner(int,getnum)?;
     loop {
         match int.read_keyword(vec!("+","-","*","/"))? {
@@ -1216,7 +1217,6 @@ fn expr_loop_main(int: &mut Interpreter,getnum : fn(&mut Interpreter) -> Result<
                 first = first / second
             }
             None => {
-                log!("    >{}",int.preview());
                 return Ok(first)
             }
         }
@@ -1242,10 +1242,8 @@ fn expr_loop_inner(int: &mut Interpreter,getnum : fn(&mut Interpreter) -> Result
 pub static NUMEXPR: NumericCommand = NumericCommand {
     name:"numexpr",
     _getvalue: |int| {
-        log!("\\numexpr starts: >{}",int.preview());
         let ret =expr_loop(int,|i| i.read_number_i(false))?;
         int.eat_relax();
-        log!("\\numexpr: {}",ret);
         Ok(ret)
     }
 };
@@ -1253,10 +1251,8 @@ pub static NUMEXPR: NumericCommand = NumericCommand {
 pub static DIMEXPR: NumericCommand = NumericCommand {
     name:"dimexpr",
     _getvalue: |int| {
-        log!("\\dimexpr starts: >{}",int.preview());
         let ret =expr_loop(int,|i| Ok(Numeric::Dim(i.read_dimension()?)))?;
         int.eat_relax();
-        log!("\\dimexpr: {}",ret);
         Ok(ret)
     }
 };
@@ -1264,10 +1260,8 @@ pub static DIMEXPR: NumericCommand = NumericCommand {
 pub static GLUEEXPR: NumericCommand = NumericCommand {
     name:"glueexpr",
     _getvalue: |int| {
-        log!("\\glueexpr starts: >{}",int.preview());
         let ret =expr_loop(int,|i| Ok(Numeric::Skip(i.read_skip()?)))?;
         int.eat_relax();
-        log!("\\glueexpr: {}",ret);
         Ok(ret)
     }
 };
@@ -1275,10 +1269,8 @@ pub static GLUEEXPR: NumericCommand = NumericCommand {
 pub static MUEXPR: NumericCommand = NumericCommand {
     name:"muexpr",
     _getvalue: |int| {
-        log!("\\muexpr starts: >{}",int.preview());
         let ret =expr_loop(int,|i| Ok(Numeric::MuSkip(i.read_muskip()?)))?;
         int.eat_relax();
-        log!("\\muexpr: {}",ret);
         Ok(ret)
     }
 };
@@ -1296,7 +1288,6 @@ pub static ROMANNUMERAL: PrimitiveExecutable = PrimitiveExecutable {
     name:"romannumeral",
     expandable:true,
     _apply:|rf,int| {
-        log!("\\romannumeral: {}",int.preview());
         let mut num = int.read_number()?;
         if num <= 0 {
             return Ok(())
@@ -1401,7 +1392,6 @@ pub static DETOKENIZE: PrimitiveExecutable = PrimitiveExecutable {
                 }
             }
         }
-        log!("\\detokenize: {}",TokenList(&exp.2));
         Ok(())
     }
 };
@@ -1859,7 +1849,7 @@ pub static VSPLIT: ProvidesBox = ProvidesBox {
             _to:None,
             rf: None
         };
-        let (first,second) = crate::stomach::split_vertical(vbox.children,target,int);
+        let (first,second) = crate::stomach::split_vertical(vbox.children,target);
         ret.children = first;
         rest.children = second;
         int.state.boxes.set(boxnum,TeXBox::V(rest),false);
@@ -1951,6 +1941,11 @@ pub static TOKS: TokAssValue = TokAssValue {
             }
             _ => TeXErr!("Expected balanced argument or token register in token assignment")
         };
+        if int.tracing(&TRACINGASSIGNS,0) {
+            let scheme = int.state.catcodes.get_scheme();
+            int.trace_assign(&format!("toks{}",num),crate::interpreter::tokens_to_string(&int.state.toks.get(&num),scheme).to_string(),
+                crate::interpreter::tokens_to_string(&toks,scheme).to_string(),global)
+        }
         int.state.toks.set(num, toks.iter().map(|x| x.cloned()).collect(), global);
         Ok(())
     },
@@ -2008,8 +2003,8 @@ pub static JOBNAME: PrimitiveExecutable = PrimitiveExecutable {
     name:"jobname",
     expandable:true,
     _apply:|rf,int| {
-        let jobname = int.jobinfo.path.file_stem().unwrap().to_str().unwrap();
-        rf.2 = crate::interpreter::string_to_tokens(jobname.into());
+        let jobname = int.jobname();
+        rf.2 = crate::interpreter::string_to_tokens(jobname.as_str().into());
         Ok(())
     }
 };
@@ -2067,13 +2062,31 @@ pub static IGNORESPACES: PrimitiveExecutable = PrimitiveExecutable {
 pub static ERRORSTOPMODE: PrimitiveExecutable = PrimitiveExecutable {
     name:"errorstopmode",
     expandable:false,
-    _apply:|_tk,_int| { Ok(()) }
+    _apply:|_tk,int| {
+        int.state.set_interaction_mode(3,true);
+        Ok(())
+    }
 };
 
 pub static DUMP: PrimitiveExecutable = PrimitiveExecutable {
     name:"dump",
-    expandable:true,
-    _apply:|_tk,_int| { Ok(()) }
+    expandable:false,
+    _apply:|_tk,int| {
+        use crate::interpreter::params::OutFiles;
+        // only write to disk if the caller allows for it (i.e. not while loading latex.ltx)
+        let dir = match int.params.outfiles() {
+            OutFiles::Memory => {
+                int.end();
+                TeXErr!("\\dump: no format file written, since output files are kept in memory")
+            }
+            OutFiles::JobDir => int.jobinfo.in_file().to_path_buf(),
+            OutFiles::Dir(d) => d
+        };
+        int.state.dump_to_file(&dir.join(int.jobname() + ".fmt"),int.params.filesystem())?;
+        // like in TeX, the job ends with \dump
+        int.end();
+        Ok(())
+    }
 };
 
 pub static VRULE: SimpleWhatsit = SimpleWhatsit {
@@ -2094,7 +2107,6 @@ pub static VRULE: SimpleWhatsit = SimpleWhatsit {
                 _ => break
             }
         }
-        log!("VRule: width:{:?}, height:{:?}, depth:{:?}",width,height,depth);
         Ok(Whatsit::Simple(SimpleWI::VRule(VRule {
             height,width,depth,sourceref:int.update_reference(tk),font:int.state.currfont.get()
         })))
@@ -2119,7 +2131,6 @@ pub static HRULE: SimpleWhatsit = SimpleWhatsit {
                 _ => break
             }
         }
-        log!("HRule: width:{:?}, height:{:?}, depth:{:?}",width,height,depth);
         Ok(Whatsit::Simple(SimpleWI::HRule(HRule {
             width,height,depth,sourceref:int.update_reference(tk)
         })))
@@ -2156,7 +2167,6 @@ pub static VSKIP: SimpleWhatsit = SimpleWhatsit {
         _ => false
     },
     _get: |tk,int| {
-        log!("\\vskip >{}",int.preview());
         let sk = int.read_skip()?;
         Ok(Whatsit::Simple(SimpleWI::VSkip(VSkip {
             skip:sk,
@@ -2545,17 +2555,7 @@ pub static PAGEGOAL: NumAssValue = NumAssValue {
         Ok(())
     },
     _getvalue: |int| {
-        let ph = int.stomach.page_height();
-        if ph == 0 {
-            Ok(Numeric::Dim(1073741823))
-        } else {
-            let pg = int.state.pagegoal;
-            if pg == 0 {
-                Ok(Numeric::Dim(int.state.dimensions_prim.get(&(VSIZE.index - 1))))
-            } else {
-                Ok(Numeric::Dim(pg))
-            }
-        }
+        Ok(Numeric::Dim(int.state.pagegoal))
     }
 };
 
@@ -2666,7 +2666,6 @@ pub static CR: PrimitiveExecutable = PrimitiveExecutable {
                     Ok(_) => (),
                     _ => TeXErr!(tk.0.clone() => "Error inserting \\endrow")
                 };
-                log!("Pushing CR template {}",TokenList(&ret));
                 tk.2 = ret;
                 Ok(())
             }
@@ -2721,7 +2720,6 @@ pub static CRCR: PrimitiveExecutable = PrimitiveExecutable {
                 int.insert_every(&EVERYCR);
                 int.requeue(ENDROW.try_with(|x| x.clone()).unwrap());
                 tk.2 = v;
-                log!("Pushing CRCR template {}",TokenList(&tk.2));
                 Ok(())
             }
             _ => Ok(())
@@ -2738,7 +2736,6 @@ pub static NOALIGN: PrimitiveExecutable = PrimitiveExecutable {
 fn do_align(int:&mut Interpreter,tabmode:BoxMode,betweenmode:BoxMode) -> Result<
         (Skip,Vec<(Vec<Token>,Vec<Token>,Skip)>,Vec<AlignBlock>),TeXError> {
     int.expand_until(false)?;
-    //unsafe {crate::LOG = true }
     let bg = int.next_token();
     match bg.catcode {
         CategoryCode::BeginGroup => (),
@@ -2752,7 +2749,7 @@ fn do_align(int:&mut Interpreter,tabmode:BoxMode,betweenmode:BoxMode) -> Result<
         _ => TeXErr!(bg.clone() => "Expected begin group token; found: {}",bg)
     }
 
-    int.state.push(int.stomach,GroupType::Box(betweenmode));
+    int.push_group(GroupType::Box(betweenmode))?;
     int.state.mode = match betweenmode {
         BoxMode::H => TeXMode::RestrictedHorizontal,
         BoxMode::V => TeXMode::InternalVertical,
@@ -2923,7 +2920,7 @@ fn do_align(int:&mut Interpreter,tabmode:BoxMode,betweenmode:BoxMode) -> Result<
             if inspan { inspan = false }
             else {
                 cells = 1;
-                int.state.push(int.stomach,GroupType::Box(tabmode));
+                int.push_group(GroupType::Box(tabmode))?;
             }
             if doheader {
                 int.state.aligns.push(Some(columns.get(columnindex).unwrap().1.clone()))
@@ -2934,20 +2931,17 @@ fn do_align(int:&mut Interpreter,tabmode:BoxMode,betweenmode:BoxMode) -> Result<
                 let next = int.next_token();
                 match next.catcode {
                     CategoryCode::Escape if next.char == endtemplate.char && next == endtemplate => {
-                        log!("Breaking cell");
                         break 'cell
                     }
                     CategoryCode::Escape if next.char == endtemplatespan.char && next == endtemplatespan => {
                         cells += 1;
                         inspan = true;
-                        log!("Breaking span cell");
                         break 'cell
                     }
                     CategoryCode::Escape if next.char == endrow.char && next == endrow => {
                         let ret = int.get_whatsit_group(GroupType::Box(tabmode))?;
                         row.push((ret,columns.get(columnindex).unwrap().2,cells));
                         int.state.mode = _oldmode;
-                        log!("Breaking row");
                         break 'row
                     }
                     _ => int.do_top(next,true)?
@@ -3358,15 +3352,11 @@ pub static PARSHAPE: PrimitiveExecutable = PrimitiveExecutable {
     name:"parshape",
     expandable:false,
     _apply:|_r,int| {
-        //unsafe { crate::LOG = true }
         let num = int.read_number()?;
-        log!("\\parshape: Reading 2*{} dimensions:",num);
         let mut vals : Vec<(i32,i32)> = vec!();
-        for i in 1..(num+1) {
+        for _ in 0..num {
             let f = int.read_dimension()?;
-            log!("\\parshape: i{}={}",i,f);
             let s = int.read_dimension()?;
-            log!("\\parshape: l{}={}",i,s);
             vals.push((f,s))
         }
         //TeXErr!(r.0.clone() => "Here!");
@@ -3442,20 +3432,29 @@ pub static INSERT: PrimitiveExecutable = PrimitiveExecutable {
 
 pub static TOPMARK: PrimitiveExecutable = PrimitiveExecutable {
     name:"topmark",
-    expandable:false,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\topmark")}
+    expandable:true,
+    _apply:|rf,int| {
+        rf.2 = int.state.topmark.clone();
+        Ok(())
+    }
 };
 
 pub static FIRSTMARK: PrimitiveExecutable = PrimitiveExecutable {
     name:"firstmark",
-    expandable:false,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\firstmark")}
+    expandable:true,
+    _apply:|rf,int| {
+        rf.2 = int.state.firstmark.clone();
+        Ok(())
+    }
 };
 
 pub static BOTMARK: PrimitiveExecutable = PrimitiveExecutable {
     name:"botmark",
-    expandable:false,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\botmark")}
+    expandable:true,
+    _apply:|rf,int| {
+        rf.2 = int.state.botmark.clone();
+        Ok(())
+    }
 };
 
 pub static SPLITFIRSTMARK: PrimitiveExecutable = PrimitiveExecutable {
@@ -3862,8 +3861,9 @@ pub static END: PrimitiveExecutable = PrimitiveExecutable {
 
 pub static BATCHMODE: PrimitiveExecutable = PrimitiveExecutable {
     name:"batchmode",
-    expandable:true,
-    _apply:|_tk,_int| {
+    expandable:false,
+    _apply:|_tk,int| {
+        int.state.set_interaction_mode(0,true);
         Ok(())
     }
 };
@@ -3950,8 +3950,12 @@ pub static FONTNAME: PrimitiveExecutable = PrimitiveExecutable {
 
 pub static SHIPOUT: PrimitiveExecutable = PrimitiveExecutable {
     name:"shipout",
-    expandable:true,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\shipout")}
+    expandable:false,
+    _apply:|_tk,int| {
+        let bx = int.read_box()?;
+        int.state.registers_prim.set(DEADCYCLES.index - 1,0,true);
+        int.stomach.ship_page(&mut int.state,bx)
+    }
 };
 
 pub static SPECIAL: PrimitiveExecutable = PrimitiveExecutable {
@@ -3984,14 +3988,20 @@ pub static NOBOUNDARY: PrimitiveExecutable = PrimitiveExecutable {
 
 pub static SCROLLMODE: PrimitiveExecutable = PrimitiveExecutable {
     name:"scrollmode",
-    expandable:true,
-    _apply:|_tk,_int| { Ok(())}
+    expandable:false,
+    _apply:|_tk,int| {
+        int.state.set_interaction_mode(2,true);
+        Ok(())
+    }
 };
 
 pub static NONSTOPMODE: PrimitiveExecutable = PrimitiveExecutable {
     name:"nonstopmode",
-    expandable:true,
-    _apply:|_tk,_int| { Ok(())}
+    expandable:false,
+    _apply:|_tk,int| {
+        int.state.set_interaction_mode(1,true);
+        Ok(())
+    }
 };
 
 pub static PAUSING: PrimitiveExecutable = PrimitiveExecutable {
@@ -4006,28 +4016,120 @@ pub static SETLANGUAGE: PrimitiveExecutable = PrimitiveExecutable {
     _apply:|_tk,_int| {TeXErr!("TODO: \\setlanguage")}
 };
 
+/// The name of `tk` as printed by `\show`
+pub(crate) fn show_name(tk:&Token,int:&Interpreter) -> String {
+    match tk.catcode {
+        CategoryCode::Escape => match int.state.catcodes.get_scheme().escapechar {
+            255 => tk.name().to_string(),
+            c => TeXString(vec!(c)).to_string() + &tk.name().to_string()
+        }
+        _ => TeXString(vec!(tk.char)).to_string()
+    }
+}
+
+fn showbox_limits(int:&Interpreter) -> (i32,i32) {
+    (int.state.registers_prim.get(&(SHOWBOXDEPTH.index - 1)),int.state.registers_prim.get(&(SHOWBOXBREADTH.index - 1)))
+}
+
 pub static SHOW: PrimitiveExecutable = PrimitiveExecutable {
     name:"show",
-    expandable:true,
-    _apply:|_tk,_int| {TeXErr!("TODO:: \\show")}
+    expandable:false,
+    _apply:|_tk,int| {
+        let next = int.next_token();
+        let scheme = int.state.catcodes.get_scheme();
+        let string = match next.catcode {
+            CategoryCode::Active | CategoryCode::Escape => {
+                match int.state.commands.get(&next.cmdname()) {
+                    None => show_name(&next,int) + "=undefined.",
+                    Some(p) => show_name(&next,int) + "=" + &p.describe(scheme)
+                }
+            }
+            _ => PrimitiveTeXCommand::Char(next).as_command().describe(scheme)
+        };
+        int.params.show(&format!("> {}\n",string));
+        Ok(())
+    }
 };
 
 pub static SHOWBOX: PrimitiveExecutable = PrimitiveExecutable {
     name:"showbox",
-    expandable:true,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\showbox")}
+    expandable:false,
+    _apply:|_tk,int| {
+        let index = int.read_number()?;
+        if !(0..=32767).contains(&index) {
+            TeXErr!("Bad register code ({})",index)
+        }
+        let (depth,breadth) = showbox_limits(int);
+        let string = match int.state.boxes.get(&(index as u16)) {
+            TeXBox::Void => "void".to_string(),
+            bx => "\n".to_string() + &crate::stomach::show::show_box(&bx,depth,breadth)
+        };
+        int.params.show(&format!("> \\box{}={}\n",index,string));
+        Ok(())
+    }
 };
 
 pub static SHOWLISTS: PrimitiveExecutable = PrimitiveExecutable {
     name:"showlists",
-    expandable:true,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\showlists")}
+    expandable:false,
+    _apply:|_tk,int| {
+        use crate::stomach::StomachGroup;
+        use crate::stomach::groups::WIGroupTrait;
+        let (depth,breadth) = showbox_limits(int);
+        // the lines the open box groups were entered on, outermost first
+        let mut boxlines = int.state.tp.ls.iter().rev().skip(1).zip(int.state.group_lines.iter())
+            .filter(|(tp,_)| matches!(tp,GroupType::Box(_))).map(|(_,(l,_))| *l);
+        // groups that do not change the mode belong to the list of the enclosing mode
+        let mut lists : Vec<(&str,usize,Vec<Whatsit>)> = vec!();
+        for g in &int.stomach.base().stomachgroups {
+            let (mode,line,ls) = match g {
+                StomachGroup::Top(ls) => (Some("vertical mode"),0,ls),
+                StomachGroup::Par(p) => (Some("horizontal mode"),p.line,&p.children),
+                StomachGroup::TeXGroup(GroupType::Box(bm),ls) => (Some(match bm {
+                    BoxMode::H => "restricted horizontal mode",
+                    BoxMode::V => "internal vertical mode",
+                    BoxMode::DM => "display math mode",
+                    _ => "math mode"
+                }),boxlines.next().unwrap_or(0),ls),
+                StomachGroup::TeXGroup(_,ls) => (None,0,ls),
+                StomachGroup::Other(wig) => (None,0,wig.children())
+            };
+            match (mode,lists.last_mut()) {
+                (None,Some((_,_,v))) => v.extend(ls.iter().cloned()),
+                (m,_) => lists.push((m.unwrap_or("vertical mode"),line,ls.clone()))
+            }
+        }
+        let prevdepth = int.state.skips_prim.get(&(PREVDEPTH.index - 1)).base;
+        let spacefactor = int.state.registers_prim.get(&(SPACEFACTOR.index - 1));
+        let mut string = "\n".to_string();
+        for (mode,line,ls) in lists.iter().rev() {
+            string += &format!("### {} entered at line {}\n",mode,line);
+            if !ls.is_empty() {
+                string += &crate::stomach::show::show_list(ls,depth,breadth);
+                string.push('\n')
+            }
+            if mode.ends_with("vertical mode") {
+                if prevdepth <= IGNORE_DEPTH.base { string += "prevdepth ignored\n" }
+                else { string += &format!("prevdepth {}\n",crate::interpreter::dimensions::numtostr(prevdepth,"")) }
+            } else if mode.ends_with("horizontal mode") {
+                string += &format!("spacefactor {}\n",spacefactor)
+            }
+        }
+        int.params.show(&string);
+        Ok(())
+    }
 };
 
 pub static SHOWTHE: PrimitiveExecutable = PrimitiveExecutable {
     name:"showthe",
-    expandable:true,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\showthe")}
+    expandable:false,
+    _apply:|rf,int| {
+        (THE._apply)(rf,int)?;
+        let tks = std::mem::take(&mut rf.2);
+        let string = crate::interpreter::tokens_to_string(&tks,int.state.catcodes.get_scheme());
+        int.params.show(&format!("> {}.\n",string));
+        Ok(())
+    }
 };
 
 pub static BOTMARKS: PrimitiveExecutable = PrimitiveExecutable {
@@ -4105,20 +4207,31 @@ pub static PARSHAPELENGTH: PrimitiveExecutable = PrimitiveExecutable {
 
 pub static SHOWGROUPS: PrimitiveExecutable = PrimitiveExecutable {
     name:"showgroups",
-    expandable:true,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\showgroups")}
+    expandable:false,
+    _apply:|_tk,int| {
+        int.params.show(&int.state.show_groups());
+        Ok(())
+    }
 };
 
 pub static SHOWIFS: PrimitiveExecutable = PrimitiveExecutable {
     name:"showifs",
-    expandable:true,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\showifs")}
+    expandable:false,
+    _apply:|_tk,int| {
+        int.params.show(&int.state.show_ifs());
+        Ok(())
+    }
 };
 
 pub static SHOWTOKENS: PrimitiveExecutable = PrimitiveExecutable {
     name:"showtokens",
-    expandable:true,
-    _apply:|_tk,_int| {TeXErr!("TODO: \\showtokens")}
+    expandable:false,
+    _apply:|_tk,int| {
+        let tks = int.read_balanced_argument(false,false,false,true)?;
+        let string = crate::interpreter::tokens_to_string(&tks,int.state.catcodes.get_scheme());
+        int.params.show(&format!("> {}.\n",string));
+        Ok(())
+    }
 };
 
 pub static SPLITBOTMARKS: PrimitiveExecutable = PrimitiveExecutable {
@@ -4625,4 +4738,75 @@ pub fn tex_commands() -> Vec<PrimitiveTeXCommand> {vec![
     PrimitiveTeXCommand::Primitive(&UNPENALTY),
     PrimitiveTeXCommand::Primitive(&VADJUST),
     PrimitiveTeXCommand::Primitive(&VFILNEG),
-]}
\ No newline at end of file
+]}
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::interpreter::Interpreter;
+    use crate::interpreter::params::Recording;
+    use crate::interpreter::state::State;
+    use crate::stomach::colon::NoColon;
+
+    /// Runs `text` and returns whether it succeeded and everything shown
+    fn show(text:&str) -> (bool,String) { show_in(State::new(),text) }
+    fn show_in(state:State,text:&str) -> (bool,String) {
+        let text = "\\catcode`\\{=1 \\catcode`\\}=2 \\catcode`\\#=6 \\catcode`\\$=3 ".to_string() + text;
+        crate::utils::with_stack_size(move || {
+            let p = Recording::new();
+            let (outcome,_,()) = Interpreter::do_string_with_state(Path::new("/test.tex"),state,&text,NoColon::new(),&p);
+            (outcome.success(),p.shown())
+        })
+    }
+
+    #[test]
+    fn show_meanings() {
+        assert_eq!(show("\\show\\relax").1,"> \\relax=\\relax.\n");
+        assert_eq!(show("\\show\\undefinedfoo").1,"> \\undefinedfoo=undefined.\n");
+        assert_eq!(show("\\show a").1,"> the letter a.\n");
+        assert_eq!(show("\\count1=5 \\showthe\\count1").1,"> 5.\n");
+        assert_eq!(show("\\showtokens{a b\\relax}").1,"> a b\\relax .\n");
+        assert_eq!(show("\\def\\foo#1{x#1}\\show\\foo").1,"> \\foo=macro:\n#1->x#1.\n");
+        let mut state = State::new();
+        state.set_store_sites(true);
+        assert_eq!(show_in(state,"\n\\def\\foo{x}\\show\\foo").1,"> \\foo=macro:\n->x.\n(defined by \\def in /test.tex (line 2, column 5))\n");
+    }
+
+    #[test]
+    fn showbox() {
+        assert_eq!(show("\\showbox0").1,"> \\box0=void\n");
+        assert_eq!(show("\\setbox0\\hbox{}\\showbox0").1,"> \\box0=\n\\hbox(0.0+0.0)x0.0\n");
+        assert_eq!(show("\\setbox2\\vbox{\\kern3pt}\\showbox2").1,"> \\box2=\n\\vbox(3.0+0.0)x0.0 []\n");
+        assert_eq!(show("\\showboxdepth=1 \\setbox2\\vbox{\\kern3pt}\\showbox2").1,"> \\box2=\n\\vbox(3.0+0.0)x0.0\n.\\kern 3.0\n");
+        assert_eq!(show("\\showbox70000"),(false,String::new()));
+        assert_eq!(show("\\showbox-1"),(false,String::new()));
+    }
+
+    #[test]
+    fn showlists() {
+        assert_eq!(show("\\showlists").1,"\n### vertical mode entered at line 0\nprevdepth ignored\n");
+        assert_eq!(show("\n\\hbox{\\kern1pt\\showlists}").1,
+            "\n### restricted horizontal mode entered at line 2\n\\kern 1.0\nspacefactor 1000\n\
+            ### vertical mode entered at line 0\nprevdepth ignored\n");
+    }
+
+    #[test]
+    fn showgroups() {
+        assert_eq!(show("\\showgroups").1,"\n### bottom level\n");
+        assert_eq!(show("\\begingroup\n{$\\showgroups$}\\endgroup").1,
+            "\n### math shift group (level 3) entered at line 2 ($)\n\
+            ### simple group (level 2) entered at line 2 ({)\n\
+            ### semi simple group (level 1) entered at line 1 (\\begingroup)\n\
+            ### bottom level\n");
+        assert_eq!(show("\\hbox{a\\hbox{\\showgroups}}").1,
+            "\n### hbox group (level 2) entered at line 1 (\\hbox{)\n\
+            ### adjusted hbox group (level 1) entered at line 1 (\\hbox{)\n\
+            ### bottom level\n");
+    }
+
+    #[test]
+    fn showifs() {
+        assert_eq!(show("\\showifs").1,"\n### no active conditionals\n");
+        assert_eq!(show("\\iftrue\n\\unless\\ifnum1>2 \\showifs\\fi\\fi").1,
+            "\n### level 2: \\unless\\ifnum entered on line 2\n### level 1: \\iftrue entered on line 1\n");
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use crate::commands::{Conditional, NumericCommand, PrimitiveExecutable, PrimitiveTeXCommand, ProvidesWhatsit, SimpleWhatsit};
 use crate::commands::conditionals::dotrue;
-use crate::{htmlannotate, htmlliteral, htmlnode, htmlparent, log, TeXErr};
+use crate::{htmlannotate, htmlliteral, htmlnode, htmlparent, TeXErr};
 use crate::interpreter::dimensions::{Numeric, pt};
 use crate::references::SourceFileReference;
 use crate::stomach::colon::ColonMode;
@@ -372,11 +372,12 @@ pub static ANNOTATE_END: SimpleWhatsit = SimpleWhatsit {
 };
 
 pub static BREAK: PrimitiveExecutable = PrimitiveExecutable {
-    _apply: |_,int| {
-        use std::io::{self, Write};
-        let prev = int.preview();
-        unsafe {crate::LOG = true}
-        log!("BREAK! {}",prev);
+    _apply: |exp,int| {
+        let params = int.params;
+        if let Some(d) = params.debugger() {
+            return d.pause(int,&exp.0)
+        }
+        int.trace(&format!("BREAK! {}",int.preview()));
         Ok(())
     },
     expandable: false,
@@ -0,0 +1,212 @@
+/*! Access to files, so that documents, their dependencies and the files they write need not live
+on disk.
+
+All file access of the [`Interpreter`](crate::interpreter::Interpreter) (`\input`, `\openin`,
+`\openout`, fonts, `\pdfximage`, ...) goes through the [`FileSystem`] provided by its
+[`InterpreterParams`](crate::interpreter::params::InterpreterParams). [`PhysicalFS`] is the disk
+(with files in the TeX installation found via [`kpathsea`](crate::kpathsea)), [`MemoryFS`] a map
+from paths to contents, and [`OverlayFS`] a stack of other file systems, e.g. an uploaded project
+in a [`MemoryFS`] on top of the [`PhysicalFS`] for the TeX installation.
+[`ArchiveFS`](archive::ArchiveFS) provides the contents of a zip or tar archive, e.g. a zipped
+project or a packed texmf tree.
+*/
+
+pub mod archive;
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// What [`FileSystem::metadata`] reports about a path
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Metadata {
+    pub is_file:bool,
+    pub is_dir:bool,
+    /// the size in bytes, if a file
+    pub len:u64
+}
+
+pub trait FileSystem : Send + Sync {
+    /// The path of the file `name` as referenced from a file in `indir` (e.g. by `\input`), and
+    /// whether it was found in the TeX installation rather than relative to `indir`
+    fn resolve(&self,name:&str,indir:&Path) -> Option<(PathBuf,bool)>;
+    fn read(&self,path:&Path) -> std::io::Result<Vec<u8>>;
+    /// Writes `content` to `path`, creating missing parent directories
+    fn write(&self,path:&Path,content:&[u8]) -> std::io::Result<()>;
+    /// `None` if nothing exists at `path`
+    fn metadata(&self,path:&Path) -> Option<Metadata>;
+    fn is_file(&self,path:&Path) -> bool {
+        self.metadata(path).map(|m| m.is_file).unwrap_or(false)
+    }
+}
+
+impl<F:FileSystem + ?Sized> FileSystem for Arc<F> {
+    fn resolve(&self,name:&str,indir:&Path) -> Option<(PathBuf,bool)> { (**self).resolve(name,indir) }
+    fn read(&self,path:&Path) -> std::io::Result<Vec<u8>> { (**self).read(path) }
+    fn write(&self,path:&Path,content:&[u8]) -> std::io::Result<()> { (**self).write(path,content) }
+    fn metadata(&self,path:&Path) -> Option<Metadata> { (**self).metadata(path) }
+}
+
+/// The disk; files not found relative to the current directory are looked up in the TeX
+/// installation
+pub struct PhysicalFS;
+impl FileSystem for PhysicalFS {
+    fn resolve(&self,name:&str,indir:&Path) -> Option<(PathBuf,bool)> {
+        crate::kpathsea::kpsewhich(name,indir)
+    }
+    fn read(&self,path:&Path) -> std::io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+    fn write(&self,path:&Path,content:&[u8]) -> std::io::Result<()> {
+        if let Some(p) = path.parent() { std::fs::create_dir_all(p)? }
+        std::fs::write(path,content)
+    }
+    fn metadata(&self,path:&Path) -> Option<Metadata> {
+        std::fs::metadata(path).ok().map(|m| Metadata { is_file:m.is_file(),is_dir:m.is_dir(),len:m.len() })
+    }
+}
+
+/// Resolves `.` and `..` in `p` without looking at any file system
+fn normalize(p:&Path) -> PathBuf {
+    let mut ret = PathBuf::new();
+    for c in p.components() {
+        match c {
+            Component::CurDir => (),
+            Component::ParentDir => { ret.pop(); }
+            c => ret.push(c)
+        }
+    }
+    ret
+}
+
+/// Looks up `name` (or `name.tex`) among `paths` like kpathsea does in an `ls-R` database, i.e.
+/// ignoring case and by the trailing components only
+fn find_by_name<'a>(paths:impl Iterator<Item=&'a PathBuf>,name:&str) -> Option<PathBuf> {
+    let name = normalize(Path::new(&name.to_uppercase()));
+    let tex = PathBuf::from(name.display().to_string() + ".TEX");
+    let mut found : Vec<&PathBuf> = paths.filter(|p| {
+        let p = PathBuf::from(p.display().to_string().to_uppercase());
+        p.ends_with(&name) || p.ends_with(&tex)
+    }).collect();
+    // shallowest first, like a breadth-first search of a texmf tree
+    found.sort_by(|a,b| a.components().count().cmp(&b.components().count()).then(a.cmp(b)));
+    found.first().map(|p| (*p).clone())
+}
+
+/// Files kept in memory. Files not found relative to the current directory are looked up by
+/// their name anywhere, like in a TeX installation.
+#[derive(Default)]
+pub struct MemoryFS {
+    files:RwLock<HashMap<PathBuf,Vec<u8>>>
+}
+impl MemoryFS {
+    pub fn new() -> MemoryFS { MemoryFS::default() }
+    pub fn insert<P:AsRef<Path>,C:Into<Vec<u8>>>(&self,path:P,content:C) {
+        self.files.write().unwrap().insert(normalize(path.as_ref()),content.into());
+    }
+}
+impl FileSystem for MemoryFS {
+    fn resolve(&self,name:&str,indir:&Path) -> Option<(PathBuf,bool)> {
+        let files = self.files.read().unwrap();
+        let path = normalize(&indir.join(name));
+        if files.contains_key(&path) { return Some((path,false)) }
+        let tex = PathBuf::from(path.display().to_string() + ".tex");
+        if files.contains_key(&tex) { return Some((tex,false)) }
+        find_by_name(files.keys(),name).map(|p| (p,true))
+    }
+    fn read(&self,path:&Path) -> std::io::Result<Vec<u8>> {
+        match self.files.read().unwrap().get(&normalize(path)) {
+            Some(c) => Ok(c.clone()),
+            None => Err(Error::new(ErrorKind::NotFound,format!("{} not found",path.display())))
+        }
+    }
+    fn write(&self,path:&Path,content:&[u8]) -> std::io::Result<()> {
+        self.insert(path,content);
+        Ok(())
+    }
+    fn metadata(&self,path:&Path) -> Option<Metadata> {
+        let path = normalize(path);
+        let files = self.files.read().unwrap();
+        match files.get(&path) {
+            Some(c) => Some(Metadata { is_file:true,is_dir:false,len:c.len() as u64 }),
+            None if files.keys().any(|p| p.starts_with(&path)) => Some(Metadata { is_file:false,is_dir:true,len:0 }),
+            None => None
+        }
+    }
+}
+
+/// A stack of file systems: a file is taken from the first layer that has it, where files
+/// relative to the current directory take precedence over files found in a TeX installation
+/// (see [`FileSystem::resolve`]). Files are written to the first layer that is not read-only,
+/// e.g. to a [`MemoryFS`] on top, or to the disk below [`ArchiveFS`](archive::ArchiveFS)s.
+pub struct OverlayFS {
+    layers:Vec<Box<dyn FileSystem>>
+}
+impl OverlayFS {
+    /// `layers` from top to bottom
+    pub fn new(layers:Vec<Box<dyn FileSystem>>) -> OverlayFS { OverlayFS { layers } }
+}
+impl FileSystem for OverlayFS {
+    fn resolve(&self,name:&str,indir:&Path) -> Option<(PathBuf,bool)> {
+        let mut installed = None;
+        let mut fallback = None;
+        for l in &self.layers {
+            match l.resolve(name,indir) {
+                Some((p,false)) if l.is_file(&p) => return Some((p,false)),
+                Some((p,true)) if l.is_file(&p) && installed.is_none() => installed = Some((p,true)),
+                Some(r) if fallback.is_none() => fallback = Some(r),
+                _ => ()
+            }
+        }
+        installed.or(fallback)
+    }
+    fn read(&self,path:&Path) -> std::io::Result<Vec<u8>> {
+        match self.layers.iter().find(|l| l.is_file(path)) {
+            Some(l) => l.read(path),
+            None => Err(Error::new(ErrorKind::NotFound,format!("{} not found",path.display())))
+        }
+    }
+    fn write(&self,path:&Path,content:&[u8]) -> std::io::Result<()> {
+        for l in &self.layers {
+            match l.write(path,content) {
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => (),
+                r => return r
+            }
+        }
+        Err(Error::new(ErrorKind::PermissionDenied,format!("Can not write {}: read-only file system",path.display())))
+    }
+    fn metadata(&self,path:&Path) -> Option<Metadata> {
+        self.layers.iter().find_map(|l| l.metadata(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlay_reads_top_down_and_writes_to_the_first_writable_layer() {
+        let top = Arc::new(MemoryFS::new());
+        let bottom = Arc::new(MemoryFS::new());
+        top.insert("/p/a.tex","top");
+        bottom.insert("/p/a.tex","bottom");
+        bottom.insert("/p/b.tex","bottom");
+        // an empty overlay is read-only
+        let fs = OverlayFS::new(vec!(Box::new(OverlayFS::new(vec!())),Box::new(top.clone()),Box::new(bottom.clone())));
+        assert_eq!(fs.read(Path::new("/p/a.tex")).unwrap(),b"top");
+        assert_eq!(fs.resolve("b",Path::new("/p")),Some((PathBuf::from("/p/b.tex"),false)));
+        fs.write(Path::new("/p/b.aux"),b"aux").unwrap();
+        assert!(top.is_file(Path::new("/p/b.aux")) && !bottom.is_file(Path::new("/p/b.aux")));
+        assert_eq!(OverlayFS::new(vec!()).write(Path::new("/p/c"),b"").unwrap_err().kind(),ErrorKind::PermissionDenied);
+    }
+    #[test]
+    fn memory_fs_finds_files_by_name() {
+        let fs = MemoryFS::new();
+        fs.insert("/texmf/tex/latex/pkg/Pkg.sty","");
+        fs.insert("/texmf/tex/latex/pkg/old/pkg.sty","");
+        assert_eq!(fs.resolve("pkg.sty",Path::new("/doc")),Some((PathBuf::from("/texmf/tex/latex/pkg/Pkg.sty"),true)));
+        assert_eq!(fs.resolve("./../texmf/tex/latex/pkg/old/pkg.sty",Path::new("/doc")),Some((PathBuf::from("/texmf/tex/latex/pkg/old/pkg.sty"),false)));
+        assert!(fs.metadata(Path::new("/texmf/tex")).map(|m| m.is_dir).unwrap_or(false));
+    }
+}
@@ -0,0 +1,482 @@
+/*! Zip and tar (optionally gzipped) archives as [`FileSystem`]s, so that zipped projects and
+packed texmf trees can be used without unpacking them first.
+
+The files of an archive appear under a mount point (by default the path of the archive itself,
+i.e. `paper.zip/main.tex`). Like in a TeX installation, files not found relative to the current
+directory are looked up by name in an index of the whole archive (similar to an `ls-R` database).
+Zip entries are only decompressed when read.
+*/
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use crate::filesystem::{find_by_name, normalize, FileSystem, Metadata};
+
+const TAR_BLOCK : usize = 512;
+/// Files in archives larger than this (when decompressed) are not read
+const MAX_FILE_SIZE : u64 = 1 << 30;
+/// Gzipped archives larger than this when decompressed are rejected
+const MAX_ARCHIVE_SIZE : u64 = 4 << 30;
+
+#[derive(Clone,Copy)]
+enum Compression { Stored, Deflated }
+
+#[derive(Clone,Copy)]
+struct Entry {
+    /// start of the (possibly compressed) content in [`ArchiveFS::data`]
+    offset:usize,
+    compressed_len:usize,
+    len:usize,
+    compression:Compression
+}
+
+/// The files of a zip, tar or tar.gz archive (read-only)
+pub struct ArchiveFS {
+    mount:PathBuf,
+    /// the archive, decompressed if it was gzipped
+    data:Vec<u8>,
+    files:HashMap<PathBuf,Entry>,
+    /// the upper-case file names with the paths of the files of that name
+    index:HashMap<String,Vec<PathBuf>>
+}
+
+fn invalid(msg:String) -> Error { Error::new(ErrorKind::InvalidData,msg) }
+
+/// Decompresses `data`, but at most `limit` bytes of it (gzip bombs)
+fn gunzip(data:&[u8],limit:u64) -> std::io::Result<Vec<u8>> {
+    let mut ret = vec!();
+    flate2::read::MultiGzDecoder::new(data).take(limit + 1).read_to_end(&mut ret)?;
+    if ret.len() as u64 > limit {
+        return Err(invalid(format!("archive larger than {} bytes when decompressed",limit)))
+    }
+    Ok(ret)
+}
+
+/// Whether `path` looks like an archive that [`ArchiveFS::open`] can read
+pub fn is_archive(path:&Path) -> bool {
+    let name = path.file_name().map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default();
+    [".zip",".tar",".tar.gz",".tgz"].iter().any(|e| name.ends_with(e))
+}
+
+impl ArchiveFS {
+    /// Reads the archive at `path` and mounts its files at `path`
+    pub fn open(path:&Path) -> std::io::Result<ArchiveFS> {
+        ArchiveFS::from_bytes(std::fs::read(path)?,path)
+    }
+    /// Reads an archive (zip, tar or gzipped tar, by its content) and mounts its files at `mount`
+    pub fn from_bytes(data:Vec<u8>,mount:&Path) -> std::io::Result<ArchiveFS> {
+        let (data,entries) = if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+            let entries = read_zip(&data)?;
+            (data,entries)
+        } else if data.starts_with(&[0x1f,0x8b]) {
+            let tar = gunzip(&data,MAX_ARCHIVE_SIZE)?;
+            let entries = read_tar(&tar)?;
+            (tar,entries)
+        } else {
+            let entries = read_tar(&data)?;
+            (data,entries)
+        };
+        let mount = normalize(mount);
+        let mut files = HashMap::new();
+        let mut index : HashMap<String,Vec<PathBuf>> = HashMap::new();
+        for (name,e) in entries {
+            let path = mount.join(normalize(Path::new(name.trim_start_matches('/'))));
+            if !path.starts_with(&mount) || path == mount { continue }
+            if let Some(f) = path.file_name() {
+                index.entry(f.to_string_lossy().to_uppercase()).or_default().push(path.clone())
+            }
+            files.insert(path,e);
+        }
+        Ok(ArchiveFS { mount,data,files,index })
+    }
+    /// Where the files of the archive appear
+    pub fn mount(&self) -> &Path { &self.mount }
+    /// All files in the archive
+    pub fn files(&self) -> impl Iterator<Item=&PathBuf> { self.files.keys() }
+    /// The main file of a project archive: the least deeply nested `.tex` file containing a
+    /// `\documentclass` (like arXiv does)
+    pub fn main_file(&self) -> Option<PathBuf> {
+        let mut candidates : Vec<&PathBuf> = self.files.keys().filter(|p| {
+            p.extension().map(|e| e.eq_ignore_ascii_case("tex")).unwrap_or(false) &&
+            match self.read(p) {
+                Ok(c) => String::from_utf8_lossy(&c).lines().any(|l| l.trim_start().starts_with("\\documentclass")),
+                _ => false
+            }
+        }).collect();
+        candidates.sort_by(|a,b| a.components().count().cmp(&b.components().count()).then(a.cmp(b)));
+        candidates.first().map(|p| (*p).clone())
+    }
+}
+
+impl FileSystem for ArchiveFS {
+    fn resolve(&self,name:&str,indir:&Path) -> Option<(PathBuf,bool)> {
+        let path = normalize(&indir.join(name));
+        if self.files.contains_key(&path) { return Some((path,false)) }
+        let tex = PathBuf::from(path.display().to_string() + ".tex");
+        if self.files.contains_key(&tex) { return Some((tex,false)) }
+        let file = Path::new(name).file_name()?.to_string_lossy().to_uppercase();
+        let candidates = self.index.get(&file).into_iter().chain(self.index.get(&(file.clone() + ".TEX"))).flatten();
+        find_by_name(candidates,name).map(|p| (p,true))
+    }
+    fn read(&self,path:&Path) -> std::io::Result<Vec<u8>> {
+        let e = match self.files.get(&normalize(path)) {
+            Some(e) => *e,
+            None => return Err(Error::new(ErrorKind::NotFound,format!("{} not found",path.display())))
+        };
+        let raw = match e.offset.checked_add(e.compressed_len).and_then(|end| self.data.get(e.offset..end)) {
+            Some(r) => r,
+            None => return Err(invalid(format!("{} is truncated",path.display())))
+        };
+        match e.compression {
+            Compression::Stored => Ok(raw.to_vec()),
+            Compression::Deflated => {
+                // the sizes come from the archive, so neither is trusted for allocating
+                let limit = (e.len as u64).min(MAX_FILE_SIZE);
+                let mut ret = Vec::new();
+                flate2::read::DeflateDecoder::new(raw).take(limit + 1).read_to_end(&mut ret)?;
+                if ret.len() as u64 > limit {
+                    return Err(invalid(format!("{} is larger than {} bytes",path.display(),limit)))
+                }
+                Ok(ret)
+            }
+        }
+    }
+    fn write(&self,path:&Path,_content:&[u8]) -> std::io::Result<()> {
+        Err(Error::new(ErrorKind::PermissionDenied,format!("Can not write {}: archives are read-only",path.display())))
+    }
+    fn metadata(&self,path:&Path) -> Option<Metadata> {
+        let path = normalize(path);
+        match self.files.get(&path) {
+            Some(e) => Some(Metadata { is_file:true,is_dir:false,len:e.len as u64 }),
+            None if path.starts_with(&self.mount) && (path == self.mount || self.files.keys().any(|p| p.starts_with(&path))) =>
+                Some(Metadata { is_file:false,is_dir:true,len:0 }),
+            None => None
+        }
+    }
+}
+
+fn u16_at(data:&[u8],i:usize) -> std::io::Result<u16> {
+    data.get(i..i + 2).map(|b| u16::from_le_bytes([b[0],b[1]])).ok_or_else(|| invalid("Truncated zip archive".to_string()))
+}
+fn u32_at(data:&[u8],i:usize) -> std::io::Result<u32> {
+    data.get(i..i + 4).map(|b| u32::from_le_bytes([b[0],b[1],b[2],b[3]])).ok_or_else(|| invalid("Truncated zip archive".to_string()))
+}
+fn u64_at(data:&[u8],i:usize) -> std::io::Result<u64> {
+    Ok(u32_at(data,i)? as u64 | ((u32_at(data,i + 4)? as u64) << 32))
+}
+
+/// The files in a zip archive, from its central directory
+fn read_zip(data:&[u8]) -> std::io::Result<Vec<(String,Entry)>> {
+    // the end of central directory record is followed by a comment of at most 65535 bytes
+    let min = data.len().saturating_sub(22 + 65535);
+    let eocd = (min..data.len().saturating_sub(21)).rev().find(|i| data[*i..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| invalid("Not a zip archive: no end of central directory".to_string()))?;
+    let mut count = u16_at(data,eocd + 10)? as u64;
+    let mut cd = u32_at(data,eocd + 16)? as u64;
+    if (count == 0xFFFF || cd == 0xFFFFFFFF) && eocd >= 20 && data[eocd - 20..].starts_with(b"PK\x06\x07") {
+        let eocd64 = u64_at(data,eocd - 20 + 8)? as usize;
+        if !data.get(eocd64..).map(|d| d.starts_with(b"PK\x06\x06")).unwrap_or(false) {
+            return Err(invalid("Invalid zip64 end of central directory".to_string()))
+        }
+        count = u64_at(data,eocd64 + 32)?;
+        cd = u64_at(data,eocd64 + 48)?;
+    }
+    let mut ret = vec!();
+    let mut i = cd as usize;
+    for _ in 0..count {
+        if !data.get(i..).map(|d| d.starts_with(b"PK\x01\x02")).unwrap_or(false) {
+            return Err(invalid("Invalid zip central directory".to_string()))
+        }
+        let flags = u16_at(data,i + 8)?;
+        let method = u16_at(data,i + 10)?;
+        let mut compressed_len = u32_at(data,i + 20)? as u64;
+        let mut len = u32_at(data,i + 24)? as u64;
+        let name_len = u16_at(data,i + 28)? as usize;
+        let extra_len = u16_at(data,i + 30)? as usize;
+        let comment_len = u16_at(data,i + 32)? as usize;
+        let mut local = u32_at(data,i + 42)? as u64;
+        let name = String::from_utf8_lossy(data.get(i + 46..i + 46 + name_len)
+            .ok_or_else(|| invalid("Truncated zip archive".to_string()))?).to_string();
+        // zip64: the 64 bit sizes and offset of the fields set to 0xFFFFFFFF, in this order
+        let mut j = i + 46 + name_len;
+        while j + 4 <= i + 46 + name_len + extra_len {
+            let (id,size) = (u16_at(data,j)?,u16_at(data,j + 2)? as usize);
+            if id == 1 {
+                let mut k = j + 4;
+                for field in [&mut len,&mut compressed_len,&mut local] {
+                    if *field == 0xFFFFFFFF && k + 8 <= j + 4 + size {
+                        *field = u64_at(data,k)?;
+                        k += 8
+                    }
+                }
+            }
+            j += 4 + size
+        }
+        i += 46 + name_len + extra_len + comment_len;
+        if name.ends_with('/') { continue }
+        if flags & 1 != 0 {
+            return Err(invalid(format!("{} is encrypted",name)))
+        }
+        let compression = match method {
+            0 => Compression::Stored,
+            8 => Compression::Deflated,
+            m => return Err(invalid(format!("{}: unsupported compression method {}",name,m)))
+        };
+        let local = local as usize;
+        if !data.get(local..).map(|d| d.starts_with(b"PK\x03\x04")).unwrap_or(false) {
+            return Err(invalid(format!("{}: invalid local header",name)))
+        }
+        let offset = local + 30 + u16_at(data,local + 26)? as usize + u16_at(data,local + 28)? as usize;
+        ret.push((name,Entry { offset,compressed_len:compressed_len as usize,len:len as usize,compression }))
+    }
+    Ok(ret)
+}
+
+/// A NUL-terminated string field of a tar header
+fn tar_str(field:&[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+/// A numeric field of a tar header: octal, or base-256 if the high bit is set
+fn tar_num(field:&[u8]) -> std::io::Result<usize> {
+    if field[0] & 0x80 != 0 {
+        return field[1..].iter().try_fold(0usize,|n,b| n.checked_mul(256).map(|n| n | *b as usize))
+            .ok_or_else(|| invalid("Number in tar header too large".to_string()))
+    }
+    let s = tar_str(field);
+    let s = s.trim_matches(|c:char| c == ' ' || c == '\0');
+    if s.is_empty() { return Ok(0) }
+    usize::from_str_radix(s,8).map_err(|_| invalid(format!("Invalid number in tar header: {}",s)))
+}
+
+/// The files in a (ustar, GNU or pax) tar archive
+fn read_tar(data:&[u8]) -> std::io::Result<Vec<(String,Entry)>> {
+    let mut ret = vec!();
+    let mut i = 0;
+    // a name set by a preceding GNU long name or pax header
+    let mut long_name : Option<String> = None;
+    while i + TAR_BLOCK <= data.len() {
+        let header = &data[i..i + TAR_BLOCK];
+        if header.iter().all(|b| *b == 0) { break }
+        let checksum = tar_num(&header[148..156])?;
+        let sum : usize = header.iter().enumerate().map(|(j,b)| if (148..156).contains(&j) { 32 } else { *b as usize }).sum();
+        if checksum != sum {
+            return Err(invalid(if i == 0 { "Not a zip or tar archive".to_string() } else { "Invalid tar header".to_string() }))
+        }
+        let size = tar_num(&header[124..136])?;
+        let start = i + TAR_BLOCK;
+        let truncated = || invalid("Truncated tar archive".to_string());
+        let content = start.checked_add(size).and_then(|end| data.get(start..end)).ok_or_else(truncated)?;
+        i = size.div_ceil(TAR_BLOCK).checked_mul(TAR_BLOCK).and_then(|s| start.checked_add(s)).ok_or_else(truncated)?;
+        match header[156] {
+            b'L' => long_name = Some(tar_str(content)),
+            b'x' => {
+                // records "<length> <key>=<value>\n"
+                for rec in String::from_utf8_lossy(content).lines() {
+                    if let Some((_,kv)) = rec.split_once(' ') {
+                        if let Some(path) = kv.strip_prefix("path=") { long_name = Some(path.to_string()) }
+                    }
+                }
+            }
+            b'0' | 0 | b'7' => {
+                let name = match long_name.take() {
+                    Some(n) => n,
+                    None if &header[257..262] == b"ustar" && header[345] != 0 =>
+                        tar_str(&header[345..500]) + "/" + &tar_str(&header[0..100]),
+                    None => tar_str(&header[0..100])
+                };
+                ret.push((name,Entry { offset:start,compressed_len:size,len:size,compression:Compression::Stored }))
+            }
+            // directories, links, global pax headers etc.
+            _ => long_name = None
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A zip archive of `(name, content, deflate, declared size)`; a declared size is stored as a
+    /// zip64 extra field instead of the actual size
+    fn zip(files:&[(&str,&[u8],bool,Option<u64>)]) -> Vec<u8> {
+        let (mut data,mut cd) = (vec!(),vec!());
+        for (name,content,deflate,declared) in files {
+            let stored = if *deflate {
+                let mut e = flate2::write::DeflateEncoder::new(vec!(),flate2::Compression::default());
+                e.write_all(content).unwrap();
+                e.finish().unwrap()
+            } else { content.to_vec() };
+            let method : u16 = if *deflate {8} else {0};
+            let local = data.len() as u32;
+            data.extend_from_slice(b"PK\x03\x04\x14\x00\x00\x00");
+            data.extend_from_slice(&method.to_le_bytes());
+            data.extend_from_slice(&[0;8]);
+            data.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&[0,0]);
+            data.extend_from_slice(name.as_bytes());
+            data.extend_from_slice(&stored);
+            let extra = match declared {
+                Some(l) => [&[1u8,0,8,0][..],&l.to_le_bytes()].concat(),
+                None => vec!()
+            };
+            cd.extend_from_slice(b"PK\x01\x02\x14\x00\x14\x00\x00\x00");
+            cd.extend_from_slice(&method.to_le_bytes());
+            cd.extend_from_slice(&[0;8]);
+            cd.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            cd.extend_from_slice(&(if declared.is_some() {0xFFFFFFFF} else {content.len() as u32}).to_le_bytes());
+            cd.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            cd.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            cd.extend_from_slice(&[0;10]);
+            cd.extend_from_slice(&local.to_le_bytes());
+            cd.extend_from_slice(name.as_bytes());
+            cd.extend_from_slice(&extra);
+        }
+        let offset = data.len() as u32;
+        data.extend_from_slice(&cd);
+        data.extend_from_slice(b"PK\x05\x06\x00\x00\x00\x00");
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(cd.len() as u32).to_le_bytes());
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&[0,0]);
+        data
+    }
+
+    /// A tar header block for `name` of type `tp` with `size`
+    fn tar_header(name:&str,prefix:&str,tp:u8,size:usize) -> Vec<u8> {
+        let mut h = vec!(0u8;TAR_BLOCK);
+        h[..name.len()].copy_from_slice(name.as_bytes());
+        h[124..135].copy_from_slice(format!("{:011o}",size).as_bytes());
+        h[156] = tp;
+        h[257..263].copy_from_slice(b"ustar\0");
+        h[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        let sum : usize = h.iter().enumerate().map(|(j,b)| if (148..156).contains(&j) { 32 } else { *b as usize }).sum();
+        h[148..155].copy_from_slice(format!("{:06o}\0",sum).as_bytes());
+        h
+    }
+    /// A tar archive of `(header name, ustar prefix, type, content)`
+    fn tar(entries:&[(&str,&str,u8,&[u8])]) -> Vec<u8> {
+        let mut data = vec!();
+        for (name,prefix,tp,content) in entries {
+            data.append(&mut tar_header(name,prefix,*tp,content.len()));
+            data.extend_from_slice(content);
+            data.resize(data.len().div_ceil(TAR_BLOCK) * TAR_BLOCK,0);
+        }
+        data.resize(data.len() + 2 * TAR_BLOCK,0);
+        data
+    }
+
+    fn read(fs:&ArchiveFS,path:&str) -> String {
+        String::from_utf8(fs.read(Path::new(path)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn zip_round_trip() {
+        let data = zip(&[
+            ("paper/",b"",false,None),
+            ("paper/main.tex",b"\\documentclass{article}",true,None),
+            ("paper/sections/intro.tex",b"Intro",false,None),
+            ("paper/sections/old.tex",b"\\documentclass{old}",true,None)
+        ]);
+        let fs = ArchiveFS::from_bytes(data,Path::new("/up/p.zip")).unwrap();
+        assert_eq!(fs.files().count(),3);
+        assert_eq!(read(&fs,"/up/p.zip/paper/main.tex"),"\\documentclass{article}");
+        assert_eq!(read(&fs,"/up/p.zip/paper/sections/intro.tex"),"Intro");
+        assert_eq!(fs.main_file(),Some(PathBuf::from("/up/p.zip/paper/main.tex")));
+        assert_eq!(fs.resolve("sections/intro",Path::new("/up/p.zip/paper")),Some((PathBuf::from("/up/p.zip/paper/sections/intro.tex"),false)));
+        assert_eq!(fs.resolve("INTRO.tex",Path::new("/elsewhere")),Some((PathBuf::from("/up/p.zip/paper/sections/intro.tex"),true)));
+        assert!(fs.metadata(Path::new("/up/p.zip/paper/sections")).unwrap().is_dir);
+        assert!(fs.write(Path::new("/up/p.zip/paper/main.aux"),b"").is_err());
+    }
+
+    #[test]
+    fn tar_round_trip() {
+        let long = "a/".repeat(60) + "long.tex";
+        let pax = format!("{} path={}\n",11 + long.len(),long);
+        let data = tar(&[
+            ("dir/","",b'5',b""),
+            ("file.tex","dir",b'0',b"in dir"),
+            ("././@LongLink","",b'L',format!("{}\0",long).as_bytes()),
+            ("truncated-name","",b'0',b"gnu"),
+            ("PaxHeader","",b'x',pax.as_bytes()),
+            ("truncated-name-2","",b'0',b"pax"),
+            ("link","",b'2',b"")
+        ]);
+        let fs = ArchiveFS::from_bytes(data.clone(),Path::new("/t.tar")).unwrap();
+        assert_eq!(fs.files().count(),2);
+        assert_eq!(read(&fs,"/t.tar/dir/file.tex"),"in dir");
+        // the pax header replaces the GNU one's name for the following entry only
+        assert_eq!(read(&fs,&format!("/t.tar/{}",long)),"pax");
+        assert!(fs.metadata(Path::new("/t.tar/truncated-name")).is_none());
+
+        let mut gz = flate2::write::GzEncoder::new(vec!(),flate2::Compression::default());
+        gz.write_all(&data).unwrap();
+        let fs = ArchiveFS::from_bytes(gz.finish().unwrap(),Path::new("/t.tar.gz")).unwrap();
+        assert_eq!(read(&fs,"/t.tar.gz/dir/file.tex"),"in dir");
+    }
+
+    #[test]
+    fn entries_stay_below_the_mount_point() {
+        let data = zip(&[
+            ("../evil.tex",b"1",false,None),
+            ("/abs/evil.tex",b"2",false,None),
+            ("a/../../../evil2.tex",b"3",true,None)
+        ]);
+        let fs = ArchiveFS::from_bytes(data,Path::new("/up/p.zip")).unwrap();
+        assert!(fs.files().all(|p| p.starts_with("/up/p.zip")));
+        assert_eq!(read(&fs,"/up/p.zip/evil.tex"),"1");
+        assert_eq!(read(&fs,"/up/p.zip/abs/evil.tex"),"2");
+        assert_eq!(read(&fs,"/up/p.zip/evil2.tex"),"3");
+        assert!(fs.read(Path::new("/up/evil.tex")).is_err());
+        assert!(fs.resolve("../evil.tex",Path::new("/up/p.zip")).map(|(p,_)| p.starts_with("/up/p.zip")).unwrap_or(true));
+
+        let data = tar(&[("../../evil.tex","",b'0',b"t"),("x","..",b'0',b"u")]);
+        let fs = ArchiveFS::from_bytes(data,Path::new("/t.tar")).unwrap();
+        assert!(fs.files().all(|p| p.starts_with("/t.tar")));
+    }
+
+    #[test]
+    fn sizes_from_the_archive_are_not_trusted() {
+        // zip64 sizes near u64::MAX or 4GiB must neither overflow nor be allocated up front
+        for declared in [u64::MAX,u64::MAX - 2,1 << 32] {
+            let data = zip(&[("bomb.tex",b"hello",true,Some(declared))]);
+            let fs = ArchiveFS::from_bytes(data,Path::new("/z.zip")).unwrap();
+            assert_eq!(read(&fs,"/z.zip/bomb.tex"),"hello");
+        }
+        // more content than declared
+        let data = zip(&[("small.tex",b"hello",true,Some(2))]);
+        let fs = ArchiveFS::from_bytes(data,Path::new("/z.zip")).unwrap();
+        assert!(fs.read(Path::new("/z.zip/small.tex")).is_err());
+
+        // base-256 tar sizes
+        let mut data = tar(&[("big.tex","",b'0',b"x")]);
+        for field in [[0x80,0,0,0,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF],[0xFF;12]] {
+            data[124..136].copy_from_slice(&field);
+            let s : usize = data[..TAR_BLOCK].iter().enumerate().map(|(j,b)| if (148..156).contains(&j) { 32 } else { *b as usize }).sum();
+            data[148..155].copy_from_slice(format!("{:06o}\0",s).as_bytes());
+            assert!(ArchiveFS::from_bytes(data.clone(),Path::new("/t.tar")).is_err());
+        }
+        assert!(ArchiveFS::from_bytes(b"PK\x05\x06 not really".to_vec(),Path::new("/z.zip")).is_err());
+    }
+
+    #[test]
+    fn gzip_output_is_bounded() {
+        // 1MiB of zeros compresses to about 1KiB
+        let mut gz = flate2::write::GzEncoder::new(vec!(),flate2::Compression::best());
+        gz.write_all(&vec!(0u8;1 << 20)).unwrap();
+        let data = gz.finish().unwrap();
+        assert!(data.len() < 1 << 12);
+        assert!(gunzip(&data,(1 << 20) - 1).is_err());
+        assert_eq!(gunzip(&data,1 << 20).unwrap().len(),1 << 20);
+        // concatenated members count together
+        let twice = [data.clone(),data].concat();
+        assert!(gunzip(&twice,(1 << 21) - 1).is_err());
+        assert_eq!(gunzip(&twice,1 << 21).unwrap().len(),1 << 21);
+    }
+}
@@ -67,6 +67,8 @@ pub struct FontFile {
     pub ligs:HashMap<(u8,u8),u8>,
     pub name:TeXStr,
     pub filepath:TeXStr,
+    /// md5 of the `.tfm` file, for [`Dependencies`](crate::interpreter::deps::Dependencies)
+    pub hash:Option<String>,
     pub chartable:Option<Arc<FontTable>>
 }
 impl PartialEq for FontFile {
@@ -84,7 +86,6 @@ impl FontState {
         (self.ret.pop().unwrap(),self.ret.pop().unwrap(),self.ret.pop().unwrap(),self.ret.pop().unwrap())
     }
 }
-use std::fs;
 use std::borrow::BorrowMut;
 
 // https://www.tug.org/TUGboat/tb02-1/tb02fuchstfm.pdf
@@ -94,9 +95,10 @@ impl FontFile {
         let filepath : TeXStr = pb.as_path().to_str().unwrap().into();
         let name : TeXStr = pb.file_stem().unwrap().to_str().unwrap().into();
         let mut state = FontState {
-            ret:fs::read(pb).unwrap(),
+            ret:params.filesystem().read(&pb).unwrap(),
             i:0
         };
+        let hash = crate::interpreter::deps::hash(Some(&state.ret));
         state.ret.reverse();
 
         let tablename : String = name.to_string().chars().map(|x| if !x.is_ascii_digit() {Some(x)} else {None}).flatten().collect();
@@ -272,7 +274,7 @@ impl FontFile {
 
         FontFile {
             hyphenchar,skewchar,dimen,size,typestr,widths,heights,depths,ics,lps,rps,ligs,name,
-            chartable,filepath
+            chartable,filepath,hash
         }
     }
 }
@@ -424,6 +426,7 @@ thread_local! {
         ligs : HashMap::new(),
         name : TeXStr::new("Nullfont".as_bytes()),
         filepath: TeXStr::new("NULL".as_bytes()),
+        hash:None,
         chartable:None
     });
     pub static NULL_FONT : ArcFont = ArcFont(std::sync::Arc::new(Font {
@@ -458,6 +461,7 @@ thread_local! {
             table:&CUSTOM_BINDINGS_MAP
         })),
         filepath: TeXStr::new("CUSTOM".as_bytes()),
+        hash:None
     });
     pub static CUSTOM_BINDINGS_FONT : std::sync::Arc<Font> = std::sync::Arc::new(Font {
             file:CUSTOM_BINDINGS_FONT_FILE.try_with(|x| x.clone()).unwrap(),at:Some(0),
@@ -25,7 +25,7 @@ use std::io::Write;
 use crate::ontology::{Expansion, Token};
 use crate::catcodes::{CategoryCode, CategoryCodeScheme};
 use std::path::{Path, PathBuf};
-use crate::commands::{TeXCommand, PrimitiveTeXCommand, ProvidesWhatsit};
+use crate::commands::{DefinitionSite, TeXCommand, PrimitiveTeXCommand, ProvidesWhatsit};
 use crate::interpreter::files::{VFile};
 use crate::interpreter::mouth::Mouths;
 use crate::interpreter::state::{GroupType, State};
@@ -38,6 +38,12 @@ pub(crate) mod files;
 pub mod dimensions;
 pub mod methods;
 pub mod params;
+pub mod diagnostics;
+pub mod deps;
+pub mod debugger;
+pub mod tracing;
+pub mod profiler;
+pub mod limits;
 
 
 pub fn tokenize(s : TeXString,cats: &CategoryCodeScheme) -> Vec<Token> {
@@ -68,17 +74,54 @@ impl Jobinfo {
     }
 }
 
+/// How processing a file ended
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Outcome {
+    Success,
+    /// there were errors, or writing the output files failed
+    Failure,
+    /// stopped early via the [`CancellationToken`](limits::CancellationToken) of the
+    /// [`InterpreterParams`]
+    Cancelled
+}
+impl Outcome {
+    pub fn success(&self) -> bool { *self == Outcome::Success }
+}
+
+/// The outcome of processing one file with [`Interpreter::do_files_parallel`]
+pub struct BatchResult<B> {
+    pub path:PathBuf,
+    pub outcome:Outcome,
+    pub passes:usize,
+    /// the colon's result, or the panic message if processing the file panicked
+    pub result:Result<B,String>,
+    pub dependencies:deps::Dependencies
+}
+
 pub struct Interpreter<'a> {
     pub state:State,
     pub jobinfo:Jobinfo,
     pub(crate) mouths:Mouths,
     pub stomach:&'a mut dyn Stomach,
-    pub params:&'a dyn InterpreterParams
+    pub params:&'a dyn InterpreterParams,
+    pub(in crate) assigning:Option<DefinitionSite>,
+    /// the mode last printed by `\tracingcommands`
+    pub(in crate) shown_mode:Option<TeXMode>,
+    limits:Limits,
+    /// the number of expansions so far, for [`Limits::expansions`]
+    expansions:usize,
+    /// the number of expansions in progress, for [`Limits::expansion_depth`]
+    pub(in crate) expansion_depth:usize,
+    /// when [`Limits::timeout`] is exceeded
+    deadline:Option<std::time::Instant>,
+    errors:usize
 }
-use crate::{TeXErr, FileEnd, log};
+use crate::{TeXErr, FileEnd};
 use crate::commands::primitives::{ENDTEMPLATE, LEFT, RIGHT};
-use crate::commands::registers::PREVGRAF;
-use crate::interpreter::params::InterpreterParams;
+use crate::commands::registers::{PREVGRAF, TRACINGCOMMANDS};
+use crate::interpreter::params::{InterpreterParams, OutFiles};
+use crate::interpreter::profiler::ProfileStep;
+use crate::interpreter::limits::Limits;
 
 pub fn string_to_tokens(s : TeXString) -> Vec<Token> {
     use crate::catcodes::OTHER_SCHEME;
@@ -116,7 +159,7 @@ pub fn tokens_to_string(tks:&Vec<Token>,catcodes:&CategoryCodeScheme) -> TeXStri
     ret.into()
 }
 
-use crate::stomach::{NoShipoutRoutine, Stomach, StomachMessage, Whatsit};
+use crate::stomach::{Stomach, StomachGroup, StomachMessage, Whatsit};
 use crate::stomach::math::{Above, GroupedMath, MathChar, MathGroup, MathKernel};
 use crate::interpreter::state::FontStyle;
 use crate::stomach::boxes::BoxMode;
@@ -129,19 +172,60 @@ impl Interpreter<'_> {
         tokens_to_string(tks,self.state.catcodes.get_scheme())
     }
 
-    pub fn kpsewhich(&self,filename: &str) -> Option<(PathBuf,bool)> {
-        crate::kpathsea::kpsewhich(filename,self.jobinfo.in_file())
+    pub fn kpsewhich(&mut self,filename: &str) -> Option<(PathBuf,bool)> {
+        let fs = self.params.filesystem();
+        if let OutFiles::Dir(d) = self.params.outfiles() {
+            for p in [d.join(filename),d.join(filename.to_string() + ".tex")] {
+                if fs.is_file(&p) { return Some((p,false)) }
+                // e.g. the .aux file, which a later run will find here
+                self.state.add_missing_dependency(&p)
+            }
+        }
+        fs.resolve(filename,self.jobinfo.in_file()).or_else(|| {
+            // files written in memory (in this or a previous pass) do not exist on the file system
+            [filename.to_string(),filename.to_string() + ".tex"].into_iter()
+                .find(|f| self.state.filestore.contains_key(&f.as_str().into()))
+                .map(|f| (self.jobinfo.in_file().join(f),false))
+        })
+    }
+
+    /// Returns the [`VFile`] for `\openout{filename}` and, depending on
+    /// [`InterpreterParams::outfiles`], the path it should be written to on `\closeout`.
+    pub fn get_outfile(&mut self,filename : &str) -> Result<(Arc<VFile>,Option<PathBuf>),TeXError> {
+        let filename = if Path::new(filename).extension().is_none() {
+            filename.to_string() + ".tex"
+        } else { filename.to_string() };
+        let target = match self.params.outfiles() {
+            OutFiles::Memory => return Ok((match self.kpsewhich(&filename) {
+                Some((p,b)) => {
+                    let vf = VFile::new(&p,b,self.jobinfo.in_file(),&mut self.state.filestore,self.params.filesystem());
+                    self.state.add_dependency(&vf);
+                    vf
+                }
+                // a new file, which (as on disk) belongs next to the job
+                None => VFile::new(&self.jobinfo.in_file().join(&filename),false,self.jobinfo.in_file(),&mut self.state.filestore,self.params.filesystem())
+            },None)),
+            OutFiles::JobDir => self.jobinfo.in_file().join(&filename),
+            OutFiles::Dir(d) => d.join(&filename)
+        };
+        Ok((VFile::new(&target,false,self.jobinfo.in_file(),&mut self.state.filestore,self.params.filesystem()),Some(target)))
+    }
+
+    /// `\jobname`
+    pub fn jobname(&self) -> String {
+        match self.params.jobname() {
+            Some(j) => j,
+            None => self.jobinfo.path.file_stem().unwrap().to_str().unwrap().to_string()
+        }
     }
 
     pub fn get_file(&mut self,filename : &str) -> Result<Arc<VFile>,TeXError> {
-        /*if filename.contains("tetrapod") {
-            unsafe {crate::LOG = true}
-            println!("Here!")
-        }*/
         match self.kpsewhich(filename) {
             None =>TeXErr!("File {} not found",filename),
             Some((p,b)) => {
-                Ok(VFile::new(&p,b,self.jobinfo.in_file(),&mut self.state.filestore))
+                let vf = VFile::new(&p,b,self.jobinfo.in_file(),&mut self.state.filestore,self.params.filesystem());
+                self.state.add_dependency(&vf);
+                Ok(vf)
             }
         }
     }
@@ -151,12 +235,20 @@ impl Interpreter<'_> {
             jobinfo:Jobinfo::new(PathBuf::new()),
             mouths:Mouths::new(),
             stomach:stomach,
-            params
+            params,
+            assigning:None,
+            shown_mode:None,
+            limits:params.limits(),
+            expansions:0,
+            expansion_depth:0,
+            deadline:params.limits().timeout.map(|t| std::time::Instant::now() + t),
+            errors:0
         }
     }
 
     fn predoc_toploop(&mut self) -> Result<bool,TeXError> {
         while self.has_next() {
+            if self.cancelled() { return Ok(false) }
             let next = self.next_token();
             let indoc = self.state.indocument;
             if !indoc {
@@ -182,11 +274,12 @@ impl Interpreter<'_> {
         Ok(false)
     }
 
-    pub fn do_string<A:'static,B:'static>(&mut self,p:&Path,text:&str,colon:A) -> (bool,B) where A:Colon<B>,B: Send {
+    pub fn do_string<A:'static,B:'static>(&mut self,p:&Path,text:&str,colon:A) -> (Outcome,B) where A:Colon<B>,B: Send {
         extern crate pathdiff;
         use files::VFileBase;
         use std::sync::RwLock;
         self.jobinfo = Jobinfo::new(p.to_path_buf());
+        self.state.dependencies.clear();
         let simple:TeXStr = pathdiff::diff_paths(p,self.jobinfo.in_file()).unwrap().to_str().unwrap().into();
         let vf = Arc::new(VFile {
             source:VFileBase::Real(p.to_str().unwrap().into()),
@@ -196,35 +289,117 @@ impl Interpreter<'_> {
         self.state.borrow_mut().filestore.insert(vf.id.clone(),vf.clone());
         self.do_vfile(vf,colon)
     }
-    pub fn do_file<A:'static,B:'static>(&mut self,p:&Path,colon:A) -> (bool,B) where A:Colon<B>,B: Send {
+    pub fn do_file<A:'static,B:'static>(&mut self,p:&Path,colon:A) -> (Outcome,B) where A:Colon<B>,B: Send {
         self.jobinfo = Jobinfo::new(p.to_path_buf());
-        let vf:Arc<VFile>  = VFile::new(p,false,self.jobinfo.in_file(),&mut self.state.borrow_mut().filestore);
+        self.state.dependencies.clear();
+        let vf:Arc<VFile>  = VFile::new(p,false,self.jobinfo.in_file(),&mut self.state.borrow_mut().filestore,self.params.filesystem());
+        self.state.add_dependency(&vf);
         self.do_vfile(vf,colon)
     }
 
-    pub fn do_string_with_state<A:'static,B:'static>(p : &Path, s : State,text:&str, colon:A,params:&dyn InterpreterParams) -> (bool,State,B) where A:Colon<B>,B:Send {
-        let mut stomach = NoShipoutRoutine::new();
-        let mut int = Interpreter::with_state(s,stomach.borrow_mut(),params);
+    pub fn do_string_with_state<A:'static,B:'static>(p : &Path, s : State,text:&str, colon:A,params:&dyn InterpreterParams) -> (Outcome,State,B) where A:Colon<B>,B:Send {
+        let mut stomach = crate::stomach::stomach_for(&colon);
+        let mut int = Interpreter::with_state(s,stomach.as_mut(),params);
         let ret = int.do_string(p,text,colon);
         (ret.0,int.state,ret.1)
     }
 
-    pub fn do_file_with_state<A:'static,B:'static>(p : &Path, s : State, colon:A,params:&dyn InterpreterParams) -> (bool,State,B) where A:Colon<B>,B:Send {
-        let mut stomach = NoShipoutRoutine::new();
-        let mut int = Interpreter::with_state(s,stomach.borrow_mut(),params);
+    pub fn do_file_with_state<A:'static,B:'static>(p : &Path, s : State, colon:A,params:&dyn InterpreterParams) -> (Outcome,State,B) where A:Colon<B>,B:Send {
+        let mut stomach = crate::stomach::stomach_for(&colon);
+        let mut int = Interpreter::with_state(s,stomach.as_mut(),params);
         let ret = int.do_file(p,colon);
         (ret.0,int.state,ret.1)
     }
 
-    fn do_vfile<A:'static,B:'static>(&mut self,vf:Arc<VFile>,mut colon:A) -> (bool,B) where A:Colon<B>,B: Send {
+    /// Processes the file `p` repeatedly, each time starting from a copy of `s` with the files
+    /// written (via `\openout`) in the previous pass made available, until their contents no
+    /// longer change or `max_passes` is reached (like `latexmk`). Only the output of the final
+    /// pass is returned; the last component of the result is the number of passes done.
+    pub fn do_file_with_state_multipass<A:'static,B:'static,F>(p : &Path, s : State, colon:F, max_passes:usize, params:&dyn InterpreterParams) -> (Outcome,State,B,usize) where A:Colon<B>,B:Send,F:Fn() -> A {
+        let mut passes = 1;
+        params.begin_pass(passes);
+        let (mut outcome,mut state,mut ret) = Interpreter::do_file_with_state(p,s.clone(),colon(),params);
+        while outcome.success() && passes < max_passes {
+            let written = state.written_files();
+            if written.is_empty() { break }
+            let mut nstate = s.clone();
+            nstate.add_written_files(&state);
+            passes += 1;
+            params.log(&std::format!("Pass {} of {}",passes,p.display()));
+            params.begin_pass(passes);
+            (outcome,state,ret) = Interpreter::do_file_with_state(p,nstate,colon(),params);
+            if state.written_files() == written { break }
+        }
+        (outcome,state,ret,passes)
+    }
+
+    /// Processes `files` concurrently on `threads` worker threads, each file starting from a
+    /// copy of `s` (and rerun up to `max_passes` times, see [`Interpreter::do_file_with_state_multipass`]).
+    /// `colon` and `params` create the colon and the [`InterpreterParams`] for a file; `done` is called on the worker
+    /// thread with the result and these params as soon as the file is finished. Panics are
+    /// caught and reported as failures of the respective file.
+    pub fn do_files_parallel<A:'static,B:'static,F,P,G,R>(files:&[PathBuf], s:&State, threads:usize, max_passes:usize, colon:F, params:G, done:R)
+        where A:Colon<B>,B:Send,F:Fn(&Path) -> A + Sync,P:InterpreterParams,G:Fn(&Path) -> P + Sync,R:Fn(BatchResult<B>,&P) + Sync {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let next = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..threads.max(1) {
+                std::thread::Builder::new().stack_size(crate::STACK_SIZE).spawn_scoped(scope,|| {
+                    while let Some(path) = files.get(next.fetch_add(1,Ordering::SeqCst)) {
+                        let p = params(path);
+                        let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(||
+                            Interpreter::do_file_with_state_multipass(path,s.clone(),|| colon(path),max_passes,&p)
+                        ));
+                        let result = match ret {
+                            Ok((outcome,state,r,passes)) => BatchResult { path:path.clone(),outcome,passes,result:Ok(r),dependencies:state.dependencies() },
+                            Err(e) => {
+                                let msg = match e.downcast_ref::<&str>() {
+                                    Some(s) => s.to_string(),
+                                    None => match e.downcast_ref::<String>() {
+                                        Some(s) => s.clone(),
+                                        None => "panic".to_string()
+                                    }
+                                };
+                                BatchResult { path:path.clone(),outcome:Outcome::Failure,passes:0,result:Err(msg),dependencies:deps::Dependencies::new() }
+                            }
+                        };
+                        done(result,&p)
+                    }
+                }).unwrap();
+            }
+        })
+    }
+
+    fn do_vfile<A:'static,B:'static>(&mut self,vf:Arc<VFile>,colon:A) -> (Outcome,B) where A:Colon<B>,B: Send {
+        self.mouths.store = self.state.store_sources;
+        self.state.sources = None;
+        // the outcome and the limit of 100 errors are per run
+        self.errors = 0;
+        let diagnostics = colon.base().diagnostics.clone();
+        let ret = self.do_vfile_i(vf,colon);
+        if let Some(p) = self.params.profiler() { p.finish() }
+        self.state.sources = self.mouths.take_source();
+        for d in diagnostics.take() { self.params.diagnostic(d) }
+        ret
+    }
+    fn do_vfile_i<A:'static,B:'static>(&mut self,vf:Arc<VFile>,mut colon:A) -> (Outcome,B) where A:Colon<B>,B: Send {
         self.push_file(vf);
         self.insert_every(&crate::commands::registers::EVERYJOB);
-        let cont = match self.predoc_toploop() {
-            Ok(b) => b,
-            Err(mut e) => {
-                e.throw(self);
-                self.params.error(e);
-                return (false,colon.close())
+        let cont = loop {
+            if self.cancelled() {
+                self.close_outfiles();
+                return (Outcome::Cancelled,colon.close())
+            }
+            // errors are recovered from in do_top
+            match self.predoc_toploop() {
+                Ok(true) => break true,
+                Ok(false) if !self.has_next() => break false,
+                Ok(false) => (),
+                Err(e) => {
+                    self.report(e);
+                    self.close_outfiles();
+                    return (Outcome::Failure,colon.close())
+                }
             }
         };
         if cont {
@@ -236,6 +411,7 @@ impl Interpreter<'_> {
                         loop {
                             match rec.try_iter().next() {
                                 Some(StomachMessage::WI(w)) => colon.ship_whatsit(w),
+                                Some(StomachMessage::Page(bx)) => colon.ship_page(bx),
                                 _ => break
                             }
                         }
@@ -246,6 +422,10 @@ impl Interpreter<'_> {
                                 colon.ship_whatsit(w);
                                 None
                             }
+                            Some(StomachMessage::Page(bx)) => {
+                                colon.ship_page(bx);
+                                None
+                            }
                             Some(StomachMessage::End) => {
                                 Some(colon.close())
                             }
@@ -258,7 +438,8 @@ impl Interpreter<'_> {
                     for msg in receiver {
                         match msg {
                             StomachMessage::End => return colon.close(),
-                            StomachMessage::WI(w) => colon.ship_whatsit(w)
+                            StomachMessage::WI(w) => colon.ship_whatsit(w),
+                            StomachMessage::Page(bx) => colon.ship_page(bx)
                         }
                     }
                     return colon.close() // sender dropped => TeXError somewhere
@@ -266,28 +447,125 @@ impl Interpreter<'_> {
             };
             while self.has_next() {
                 colonthread.next();
+                if self.cancelled() {
+                    self.stomach.finish(&mut self.state);
+                    self.close_outfiles();
+                    return match colonthread.join() {
+                        Ok(r) => (Outcome::Cancelled,r),
+                        _ => panic!("Error in colon thread")
+                    }
+                }
                 let next = self.next_token();
-                match self.do_top(next,false) {
-                    Ok(b) => b,
-                    Err(mut e) =>  {
-                        e.throw(self);
-                        self.params.error(e);
-                        self.stomach.finish(&mut self.state);
-                        return match colonthread.join() {
-                            Ok(r) => (false,r),
-                            _ => panic!("Error in colon thread")
-                        }
+                // errors are recovered from in do_top; those that are not end the job
+                if let Err(e) = self.do_top(next,false).and_then(|_| self.build_page(false).map(|_| ())) {
+                    self.report(e);
+                    self.stomach.finish(&mut self.state);
+                    self.close_outfiles();
+                    return match colonthread.join() {
+                        Ok(r) => (Outcome::Failure,r),
+                        _ => panic!("Error in colon thread")
                     }
-                };
+                }
+            }
+            if let Err(e) = self.flush_pages() {
+                self.report(e);
+                self.stomach.finish(&mut self.state);
+                self.close_outfiles();
+                return match colonthread.join() {
+                    Ok(r) => (Outcome::Failure,r),
+                    _ => panic!("Error in colon thread")
+                }
             }
 
             self.stomach.borrow_mut().finish(&mut self.state);
+            let outcome = self.finish_outcome();
             match colonthread.join() {
-                Ok(r) => return (true,r),
+                Ok(r) => return (outcome,r),
                 Err(_) => panic!("Error in colon thread")
             }
         } else {
-            (true,colon.close())
+            let outcome = self.finish_outcome();
+            (outcome,colon.close())
+        }
+    }
+    /// Closes the output files and determines the [`Outcome`] of a run that was not aborted
+    fn finish_outcome(&mut self) -> Outcome {
+        if self.close_outfiles() && self.errors == 0 { Outcome::Success } else { Outcome::Failure }
+    }
+
+    fn report(&mut self,mut e:TeXError) {
+        e.throw(self);
+        self.params.error(e)
+    }
+
+    /// In `\batchmode`, `\nonstopmode` and `\scrollmode` (`\interactionmode` < 3), reports
+    /// the error and continues like TeX would: box/math groups above `depth` that the error left
+    /// open are closed (discarding their contents), `mode` (the mode before the failed command)
+    /// is restored and the error's repair tokens, if any, are inserted. Gives up after 100 errors.
+    fn recover(&mut self,e:TeXError,mode:TeXMode,depth:usize) -> Result<(),TeXError> {
+        if e.fatal || self.state.interaction_mode() >= 3 || self.errors >= 100 {
+            return Err(e)
+        }
+        let mut e = e;
+        let recovery = std::mem::take(&mut e.recovery);
+        self.report(e);
+        self.errors += 1;
+        if self.errors >= 100 {
+            TeXErr!("That makes 100 errors; please try again.")
+        }
+        let mut closed = false;
+        while self.state.stack_depth() > depth {
+            match self.state.tp.ls.front().cloned() {
+                Some(GroupType::Box(bm)) => { self.get_whatsit_group(GroupType::Box(bm))?; }
+                _ => break
+            }
+            closed = true
+        }
+        if closed { self.state.mode = mode }
+        self.push_tokens(recovery);
+        Ok(())
+    }
+
+    /// Runs the page builder of the stomach and, if it has filled `\box255`, `\output`
+    fn build_page(&mut self,last:bool) -> Result<bool,TeXError> {
+        use crate::commands::registers::{DEADCYCLES, OUTPUT};
+        if self.state.mode != TeXMode::Vertical || !self.stomach.build_page(&mut self.state,last)? {
+            return Ok(false)
+        }
+        let deadcycles = self.state.registers_prim.get(&(DEADCYCLES.index - 1));
+        self.state.registers_prim.set(DEADCYCLES.index - 1,deadcycles + 1,true);
+        let mut tks = vec!(Token::new(123,CategoryCode::BeginGroup,None,None,true));
+        tks.append(&mut self.state.toks_prim.get(&(OUTPUT.index - 1)));
+        tks.push(Token::new(125,CategoryCode::EndGroup,None,None,true));
+        self.push_tokens(tks);
+        let leftover = self.read_whatsit_group(BoxMode::V,false)?;
+        self.stomach.output_done(&mut self.state,leftover)?;
+        Ok(true)
+    }
+
+    /// At the end of the job: runs `\output` until the main vertical list is empty (or
+    /// `\maxdeadcycles` is exceeded)
+    fn flush_pages(&mut self) -> Result<(),TeXError> {
+        use crate::commands::registers::{DEADCYCLES, MAXDEADCYCLES};
+        if self.state.mode == TeXMode::Horizontal { self.end_paragraph(false)? }
+        loop {
+            let max = match self.state.registers_prim.get(&(MAXDEADCYCLES.index - 1)) {
+                0 => 25,
+                i => i
+            };
+            if self.state.registers_prim.get(&(DEADCYCLES.index - 1)) >= max || !self.build_page(true)? {
+                return Ok(())
+            }
+        }
+    }
+
+    fn close_outfiles(&mut self) -> bool {
+        match self.state.file_closeout_all(self.params) {
+            Ok(_) => true,
+            Err(e) => {
+                self.params.error(e);
+                false
+            }
         }
     }
 
@@ -309,20 +587,34 @@ impl Interpreter<'_> {
     }
 
     pub fn do_top(&mut self,next:Token,inner:bool) -> Result<(),TeXError> {
+        let (mode,depth) = (self.state.mode,self.state.stack_depth());
+        self.do_top_i(next,inner).or_else(|e| self.recover(e,mode,depth))
+    }
+
+    fn do_top_i(&mut self,next:Token,inner:bool) -> Result<(),TeXError> {
         use crate::commands::primitives;
         use crate::catcodes::CategoryCode::*;
         use TeXMode::*;
         use PrimitiveTeXCommand::*;
+        self.check_timeout(&next)?;
         let mode = self.state.mode;
-        /*if self.current_line().starts_with("/home/jazzpirate/work/LaTeX/Papers/19 - Thesis/img/int-partial-biview.tex (14, 65)") {
-            unsafe { crate::LOG = true }
-            println!("Here!: {}",self.preview())
-
-        }*/
+        if !matches!(next.catcode,Active | Escape) {
+            self.profile_hook(&next,ProfileStep::Character);
+            self.debug_hook(&next)?;
+            if self.tracing(&TRACINGCOMMANDS,0) && !(next.catcode == EOL && next.name() == "EOF") {
+                self.trace_command(&next,None)
+            }
+        }
         match (next.catcode,mode) {
             (EOL,_) if next.name() == "EOF" => Ok(()),
             (Active | Escape,_) => {
                 let p = self.get_command(&next.cmdname())?;
+                // expandable commands pass the hook in get_expansion
+                if !p.expandable(true) {
+                    self.profile_hook(&next,ProfileStep::Execution);
+                    self.debug_hook(&next)?;
+                    if self.tracing(&TRACINGCOMMANDS,0) { self.trace_command(&next,Some(&p.orig)) }
+                }
                 if p.assignable() {
                     return p.assign(next,self,false)
                 } else if p.expandable(true) {
@@ -365,12 +657,14 @@ impl Interpreter<'_> {
                    /* (Whatsit(crate::commands::ProvidesWhatsit::Simple(p)), RestrictedHorizontal) if **p == VFIL || **p == VFILL => {
                         Ok(())
                     }*/
+                    (Whatsit(w), Vertical | InternalVertical | Horizontal | RestrictedHorizontal) if w.allowed_in(TeXMode::Math) =>
+                        Err(self.missing_dollar(next)),
                     _ => TeXErr!(next.clone() => "TODO: {} in {}",next,self.current_line())
 
                 }
             },
             //(Active | Escape,_) => Ok(()),
-            (BeginGroup,_) => Ok(self.state.push(self.stomach,GroupType::Token)),
+            (BeginGroup,_) => self.push_group(GroupType::Token),
             (EndGroup,_) => self.pop_group(GroupType::Token),
             (Space | EOL, Vertical | InternalVertical | Math | Displaymath ) => Ok(()),
             (Space | EOL, Horizontal | RestrictedHorizontal) => {
@@ -424,10 +718,18 @@ impl Interpreter<'_> {
                     _ => TeXErr!(next => "Misplaced alignment tab")
                 }
             }
+            (Superscript | Subscript, Vertical | InternalVertical | Horizontal | RestrictedHorizontal) =>
+                Err(self.missing_dollar(next)),
             _ => TeXErr!(next.clone() => "Not allowed in {} mode: {} of category code {}",mode,next,next.catcode),
         }
     }
 
+    /// A math-only token outside of math mode; recovers by inserting `$` before it
+    fn missing_dollar(&self,next:Token) -> TeXError {
+        let dollar = Token::new(36,CategoryCode::MathShift,None,None,true);
+        TeXError::new(std::format!("Missing $ inserted before {}",next),Some(next.clone())).recover_with(vec!(dollar,next))
+    }
+
     pub fn do_math_char(&self,tk:Option<Token>,mc:u32) -> MathChar {
         let num = mc;
         let (mut cls,mut fam,pos) = {
@@ -492,7 +794,11 @@ impl Interpreter<'_> {
         self.insert_every(&crate::commands::registers::EVERYPAR);
         let parskip = self.state.skips_prim.get(&(crate::commands::registers::PARSKIP.index - 1));
         self.stomach.borrow_mut().start_paragraph(parskip.base);
+        if let Some(StomachGroup::Par(p)) = self.stomach.base_mut().stomachgroups.last_mut() {
+            p.line = self.mouths.line_no().0
+        }
         self.state.registers_prim.set((PREVGRAF.index - 1),0,true);
+        self.state.registers_prim.set(crate::commands::registers::SPACEFACTOR.index - 1,1000,false);
         if indent != 0 {
             self.stomach_add(Indent {
                 dim: indent,
@@ -528,13 +834,13 @@ impl Interpreter<'_> {
         let _oldmode = self.state.mode;
         let bm = if display {
             let m = BoxMode::DM;
-            self.state.push(self.stomach,GroupType::Box(m));
+            self.push_group(GroupType::Box(m))?;
             self.state.mode = TeXMode::Displaymath;
             self.insert_every(&crate::commands::registers::EVERYDISPLAY);
             m
         } else {
             let m = BoxMode::M;
-            self.state.push(self.stomach,GroupType::Box(m));
+            self.push_group(GroupType::Box(m))?;
             self.state.mode = TeXMode::Math;
             self.insert_every(&crate::commands::registers::EVERYMATH);
             m
@@ -615,7 +921,7 @@ impl Interpreter<'_> {
                         TeXMode::Displaymath => BoxMode::DM,
                         _ => unreachable!()
                     };
-                    self.state.push(self.stomach,GroupType::Box(mode));
+                    self.push_group(GroupType::Box(mode))?;
                     return Ok(Some(Interpreter::build_mathgroup(self.read_math_group(mode,false)?,self.state.displaymode.get())))
                 }
                 Superscript => {
@@ -662,7 +968,7 @@ impl Interpreter<'_> {
                             return Ok(None)
                         }
                         Whatsit(ProvidesWhatsit::Math(mw)) if **mw == LEFT => {
-                            self.state.push(self.stomach,GroupType::Box(BoxMode::LeftRight));
+                            self.push_group(GroupType::Box(BoxMode::LeftRight))?;
                             let left = (LEFT._get)(&next,self)?;
                             while self.has_next() {
                                 let next = self.next_token();
@@ -936,4 +1242,92 @@ impl Interpreter<'_> {
             FileEnd!(self)
         }
     } */
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::interpreter::Interpreter;
+    use crate::filesystem::MemoryFS;
+    use crate::interpreter::params::{DefaultParams, OutFiles, Verbosity};
+    use crate::interpreter::state::State;
+    use crate::stomach::colon::NoColon;
+
+    #[test]
+    fn recovery() {
+        crate::utils::with_stack_size(|| {
+            let p = DefaultParams::new(false,true,None);
+            let mut stomach = crate::stomach::stomach_for(&NoColon::new());
+            let mut int = Interpreter::with_state(State::new(),stomach.as_mut(),&p);
+            let path = Path::new("/test.tex");
+            let runaway = "\\catcode`\\{=1 \\catcode`\\}=2 \\catcode`\\#=6 \\def\\foo#1.{}\\foo a\\par b.\\count2=3 ";
+            let (outcome,()) = int.do_string(path,runaway,NoColon::new());
+            assert!(!outcome.success());
+            assert_eq!(int.state.registers.get(&2),0);
+            let (outcome,()) = int.do_string(path,&("\\nonstopmode ".to_string() + runaway),NoColon::new());
+            assert!(!outcome.success());
+            assert_eq!(int.state.registers.get(&2),3);
+            // errors of previous runs do not count
+            let (outcome,()) = int.do_string(path,"\\count2=4 ",NoColon::new());
+            assert!(outcome.success());
+            assert_eq!(int.state.registers.get(&2),4);
+        })
+    }
+
+    #[test]
+    fn outfiles() {
+        crate::utils::with_stack_size(|| {
+            let text = "\\catcode`\\{=1 \\catcode`\\}=2 \\immediate\\openout1=out \\immediate\\write1{a}\\immediate\\closeout1 \\openout2=out.aux \\write2{b}";
+            let mut p = DefaultParams::new(false,true,None);
+            p.verbosity = Verbosity::Quiet;
+            for (outfiles,dir) in [(OutFiles::Memory,None),(OutFiles::JobDir,Some("/job")),(OutFiles::Dir("/out".into()),Some("/out"))] {
+                p.outfiles = outfiles;
+                p.filesystem = Box::new(MemoryFS::new());
+                let (outcome,state,()) = Interpreter::do_string_with_state(Path::new("/job/test.tex"),State::new(),text,NoColon::new(),&p);
+                assert!(outcome.success());
+                let written = state.written_files();
+                assert_eq!(written.len(),2);
+                match dir {
+                    // the extension defaults to .tex; files still open at the end are closed
+                    Some(d) => {
+                        assert_eq!(p.filesystem.read(&Path::new(d).join("out.tex")).unwrap(),b"a\n");
+                        assert_eq!(p.filesystem.read(&Path::new(d).join("out.aux")).unwrap(),b"b\n");
+                    }
+                    None => {
+                        assert!(p.filesystem.read(Path::new("/job/out.tex")).is_err());
+                        let mut contents : Vec<String> = written.values().map(|s| s.as_ref().unwrap().to_string()).collect();
+                        contents.sort();
+                        assert_eq!(contents,vec!("a\n".to_string(),"b\n".to_string()));
+                    }
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn multipass() {
+        crate::utils::with_stack_size(|| {
+            // counts up to 2 in test.aux, i.e. the aux file is stable in the third pass
+            let text = "\\catcode`\\{=1 \\catcode`\\}=2 \\count1=0 \\openin1=test.aux \\ifeof1 \\else\\closein1 \\input test.aux \\fi\n\
+                \\ifnum\\count1<2 \\advance\\count1 by 1 \\fi\\immediate\\openout1=test.aux \\immediate\\write1{\\noexpand\\count1=\\the\\count1}\\immediate\\closeout1 ";
+            // each time on a fresh file system, since the output files of Dir(..) stay there
+            let passes = |text:&str,outfiles:&OutFiles,max| {
+                let mut p = DefaultParams::new(false,true,None);
+                p.verbosity = Verbosity::Quiet;
+                p.outfiles = outfiles.clone();
+                let fs = MemoryFS::new();
+                fs.insert("/job/test.tex",text);
+                p.filesystem = Box::new(fs);
+                let (outcome,state,(),passes) = Interpreter::do_file_with_state_multipass(Path::new("/job/test.tex"),State::new(),NoColon::new,max,&p);
+                assert!(outcome.success());
+                (state.registers.get(&1),passes)
+            };
+            for outfiles in [OutFiles::Memory,OutFiles::Dir("/out".into())] {
+                assert_eq!(passes(text,&outfiles,1),(1,1));
+                assert_eq!(passes(text,&outfiles,2),(2,2));
+                assert_eq!(passes(text,&outfiles,10),(2,3));
+            }
+            // nothing written, so nothing to rerun
+            assert_eq!(passes("\\count1=5 ",&OutFiles::Memory,10),(5,1));
+        })
+    }
+}
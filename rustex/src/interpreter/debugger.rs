@@ -0,0 +1,366 @@
+/*! An interactive debugger for macro expansion, reading commands from stdin (or any other
+[`BufRead`]).
+
+The interpreter calls [`Interpreter::debug_hook`] before every expansion and before every
+other token it processes. If the [`InterpreterParams`](crate::interpreter::params::InterpreterParams)
+provide a [`Debugger`], it pauses there when stepping, on a [`Breakpoint`] and on
+`\rustexBREAK`, and then reads commands until told to continue. Type `help` at the prompt for
+a list of commands.
+*/
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+use crate::catcodes::CategoryCode;
+use crate::commands::{AssignableValue, PrimitiveTeXCommand};
+use crate::interpreter::dimensions::dimtostr;
+use crate::interpreter::{Interpreter, tokens_to_string};
+use crate::interpreter::state::State;
+use crate::ontology::Token;
+use crate::utils::{TeXError, TeXStr};
+
+static HELP : &str = "\
+step (s)              continue until the next control sequence
+continue (c)          continue until the next breakpoint
+peek (p) [n]          print the next n (default 300) characters of the input
+meaning (m) \\cs       print the meaning of \\cs and where it was defined
+the (t) \\cs           print the value of the register or parameter \\cs
+count|dimen|skip|muskip|toks N
+                      print the value of the numbered register N
+box N                 print the contents of \\box N
+groups (g)            print the group stack
+ifs                   print the currently open conditionals
+where (w)             print where the current token comes from
+break (b) \\cs|file:line
+                      add a breakpoint
+delete (d) \\cs|file:line
+                      remove a breakpoint
+breakpoints (bl)      list all breakpoints
+quit (q)              abort processing
+help (h)              print this help
+";
+
+/// Where the [`Debugger`] pauses
+#[derive(Clone,PartialEq)]
+pub enum Breakpoint {
+    /// before a control sequence (or active character) with this name is expanded or executed
+    Command(TeXStr),
+    /// whenever processing reaches the given line in a file whose path ends with the given one
+    Line(String,usize)
+}
+impl Breakpoint {
+    /// `\foo` or `foo` for [`Breakpoint::Command`], `file:line` for [`Breakpoint::Line`]
+    pub fn parse(s:&str) -> Breakpoint {
+        let s = s.trim();
+        if !s.starts_with('\\') {
+            if let Some((file,line)) = s.rsplit_once(':') {
+                if let Ok(l) = line.parse::<usize>() {
+                    return Breakpoint::Line(file.to_string(),l)
+                }
+            }
+        }
+        Breakpoint::Command(s.strip_prefix('\\').unwrap_or(s).into())
+    }
+}
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Command(name) => write!(f,"\\{}",name),
+            Breakpoint::Line(file,line) => write!(f,"{}:{}",file,line)
+        }
+    }
+}
+
+struct DebuggerState {
+    breakpoints:Vec<Breakpoint>,
+    commands:HashSet<TeXStr>,
+    stepping:bool,
+    /// the line breakpoint last paused at, so that we pause only once per line
+    last_line:Option<(TeXStr,usize)>
+}
+impl DebuggerState {
+    fn update(&mut self) {
+        self.commands = self.breakpoints.iter().filter_map(|b| match b {
+            Breakpoint::Command(n) => Some(n.clone()),
+            _ => None
+        }).collect()
+    }
+}
+
+pub struct Debugger {
+    state:Mutex<DebuggerState>,
+    input:Mutex<Box<dyn BufRead + Send>>,
+    output:Mutex<Box<dyn Write + Send>>
+}
+impl Debugger {
+    /// If `stepping` is set, the debugger pauses before the very first expansion
+    pub fn new(input:Box<dyn BufRead + Send>,output:Box<dyn Write + Send>,breakpoints:Vec<Breakpoint>,stepping:bool) -> Debugger {
+        let mut state = DebuggerState { breakpoints, commands:HashSet::new(), stepping, last_line:None };
+        state.update();
+        Debugger { state:Mutex::new(state), input:Mutex::new(input), output:Mutex::new(output) }
+    }
+    /// A debugger reading commands from stdin and printing to stdout
+    pub fn stdio(breakpoints:Vec<Breakpoint>,stepping:bool) -> Debugger {
+        Debugger::new(Box::new(std::io::BufReader::new(std::io::stdin())),Box::new(std::io::stdout()),breakpoints,stepping)
+    }
+    pub fn add_breakpoint(&self,b:Breakpoint) {
+        let mut state = self.state.lock().unwrap();
+        if !state.breakpoints.contains(&b) { state.breakpoints.push(b) }
+        state.update()
+    }
+    pub fn remove_breakpoint(&self,b:&Breakpoint) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let len = state.breakpoints.len();
+        state.breakpoints.retain(|x| x != b);
+        state.update();
+        state.breakpoints.len() != len
+    }
+    pub fn breakpoints(&self) -> Vec<Breakpoint> {
+        self.state.lock().unwrap().breakpoints.clone()
+    }
+
+    fn should_break(&self,tk:&Token,int:&Interpreter) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if matches!(tk.catcode,CategoryCode::Escape | CategoryCode::Active) {
+            if state.stepping || state.commands.contains(&tk.cmdname()) { return true }
+        }
+        if state.commands.len() == state.breakpoints.len() { return false }
+        let file = int.current_file();
+        let line = int.mouths.line_no().0;
+        if state.last_line.as_ref().map(|(f,l)| *f == file && *l == line).unwrap_or(false) {
+            return false
+        }
+        let path = file.to_string();
+        let hit = state.breakpoints.iter().any(|b| match b {
+            Breakpoint::Line(f,l) => *l == line && Path::new(&path).ends_with(f),
+            _ => false
+        });
+        state.last_line = if hit { Some((file,line)) } else { None };
+        hit
+    }
+
+    fn print(&self,s:&str) {
+        let mut out = self.output.lock().unwrap();
+        let _ = write!(out,"{}",s);
+        let _ = out.flush();
+    }
+
+    /// Pauses before `tk` and reads commands until `step`, `continue` or the end of the input
+    pub fn pause(&self,int:&mut Interpreter,tk:&Token) -> Result<(),TeXError> {
+        let start = Instant::now();
+        let ret = self.pause_i(int,tk);
+        // waiting for the user is not part of the profile
+        if let Some(p) = int.params.profiler() { p.exclude(start.elapsed()) }
+        ret
+    }
+    fn pause_i(&self,int:&mut Interpreter,tk:&Token) -> Result<(),TeXError> {
+        self.print(&format!("\n*** {} at {}\n",crate::commands::primitives::show_name(tk,int),int.current_line()));
+        self.print(&format!("  >>{}\n",first_line(&int.preview().to_string(),100)));
+        loop {
+            self.print("debug> ");
+            let mut line = String::new();
+            match self.input.lock().unwrap().read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    self.state.lock().unwrap().stepping = false;
+                    return Ok(())
+                }
+                Ok(_) => ()
+            }
+            let line = line.trim();
+            let (cmd,arg) = match line.split_once(char::is_whitespace) {
+                Some((c,a)) => (c,a.trim()),
+                None => (line,"")
+            };
+            match cmd {
+                "s" | "step" => {
+                    self.state.lock().unwrap().stepping = true;
+                    return Ok(())
+                }
+                "c" | "continue" => {
+                    self.state.lock().unwrap().stepping = false;
+                    return Ok(())
+                }
+                "p" | "peek" => {
+                    let n = arg.parse::<usize>().unwrap_or(300);
+                    let pv : String = int.preview().to_string().chars().take(n).collect();
+                    self.print(&format!("{}\n",pv))
+                }
+                "m" | "meaning" if !arg.is_empty() => {
+                    let name : TeXStr = arg.strip_prefix('\\').unwrap_or(arg).into();
+                    let scheme = int.state.catcodes.get_scheme();
+                    match int.state.commands.get(&name) {
+                        None => self.print(&format!("\\{}=undefined\n",name)),
+                        Some(c) => self.print(&format!("\\{}={}\n",name,c.describe(scheme)))
+                    }
+                }
+                "t" | "the" if !arg.is_empty() => {
+                    let name = arg.strip_prefix('\\').unwrap_or(arg);
+                    // \count1 etc.
+                    let split = name.find(|c:char| c.is_ascii_digit()).unwrap_or(name.len());
+                    let v = match numbered(&int.state,&name[..split],&name[split..]) {
+                        Some(v) => Some(v),
+                        None => int.state.commands.get(&name.into()).and_then(|c| value(&int.state,&c.orig))
+                    };
+                    match v {
+                        Some(v) => self.print(&format!("{}\n",v)),
+                        None => self.print(&format!("\\{} is not a register or parameter\n",name))
+                    }
+                }
+                "count" | "dimen" | "skip" | "muskip" | "toks" | "box" => match numbered(&int.state,cmd,arg) {
+                    Some(v) => self.print(&format!("{}\n",v)),
+                    None => self.print(&format!("Expected a register number after {}\n",cmd))
+                }
+                "g" | "groups" => self.print(&int.state.show_groups()),
+                "ifs" => self.print(&int.state.show_ifs()),
+                "w" | "where" => {
+                    for (t,l) in crate::utils::stacktrace(tk.clone()) {
+                        self.print(&format!("{} - {}\n",t,l))
+                    }
+                }
+                "b" | "break" if !arg.is_empty() => {
+                    let b = Breakpoint::parse(arg);
+                    self.print(&format!("Breakpoint at {}\n",b));
+                    self.add_breakpoint(b)
+                }
+                "d" | "delete" if !arg.is_empty() => {
+                    let b = Breakpoint::parse(arg);
+                    if !self.remove_breakpoint(&b) { self.print(&format!("No breakpoint at {}\n",b)) }
+                }
+                "bl" | "breakpoints" => {
+                    for b in self.breakpoints() { self.print(&format!("{}\n",b)) }
+                }
+                "q" | "quit" => return Err(TeXError::new("Aborted in the debugger".to_string(),Some(tk.clone())).fatal()),
+                "h" | "help" => self.print(HELP),
+                "" => (),
+                _ => self.print(&format!("Unknown command: {} (type help for a list of commands)\n",line))
+            }
+        }
+    }
+}
+
+fn first_line(s:&str,max:usize) -> String {
+    s.lines().next().unwrap_or("").chars().take(max).collect()
+}
+
+/// The value of the numbered register `\<kind><index>` (or the contents of `\box<index>`)
+fn numbered(state:&State,kind:&str,index:&str) -> Option<String> {
+    let i = index.trim().parse::<u16>().ok()?;
+    Some(match kind {
+        "count" => state.registers.get(&i).to_string(),
+        "dimen" => dimtostr(state.dimensions.get(&i)),
+        "skip" => state.skips.get(&i).to_string(),
+        "muskip" => state.muskips.get(&i).to_string(),
+        "toks" => tokens_to_string(&state.toks.get(&i),state.catcodes.get_scheme()).to_string(),
+        "box" => {
+            use crate::commands::registers::{SHOWBOXBREADTH, SHOWBOXDEPTH};
+            let depth = state.registers_prim.get(&(SHOWBOXDEPTH.index - 1)).max(10);
+            let breadth = state.registers_prim.get(&(SHOWBOXBREADTH.index - 1)).max(100);
+            crate::stomach::show::show_box(&state.boxes.get(&i),depth,breadth)
+        }
+        _ => return None
+    })
+}
+
+/// The value of a register or internal parameter, like `\the` would return it
+fn value(state:&State,cmd:&PrimitiveTeXCommand) -> Option<String> {
+    use AssignableValue::*;
+    let scheme = state.catcodes.get_scheme();
+    Some(match cmd {
+        PrimitiveTeXCommand::AV(av) => match av {
+            Register(i) => state.registers.get(i).to_string(),
+            PrimReg(r) => state.registers_prim.get(&(r.index - 1)).to_string(),
+            Dim(i) => dimtostr(state.dimensions.get(i)),
+            PrimDim(r) => dimtostr(state.dimensions_prim.get(&(r.index - 1))),
+            Skip(i) => state.skips.get(i).to_string(),
+            PrimSkip(r) => state.skips_prim.get(&(r.index - 1)).to_string(),
+            MuSkip(i) => state.muskips.get(i).to_string(),
+            PrimMuSkip(r) => state.muskips_prim.get(&(r.index - 1)).to_string(),
+            Toks(i) => tokens_to_string(&state.toks.get(i),scheme).to_string(),
+            PrimToks(r) => tokens_to_string(&state.toks_prim.get(&(r.index - 1)),scheme).to_string(),
+            FontRef(f) => f.file.name.to_string(),
+            _ => return None
+        },
+        PrimitiveTeXCommand::Char(tk) => tk.char.to_string(),
+        PrimitiveTeXCommand::MathChar(i) => i.to_string(),
+        _ => return None
+    })
+}
+
+impl Interpreter<'_> {
+    /// Called before every expansion and every other token processed; pauses in the
+    /// [`Debugger`] if there is one and it wants to break before `tk`
+    pub fn debug_hook(&mut self,tk:&Token) -> Result<(),TeXError> {
+        let params = self.params;
+        match params.debugger() {
+            Some(d) if d.should_break(tk,self) => d.pause(self,tk),
+            _ => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use crate::interpreter::Interpreter;
+    use crate::interpreter::params::DefaultParams;
+    use crate::interpreter::state::State;
+    use crate::stomach::colon::NoColon;
+    use super::{Breakpoint, Debugger};
+
+    #[derive(Clone,Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    /// Runs `text` with a debugger reading `script`; returns the final state and what the
+    /// debugger printed
+    fn debug(text:&str,script:&str,breakpoints:Vec<Breakpoint>,stepping:bool) -> (bool,State,String) {
+        let out = Output::default();
+        let (text,script,output) = (text.to_string(),script.to_string(),out.clone());
+        let (outcome,state,()) = crate::utils::with_stack_size(move || {
+            let mut p = DefaultParams::new(false,true,None);
+            p.debugger = Some(Debugger::new(Box::new(Cursor::new(script)),Box::new(output),breakpoints,stepping));
+            let text = "\\catcode`\\{=1 \\catcode`\\}=2 ".to_string() + &text;
+            Interpreter::do_string_with_state(Path::new("/test.tex"),State::new(),&text,NoColon::new(),&p)
+        });
+        let printed = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        (outcome.success(),state,printed)
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (_,state,out) = debug("\\def\\foo{}\\count1=5 \\foo\n\\count1=7 \\foo\n\\count2=3\n",
+            "the \\count1\nc\nthe \\count1\nb \\count\nc\ncount1\nd \\count\nc\n",
+            vec!(Breakpoint::parse("\\foo"),Breakpoint::parse("test.tex:3")),false);
+        let pauses : Vec<&str> = out.lines().filter(|l| l.starts_with("***")).collect();
+        assert_eq!(pauses.len(),3,"{}",out);
+        assert!(pauses[0].contains("foo") && pauses[1].contains("foo") && pauses[2].contains("count"));
+        assert!(out.contains("debug> 5\n") && out.contains("debug> 7\n"));
+        assert!(out.contains("Breakpoint at \\count\n"));
+        assert_eq!(state.registers.get(&2),3);
+    }
+
+    #[test]
+    fn stepping() {
+        let (ok,_,out) = debug("\\def\\foo{\\relax}\\foo\\relax\\relax","s\ns\nc\n",vec!(Breakpoint::parse("foo")),false);
+        assert!(ok);
+        // \foo, \relax from \foo, then continue at the next \relax
+        assert_eq!(out.lines().filter(|l| l.starts_with("***")).count(),3,"{}",out);
+    }
+
+    #[test]
+    fn quit() {
+        let (ok,state,_) = debug("\\batchmode\\count1=5 \\def\\foo{}\\foo\\count2=3 ","q\n",vec!(Breakpoint::parse("foo")),false);
+        assert!(!ok);
+        assert_eq!(state.registers.get(&1),5);
+        // not recovered from, even in batch mode
+        assert_eq!(state.registers.get(&2),0);
+    }
+}
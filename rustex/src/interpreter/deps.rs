@@ -0,0 +1,122 @@
+use std::path::{Path, PathBuf};
+use crate::filesystem::FileSystem;
+use crate::fonts::FontFile;
+use crate::interpreter::files::{VFile, VFileBase};
+use crate::interpreter::state::State;
+
+/// Identifies the engine in dependency files; outputs of a different version are always rebuilt
+pub static ENGINE : &str = concat!("RusTeX ",env!("CARGO_PKG_VERSION"));
+
+/// The files a job has read, with the hashes of their contents at the time, for incremental
+/// rebuilds: if neither the engine nor any of the files changed, neither will the output.
+#[derive(Clone,PartialEq,Debug)]
+pub struct Dependencies {
+    pub engine:String,
+    /// (path, md5 of the contents, or `None` if the file did not exist)
+    pub files:Vec<(PathBuf,Option<String>)>
+}
+
+pub(in crate) fn hash(content:Option<&[u8]>) -> Option<String> {
+    content.map(|c| std::format!("{:x}",md5::compute(c)))
+}
+
+impl Dependencies {
+    pub fn new() -> Dependencies {
+        Dependencies { engine:ENGINE.to_string(), files:vec!() }
+    }
+    /// Adds `path` with its current contents in `fs`
+    pub fn add_file(&mut self,path:&Path,fs:&dyn FileSystem) {
+        self.files.push((path.to_path_buf(),hash(fs.read(path).ok().as_deref())))
+    }
+    /// Whether this is the current engine and all files still have the same contents in `fs`
+    pub fn up_to_date(&self,fs:&dyn FileSystem) -> bool {
+        self.engine == ENGINE && self.files.iter().all(|(p,h)| hash(fs.read(p).ok().as_deref()) == *h)
+    }
+    /// Writes one line for the engine, then one line per file: hash (`-` for missing files)
+    /// and path, separated by a space
+    pub fn write_to(&self,path:&Path) -> std::io::Result<()> {
+        let mut s = self.engine.clone();
+        for (p,h) in &self.files {
+            s.push('\n');
+            s.push_str(h.as_deref().unwrap_or("-"));
+            s.push(' ');
+            s.push_str(&p.display().to_string());
+        }
+        std::fs::write(path,s)
+    }
+    pub fn read_from(path:&Path) -> Option<Dependencies> {
+        let s = std::fs::read_to_string(path).ok()?;
+        let mut lines = s.lines();
+        let engine = lines.next()?.to_string();
+        let mut files = vec!();
+        for l in lines {
+            let (h,p) = l.split_once(' ')?;
+            files.push((PathBuf::from(p),if h == "-" {None} else {Some(h.to_string())}))
+        }
+        Some(Dependencies { engine,files })
+    }
+}
+
+impl State {
+    /// Records that `file` was read in this job (see [`State::dependencies`])
+    pub(in crate) fn add_dependency(&mut self,file:&VFile) {
+        if let VFileBase::Real(p) = &file.source {
+            let p = PathBuf::from(p.to_string());
+            if !self.dependencies.contains_key(&p) {
+                let h = hash(file.string.read().unwrap().as_ref().map(|s| s.0.as_slice()));
+                self.dependencies.insert(p,h);
+            }
+        }
+    }
+    /// Records that the file at `path` (e.g. an image) with `content` was read in this job
+    pub(in crate) fn add_dependency_file(&mut self,path:&Path,content:&[u8]) {
+        if !self.dependencies.contains_key(path) {
+            self.dependencies.insert(path.to_path_buf(),hash(Some(content)));
+        }
+    }
+    /// Records that the file at `path` was looked for in this job, but did not exist
+    pub(in crate) fn add_missing_dependency(&mut self,path:&Path) {
+        self.dependencies.entry(path.to_path_buf()).or_insert(None);
+    }
+    /// Records that the font file `ff` was read in this job
+    pub(in crate) fn add_font_dependency(&mut self,ff:&FontFile) {
+        self.dependencies.entry(PathBuf::from(ff.filepath.to_string())).or_insert_with(|| ff.hash.clone());
+    }
+    /// All files read by the last job processed with this state, other than the ones
+    /// already read while building the state itself (e.g. `latex.ltx`)
+    pub fn dependencies(&self) -> Dependencies {
+        let mut files : Vec<(PathBuf,Option<String>)> = self.dependencies.iter().map(|(p,h)| (p.clone(),h.clone())).collect();
+        files.sort();
+        Dependencies { engine:ENGINE.to_string(), files }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filesystem::PhysicalFS;
+    use crate::interpreter::Interpreter;
+    use crate::interpreter::params::{DefaultParams, OutFiles};
+    use crate::interpreter::state::State;
+    use crate::stomach::colon::NoColon;
+
+    #[test]
+    fn missing_files() {
+        let dir = std::env::temp_dir().join(std::format!("rustex-deps-{}",std::process::id()));
+        let out = dir.join("out");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&out).unwrap();
+        let (doc,outdir) = (dir.join("doc.tex"),out.clone());
+        let deps = crate::utils::with_stack_size(move || {
+            let mut p = DefaultParams::new(false,true,None);
+            p.outfiles = OutFiles::Dir(outdir);
+            let (_,state,()) = Interpreter::do_string_with_state(&doc,State::new(),"\\openin1=doc.aux ",NoColon::new(),&p);
+            state.dependencies()
+        });
+        assert!(deps.files.contains(&(out.join("doc.aux"),None)),"{:?}",deps.files);
+        assert!(deps.files.contains(&(dir.join("doc.aux"),None)),"{:?}",deps.files);
+        assert!(deps.up_to_date(&PhysicalFS));
+        std::fs::write(out.join("doc.aux"),"\\relax").unwrap();
+        assert!(!deps.up_to_date(&PhysicalFS));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use regex::Regex;
+use crate::references::SourceFileReference;
+use crate::utils::TeXError;
+
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum Severity { Error, Warning, Info }
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info"
+        }
+    }
+}
+
+/// A structured error or warning, as passed to [`InterpreterParams::diagnostic`](crate::interpreter::params::InterpreterParams::diagnostic)
+#[derive(Clone,Debug)]
+pub struct Diagnostic {
+    pub severity:Severity,
+    pub message:String,
+    /// the package/class that issued a warning (`LaTeX` for the kernel)
+    pub source:Option<String>,
+    pub file:Option<String>,
+    pub line:Option<usize>,
+    pub column:Option<usize>,
+    /// the TeX-level expansion trace (token, location)
+    pub textrace:Vec<(String,String)>,
+    /// the Rust backtrace, for errors
+    pub backtrace:Option<String>
+}
+
+lazy_static! {
+    static ref LATEX_WARNING : Regex = Regex::new(r"^(LaTeX|Package (\S+)|Class (\S+))( Font)? (Warning|Info): ").unwrap();
+    static ref BOX_WARNING : Regex = Regex::new(r"^(Overfull|Underfull) \\[hv]box \(.*?\)").unwrap();
+    static ref CONTINUATION : Regex = Regex::new(r"^\((\S+)\)\s*").unwrap();
+    static ref INPUT_LINE : Regex = Regex::new(r"(?:on input line|at lines?) (\d+)").unwrap();
+}
+
+impl Diagnostic {
+    /// Takes file/line/column from the innermost file the error occured in. The error
+    /// should have been [thrown](TeXError::throw) already. Resolving the Rust `backtrace`
+    /// is expensive, so it is only included if asked for.
+    pub fn from_error(e:&TeXError,backtrace:bool) -> Diagnostic {
+        let (file,line,column) = match e.toplinepos.last() {
+            Some((f,l,c)) => (Some(f.clone()),Some(*l),Some(*c)),
+            None => (None,None,None)
+        };
+        Diagnostic {
+            severity:Severity::Error,
+            message:e.msg.clone(),
+            source:None,
+            file,line,column,
+            textrace:e.textrace.clone(),
+            backtrace:if backtrace { Some(e.rust_backtrace()) } else { None }
+        }
+    }
+    /// A warning about the part of the document at `at`
+    pub fn warning(message:String,at:Option<&SourceFileReference>) -> Diagnostic {
+        Diagnostic {
+            severity:Severity::Warning,message,source:None,
+            file:at.map(|r| r.file.to_string()),
+            line:at.map(|r| r.start.0),
+            column:at.map(|r| r.start.1),
+            textrace:vec!(),
+            backtrace:None
+        }
+    }
+    /// Recognizes LaTeX/package/class warnings and over-/underfull box messages in
+    /// the output of a `\write` (or `\message`)
+    pub fn from_write(s:&str) -> Option<Diagnostic> {
+        let s = s.trim_start_matches(|c:char| c.is_whitespace());
+        let (severity,source,first) = if let Some(c) = LATEX_WARNING.captures(s) {
+            let severity = if c.get(5).unwrap().as_str() == "Info" { Severity::Info } else { Severity::Warning };
+            let source = c.get(2).or(c.get(3)).map(|m| m.as_str()).unwrap_or("LaTeX");
+            (severity,Some(source.to_string()),&s[c.get(0).unwrap().end()..])
+        } else if BOX_WARNING.is_match(s) {
+            (Severity::Warning,None,s)
+        } else { return None };
+        let mut lines = first.lines();
+        let mut message = lines.next().unwrap_or("").trim_end().to_string();
+        for l in lines {
+            let l = CONTINUATION.replace(l,"");
+            let l = l.trim();
+            if l.is_empty() { break }
+            message.push(' ');
+            message.push_str(l);
+        }
+        let line = INPUT_LINE.captures(&message).and_then(|c| c.get(1).unwrap().as_str().parse().ok());
+        Some(Diagnostic {
+            severity,message,source,
+            file:None,line,column:None,
+            textrace:vec!(),
+            backtrace:None
+        })
+    }
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "severity":self.severity.as_str(),
+            "message":self.message,
+            "source":self.source,
+            "file":self.file,
+            "line":self.line,
+            "column":self.column,
+            "textrace":self.textrace,
+            "backtrace":self.backtrace
+        })
+    }
+    pub fn to_json(&self) -> String { self.json().to_string() }
+}
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.file,self.line) {
+            (Some(file),Some(l)) => write!(f,"{}:{}:{}: ",file,l,self.column.unwrap_or(0))?,
+            (None,Some(l)) => write!(f,"l.{}: ",l)?,
+            _ => ()
+        }
+        write!(f,"{}: ",self.severity.as_str())?;
+        if let Some(s) = &self.source { write!(f,"[{}] ",s)? }
+        write!(f,"{}",self.message)
+    }
+}
+
+/// Collects [`Diagnostic`]s; usable from `&self` methods of [`InterpreterParams`](crate::interpreter::params::InterpreterParams)
+#[derive(Default)]
+pub struct Diagnostics(Mutex<Vec<Diagnostic>>);
+impl Diagnostics {
+    pub fn new() -> Diagnostics { Diagnostics::default() }
+    pub fn push(&self,d:Diagnostic) {
+        self.0.lock().unwrap().push(d)
+    }
+    pub fn get(&self) -> Vec<Diagnostic> {
+        self.0.lock().unwrap().clone()
+    }
+    pub fn take(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+    pub fn has_errors(&self) -> bool {
+        self.0.lock().unwrap().iter().any(|d| d.severity == Severity::Error)
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::Value::from_iter(self.0.lock().unwrap().iter().map(|d| d.json())).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Severity};
+
+    fn parse(s:&str) -> (Severity,Option<String>,String,Option<usize>) {
+        let d = Diagnostic::from_write(s).unwrap();
+        (d.severity,d.source,d.message,d.line)
+    }
+
+    #[test]
+    fn latex_warnings() {
+        assert_eq!(parse("\nPackage hyperref Warning: Token not allowed in a PDF string (Unicode):\n(hyperref)                removing `math shift' on input line 12.\n\n"),
+            (Severity::Warning,Some("hyperref".to_string()),"Token not allowed in a PDF string (Unicode): removing `math shift' on input line 12.".to_string(),Some(12)));
+        assert_eq!(parse("Class article Info: Using the default layout"),
+            (Severity::Info,Some("article".to_string()),"Using the default layout".to_string(),None));
+        assert_eq!(parse("LaTeX Font Warning: Font shape `OT1/cmr/bx/sc' undefined\n(Font)              using `OT1/cmr/bx/n' instead on input line 5.\n"),
+            (Severity::Warning,Some("LaTeX".to_string()),"Font shape `OT1/cmr/bx/sc' undefined using `OT1/cmr/bx/n' instead on input line 5.".to_string(),Some(5)));
+        // lines broken by TeX are joined
+        assert_eq!(parse("LaTeX Warning: Reference `foo' on page 1 undefined on input\nline 3.\n"),
+            (Severity::Warning,Some("LaTeX".to_string()),"Reference `foo' on page 1 undefined on input line 3.".to_string(),Some(3)));
+        assert_eq!(parse("Overfull \\hbox (1.5pt too wide) in paragraph at lines 7--9"),
+            (Severity::Warning,None,"Overfull \\hbox (1.5pt too wide) in paragraph at lines 7--9".to_string(),Some(7)));
+        assert!(Diagnostic::from_write("Some other Warning: text").is_none());
+        assert!(Diagnostic::from_write("(./foo.tex").is_none());
+    }
+}
@@ -57,6 +57,10 @@ pub struct Skip {
 impl Default for Skip {
     fn default() -> Self {Skip{base:0,stretch:None,shrink:None}}
 }
+/// `\maxdimen`, the largest dimension TeX allows
+pub const MAX_DIMEN : i32 = 1073741823;
+/// `\prevdepth` at the start of a vertical list: -1000pt, i.e. no interline glue
+pub const IGNORE_DEPTH : Skip = Skip{base:-65536000,stretch:None,shrink:None};
 impl Display for Skip {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f,"{}",self.to_string())
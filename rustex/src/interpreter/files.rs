@@ -1,6 +1,6 @@
 use std::path::Path;
 use crate::utils::TeXStr;
-use std::fs;
+use crate::filesystem::FileSystem;
 use crate::utils::TeXString;
 
 #[derive(Clone,PartialEq)]
@@ -24,7 +24,13 @@ use crate::{HYPHEN_CFG, /*PGFSYS_COMMON,*/ PGFSYS_RUST};
 use crate::interpreter::state::store::RusTeXMap;
 
 impl VFile {
-    pub(in crate::interpreter) fn new<'a>(fp : &Path,intexmf:bool, in_file: &Path, filestore:&mut RusTeXMap<TeXStr,Arc<VFile>>) -> Arc<VFile> {
+    pub(in crate) fn write_to(&self,path:&Path,fs:&dyn FileSystem) -> std::io::Result<()> {
+        match &*self.string.read().unwrap() {
+            Some(s) => fs.write(path,&s.0),
+            None => fs.write(path,&[])
+        }
+    }
+    pub(in crate::interpreter) fn new<'a>(fp : &Path,intexmf:bool, in_file: &Path, filestore:&mut RusTeXMap<TeXStr,Arc<VFile>>,fs:&dyn FileSystem) -> Arc<VFile> {
         use crate::{LANGUAGE_DAT,UNICODEDATA_TXT};
         let simplename : TeXStr = (if intexmf {
             "<texmf>/".to_owned() + fp.file_name().expect("wut").to_ascii_uppercase().to_str().unwrap()
@@ -70,8 +76,8 @@ impl VFile {
                 } */ else {
                     VFile {
                         source:VFileBase::Real(fp.to_str().unwrap().into()),
-                        string:Arc::new(RwLock::new(if fp.exists() {
-                            fs::read(fp).ok().map(|x| x.into())
+                        string:Arc::new(RwLock::new(if fs.is_file(fp) {
+                            fs.read(fp).ok().map(|x| x.into())
                         } else if simplename.to_string() == "<texmf>/NUL:" {
                             Some("".into())
                         } else {None})),
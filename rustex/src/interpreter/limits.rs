@@ -0,0 +1,176 @@
+/*! Resource limits that turn runaway documents (e.g. infinitely recursive macros) into
+[`TeXError`]s instead of exhausting memory, the stack or the caller's patience.
+
+The limits are provided by [`InterpreterParams::limits`](crate::interpreter::params::InterpreterParams::limits)
+and checked by the [`Interpreter`] before every expansion and whenever a group is opened; the
+timeout additionally before every top-level token. Errors caused by exceeding a limit are fatal,
+i.e. not recovered from even in `\nonstopmode`.
+
+Hosts can additionally stop a run from another thread with a [`CancellationToken`]; the
+interpreter then finishes the output produced so far and returns
+[`Outcome::Cancelled`](crate::interpreter::Outcome::Cancelled).
+*/
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::interpreter::Interpreter;
+use crate::ontology::Token;
+use crate::utils::TeXError;
+
+/// Expansions between two checks of [`Limits::timeout`]
+const TIMEOUT_INTERVAL : usize = 1024;
+/// The default [`Limits::expansion_depth`]; lower than TeX's 10000, since every nested expansion
+/// takes several stack frames
+const EXPANSION_DEPTH : usize = 500;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Limits {
+    /// maximal number of nested token lists and files (TeX's input stack size)
+    pub input_stack:usize,
+    /// maximal number of nested groups (TeX's grouping levels)
+    pub group_depth:usize,
+    /// maximal number of expansions in progress at the same time, e.g. `\number\a` with
+    /// `\def\a{\number\a}`
+    pub expansion_depth:usize,
+    /// maximal number of tokens a single expansion may produce
+    pub expansion_tokens:usize,
+    /// maximal number of expansions in total, per run
+    pub expansions:Option<usize>,
+    /// maximal wall-clock time per run
+    pub timeout:Option<Duration>
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            input_stack:5000,
+            group_depth:255,
+            expansion_depth:EXPANSION_DEPTH,
+            expansion_tokens:1000000,
+            expansions:None,
+            timeout:None
+        }
+    }
+}
+
+/// A flag shared between a host and a running [`Interpreter`], which checks it before every
+/// top-level token and stops cleanly once it is set
+#[derive(Clone,Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+impl CancellationToken {
+    pub fn new() -> CancellationToken { CancellationToken::default() }
+    /// Asks the interpreter(s) using this token to stop
+    pub fn cancel(&self) { self.0.store(true,Ordering::Relaxed) }
+    pub fn is_cancelled(&self) -> bool { self.0.load(Ordering::Relaxed) }
+}
+
+fn capacity_exceeded(what:&str,limit:impl std::fmt::Display,tk:&Token) -> TeXError {
+    TeXError::new(format!("TeX capacity exceeded, sorry [{}={}]",what,limit),Some(tk.clone())).fatal()
+}
+
+impl Interpreter<'_> {
+    /// Whether the host asked to stop via [`InterpreterParams::cancellation`](crate::interpreter::params::InterpreterParams::cancellation)
+    pub(crate) fn cancelled(&self) -> bool {
+        self.params.cancellation().map(|c| c.is_cancelled()).unwrap_or(false)
+    }
+    /// Checks the input stack depth, the expansion depth, the number of expansions so far and the timeout before
+    /// expanding `tk`
+    pub(crate) fn check_limits(&mut self,tk:&Token) -> Result<(),TeXError> {
+        if self.mouths.mouths.len() > self.limits.input_stack {
+            return Err(capacity_exceeded("input stack size",self.limits.input_stack,tk))
+        }
+        if self.expansion_depth >= self.limits.expansion_depth {
+            return Err(capacity_exceeded("expansion depth",self.limits.expansion_depth,tk))
+        }
+        self.expansions += 1;
+        match self.limits.expansions {
+            Some(max) if self.expansions > max => return Err(capacity_exceeded("expansions",max,tk)),
+            _ => ()
+        }
+        if self.expansions % TIMEOUT_INTERVAL == 0 { self.check_timeout(tk) } else { Ok(()) }
+    }
+    /// Checks [`Limits::timeout`]; also done for every top-level token, since loops need not expand
+    /// anything (e.g. an output routine that never empties the page)
+    pub(crate) fn check_timeout(&self,tk:&Token) -> Result<(),TeXError> {
+        match self.deadline {
+            Some(d) if Instant::now() > d =>
+                Err(TeXError::new(format!("Timeout: processing took longer than {}s",
+                    self.limits.timeout.map(|t| t.as_secs_f64()).unwrap_or(0.0)),Some(tk.clone())).fatal()),
+            _ => Ok(())
+        }
+    }
+    /// Checks the number of tokens produced by expanding `tk`
+    pub(crate) fn check_expansion_size(&self,tk:&Token,len:usize) -> Result<(),TeXError> {
+        if len > self.limits.expansion_tokens {
+            Err(capacity_exceeded("tokens per expansion",self.limits.expansion_tokens,tk))
+        } else { Ok(()) }
+    }
+    /// Checks the group depth before opening a new group
+    pub(crate) fn check_group_depth(&self) -> Result<(),TeXError> {
+        if self.state.stack_depth() >= self.limits.group_depth {
+            Err(TeXError::new(format!("TeX capacity exceeded, sorry [grouping levels={}]",self.limits.group_depth),None).fatal())
+        } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::fonts::ArcFont;
+    use crate::interpreter::{Interpreter, Outcome};
+    use crate::interpreter::params::{DefaultParams, Verbosity};
+    use crate::interpreter::state::State;
+    use crate::stomach::Whatsit;
+    use crate::stomach::colon::{Colon, ColonBase, NoColon};
+    use crate::utils::TeXStr;
+    use super::{CancellationToken, Limits};
+
+    /// Cancels `token` as soon as the document begins; returns whether it was closed
+    struct Canceller(ColonBase,CancellationToken);
+    impl Colon<bool> for Canceller {
+        fn base_mut(&mut self) -> &mut ColonBase { &mut self.0 }
+        fn base(&self) -> &ColonBase { &self.0 }
+        fn ship_whatsit(&mut self, _:Whatsit) {}
+        fn close(&mut self) -> bool { true }
+        fn initialize(&mut self,_:ArcFont,_:TeXStr,_:&Interpreter) { self.1.cancel() }
+    }
+
+    #[test]
+    fn cancellation() {
+        let (outcome,state,closed) = crate::utils::with_stack_size(|| {
+            // not single-threaded, i.e. the colon runs in its own thread
+            let mut p = DefaultParams::new(false,false,None);
+            let token = CancellationToken::new();
+            p.cancellation = Some(token.clone());
+            Interpreter::do_string_with_state(Path::new("/test.tex"),State::new(),
+                "\\catcode`\\{=1 \\catcode`\\}=2 \\catcode`\\@=11 \\def\\@freelist{}\\count1=5 \\def\\document{}\\document\n\\count1=6 ",Canceller(ColonBase::new(),token),&p)
+        });
+        assert!(matches!(outcome,Outcome::Cancelled));
+        assert!(closed);
+        assert_eq!(state.registers.get(&1),5);
+    }
+
+    /// Runs `text` in `\\nonstopmode`; returns the messages of the errors and `\\count1`
+    fn run(text:&str,limits:impl FnOnce(&mut Limits) + Send + 'static) -> (Vec<String>,i32) {
+        let text = "\\catcode`\\{=1 \\catcode`\\}=2 \\nonstopmode ".to_string() + text + "\\count1=1 ";
+        crate::utils::with_stack_size(move || {
+            let mut p = DefaultParams::new(false,true,None);
+            p.verbosity = Verbosity::Quiet;
+            limits(&mut p.limits);
+            let (_,state,()) = Interpreter::do_string_with_state(Path::new("/test.tex"),State::new(),&text,NoColon::new(),&p);
+            (p.diagnostics.take().into_iter().map(|d| d.message).collect(),state.registers.get(&1))
+        })
+    }
+
+    #[test]
+    fn capacity_exceeded() {
+        // the errors are fatal, i.e. the rest is not processed even in \nonstopmode;
+        // as in TeX, tail recursion does not fill the input stack, so only the number of expansions stops it
+        assert_eq!(run("\\def\\a{\\a}\\a",|l| l.expansions = Some(10000)),(vec!("TeX capacity exceeded, sorry [expansions=10000]".to_string()),0));
+        assert_eq!(run("\\def\\a{\\a x}\\a",|_| ()),(vec!("TeX capacity exceeded, sorry [input stack size=5000]".to_string()),0));
+        assert_eq!(run("\\def\\a{\\number\\a}\\a",|_| ()),(vec!("TeX capacity exceeded, sorry [expansion depth=500]".to_string()),0));
+        assert_eq!(run(&"{".repeat(300),|_| ()),(vec!("TeX capacity exceeded, sorry [grouping levels=255]".to_string()),0));
+        assert_eq!(run(&"{".repeat(10),|l| l.group_depth = 5),(vec!("TeX capacity exceeded, sorry [grouping levels=5]".to_string()),0));
+        assert_eq!(run(&("{".repeat(5) + &"}".repeat(5)),|l| l.group_depth = 5),(vec!(),1));
+    }
+}
@@ -3,11 +3,11 @@ use crate::interpreter::{Interpreter, TeXMode};
 use crate::ontology::Token;
 use crate::utils::{TeXError, TeXString};
 use std::str::FromStr;
-use crate::commands::{TokReference, PrimitiveTeXCommand, AssignableValue, TokenList};
-use crate::{TeXErr,FileEnd,log};
+use crate::commands::{TokReference, PrimitiveTeXCommand, AssignableValue};
+use crate::{TeXErr,FileEnd};
 use crate::catcodes::CategoryCode::BeginGroup;
 use crate::commands::primitives::{HANGINDENT, PARSHAPE};
-use crate::interpreter::dimensions::{Skip, Numeric, SkipDim, MuSkipDim, MuSkip, round_f};
+use crate::interpreter::dimensions::{Skip, Numeric, SkipDim, MuSkipDim, MuSkip, round_f, IGNORE_DEPTH};
 use crate::interpreter::state::GroupType;
 use crate::stomach::whatsits::Whatsit;
 use crate::stomach::boxes::{BoxMode,TeXBox};
@@ -19,9 +19,7 @@ impl Interpreter<'_> {
     pub fn insert_every(&mut self,tr:&TokReference) {
         let i = tr.index - 1;
         let insert = self.state.toks_prim.get(&i);
-        if unsafe {crate::LOG} {
-            log!("Insert Every: {}",TokenList(&insert));
-        }
+        self.trace_token_list(tr.name,&insert);
         self.push_tokens(insert)
     }
 
@@ -270,7 +268,6 @@ impl Interpreter<'_> {
                         match &*cmd.orig {
                             PrimitiveTeXCommand::Char(tk) if eat_space && (tk.catcode == CategoryCode::Space || tk.catcode == CategoryCode::EOL) => return Ok(()),
                             _ => {
-                                log!("expand_until ended by {}",next);
                                 self.requeue(next);
                                 return Ok(())
                             }
@@ -279,7 +276,6 @@ impl Interpreter<'_> {
                 },
                 CategoryCode::Space | CategoryCode::EOL if eat_space => return Ok(()),
                 _ => {
-                    log!("expand_until ended by {}",next);
                     self.requeue(next);
                     return Ok(())
                 }
@@ -348,16 +344,17 @@ impl Interpreter<'_> {
             _ => TeXErr!(next => "Expected Begin Group Token")
         };
         let _oldmode = self.state.mode;
-        self.state.push(self.stomach,GroupType::Box(bm));
+        self.push_group(GroupType::Box(bm))?;
         self.state.mode = match bm {
             BoxMode::H => {
                 if insertevery { self.insert_every(&crate::commands::registers::EVERYHBOX) };
+                self.state.registers_prim.set(crate::commands::registers::SPACEFACTOR.index - 1,1000,false);
                 TeXMode::RestrictedHorizontal
             },
             BoxMode::V => {
                 if insertevery { self.insert_every(&crate::commands::registers::EVERYVBOX) };
                 self.stomach.reset_par(&mut self.state);
-                //log!("Parshape: {:?}",self.state.parshape.get());
+                self.state.skips_prim.set(crate::commands::registers::PREVDEPTH.index - 1,IGNORE_DEPTH,false);
                 TeXMode::InternalVertical
             },
             BoxMode::M => TeXMode::Math,
@@ -417,7 +414,7 @@ impl Interpreter<'_> {
                 CategoryCode::EndGroup if ingroups == 0 => return Ok(()),
                 CategoryCode::BeginGroup => {
                     ingroups += 1;
-                    self.state.push(self.stomach,GroupType::Token);
+                    self.push_group(GroupType::Token)?;
                 }
                 CategoryCode::EndGroup => {
                     ingroups -= 1;
@@ -472,7 +469,6 @@ impl Interpreter<'_> {
         let mut isoct = false;
         let mut isfloat = false;
         let mut ret : TeXString = "".into();
-        log!("Reading number {}",self.preview());
         while self.has_next() {
             let next = self.next_token();
             match next.catcode {
@@ -9,7 +9,7 @@ use std::vec::IntoIter;
 use crate::ontology::{Comment, Expansion, LaTeXFile, Token, LaTeXObject, EMPTY_NAME, trivial_name};
 use crate::catcodes::{CategoryCode, CategoryCodeScheme};
 use crate::commands::primitives::{CR, CRCR, ENDROW, ENDTEMPLATE, RELAX};
-use crate::commands::{PrimitiveTeXCommand, TokenList};
+use crate::commands::PrimitiveTeXCommand;
 use crate::commands::registers::EVERYCR;
 use crate::references::{SourceFileReference, SourceReference};
 use crate::utils::{TeXStr, TeXString};
@@ -157,7 +157,8 @@ pub struct StringMouth {
     atendofline:Option<u8>,
     charbuffer:Option<(u8,usize,usize)>,
     pub(in crate) source : StringMouthSource,
-    iseof : bool
+    iseof : bool,
+    store : bool
 }
 
 impl StringMouth {
@@ -263,7 +264,8 @@ impl StringMouth {
                     pos:0,
                     charbuffer: None,
                     source,
-                    iseof: false
+                    iseof: false,
+                    store: false
                 }
             }
             None =>
@@ -277,7 +279,8 @@ impl StringMouth {
                     pos:0,
                     charbuffer: None,
                     source,
-                    iseof: true
+                    iseof: true,
+                    store: false
                 }
         }
     }
@@ -327,7 +330,7 @@ impl StringMouth {
                             match self.atendofline {
                                 Some(cb) => {
                                     self.atendofline = None;
-                                    return Some((cb, self.line, self.pos))
+                                    return Some((cb, self.line, self.pos + 1))
                                 },
                                 None => match self.do_line(endlinechar) {
                                     true => {},
@@ -367,8 +370,14 @@ impl StringMouth {
             Some(r) => Some(self.make_file_reference(r,line,pos))
         }
     }
+    /// `pos` is the column of the token's first character; the end is exclusive, i.e. the position
+    /// of the first character not belonging to the token (which may have been read ahead already)
     fn make_file_reference(&self,f : &LaTeXFile,line:usize,pos:usize) -> Arc<SourceReference> {
-        Arc::new(SourceReference::File(unsafe{f.path.as_ref().unwrap_unchecked().clone()},(line,pos),(self.line,self.pos)))
+        let end = match self.charbuffer {
+            Some((_,l,p)) => (l,p),
+            None => (self.line,self.pos + 1)
+        };
+        Arc::new(SourceReference::File(unsafe{f.path.as_ref().unwrap_unchecked().clone()},(line,pos),std::cmp::max(end,(line,pos + 1))))
     }
 
     pub fn has_next(&mut self,catcodes:&CategoryCodeScheme, nocomment: bool,allowignore:bool) -> bool {
@@ -386,14 +395,14 @@ impl StringMouth {
                     match self.next_char(catcodes.endlinechar) {
                         None => return false, // ret = Some(false),
                         Some(next) => match catcodes.get_code(next.0) {
-                            CategoryCode::Ignored if !allowignore && STORE_IN_FILE => {
+                            CategoryCode::Ignored if !allowignore && self.store => {
                                 let file = self.source.get_file();
                                 match file {
                                     Some(ltxf) => {
                                         let nrf = Some(Arc::new(SourceReference::File(
                                             unsafe{ltxf.path.as_ref().unwrap_unchecked().clone()},
                                             (next.1, next.2),
-                                            (self.line, self.pos)
+                                            (self.line, self.pos + 1)
                                         )));
                                         let tk = Token::new(next.0, CategoryCode::Ignored, None, nrf, true);
                                         ltxf.add(LaTeXObject::Token(tk))
@@ -406,13 +415,12 @@ impl StringMouth {
                             CategoryCode::Comment if !allowignore => if nocomment {
                                 let mut rest: Vec<u8> = (*self.string.as_ref().unwrap()).0[self.pos..].to_vec();//..slice(self.pos as usize,self.string.unwrap().len()).to_vec();
                                 rest.insert(0, next.0);
-                                match (STORE_IN_FILE, self.source.get_file()) {
+                                match (self.store, self.source.get_file()) {
                                     (true, Some(ltxf)) => {
-                                        let txt = std::str::from_utf8(rest.as_slice()).unwrap().to_string();
-                                        let end = txt.len();
-                                        self.pos += end;
+                                        self.pos += rest.len() - 1;
+                                        let txt = TeXString(rest).to_string();
                                         let nrf = SourceReference::File(ltxf.path.as_ref().unwrap().clone(),
-                                                                        (next.1, next.2), (self.line, self.pos)
+                                                                        (next.1, next.2), (self.line, self.pos + 1)
                                         );
                                         let tk = Comment {
                                             text: txt,
@@ -542,7 +550,7 @@ impl StringMouth {
                     Token::new(char,o,None,self.make_reference(l,p),true)
                 }
             };
-            match (STORE_IN_FILE,self.source.get_file()) {
+            match (self.store,self.source.get_file()) {
                 (true,Some(ltxf)) => {
                     ltxf.add(LaTeXObject::Token(ret.clone()))
                 }
@@ -576,18 +584,22 @@ impl StringMouth {
 
 use crate::interpreter::files::VFile;
 use crate::interpreter::params::InterpreterParams;
-use crate::{log, STORE_IN_FILE};
 
 pub (in crate) struct Mouths {
     pub mouths: Vec<Mouth>,
     buffer: Option<Token>,
+    /// record the [`LaTeXFile`]s of files pushed from now on
+    pub(in crate) store: bool,
+    source: Option<LaTeXFile>
 }
 
 impl Mouths {
     pub fn new() -> Mouths {
         Mouths {
             mouths:Vec::new(),
-            buffer:None
+            buffer:None,
+            store:false,
+            source:None
         }
     }
     pub(in crate::interpreter::mouth) fn has_next(&mut self,catcodes:&CategoryCodeScheme,io:&dyn InterpreterParams) -> Result<bool,EOF> {
@@ -603,7 +615,7 @@ impl Mouths {
                                     self.mouths.push(Mouth::File(f));
                                     return Ok(false)
                                 }
-                                Mouth::File(fm) if STORE_IN_FILE => {
+                                Mouth::File(fm) if fm.store => {
                                     io.file_close();
                                     let lastfile = self.mouths.iter_mut().rev().find(|x| match x {
                                         Mouth::File(_) => true,
@@ -651,6 +663,12 @@ impl Mouths {
     }
     pub(in crate::interpreter) fn push_tokens(&mut self, mut tks : Vec<Token>) {
         if !tks.is_empty() {
+            // like TeX, drop finished token lists first, so that tail recursion does not grow
+            // the input stack
+            while let Some(Mouth::Token(tm)) = self.mouths.last_mut() {
+                if tm.has_next(true) { break }
+                self.mouths.pop();
+            }
             match self.buffer.take() {
                 Some(t) => tks.push(t),
                 _ => ()
@@ -674,7 +692,9 @@ impl Mouths {
             Some(t) => self.mouths.push(Mouth::Token(TokenMouth::new(vec!(t)))),
             _ => ()
         }
-        self.mouths.push(Mouth::File(StringMouth::new_from_file(file,false)))
+        let mut sm = StringMouth::new_from_file(file,false);
+        sm.store = self.store;
+        self.mouths.push(Mouth::File(sm))
     }
     pub(in crate::interpreter::mouth) fn push_string(&mut self,exp:Expansion,string : TeXString,filelike:bool) {
         match self.buffer.take() {
@@ -791,8 +811,30 @@ impl Mouths {
         }
     }
     pub fn close(&mut self) {
+        if self.store { self.source = self.collect_source() }
         self.mouths.clear()
     }
+    /// Nests the [`LaTeXFile`]s of all files still open into the outermost one and returns it
+    fn collect_source(&mut self) -> Option<LaTeXFile> {
+        let mut ret : Option<LaTeXFile> = None;
+        while let Some(m) = self.mouths.pop() {
+            if let Mouth::File(sm) = m {
+                if let Some(mut f) = sm.source.pop_file() {
+                    if let Some(inner) = ret.take() { f.add(LaTeXObject::File(inner)) }
+                    ret = Some(f)
+                }
+            }
+        }
+        ret
+    }
+    /// The recorded source model of the job, if [`Mouths::store`] was set
+    pub(in crate::interpreter) fn take_source(&mut self) -> Option<LaTeXFile> {
+        if !self.store { return None }
+        match self.source.take() {
+            Some(f) => Some(f),
+            None => self.collect_source()
+        }
+    }
 }
 
 impl Interpreter<'_> {
@@ -852,7 +894,6 @@ impl Interpreter<'_> {
                     let mut v = self.state.borrow_mut().aligns.pop().unwrap().unwrap();
                     self.state.borrow_mut().aligns.push(None);
                     v.push(ENDTEMPLATE.try_with(|x| x.clone()).unwrap());
-                    log!("Pushing template {}",TokenList(&v));
                     self.push_tokens(v);
                     self.next_token()
                 }
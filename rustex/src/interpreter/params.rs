@@ -12,6 +12,18 @@ pub trait CommandListener : Sync {
     }
 }
 
+/// Where the contents of files opened with `\openout` end up
+#[derive(Clone,PartialEq)]
+pub enum OutFiles {
+    /// only kept in the [`State`]'s file store; nothing is written to disk
+    Memory,
+    /// written to disk (on `\closeout` or at the end of the job) next to the job file
+    JobDir,
+    /// written to disk in the given directory (like pdfTeX's `-output-directory`),
+    /// which is also searched first when reading files
+    Dir(PathBuf)
+}
+
 pub trait InterpreterParams {
     fn singlethreaded(&self) -> bool;
     fn do_log(&self) -> bool;
@@ -28,13 +40,60 @@ pub trait InterpreterParams {
     fn file_open(&self,s:&str);
     fn file_close(&self);
     fn error(&self,t:TeXError);
+    /// Warnings recognized in `\write` output (see [`Diagnostic::from_write`])
+    fn diagnostic(&self,d:Diagnostic);
     fn message(&self,s:&str);
+    /// Output of `\show`, `\showthe`, `\showbox` etc., including the leading `> ` and
+    /// the trailing newline
+    fn show(&self,s:&str);
     fn command_listeners(&self) -> &Vec<Box<dyn CommandListener>>;
+    fn outfiles(&self) -> OutFiles;
+    /// overrides `\jobname` (otherwise the name of the input file without extension)
+    fn jobname(&self) -> Option<String>;
+    /// whether `\write18` executes its argument as a shell command
+    fn shell_escape(&self) -> bool;
+    /// called by [`Interpreter::do_file_with_state_multipass`](crate::interpreter::Interpreter::do_file_with_state_multipass) before every pass
+    fn begin_pass(&self,pass:usize);
+    /// the interactive debugger to pause in, if any
+    fn debugger(&self) -> Option<&Debugger>;
+    /// the profiler to record expansions and timings in, if any
+    fn profiler(&self) -> Option<&Profiler>;
+    /// the resource limits to enforce
+    fn limits(&self) -> Limits;
+    /// a token the host can use to stop processing early
+    fn cancellation(&self) -> Option<&CancellationToken>;
+    /// where all files are read from and written to
+    fn filesystem(&self) -> &dyn FileSystem;
+}
+
+/// How much [`DefaultParams`] prints
+#[derive(Clone,Copy,PartialEq,PartialOrd,Debug)]
+pub enum Verbosity {
+    /// nothing (diagnostics are still collected)
+    Quiet,
+    /// only errors
+    Errors,
+    /// terminal output (`\write16`, `\message`, file names...) and errors
+    Normal,
+    /// additionally the log
+    Verbose
 }
 
 pub struct DefaultParams {
     pub log:bool,
     pub singlethreaded:bool,
+    pub verbosity:Verbosity,
+    pub jobname:Option<String>,
+    pub shell_escape:bool,
+    pub outfiles:OutFiles,
+    pub diagnostics:Diagnostics,
+    /// include the Rust backtrace in the [`Diagnostic`]s of errors (always done at [`Verbosity::Verbose`])
+    pub backtraces:bool,
+    pub debugger:Option<Debugger>,
+    pub profiler:Option<Profiler>,
+    pub limits:Limits,
+    pub cancellation:Option<CancellationToken>,
+    pub filesystem:Box<dyn FileSystem>,
     pub listeners: Vec<Box<dyn CommandListener>>
 }
 
@@ -42,6 +101,17 @@ impl DefaultParams {
     pub fn new(log:bool,singlethreaded:bool,listeners:Option<Vec<Box<dyn CommandListener>>>) -> DefaultParams {
         DefaultParams {
             log,singlethreaded,
+            verbosity:if log {Verbosity::Verbose} else {Verbosity::Normal},
+            jobname:None,
+            shell_escape:false,
+            outfiles:OutFiles::Memory,
+            diagnostics:Diagnostics::new(),
+            backtraces:false,
+            debugger:None,
+            profiler:None,
+            limits:Limits::default(),
+            cancellation:None,
+            filesystem:Box::new(PhysicalFS),
             listeners: match listeners {
                 Some(v) => v,
                 None => DefaultParams::default_listeners()
@@ -54,8 +124,14 @@ impl DefaultParams {
 }
 
 use std::cmp::max;
+use std::path::PathBuf;
 use ansi_term::Colour::*;
 use crate::commands::{DefMacro, ParamToken, PrimitiveTeXCommand, Signature, TeXCommand};
+use crate::interpreter::debugger::Debugger;
+use crate::interpreter::profiler::Profiler;
+use crate::interpreter::limits::{CancellationToken, Limits};
+use crate::filesystem::{FileSystem, PhysicalFS};
+use crate::interpreter::diagnostics::{Diagnostic, Diagnostics};
 use crate::interpreter::state::State;
 use crate::ontology::Token;
 use crate::utils::{TeXError, TeXStr};
@@ -67,26 +143,44 @@ impl InterpreterParams for DefaultParams {
     fn store_in_file(&self) -> bool { false }
     fn copy_tokens_full(&self) -> bool { true }
     fn copy_commands_full(&self) -> bool { true }
-    fn log(&self,_s:&str) {}
-    fn write_16(&self,s:&str) { print!("{}",White.bold().paint(s)) }
-    fn write_17(&self,s:&str) { print!("{}",s) }
-    fn write_18(&self,_s:&str) { }
-    fn write_neg_1(&self,s:&str) { print!("{}",Black.on(Blue).paint(s)) }
-    fn write_other(&self,s:&str) { print!("{}",Black.on(Green).paint(s)) }
-    fn file_open(&self, s: &str) { print!("{}",s) }
-    fn file_close(&self) { print!(")") }
-    fn message(&self,s:&str) { print!("{}",Yellow.paint(s)) }
+    fn log(&self,s:&str) { if self.verbosity >= Verbosity::Verbose { println!("{}",Purple.paint(s)) } }
+    fn write_16(&self,s:&str) { if self.verbosity >= Verbosity::Normal { print!("{}",White.bold().paint(s)) } }
+    fn write_17(&self,s:&str) { if self.verbosity >= Verbosity::Normal { print!("{}",s) } }
+    fn write_18(&self,s:&str) { if self.verbosity >= Verbosity::Verbose { println!("runsystem({})",s) } }
+    fn write_neg_1(&self,s:&str) { if self.verbosity >= Verbosity::Normal { print!("{}",Black.on(Blue).paint(s)) } }
+    fn write_other(&self,s:&str) { if self.verbosity >= Verbosity::Normal { print!("{}",Black.on(Green).paint(s)) } }
+    fn file_open(&self, s: &str) { if self.verbosity >= Verbosity::Normal { print!("{}",s) } }
+    fn file_close(&self) { if self.verbosity >= Verbosity::Normal { print!(")") } }
+    fn message(&self,s:&str) { if self.verbosity >= Verbosity::Normal { print!("{}",Yellow.paint(s)) } }
+    fn show(&self,s:&str) { if self.verbosity >= Verbosity::Normal { print!("{}",Cyan.paint(s)) } }
     fn error(&self,t:TeXError) {
-        println!("{}",Red.paint(std::format!("{}",t)))
+        if self.verbosity >= Verbosity::Errors { println!("{}",Red.paint(std::format!("{}",t))) }
+        self.diagnostics.push(Diagnostic::from_error(&t,self.backtraces || self.verbosity == Verbosity::Verbose))
     }
+    fn diagnostic(&self,d:Diagnostic) { self.diagnostics.push(d) }
     fn command_listeners(&self) -> &Vec<Box<dyn CommandListener>> { &self.listeners }
+    fn outfiles(&self) -> OutFiles { self.outfiles.clone() }
+    fn jobname(&self) -> Option<String> { self.jobname.clone() }
+    fn shell_escape(&self) -> bool { self.shell_escape }
+    fn begin_pass(&self,pass:usize) {
+        // only the diagnostics of the final pass are relevant
+        if pass > 1 { self.diagnostics.take(); }
+        if let Some(p) = &self.profiler { p.begin_pass(pass) }
+    }
+    fn debugger(&self) -> Option<&Debugger> { self.debugger.as_ref() }
+    fn profiler(&self) -> Option<&Profiler> { self.profiler.as_ref() }
+    fn limits(&self) -> Limits { self.limits }
+    fn cancellation(&self) -> Option<&CancellationToken> { self.cancellation.as_ref() }
+    fn filesystem(&self) -> &dyn FileSystem { &*self.filesystem }
 }
 pub struct NoOutput {
+    pub diagnostics:Diagnostics,
     pub listeners: Vec<Box<dyn CommandListener>>
 }
 impl NoOutput {
     pub fn new(listeners:Option<Vec<Box<dyn CommandListener>>>) -> NoOutput {
         NoOutput {
+            diagnostics:Diagnostics::new(),
             listeners: match listeners {
                 Some(v) => v,
                 None => DefaultParams::default_listeners()
@@ -110,8 +204,75 @@ impl InterpreterParams for NoOutput {
     fn file_open(&self, _s: &str) {}
     fn file_close(&self) {}
     fn message(&self, _s: &str) {}
+    fn show(&self, _s: &str) {}
     fn error(&self,t:TeXError) {
-        println!("{}",Red.paint(std::format!("{}",t)))
+        println!("{}",Red.paint(std::format!("{}",t)));
+        self.diagnostics.push(Diagnostic::from_error(&t,false))
     }
+    fn diagnostic(&self,d:Diagnostic) { self.diagnostics.push(d) }
     fn command_listeners(&self) -> &Vec<Box<dyn CommandListener>> { &self.listeners }
-}
\ No newline at end of file
+    fn outfiles(&self) -> OutFiles { OutFiles::Memory }
+    fn jobname(&self) -> Option<String> { None }
+    fn shell_escape(&self) -> bool { false }
+    fn begin_pass(&self,pass:usize) {
+        if pass > 1 { self.diagnostics.take(); }
+    }
+    fn debugger(&self) -> Option<&Debugger> { None }
+    fn profiler(&self) -> Option<&Profiler> { None }
+    fn limits(&self) -> Limits { Limits::default() }
+    fn cancellation(&self) -> Option<&CancellationToken> { None }
+    fn filesystem(&self) -> &dyn FileSystem { &PhysicalFS }
+}
+/// [`DefaultParams`] that additionally record everything passed to [`InterpreterParams::log`]
+/// (one line per call) and [`InterpreterParams::show`], for comparing against pdfTeX's output
+#[cfg(test)]
+pub(crate) struct Recording {
+    pub params:DefaultParams,
+    pub log:std::sync::Mutex<String>,
+    pub shown:std::sync::Mutex<String>
+}
+#[cfg(test)]
+impl Recording {
+    pub fn new() -> Recording {
+        let mut params = DefaultParams::new(false,true,None);
+        params.verbosity = Verbosity::Quiet;
+        Recording { params, log:Default::default(), shown:Default::default() }
+    }
+    pub fn log(&self) -> String { self.log.lock().unwrap().clone() }
+    pub fn shown(&self) -> String { self.shown.lock().unwrap().clone() }
+}
+#[cfg(test)]
+impl InterpreterParams for Recording {
+    fn singlethreaded(&self) -> bool { self.params.singlethreaded() }
+    fn do_log(&self) -> bool { self.params.do_log() }
+    fn set_log(&mut self,b: bool) { self.params.set_log(b) }
+    fn store_in_file(&self) -> bool { self.params.store_in_file() }
+    fn copy_tokens_full(&self) -> bool { self.params.copy_tokens_full() }
+    fn copy_commands_full(&self) -> bool { self.params.copy_commands_full() }
+    fn log(&self,s:&str) {
+        let mut log = self.log.lock().unwrap();
+        log.push_str(s);
+        log.push('\n')
+    }
+    fn write_16(&self,s:&str) { self.params.write_16(s) }
+    fn write_17(&self,s:&str) { self.params.write_17(s) }
+    fn write_18(&self,s:&str) { self.params.write_18(s) }
+    fn write_neg_1(&self,s:&str) { self.params.write_neg_1(s) }
+    fn write_other(&self,s:&str) { self.params.write_other(s) }
+    fn file_open(&self, s: &str) { self.params.file_open(s) }
+    fn file_close(&self) { self.params.file_close() }
+    fn message(&self,s:&str) { self.params.message(s) }
+    fn show(&self,s:&str) { self.shown.lock().unwrap().push_str(s) }
+    fn error(&self,t:TeXError) { self.params.error(t) }
+    fn diagnostic(&self,d:Diagnostic) { self.params.diagnostic(d) }
+    fn command_listeners(&self) -> &Vec<Box<dyn CommandListener>> { self.params.command_listeners() }
+    fn outfiles(&self) -> OutFiles { self.params.outfiles() }
+    fn jobname(&self) -> Option<String> { self.params.jobname() }
+    fn shell_escape(&self) -> bool { self.params.shell_escape() }
+    fn begin_pass(&self,pass:usize) { self.params.begin_pass(pass) }
+    fn debugger(&self) -> Option<&Debugger> { self.params.debugger() }
+    fn profiler(&self) -> Option<&Profiler> { self.params.profiler() }
+    fn limits(&self) -> Limits { self.params.limits() }
+    fn cancellation(&self) -> Option<&CancellationToken> { self.params.cancellation() }
+    fn filesystem(&self) -> &dyn FileSystem { self.params.filesystem() }
+}
@@ -0,0 +1,319 @@
+/*! An opt-in profiler that attributes expansions, primitive executions and wall time to
+control sequences and source files.
+
+The interpreter calls [`Interpreter::profile_hook`] before every expansion and before every
+other token it processes. If the [`InterpreterParams`](crate::interpreter::params::InterpreterParams)
+provide a [`Profiler`], the time until the next such call is attributed to the current token,
+the macros it was expanded from (according to its [`SourceReference`]) and the file it
+ultimately comes from. [`Profiler::report`] returns a flat table of the results,
+[`Profiler::collapsed_stacks`] the same data in the "collapsed stack" format read by flamegraph
+tools (`file;\outer;\inner microseconds`).
+
+Time paused in the [`Debugger`](crate::interpreter::debugger::Debugger) is not counted, and of
+a document that needs several passes, only the final one is reported.
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use crate::catcodes::CategoryCode;
+use crate::interpreter::Interpreter;
+use crate::ontology::Token;
+use crate::references::SourceReference;
+use crate::utils::TeXString;
+
+/// Expansion chains longer than this (e.g. from tail recursive loops) are cut off
+const MAX_DEPTH : usize = 1000;
+
+/// What the interpreter is about to do with a token
+#[derive(Clone,Copy,PartialEq)]
+pub enum ProfileStep {
+    /// expand a macro or an expandable primitive
+    Expansion,
+    /// execute an unexpandable primitive
+    Execution,
+    /// process a character
+    Character
+}
+
+#[derive(Default,Clone)]
+struct Stats {
+    expansions:usize,
+    executions:usize,
+    /// time spent in steps where this is the innermost frame
+    self_time:Duration,
+    /// time spent in steps where this occurs anywhere in the stack
+    total_time:Duration
+}
+
+/// A distinct stack: a file at the root, then the macros it was expanded from, outermost first
+struct Node {
+    name:String,
+    parent:Option<usize>,
+    depth:usize,
+    expansions:usize,
+    executions:usize,
+    /// time spent in steps with exactly this stack
+    time:Duration
+}
+
+struct ProfilerState {
+    nodes:Vec<Node>,
+    /// (parent, name) to node
+    children:HashMap<(Option<usize>,String),usize>,
+    /// the nodes of the expansions tokens were recently read from; all tokens of an expansion
+    /// share its reference, so a step usually needs no walk along the expansion chain
+    refs:HashMap<usize,(Weak<SourceReference>,usize)>,
+    /// the size of `refs` after it was last cleaned up
+    refs_kept:usize,
+    last:Instant,
+    /// the node of the previous step
+    current:Option<usize>,
+    pass:usize
+}
+impl ProfilerState {
+    fn new(pass:usize) -> ProfilerState {
+        ProfilerState {
+            nodes:vec!(),children:HashMap::new(),refs:HashMap::new(),refs_kept:0,
+            last:Instant::now(),current:None,pass
+        }
+    }
+    /// attributes the time since the previous step to its stack
+    fn flush(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.last);
+        self.last = now;
+        if let Some(n) = self.current { self.nodes[n].time += elapsed }
+    }
+    fn child(&mut self,parent:Option<usize>,name:String) -> usize {
+        let (parent,depth) = match parent.map(|p| &self.nodes[p]) {
+            // merge direct recursion, e.g. \loop ... \repeat
+            Some(p) if p.depth > 0 && p.name == name => return parent.unwrap(),
+            // frames beyond MAX_DEPTH are collapsed into a single "..." frame
+            Some(p) if p.depth > MAX_DEPTH && p.name == "..." => (parent,p.depth + 1),
+            Some(p) if p.depth > MAX_DEPTH => (p.parent,p.depth),
+            Some(p) if p.depth == MAX_DEPTH => return {
+                let dots = self.intern(parent,"...".to_string(),MAX_DEPTH + 1);
+                self.intern(Some(dots),name,MAX_DEPTH + 2)
+            },
+            Some(p) => (parent,p.depth + 1),
+            None => (None,0)
+        };
+        self.intern(parent,name,depth)
+    }
+    fn intern(&mut self,parent:Option<usize>,name:String,depth:usize) -> usize {
+        let nodes = &mut self.nodes;
+        *self.children.entry((parent,name.clone())).or_insert_with(|| {
+            nodes.push(Node { name,parent,depth,expansions:0,executions:0,time:Duration::ZERO });
+            nodes.len() - 1
+        })
+    }
+    fn cached(&self,r:&Arc<SourceReference>) -> Option<usize> {
+        match self.refs.get(&(Arc::as_ptr(r) as usize)) {
+            Some((w,n)) if w.as_ptr() == Arc::as_ptr(r) && w.strong_count() > 0 => Some(*n),
+            _ => None
+        }
+    }
+    fn cache(&mut self,r:&Arc<SourceReference>,node:usize) {
+        if self.refs.len() > 2 * self.refs_kept + 1024 {
+            self.refs.retain(|_,(w,_)| w.strong_count() > 0);
+            self.refs_kept = self.refs.len()
+        }
+        self.refs.insert(Arc::as_ptr(r) as usize,(Arc::downgrade(r),node));
+    }
+    /// The node of the expansion (or file) `r`
+    fn node_of(&mut self,r:&Option<Arc<SourceReference>>) -> usize {
+        // the expansions not seen yet, innermost first
+        let mut pending : Vec<Arc<SourceReference>> = vec!();
+        let mut current = r.clone();
+        let mut node = loop {
+            let r = match current {
+                None => break self.child(None,"<unknown>".to_string()),
+                Some(r) => r
+            };
+            if let Some(n) = self.cached(&r) { break n }
+            match &*r {
+                SourceReference::File(f,_,_) => {
+                    let n = self.child(None,f.to_string());
+                    self.cache(&r,n);
+                    break n
+                }
+                SourceReference::Exp(ntk,_) => {
+                    current = ntk.reference.clone();
+                    pending.push(r)
+                }
+            }
+        };
+        while let Some(r) = pending.pop() {
+            if let SourceReference::Exp(ntk,_) = &*r { node = self.child(Some(node),frame_name(ntk)) }
+            self.cache(&r,node)
+        }
+        node
+    }
+    /// The names of the frames of `node`, outermost (i.e. the file) first
+    fn stack(&self,mut node:usize) -> Vec<&str> {
+        let mut ret = vec!(self.nodes[node].name.as_str());
+        while let Some(p) = self.nodes[node].parent {
+            ret.push(self.nodes[p].name.as_str());
+            node = p
+        }
+        ret.reverse();
+        ret
+    }
+    /// (control sequences, files)
+    fn tables(&self) -> (HashMap<String,Stats>,HashMap<String,Stats>) {
+        let mut commands : HashMap<String,Stats> = HashMap::new();
+        let mut files : HashMap<String,Stats> = HashMap::new();
+        for (i,n) in self.nodes.iter().enumerate() {
+            let stack = self.stack(i);
+            let file = files.entry(stack[0].to_string()).or_default();
+            file.expansions += n.expansions;
+            file.executions += n.executions;
+            file.self_time += n.time;
+            file.total_time += n.time;
+            if n.depth == 0 || n.name == "..." { continue }
+            let cmd = commands.entry(n.name.clone()).or_default();
+            cmd.expansions += n.expansions;
+            cmd.executions += n.executions;
+            cmd.self_time += n.time;
+            if n.time.is_zero() { continue }
+            // recursive macros count only once
+            let mut seen = HashSet::new();
+            for f in stack.into_iter().skip(1) {
+                if f != "..." && seen.insert(f) {
+                    commands.entry(f.to_string()).or_default().total_time += n.time
+                }
+            }
+        }
+        (commands,files)
+    }
+}
+
+pub struct Profiler {
+    state:Mutex<ProfilerState>
+}
+
+fn frame_name(tk:&Token) -> String {
+    let name = match tk.catcode {
+        CategoryCode::Escape => "\\".to_string() + &tk.name().to_string(),
+        _ => TeXString(vec!(tk.char)).to_string()
+    };
+    // ; separates frames in collapsed stacks
+    name.replace(';',"%3B")
+}
+
+fn ms(d:&Duration) -> String { format!("{:.3}",d.as_secs_f64() * 1000.0) }
+
+fn table(title:&str,map:&HashMap<String,Stats>,with_self:bool) -> String {
+    let mut entries : Vec<(&String,&Stats)> = map.iter().collect();
+    entries.sort_by(|a,b| b.1.total_time.cmp(&a.1.total_time).then(a.0.cmp(b.0)));
+    let width = entries.iter().map(|e| e.0.chars().count()).max().unwrap_or(0).max(title.len());
+    let mut ret = format!("{:<width$}  {:>10}  {:>10}",title,"expansions","executions",width = width);
+    if with_self { ret += &format!("  {:>12}","self (ms)") }
+    ret += &format!("  {:>12}\n","total (ms)");
+    for (name,s) in entries {
+        ret += &format!("{:<width$}  {:>10}  {:>10}",name,s.expansions,s.executions,width = width);
+        if with_self { ret += &format!("  {:>12}",ms(&s.self_time)) }
+        ret += &format!("  {:>12}\n",ms(&s.total_time));
+    }
+    ret
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler { state:Mutex::new(ProfilerState::new(1)) }
+    }
+    /// Records that the interpreter is about to process `tk` as `step`
+    pub fn step(&self,tk:&Token,step:ProfileStep) {
+        let mut state = self.state.lock().unwrap();
+        state.flush();
+        let mut node = state.node_of(&tk.reference);
+        if step != ProfileStep::Character { node = state.child(Some(node),frame_name(tk)) }
+        match step {
+            ProfileStep::Expansion => state.nodes[node].expansions += 1,
+            ProfileStep::Execution => state.nodes[node].executions += 1,
+            ProfileStep::Character => ()
+        }
+        state.current = Some(node)
+    }
+    /// Ends the last step; called when the interpreter is done with a file
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.flush();
+        state.current = None
+    }
+    /// Discards everything recorded so far; only the final pass of a document is reported
+    pub fn begin_pass(&self,pass:usize) {
+        *self.state.lock().unwrap() = ProfilerState::new(pass)
+    }
+    /// Does not attribute the last `d` (e.g. spent in the [`Debugger`](crate::interpreter::debugger::Debugger))
+    /// to the current step
+    pub fn exclude(&self,d:Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.last += d
+    }
+    /// Control sequences and files, sorted by the total time spent in them
+    pub fn report(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.flush();
+        let (commands,files) = state.tables();
+        let head = if state.pass > 1 { format!("pass {}\n\n",state.pass) } else { String::new() };
+        head + &table("control sequence",&commands,true) + "\n" + &table("file",&files,false)
+    }
+    /// One line `file;\outer;...;\inner microseconds` per distinct stack, as used by
+    /// flamegraph tools
+    pub fn collapsed_stacks(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.flush();
+        let mut stacks : Vec<(String,Duration)> = state.nodes.iter().enumerate()
+            .filter(|(_,n)| !n.time.is_zero())
+            .map(|(i,n)| (state.stack(i).join(";"),n.time))
+            .collect();
+        stacks.sort();
+        let mut ret = String::new();
+        for (stack,time) in stacks {
+            ret += &format!("{} {}\n",stack,time.as_micros())
+        }
+        ret
+    }
+}
+
+impl Interpreter<'_> {
+    /// Called before every expansion and every other token processed; records `tk` in the
+    /// [`Profiler`] if there is one
+    pub fn profile_hook(&self,tk:&Token,step:ProfileStep) {
+        if let Some(p) = self.params.profiler() { p.step(tk,step) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::interpreter::Interpreter;
+    use crate::interpreter::params::{DefaultParams, InterpreterParams};
+    use crate::interpreter::state::State;
+    use crate::stomach::colon::NoColon;
+    use super::Profiler;
+
+    #[test]
+    fn deep_recursion() {
+        let (report,stacks,next) = crate::utils::with_stack_size(move || {
+            let mut p = DefaultParams::new(false,true,None);
+            p.profiler = Some(Profiler::new());
+            let text = "\\catcode`\\{=1 \\catcode`\\}=2 \
+                \\def\\a{\\ifnum\\count1>0 \\advance\\count1 -1 \\expandafter\\b\\fi}\
+                \\def\\b{\\ifnum\\count1>0 \\advance\\count1 -1 \\expandafter\\a\\fi}\
+                \\count1=1500 \\a";
+            Interpreter::do_string_with_state(Path::new("/test.tex"),State::new(),text,NoColon::new(),&p);
+            let profiler = p.profiler.as_ref().unwrap();
+            let ret = (profiler.report(),profiler.collapsed_stacks());
+            p.begin_pass(2);
+            (ret.0,ret.1,p.profiler.as_ref().unwrap().report())
+        });
+        assert!(!report.contains("<unknown>"),"{}",report);
+        assert!(report.lines().any(|l| l.starts_with("\\a ") && l.contains(" 751 ")),"{}",report);
+        assert!(stacks.lines().all(|l| l.starts_with("/test.tex")));
+        assert!(stacks.contains(";...;") || stacks.contains(";... "));
+        assert!(next.starts_with("pass 2\n") && !next.contains("\\a"),"{}",next);
+    }
+}
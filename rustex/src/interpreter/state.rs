@@ -1,4 +1,5 @@
 pub mod store;
+pub mod dump;
 
 use ahash::RandomState;
 use std::collections::{HashMap, VecDeque};
@@ -6,18 +7,18 @@ use std::fmt::{Display, Formatter};
 use std::hash::Hash;
 use std::marker::PhantomData;
 use std::ops::Deref;
-use std::path::Path;
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use crate::catcodes::{CategoryCode, CategoryCodeScheme, STARTING_SCHEME};
-use crate::commands::TeXCommand;
+use crate::commands::{DefinitionSite, TeXCommand};
 use crate::fonts::{ArcFont, Font, FontFile, NULL_FONT};
-use crate::interpreter::dimensions::{MuSkip, Skip};
+use crate::interpreter::dimensions::{MuSkip, Skip, MAX_DIMEN};
 use crate::interpreter::mouth::StringMouth;
 use crate::interpreter::TeXMode;
 use crate::stomach::boxes::{BoxMode, TeXBox};
 use crate::stomach::simple::{PDFXForm, PDFXImage};
 use crate::stomach::{Stomach, Whatsit};
-use crate::{Interpreter, log, TeXErr, TeXString, Token};
+use crate::{Interpreter, TeXErr, TeXString, Token};
 use crate::commands::conditionals::conditional_commands;
 use crate::commands::pdftex::pdftex_commands;
 use crate::commands::pgfsvg::pgf_commands;
@@ -25,9 +26,13 @@ use crate::commands::primitives::tex_commands;
 use crate::commands::rustex_specials::rustex_special_commands;
 use crate::utils::{PWD, TeXError, TeXStr};
 use crate::interpreter::files::VFile;
+use crate::ontology::LaTeXFile;
 use crate::interpreter::params::{InterpreterParams, NoOutput};
+use crate::interpreter::diagnostics::Diagnostic;
 use crate::interpreter::state::store::PrimStore;
 use crate::stomach::colon::NoColon;
+use crate::commands::registers::{TRACINGASSIGNS, TRACINGGROUPS};
+use crate::interpreter::tracing::{group_name, group_opener};
 
 
 #[derive(Copy,Clone,PartialEq)]
@@ -76,7 +81,7 @@ pub struct State {
     pub catcodes:store::LinkedCatScheme,
     pub commands: store::StateStore<TeXStr,Option<TeXCommand>,store::RusTeXMap<TeXStr,Option<TeXCommand>>>,
 
-    pub registers_prim: store::StateStore<usize,i32,[i32;79]>,
+    pub registers_prim: store::StateStore<usize,i32,[i32;80]>,
     pub registers: store::StateStore<u16,i32,Vec<i32>>,
     pub dimensions_prim: store::StateStore<usize,i32,[i32;34]>,
     pub dimensions: store::StateStore<u16,i32,Vec<i32>>,
@@ -106,7 +111,13 @@ pub struct State {
 
     // DIRECT ------------------------------------------
     pub(in crate) conditions:Vec<Option<bool>>,
-    pub(in crate) outfiles:store::RusTeXMap<u8,Arc<VFile>>,
+    /// for every entry in `conditions`: the conditional, whether it is `\unless`ed and the
+    /// line it was entered on (for `\showifs`)
+    pub(in crate) condition_types:Vec<(&'static str,bool,usize)>,
+    /// the lines the currently open groups were entered on and the modes they were entered
+    /// in, innermost last
+    pub(in crate) group_lines:Vec<(usize,TeXMode)>,
+    pub(in crate) outfiles:store::RusTeXMap<u8,(Arc<VFile>,Option<PathBuf>)>,
     pub(in crate) infiles:store::RusTeXMap<u8,StringMouth>,
     pub(in crate) incs : u8,
     pub(in crate) mode:TeXMode,
@@ -120,6 +131,8 @@ pub struct State {
     pub(in crate) insetbox:bool,
     pub(in crate) vadjust:Vec<Whatsit>,
     pub (in crate) inserts:store::RusTeXMap<u16,Vec<Whatsit>>,
+    /// `\pagegoal`: `\vsize` minus the space needed for inserts once the current page is
+    /// not empty, [`MAX_DIMEN`] otherwise
     pub(in crate) pagegoal:i32,
     pub(in crate) pdfximages:Vec<PDFXImage>,
     pub(in crate) aligns: Vec<Option<Vec<Token>>>,
@@ -130,6 +143,11 @@ pub struct State {
     pub(in crate) splitbotmark : Vec<Token>,
     // TODO -----------------------------------------
     pub (in crate) filestore:store::RusTeXMap<TeXStr,Arc<VFile>>,
+    pub (in crate) writtenfiles:store::RusTeXMap<TeXStr,Arc<VFile>>,
+    pub (in crate) dependencies:HashMap<PathBuf,Option<String>>,
+    pub (in crate) store_sources:bool,
+    pub (in crate) sources:Option<LaTeXFile>,
+    pub (in crate) store_sites:bool,
 }
 
 macro_rules! pass_on {
@@ -165,7 +183,9 @@ macro_rules! pass_on {
 
     }
 }
-static mut FONT_FILES: Option<store::RusTeXMap<TeXStr,Arc<FontFile>>> = None;
+lazy_static! {
+    static ref FONT_FILES: std::sync::RwLock<store::RusTeXMap<TeXStr,Arc<FontFile>>> = std::sync::RwLock::new(store::RusTeXMap::default());
+}
 
 macro_rules! unwrap {
     ($e:expr) => {
@@ -178,17 +198,11 @@ macro_rules! unwrap {
 
 impl State {
     pub fn push(&mut self,stomach:&mut dyn Stomach,gt:GroupType) {
-        /*if self.stack_depth() > 249 {
-            unsafe {crate::LOG = true}
-            log!("Here!")
-        }*/
-        log!("Push: {} -> {}",gt,self.stack_depth() + 1);
         pass_on!(self,push);
         self.tp.push_v(gt);
         stomach.new_group(gt);
     }
     pub fn pop(&mut self,tp:GroupType) -> Result<Option<Vec<Token>>,TeXError> {
-        log!("Pop: {} -> {}",tp,self.stack_depth());
         match unwrap!(self.tp.ls.front()) {
             t if *t == tp => (),
             t => TeXErr!("Group opened by {} ended by {}",t,tp)
@@ -199,15 +213,76 @@ impl State {
         };
         pass_on!(self,pop);
         self.tp.pop();
+        self.group_lines.pop();
         Ok(ag)
     }
+    /// `\interactionmode`: 0 = `\batchmode`, 1 = `\nonstopmode`, 2 = `\scrollmode`, 3 = `\errorstopmode`
+    pub fn interaction_mode(&self) -> i32 {
+        self.registers_prim.get(&(crate::commands::registers::INTERACTIONMODE.index - 1))
+    }
+    pub fn set_interaction_mode(&mut self,mode:i32,globally:bool) {
+        self.registers_prim.set(crate::commands::registers::INTERACTIONMODE.index - 1,mode,globally)
+    }
+    /// Whether jobs processed with this state record the [`LaTeXFile`] source model of
+    /// their input (see [`State::sources`]); off by default, since it keeps every token
+    pub fn set_store_sources(&mut self,store:bool) {
+        self.store_sources = store
+    }
+    /// Whether (re)definitions record where they happened (see [`State::defined_at`]); off by
+    /// default, since it costs a lookup of the current position on every assignment
+    pub fn set_store_sites(&mut self,store:bool) {
+        self.store_sites = store
+    }
+    /// The source model of the last job processed with this state, i.e. all tokens and
+    /// comments of the main file, with the files it `\input`s nested in it; `None` unless
+    /// enabled via [`State::set_store_sources`]
+    pub fn sources(&self) -> Option<&LaTeXFile> {
+        self.sources.as_ref()
+    }
+    pub fn take_sources(&mut self) -> Option<LaTeXFile> {
+        self.sources.take()
+    }
+    /// where the command `name` was last (re)defined, if it is defined and the site is known
+    /// (i.e. recorded, see [`State::set_store_sites`])
+    pub fn defined_at(&self,name:&TeXStr) -> Option<Arc<DefinitionSite>> {
+        self.commands.get(name).and_then(|c| c.defined_at)
+    }
+    /// The group stack, innermost group first, as printed by `\showgroups`
+    pub fn show_groups(&self) -> String {
+        let level = self.stack_depth();
+        let mut ret = "\n".to_string();
+        // the last entry is the bottom level
+        for (i,tp) in self.tp.ls.iter().take(level).enumerate() {
+            let (line,mode) = self.group_lines.get(level - i - 1).copied().unwrap_or((0,TeXMode::Vertical));
+            ret += &format!("### {} group (level {})",group_name(tp,mode),level - i);
+            if line != 0 { ret += &format!(" entered at line {}",line) }
+            ret += &format!(" ({})\n",group_opener(tp,mode))
+        }
+        ret + "### bottom level\n"
+    }
+    /// The currently open conditionals, innermost first, as printed by `\showifs`
+    pub fn show_ifs(&self) -> String {
+        if self.condition_types.is_empty() {
+            return "\n### no active conditionals\n".to_string()
+        }
+        let mut ret = "\n".to_string();
+        for (i,(name,unless,line)) in self.condition_types.iter().enumerate().rev() {
+            ret += &format!("### level {}: {}\\{}",i + 1,if *unless {"\\unless"} else {""},name);
+            if *line != 0 { ret += &format!(" entered on line {}",line) }
+            ret.push('\n')
+        }
+        ret
+    }
     pub fn stack_depth(&self) -> usize {
         let mut curr = &self.tp;
         curr.ls.len() - 1
     }
-    pub fn new() -> State {
-        let mut state = State {
+    /// A state without any commands and with all registers and codes zero
+    fn empty() -> State {
+        State {
             conditions:vec!(),
+            condition_types:vec!(),
+            group_lines:vec!(),
             outfiles:store::RusTeXMap::default(),
             infiles:store::RusTeXMap::default(),
             incs:0,
@@ -222,7 +297,7 @@ impl State {
             insetbox:false,
             vadjust:vec!(),
             inserts:store::RusTeXMap::default(),
-            pagegoal:0,
+            pagegoal:MAX_DIMEN,
             pdfximages:vec!(),
             aligns:vec!(),
             topmark:vec!(),
@@ -261,8 +336,16 @@ impl State {
             hangafter: Default::default(),
             // TODO...
             filestore: Default::default(),
+            writtenfiles: Default::default(),
+            dependencies: Default::default(),
+            store_sources: false,
+            store_sites: false,
+            sources: None,
             //fontfiles: Default::default()
-        };
+        }
+    }
+    pub fn new() -> State {
+        let mut state = State::empty();
         for c in conditional_commands() {
             let c = c.as_command();
             state.commands.set_locally(unsafe {c.name().unwrap_unchecked()},Some(c))
@@ -277,7 +360,9 @@ impl State {
         }
         state.registers_prim.set_locally((crate::commands::registers::MAG.index -1) as usize,1000);
         state.registers_prim.set_locally((crate::commands::registers::FAM.index -1) as usize,-1);
+        state.registers_prim.set_locally((crate::commands::registers::INTERACTIONMODE.index -1) as usize,3);
         state.dimensions_prim.set_locally((crate::commands::registers::PDFPXDIMEN.index - 1) as usize,65536);
+        state.skips_prim.set_locally(crate::commands::registers::PREVDEPTH.index - 1,crate::interpreter::dimensions::IGNORE_DEPTH);
         for i in 0..=255 {
             state.uccodes.set_locally(i,i);
             state.lccodes.set_locally(i,i);
@@ -305,13 +390,27 @@ impl State {
         }
         state
     }
+    /// The state after processing `pdftexconfig.tex` and `latex.ltx`; panics if there is no
+    /// TeX installation (see [`State::try_pdf_latex`])
     pub fn pdf_latex() -> State {
+        match State::try_pdf_latex() {
+            Ok(s) => s,
+            Err(e) => panic!("{}",e.msg)
+        }
+    }
+    /// The state after processing `pdftexconfig.tex` and `latex.ltx`, or an error if there is no
+    /// TeX installation containing them
+    pub fn try_pdf_latex() -> Result<State,TeXError> {
         crate::utils::with_stack_size(|| {
-            use crate::interpreter::params::DefaultParams;
-            let mut state = State::new();
-            let pdftex_cfg = crate::kpathsea::kpsewhich("pdftexconfig.tex", &PWD).expect("pdftexconfig.tex not found").0;
-            let latex_ltx = crate::kpathsea::kpsewhich("latex.ltx", &PWD).expect("No latex.ltx found").0;
+            if let Err(e) = crate::kpathsea::check_installation() { TeXErr!("{}",e) }
             let p = /* DefaultParams::new(false,false,None); // */ NoOutput::new(None);
+            let find = |name:&str| match p.filesystem().resolve(name, &PWD) {
+                Some((f,_)) if p.filesystem().is_file(&f) => Ok(f),
+                _ => TeXErr!("{} not found in the TeX installation",name)
+            };
+            let pdftex_cfg = find("pdftexconfig.tex")?;
+            let latex_ltx = find("latex.ltx")?;
+            let mut state = State::new();
 
             for c in pdftex_commands() {
                 let c = c.as_command();
@@ -328,7 +427,7 @@ impl State {
                 let c = c.as_command();
                 state.commands.set_locally(unsafe {c.name().unwrap_unchecked()}, Some(c))
             }
-            state
+            Ok(state)
         })
     }
     pub fn file_read_line(&mut self,index:u8) -> Result<Vec<Token>,TeXError> {
@@ -359,7 +458,8 @@ impl State {
     }
     pub fn file_eof(&mut self,index:u8) -> Result<bool,TeXError> {
         match self.infiles.get_mut(&index) {
-            None => TeXErr!("No file open at index {}",index),
+            // as in TeX, a stream that is not open is at its end
+            None => Ok(true),
             Some(fm) => {
                 Ok(fm.is_eof())
             }
@@ -379,12 +479,20 @@ impl State {
         }
         Ok(())
     }
-    pub fn file_openout(&mut self,index:u8,file:Arc<VFile>) -> Result<(),TeXError> {
+    pub fn file_openout(&mut self,index:u8,file:Arc<VFile>,target:Option<PathBuf>,params:&dyn InterpreterParams) -> Result<(),TeXError> {
+        self.file_closeout(index,params)?;
         file.string.write().unwrap().take();
-        self.outfiles.insert(index,file);
+        self.writtenfiles.insert(file.id.clone(),file.clone());
+        self.outfiles.insert(index,(file,target));
         Ok(())
     }
-    pub fn file_write(&mut self,index:u8,s:TeXString,params:&dyn InterpreterParams) -> Result<(),TeXError> {
+    /// `\write`; shell commands (`\write18`) are run in `jobdir`
+    pub fn file_write(&mut self,index:u8,s:TeXString,params:&dyn InterpreterParams,jobdir:&Path) -> Result<(),TeXError> {
+        if index != 18 && (index == 16 || index == 17 || !self.outfiles.contains_key(&index)) {
+            if let Some(d) = Diagnostic::from_write(s.to_utf8().as_str()) {
+                params.diagnostic(d)
+            }
+        }
         match index {
             17 => {
                 params.write_17(s.to_utf8().as_str());
@@ -395,7 +503,23 @@ impl State {
                 Ok(())
             }
             18 => {
-                params.write_18(s.to_utf8().as_str());
+                let cmd = s.to_utf8();
+                params.write_18(cmd.as_str());
+                if params.shell_escape() {
+                    let mut shell = if cfg!(windows) {
+                        let mut c = std::process::Command::new("cmd");
+                        c.arg("/C");
+                        c
+                    } else {
+                        let mut c = std::process::Command::new("sh");
+                        c.arg("-c");
+                        c
+                    };
+                    match shell.arg(&cmd).current_dir(jobdir).status() {
+                        Ok(_) => (),
+                        Err(e) => TeXErr!("runsystem({}) failed: {}",cmd,e)
+                    }
+                }
                 Ok(())
             }
             255 => {
@@ -408,7 +532,7 @@ impl State {
             }
             _ => {
                 match self.outfiles.get_mut(&index) {
-                    Some(f) => {
+                    Some((f,_)) => {
                         let mut string = f.string.write().unwrap();
                         match &mut*string {
                             None => {*string = Some(s) },
@@ -422,44 +546,75 @@ impl State {
         }
     }
     pub fn get_font(&mut self,indir:&Path,name:TeXStr,params:&dyn InterpreterParams) -> Result<Arc<FontFile>,TeXError> {
-        unsafe {
-            match FONT_FILES {
-                None => FONT_FILES = Some(store::RusTeXMap::default()),
-                _ => ()
+        if let Some(ff) = FONT_FILES.read().unwrap().get(&name) {
+            self.add_font_dependency(ff);
+            return Ok(Arc::clone(ff))
+        }
+        let fs = params.filesystem();
+        let ret = fs.resolve(unsafe { std::str::from_utf8_unchecked(name.iter()) },indir);
+        match ret {
+            Some((pb,_)) if fs.is_file(&pb) => {
+                let f = Arc::new(FontFile::new(pb,params));
+                let f = Arc::clone(FONT_FILES.write().unwrap().entry(name).or_insert(f));
+                self.add_font_dependency(&f);
+                Ok(f)
             }
-            match FONT_FILES.as_ref().unwrap().get(&name) {
-                Some(ff) => Ok(Arc::clone(ff)),
-                None => {
-                    let ret = crate::kpathsea::kpsewhich(std::str::from_utf8_unchecked(name.iter()),indir);
-                    match ret {
-                        Some((pb,_)) if pb.exists() => {
-                            let f = Arc::new(FontFile::new(pb,params));
-                            FONT_FILES.as_mut().unwrap().insert(name, Arc::clone(&f));
-                            Ok(f)
-                        }
-                        _ => {
-                            //println!("Here! {}", self.current_line());
-                            TeXErr!("Font file {} not found",name)
-                        }
-                    }
-                }
+            _ => {
+                //println!("Here! {}", self.current_line());
+                TeXErr!("Font file {} not found",name)
             }
         }
     }
-    pub fn file_closeout(&mut self,index:u8) {
-        self.outfiles.remove(&index);
+    pub fn file_closeout(&mut self,index:u8,params:&dyn InterpreterParams) -> Result<(),TeXError> {
+        match self.outfiles.remove(&index) {
+            Some((f,Some(target))) => match f.write_to(&target,params.filesystem()) {
+                Ok(_) => Ok(()),
+                Err(e) => TeXErr!("Could not write file {}: {}",target.display(),e)
+            }
+            _ => Ok(())
+        }
+    }
+    /// the current contents of all files opened via `\openout` in this state
+    pub fn written_files(&self) -> HashMap<TeXStr,Option<TeXString>> {
+        self.writtenfiles.iter().map(|(k,f)| (k.clone(),f.string.read().unwrap().clone())).collect()
+    }
+    /// makes the files written in a previous run (see [`State::written_files`]) available
+    /// for reading, overriding whatever is on disk
+    pub fn add_written_files(&mut self,old:&State) {
+        for (id,f) in old.writtenfiles.iter() {
+            let vf = Arc::new(VFile {
+                source:f.source.clone(),
+                string:Arc::new(RwLock::new(f.string.read().unwrap().clone())),
+                id:id.clone()
+            });
+            self.filestore.insert(id.clone(),vf);
+        }
+    }
+    /// closes all files still open for writing, e.g. at the end of the job
+    pub fn file_closeout_all(&mut self,params:&dyn InterpreterParams) -> Result<(),TeXError> {
+        let indices : Vec<u8> = self.outfiles.keys().copied().collect();
+        for i in indices { self.file_closeout(i,params)? }
+        Ok(())
     }
 }
 
 impl Interpreter<'_> {
-    pub fn push_condition(&mut self,cond : Option<bool>) {
+    pub fn push_condition(&mut self,cond : Option<bool>,name:&'static str,unless:bool) {
         //println!("CONDITION: ({}",self.current_line());
+        let line = self.mouths.line_no().0;
+        self.state.condition_types.push((name,unless,line));
         self.state.conditions.push(cond)
     }
     pub fn pop_condition(&mut self) -> Option<bool> {
         //println!("CONDITION: {})",self.current_line());
+        self.state.condition_types.pop();
         self.state.conditions.pop().unwrap()
     }
+    /// The current position in the current file, as the site of a definition by `primitive`
+    pub fn definition_site(&self,primitive:Option<TeXStr>) -> DefinitionSite {
+        let (line,column) = self.mouths.line_no();
+        DefinitionSite { file:self.current_file(),line,column,primitive }
+    }
     pub fn change_command(&mut self,cmdname:TeXStr,proc:Option<TeXCommand>,globally:bool) {
         /*if cmdname.to_string() == "prentry@@norm" {//(cmdname == TeXStr::new(&[0,1,2,3,4,255,254,253,252,251,95])) {//"scr@dte@chapter@init" || cmdname.to_string() == "scr@dte@tocline@init") {
             println!("Here! {}\n{}\n{}",cmdname,proc.as_ref().unwrap().meaning(&crate::catcodes::DEFAULT_SCHEME),self.current_line());
@@ -467,18 +622,43 @@ impl Interpreter<'_> {
         }*/
         let file = self.current_file();
         let line = self.mouths.current_line();
+        let proc = match proc {
+            Some(p) if self.state.store_sites => Some(p.with_site(match &self.assigning {
+                Some(site) => site.clone(),
+                None => self.definition_site(None)
+            })),
+            p => p
+        };
+        let mut proc = proc;
         for cl in self.params.command_listeners() {
             match cl.apply(&cmdname,&proc,&file,&line,&mut self.state) {
                 Some(r) => {
-                    self.state.commands.set(cmdname,r,globally);
-                    return ()
+                    proc = r;
+                    break
                 },
                 _ => ()
             }
         }
+        if self.tracing(&TRACINGASSIGNS,0) {
+            let scheme = self.state.catcodes.get_scheme();
+            let meaning = |c:&Option<TeXCommand>| match c {
+                Some(c) => c.meaning(scheme).to_string(),
+                None => "undefined".to_string()
+            };
+            self.trace_assign(&cmdname.to_string(),meaning(&self.state.commands.get(&cmdname)),meaning(&proc),globally)
+        }
         self.state.commands.set(cmdname,proc,globally)
     }
+    /// Opens a new group of type `gt`
+    pub fn push_group(&mut self,gt:GroupType) -> Result<(),TeXError> {
+        self.check_group_depth()?;
+        self.state.push(self.stomach,gt);
+        self.state.group_lines.push((self.mouths.line_no().0,self.state.mode));
+        if self.tracing(&TRACINGGROUPS,0) { self.trace_group(true) }
+        Ok(())
+    }
     pub fn pop_group(&mut self,tp:GroupType) -> Result<(),TeXError> {
+        if self.tracing(&TRACINGGROUPS,0) { self.trace_group(false) }
         let ag = self.state.pop(tp)?;
         match ag {
             Some(v) => self.push_tokens(v),
@@ -488,6 +668,7 @@ impl Interpreter<'_> {
     }
     pub fn get_whatsit_group(&mut self,tp:GroupType) -> Result<Vec<Whatsit>,TeXError> {
         let ret = self.stomach.pop_group(&mut self.state)?;
+        if self.tracing(&TRACINGGROUPS,0) { self.trace_group(false) }
         let ag = self.state.pop(tp)?;
         match ag {
             Some(v) => self.push_tokens(v),
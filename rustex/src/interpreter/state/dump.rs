@@ -0,0 +1,732 @@
+/*! Format files: a binary serialization of a [`State`] (like pdfTeX's `.fmt` files), so that e.g.
+the state after processing `latex.ltx` (or a project's preamble) can be loaded in milliseconds
+instead of being recomputed on every start.
+
+What is stored: the catcode scheme, all commands (including their
+definition sites), all (primitive and numbered) registers,
+dimensions, skips, muskips and token registers, the sf/lc/uc/math/delcodes, `\parshape`,
+`\hangindent`, `\hangafter`, the current text/script/scriptscript fonts, the marks, the pdf color
+stacks and the contents of `\pdfobj`s. Fonts are stored by name and reloaded via kpathsea.
+External commands ([`PrimitiveTeXCommand::Ext`]) are stored by name and have to be passed to
+[`State::undump_with`] again.
+
+Dumping fails (rather than silently dropping anything) if the state is inside a group or has
+non-void box registers, open files, pending inserts or `\vadjust`s, or `\pdfxform`s or
+`\pdfximage`s.
+*/
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use crate::catcodes::CategoryCode;
+use crate::filesystem::FileSystem;
+use crate::commands::{AssignableValue, DefMacro, DefinitionSite, ExternalCommand, ParamToken, PrimitiveTeXCommand, Signature, TeXCommand};
+use crate::fonts::{ArcFont, Font};
+use crate::interpreter::dimensions::{MuSkip, MuSkipDim, Skip, SkipDim};
+use crate::interpreter::params::InterpreterParams;
+use crate::interpreter::state::State;
+use crate::ontology::{trivial_name, Token};
+use crate::stomach::boxes::TeXBox;
+use crate::utils::{PWD, TeXError, TeXStr};
+use crate::TeXErr;
+
+static MAGIC : &[u8] = b"RUSTEXFMT";
+/// Bumped (after the `+`) whenever the layout of format files changes
+static VERSION : &str = concat!(env!("CARGO_PKG_VERSION"),"+2");
+
+impl State {
+    /// Writes this state as a format file to `w`; see [the module documentation](self).
+    pub fn dump<W:Write>(&self,w:&mut W) -> Result<(),TeXError> {
+        self.check_dumpable()?;
+        let mut d = Dumper::default();
+        d.state(self)?;
+        let mut ret : Vec<u8> = Vec::with_capacity(d.body.len() + 1024);
+        ret.extend_from_slice(MAGIC);
+        let version = VERSION.as_bytes();
+        write_u32(&mut ret,version.len() as u32);
+        ret.extend_from_slice(version);
+        write_u32(&mut ret,d.strings.len() as u32);
+        for s in &d.strings {
+            write_u32(&mut ret,s.len() as u32);
+            ret.extend_from_slice(s.iter())
+        }
+        ret.append(&mut d.body);
+        match w.write_all(&ret) {
+            Ok(_) => Ok(()),
+            Err(e) => TeXErr!("Error writing format file: {}",e)
+        }
+    }
+    /// Fails if this state contains anything that can not be stored in a format file
+    fn check_dumpable(&self) -> Result<(),TeXError> {
+        if self.stack_depth() != 0 {
+            TeXErr!("Can not dump a state inside a group (level {})",self.stack_depth())
+        }
+        if let Some(i) = self.boxes.base().iter().position(|b| !matches!(b,TeXBox::Void)) {
+            TeXErr!("Can not dump a state with non-void box registers (\\box{})",i)
+        }
+        if let Some(i) = self.outfiles.keys().chain(self.infiles.keys()).next() {
+            TeXErr!("Can not dump a state with open files (stream {})",i)
+        }
+        if !self.inserts.is_empty() || !self.vadjust.is_empty() {
+            TeXErr!("Can not dump a state with pending inserts or \\vadjusts")
+        }
+        if !self.pdfxforms.is_empty() || !self.pdfximages.is_empty() {
+            TeXErr!("Can not dump a state with \\pdfxforms or \\pdfximages")
+        }
+        Ok(())
+    }
+    /// Writes this state as a format file to `p` in `fs`
+    pub fn dump_to_file(&self,p:&Path,fs:&dyn FileSystem) -> Result<(),TeXError> {
+        let mut data : Vec<u8> = vec!();
+        self.dump(&mut data)?;
+        match fs.write(p,&data) {
+            Ok(_) => Ok(()),
+            Err(e) => TeXErr!("Could not write format file {}: {}",p.display(),e)
+        }
+    }
+    /// Reads a state from a format file previously written by [`State::dump`]. Fails if the file
+    /// was written by a different version of RusTeX or contains external commands.
+    pub fn undump<R:Read>(r:&mut R,params:&dyn InterpreterParams) -> Result<State,TeXError> {
+        State::undump_with(r,params,vec!())
+    }
+    /// Like [`State::undump`], with the external commands the format file refers to
+    pub fn undump_with<R:Read>(r:&mut R,params:&dyn InterpreterParams,externals:Vec<Arc<dyn ExternalCommand>>) -> Result<State,TeXError> {
+        let mut data : Vec<u8> = vec!();
+        if let Err(e) = r.read_to_end(&mut data) {
+            TeXErr!("Error reading format file: {}",e)
+        }
+        let externals = externals.into_iter().map(|e| (TeXStr::from(e.name().as_str()),e)).collect();
+        let mut u = Undumper { data, pos:0, strings:vec!(), fonts:vec!(), builtins:builtin_commands(), externals };
+        if u.bytes(MAGIC.len())? != MAGIC {
+            TeXErr!("Not a RusTeX format file")
+        }
+        let len = u.u32()? as usize;
+        let version = u.bytes(len)?;
+        if version != VERSION.as_bytes() {
+            TeXErr!("Format file was written by RusTeX version {}",TeXStr::new(version))
+        }
+        let len = u.u32()?;
+        for _ in 0..len {
+            let l = u.u32()? as usize;
+            let s = TeXStr::new(u.bytes(l)?);
+            u.strings.push(s)
+        }
+        u.state(params)
+    }
+    /// Reads a state from the format file `p` in the [`FileSystem`] of `params`
+    pub fn from_format_file(p:&Path,params:&dyn InterpreterParams) -> Result<State,TeXError> {
+        let data = match params.filesystem().read(p) {
+            Ok(d) => d,
+            Err(e) => TeXErr!("Could not open format file {}: {}",p.display(),e)
+        };
+        State::undump(&mut data.as_slice(),params)
+    }
+}
+
+fn builtin_commands() -> HashMap<TeXStr,PrimitiveTeXCommand> {
+    use crate::commands::conditionals::conditional_commands;
+    use crate::commands::pdftex::pdftex_commands;
+    use crate::commands::pgfsvg::pgf_commands;
+    use crate::commands::primitives::tex_commands;
+    use crate::commands::rustex_specials::rustex_special_commands;
+    let mut ret = HashMap::new();
+    for c in conditional_commands().into_iter().chain(tex_commands()).chain(pdftex_commands())
+        .chain(rustex_special_commands()).chain(pgf_commands()) {
+        if let Some(n) = c.name() { ret.insert(n,c); }
+    }
+    ret
+}
+
+// Writing ----------------------------------------------------------------------------------------
+
+fn write_u32(v:&mut Vec<u8>,i:u32) { v.extend_from_slice(&i.to_le_bytes()) }
+
+#[derive(Default)]
+struct Dumper {
+    body:Vec<u8>,
+    strings:Vec<TeXStr>,
+    stringmap:HashMap<TeXStr,u32>,
+    fonts:Vec<ArcFont>
+}
+impl Dumper {
+    fn u8(&mut self,u:u8) { self.body.push(u) }
+    fn bool(&mut self,b:bool) { self.body.push(b as u8) }
+    fn u16(&mut self,u:u16) { self.body.extend_from_slice(&u.to_le_bytes()) }
+    fn u32(&mut self,u:u32) { write_u32(&mut self.body,u) }
+    fn i32(&mut self,i:i32) { self.body.extend_from_slice(&i.to_le_bytes()) }
+    fn str(&mut self,s:&TeXStr) {
+        let i = match self.stringmap.get(s) {
+            Some(i) => *i,
+            None => {
+                let i = self.strings.len() as u32;
+                self.strings.push(s.clone());
+                self.stringmap.insert(s.clone(),i);
+                i
+            }
+        };
+        self.u32(i)
+    }
+    fn token(&mut self,tk:&Token) {
+        self.u8(tk.char);
+        self.u8(tk.catcode.toint());
+        self.bool(tk.expand);
+        if tk.name_opt == trivial_name(tk.char) { self.bool(false) } else {
+            self.bool(true);
+            self.str(&tk.name_opt)
+        }
+    }
+    fn tokens(&mut self,tks:&Vec<Token>) {
+        self.u32(tks.len() as u32);
+        for t in tks { self.token(t) }
+    }
+    fn skipdim(&mut self,sd:&Option<SkipDim>) {
+        match sd {
+            None => self.u8(0),
+            Some(SkipDim::Pt(i)) => {self.u8(1);self.i32(*i)}
+            Some(SkipDim::Fil(i)) => {self.u8(2);self.i32(*i)}
+            Some(SkipDim::Fill(i)) => {self.u8(3);self.i32(*i)}
+            Some(SkipDim::Filll(i)) => {self.u8(4);self.i32(*i)}
+        }
+    }
+    fn skip(&mut self,s:&Skip) {
+        self.i32(s.base);
+        self.skipdim(&s.stretch);
+        self.skipdim(&s.shrink)
+    }
+    fn muskipdim(&mut self,sd:&Option<MuSkipDim>) {
+        match sd {
+            None => self.u8(0),
+            Some(MuSkipDim::Mu(i)) => {self.u8(1);self.i32(*i)}
+            Some(MuSkipDim::Fil(i)) => {self.u8(2);self.i32(*i)}
+            Some(MuSkipDim::Fill(i)) => {self.u8(3);self.i32(*i)}
+            Some(MuSkipDim::Filll(i)) => {self.u8(4);self.i32(*i)}
+        }
+    }
+    fn muskip(&mut self,s:&MuSkip) {
+        self.i32(s.base);
+        self.muskipdim(&s.stretch);
+        self.muskipdim(&s.shrink)
+    }
+    fn font(&mut self,f:&ArcFont) {
+        let i = match self.fonts.iter().position(|x| Arc::ptr_eq(x,f)) {
+            Some(i) => i,
+            None => {
+                self.fonts.push(f.clone());
+                self.fonts.len() - 1
+            }
+        };
+        self.u32(i as u32)
+    }
+    fn command(&mut self,cmd:&Option<TeXCommand>) -> Result<(),TeXError> {
+        use PrimitiveTeXCommand::*;
+        let cmd = match cmd {
+            None => {self.u8(0);return Ok(())}
+            Some(c) => c
+        };
+        match &*cmd.orig {
+            Char(tk) => {self.u8(2);self.token(tk)}
+            MathChar(i) => {self.u8(3);self.u32(*i)}
+            Def(d) => {
+                self.u8(4);
+                self.bool(d.protected);
+                self.bool(d.long);
+                self.bool(d.sig.endswithbrace);
+                self.u8(d.sig.arity);
+                self.u32(d.sig.elems.len() as u32);
+                for e in &d.sig.elems { match e {
+                    ParamToken::Param(i) => {self.u8(0);self.u8(*i)}
+                    ParamToken::Token(tk) => {self.u8(1);self.token(tk)}
+                }}
+                self.tokens(&d.ret)
+            }
+            AV(AssignableValue::Dim(i)) => {self.u8(5);self.u16(*i)}
+            AV(AssignableValue::Register(i)) => {self.u8(6);self.u16(*i)}
+            AV(AssignableValue::Skip(i)) => {self.u8(7);self.u16(*i)}
+            AV(AssignableValue::MuSkip(i)) => {self.u8(8);self.u16(*i)}
+            AV(AssignableValue::Toks(i)) => {self.u8(9);self.u16(*i)}
+            AV(AssignableValue::FontRef(f)) => {self.u8(10);self.font(f)}
+            Ext(e) => {self.u8(11);self.str(&e.name().as_str().into())}
+            p => match p.name() {
+                Some(n) => {self.u8(1);self.str(&n)}
+                None => TeXErr!("Can not dump command {}",p)
+            }
+        }
+        match &cmd.defined_at {
+            None => self.bool(false),
+            Some(site) => {
+                self.bool(true);
+                self.str(&site.file);
+                self.u32(site.line as u32);
+                self.u32(site.column as u32);
+                match &site.primitive {
+                    None => self.bool(false),
+                    Some(p) => {self.bool(true);self.str(p)}
+                }
+            }
+        }
+        Ok(())
+    }
+    fn state(&mut self,state:&State) -> Result<(),TeXError> {
+        let scheme = state.catcodes.get_scheme();
+        for c in scheme.catcodes.iter() { self.u8(c.toint()) }
+        self.u8(scheme.newlinechar);
+        self.u8(scheme.endlinechar);
+        self.u8(scheme.escapechar);
+
+        let commands = state.commands.base();
+        self.u32(commands.len() as u32);
+        for (n,c) in commands.iter() {
+            self.str(n);
+            self.command(c)?
+        }
+
+        for i in 0..state.registers_prim.base().len() { self.i32(state.registers_prim.get(&i)) }
+        self.u32(state.registers.base().len() as u32);
+        for i in state.registers.base() { self.i32(*i) }
+        for i in 0..state.dimensions_prim.base().len() { self.i32(state.dimensions_prim.get(&i)) }
+        self.u32(state.dimensions.base().len() as u32);
+        for i in state.dimensions.base() { self.i32(*i) }
+        for i in 0..state.skips_prim.base().len() { self.skip(&state.skips_prim.get(&i)) }
+        self.u32(state.skips.base().len() as u32);
+        for s in state.skips.base() { self.skip(s) }
+        for i in 0..state.muskips_prim.base().len() { self.muskip(&state.muskips_prim.get(&i)) }
+        self.u32(state.muskips.base().len() as u32);
+        for s in state.muskips.base() { self.muskip(s) }
+        for i in 0..state.toks_prim.base().len() { self.tokens(&state.toks_prim.get(&i)) }
+        self.u32(state.toks.base().len() as u32);
+        for t in state.toks.base() { self.tokens(t) }
+
+        for i in 0..=255 {
+            self.i32(state.sfcodes.get(&i));
+            self.u8(state.lccodes.get(&i));
+            self.u8(state.uccodes.get(&i));
+            self.i32(state.mathcodes.get(&i));
+            self.i32(state.delcodes.get(&i));
+        }
+        let parshape = state.parshape.get();
+        self.u32(parshape.len() as u32);
+        for (a,b) in parshape { self.i32(a);self.i32(b) }
+        self.i32(state.hangindent.get());
+        self.u32(state.hangafter.get() as u32);
+
+        self.font(&state.currfont.get());
+        for i in 0..16 {
+            self.font(&state.textfonts.get(&i));
+            self.font(&state.scriptfonts.get(&i));
+            self.font(&state.scriptscriptfonts.get(&i));
+        }
+
+        for m in [&state.topmark,&state.firstmark,&state.botmark,&state.splitfirstmark,&state.splitbotmark] {
+            self.tokens(m)
+        }
+        self.u32(state.pdfcolorstacks.len() as u32);
+        for stack in &state.pdfcolorstacks {
+            self.u32(stack.len() as u32);
+            for c in stack { self.str(c) }
+        }
+        self.u32(state.pdfobjs.len() as u32);
+        for (i,o) in state.pdfobjs.iter() {self.u16(*i);self.str(o)}
+
+        // the font table is only complete now, but has to be read first
+        let rest = std::mem::take(&mut self.body);
+        let fonts = std::mem::take(&mut self.fonts);
+        self.u32(fonts.len() as u32);
+        for f in fonts {
+            if f.file.filepath.iter() == b"NULL" || f.file.filepath.iter() == b"CUSTOM" {
+                self.u8(0);
+                continue
+            }
+            self.u8(1);
+            self.str(&(f.file.name.to_string() + ".tfm").into());
+            self.str(&f.name);
+            match f.at {
+                None => self.bool(false),
+                Some(at) => {self.bool(true);self.i32(at)}
+            }
+            let inner = f.inner.read().unwrap();
+            self.u16(inner.hyphenchar);
+            self.u16(inner.skewchar);
+            self.u32(inner.dimen.len() as u32);
+            for (k,v) in inner.dimen.iter() {self.u16(*k);self.i32(*v)}
+            self.u32(inner.lps.len() as u32);
+            for (k,v) in inner.lps.iter() {self.u16(*k);self.u8(*v)}
+            self.u32(inner.rps.len() as u32);
+            for (k,v) in inner.rps.iter() {self.u16(*k);self.u8(*v)}
+        }
+        self.body.extend(rest);
+        Ok(())
+    }
+}
+
+// Reading ----------------------------------------------------------------------------------------
+
+struct Undumper {
+    data:Vec<u8>,
+    pos:usize,
+    strings:Vec<TeXStr>,
+    fonts:Vec<ArcFont>,
+    builtins:HashMap<TeXStr,PrimitiveTeXCommand>,
+    externals:HashMap<TeXStr,Arc<dyn ExternalCommand>>
+}
+impl Undumper {
+    fn bytes(&mut self,len:usize) -> Result<&[u8],TeXError> {
+        if self.pos + len > self.data.len() {
+            TeXErr!("Format file ended unexpectedly")
+        }
+        self.pos += len;
+        Ok(&self.data[self.pos - len..self.pos])
+    }
+    fn u8(&mut self) -> Result<u8,TeXError> { Ok(self.bytes(1)?[0]) }
+    fn bool(&mut self) -> Result<bool,TeXError> { Ok(self.u8()? != 0) }
+    fn u16(&mut self) -> Result<u16,TeXError> { Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap())) }
+    fn u32(&mut self) -> Result<u32,TeXError> { Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap())) }
+    fn i32(&mut self) -> Result<i32,TeXError> { Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap())) }
+    fn str(&mut self) -> Result<TeXStr,TeXError> {
+        let i = self.u32()? as usize;
+        match self.strings.get(i) {
+            Some(s) => Ok(s.clone()),
+            None => TeXErr!("Malformed format file: string index {} out of range",i)
+        }
+    }
+    fn catcode(&mut self) -> Result<CategoryCode,TeXError> {
+        match self.u8()? {
+            i if i < 16 => Ok(CategoryCode::fromint(i as i32)),
+            i => TeXErr!("Malformed format file: invalid category code {}",i)
+        }
+    }
+    fn token(&mut self) -> Result<Token,TeXError> {
+        let char = self.u8()?;
+        let catcode = self.catcode()?;
+        let expand = self.bool()?;
+        let name = if self.bool()? { Some(self.str()?) } else { None };
+        Ok(Token::new(char,catcode,name,None,expand))
+    }
+    fn tokens(&mut self) -> Result<Vec<Token>,TeXError> {
+        let len = self.u32()? as usize;
+        let mut ret = Vec::with_capacity(len);
+        for _ in 0..len { ret.push(self.token()?) }
+        Ok(ret)
+    }
+    fn skipdim(&mut self) -> Result<Option<SkipDim>,TeXError> {
+        Ok(match self.u8()? {
+            0 => None,
+            1 => Some(SkipDim::Pt(self.i32()?)),
+            2 => Some(SkipDim::Fil(self.i32()?)),
+            3 => Some(SkipDim::Fill(self.i32()?)),
+            4 => Some(SkipDim::Filll(self.i32()?)),
+            i => TeXErr!("Malformed format file: invalid skip {}",i)
+        })
+    }
+    fn skip(&mut self) -> Result<Skip,TeXError> {
+        Ok(Skip { base:self.i32()?, stretch:self.skipdim()?, shrink:self.skipdim()? })
+    }
+    fn muskipdim(&mut self) -> Result<Option<MuSkipDim>,TeXError> {
+        Ok(match self.u8()? {
+            0 => None,
+            1 => Some(MuSkipDim::Mu(self.i32()?)),
+            2 => Some(MuSkipDim::Fil(self.i32()?)),
+            3 => Some(MuSkipDim::Fill(self.i32()?)),
+            4 => Some(MuSkipDim::Filll(self.i32()?)),
+            i => TeXErr!("Malformed format file: invalid muskip {}",i)
+        })
+    }
+    fn muskip(&mut self) -> Result<MuSkip,TeXError> {
+        Ok(MuSkip { base:self.i32()?, stretch:self.muskipdim()?, shrink:self.muskipdim()? })
+    }
+    fn font(&mut self) -> Result<ArcFont,TeXError> {
+        let i = self.u32()? as usize;
+        match self.fonts.get(i) {
+            Some(f) => Ok(f.clone()),
+            None => TeXErr!("Malformed format file: font index {} out of range",i)
+        }
+    }
+    fn command(&mut self) -> Result<Option<TeXCommand>,TeXError> {
+        use PrimitiveTeXCommand::*;
+        let ret = match self.u8()? {
+            0 => return Ok(None),
+            1 => {
+                let name = self.str()?;
+                match self.builtins.get(&name) {
+                    Some(Primitive(p)) => Primitive(p),
+                    Some(AV(av)) => AV(av.clone()),
+                    Some(Cond(c)) => Cond(c),
+                    Some(Num(n)) => Num(n),
+                    Some(Ass(a)) => Ass(a),
+                    Some(Whatsit(w)) => Whatsit(w.clone()),
+                    _ => TeXErr!("Unknown primitive in format file: {}",name)
+                }
+            }
+            2 => Char(self.token()?),
+            3 => MathChar(self.u32()?),
+            4 => {
+                let protected = self.bool()?;
+                let long = self.bool()?;
+                let endswithbrace = self.bool()?;
+                let arity = self.u8()?;
+                let len = self.u32()? as usize;
+                let mut elems = Vec::with_capacity(len);
+                for _ in 0..len {
+                    elems.push(match self.u8()? {
+                        0 => ParamToken::Param(self.u8()?),
+                        _ => ParamToken::Token(self.token()?)
+                    })
+                }
+                let ret = self.tokens()?;
+                Def(DefMacro { protected, long, sig:Signature { elems, endswithbrace, arity }, ret })
+            }
+            5 => AV(AssignableValue::Dim(self.u16()?)),
+            6 => AV(AssignableValue::Register(self.u16()?)),
+            7 => AV(AssignableValue::Skip(self.u16()?)),
+            8 => AV(AssignableValue::MuSkip(self.u16()?)),
+            9 => AV(AssignableValue::Toks(self.u16()?)),
+            10 => AV(AssignableValue::FontRef(self.font()?)),
+            11 => {
+                let name = self.str()?;
+                match self.externals.get(&name) {
+                    Some(e) => Ext(e.clone()),
+                    None => TeXErr!("Format file needs the external command {}",name)
+                }
+            }
+            i => TeXErr!("Malformed format file: invalid command type {}",i)
+        };
+        let ret = ret.as_command();
+        Ok(Some(if self.bool()? {
+            let file = self.str()?;
+            let line = self.u32()? as usize;
+            let column = self.u32()? as usize;
+            let primitive = if self.bool()? { Some(self.str()?) } else { None };
+            ret.with_site(DefinitionSite { file, line, column, primitive })
+        } else { ret }))
+    }
+    fn state(&mut self,params:&dyn InterpreterParams) -> Result<State,TeXError> {
+        let mut state = State::empty();
+        let len = self.u32()?;
+        for _ in 0..len {
+            if self.u8()? == 0 {
+                self.fonts.push(ArcFont::default());
+                continue
+            }
+            let filename = self.str()?;
+            let name = self.str()?;
+            let at = if self.bool()? { Some(self.i32()?) } else { None };
+            let font = Font::new(state.get_font(&PWD,filename,params)?,at,name);
+            {
+                let mut inner = font.inner.write().unwrap();
+                inner.hyphenchar = self.u16()?;
+                inner.skewchar = self.u16()?;
+                let len = self.u32()?;
+                for _ in 0..len { let k = self.u16()?; let v = self.i32()?; inner.dimen.insert(k,v); }
+                let len = self.u32()?;
+                for _ in 0..len { let k = self.u16()?; let v = self.u8()?; inner.lps.insert(k,v); }
+                let len = self.u32()?;
+                for _ in 0..len { let k = self.u16()?; let v = self.u8()?; inner.rps.insert(k,v); }
+            }
+            self.fonts.push(font)
+        }
+
+        for i in 0..=255 {
+            let cc = self.catcode()?;
+            state.catcodes.set(i,cc,true)
+        }
+        let nl = self.u8()?;
+        state.catcodes.set_newline(nl,true);
+        let el = self.u8()?;
+        state.catcodes.set_endline(el,true);
+        let ec = self.u8()?;
+        state.catcodes.set_escape(ec,true);
+
+        let len = self.u32()?;
+        for _ in 0..len {
+            let name = self.str()?;
+            let cmd = self.command()?;
+            state.commands.set_locally(name,cmd)
+        }
+
+        for i in 0..state.registers_prim.base().len() { let v = self.i32()?; state.registers_prim.set_locally(i,v) }
+        let len = self.u32()? as u16;
+        for i in 0..len { let v = self.i32()?; state.registers.set_locally(i,v) }
+        for i in 0..state.dimensions_prim.base().len() { let v = self.i32()?; state.dimensions_prim.set_locally(i,v) }
+        let len = self.u32()? as u16;
+        for i in 0..len { let v = self.i32()?; state.dimensions.set_locally(i,v) }
+        for i in 0..state.skips_prim.base().len() { let v = self.skip()?; state.skips_prim.set_locally(i,v) }
+        let len = self.u32()? as u16;
+        for i in 0..len { let v = self.skip()?; state.skips.set_locally(i,v) }
+        for i in 0..state.muskips_prim.base().len() { let v = self.muskip()?; state.muskips_prim.set_locally(i,v) }
+        let len = self.u32()? as u16;
+        for i in 0..len { let v = self.muskip()?; state.muskips.set_locally(i,v) }
+        for i in 0..state.toks_prim.base().len() { let v = self.tokens()?; state.toks_prim.set_locally(i,v) }
+        let len = self.u32()? as u16;
+        for i in 0..len { let v = self.tokens()?; state.toks.set_locally(i,v) }
+
+        for i in 0..=255 {
+            let v = self.i32()?; state.sfcodes.set_locally(i,v);
+            let v = self.u8()?; state.lccodes.set_locally(i,v);
+            let v = self.u8()?; state.uccodes.set_locally(i,v);
+            let v = self.i32()?; state.mathcodes.set_locally(i,v);
+            let v = self.i32()?; state.delcodes.set_locally(i,v);
+        }
+        let len = self.u32()?;
+        let mut parshape = vec!();
+        for _ in 0..len { parshape.push((self.i32()?,self.i32()?)) }
+        state.parshape.set_locally(parshape);
+        let v = self.i32()?; state.hangindent.set_locally(v);
+        let v = self.u32()? as usize; state.hangafter.set_locally(v);
+
+        let f = self.font()?;
+        state.currfont.set_locally(f);
+        for i in 0..16 {
+            let f = self.font()?; state.textfonts.set_locally(i,f);
+            let f = self.font()?; state.scriptfonts.set_locally(i,f);
+            let f = self.font()?; state.scriptscriptfonts.set_locally(i,f);
+        }
+
+        for m in [&mut state.topmark,&mut state.firstmark,&mut state.botmark,&mut state.splitfirstmark,&mut state.splitbotmark] {
+            *m = self.tokens()?
+        }
+        let len = self.u32()?;
+        state.pdfcolorstacks = vec!();
+        for _ in 0..len {
+            let l = self.u32()?;
+            let mut stack = vec!();
+            for _ in 0..l { stack.push(self.str()?) }
+            state.pdfcolorstacks.push(stack)
+        }
+        let len = self.u32()?;
+        for _ in 0..len {
+            let i = self.u16()?;
+            let o = self.str()?;
+            state.pdfobjs.insert(i,o);
+        }
+        if self.pos != self.data.len() {
+            TeXErr!("Malformed format file: trailing data")
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::PrimitiveTeXCommand::*;
+    use crate::interpreter::dimensions::Numeric;
+    use crate::interpreter::params::NoOutput;
+    use crate::ontology::Expansion;
+    use crate::stomach::colon::NoColon;
+    use crate::Interpreter;
+
+    fn run(state:State,text:&str) -> State {
+        let p = NoOutput::new(None);
+        let (outcome,state,_) = Interpreter::do_string_with_state(Path::new("/test.tex"),state,text,NoColon::new(),&p);
+        assert!(outcome.success());
+        state
+    }
+    fn round_trip(state:&State) -> Result<State,TeXError> {
+        let mut data = vec!();
+        state.dump(&mut data)?;
+        State::undump(&mut data.as_slice(),&NoOutput::new(None))
+    }
+    fn body(state:&State,name:&str) -> String {
+        match state.commands.get(&name.into()).as_ref().map(|c| &*c.orig) {
+            Some(Def(d)) => d.ret.iter().map(|t| t.char as char).collect(),
+            _ => panic!("{} is not a macro",name)
+        }
+    }
+
+    #[test]
+    fn dump_undump() {
+        crate::utils::with_stack_size(|| {
+            let state = run(State::new(),
+                "\\catcode`\\{=1 \\catcode`\\}=2 \\catcode`\\#=6 \\catcode`\\@=11 \
+                \\def\\foo#1.{[#1]}\\let\\bar\\relax \\countdef\\c=20 \\c=7 \\count10=42 \
+                \\dimen3=2pt \\skip2=1pt plus 2fil \\toks5={a b}\\lccode`\\A=`\\b \\pdfobj useobjnum 1 {obj}");
+            let new = round_trip(&state).unwrap();
+            assert!(new.catcodes.get_scheme().catcodes[b'@' as usize] == CategoryCode::Letter);
+            assert_eq!(new.registers.get(&20),7);
+            assert_eq!(new.registers.get(&10),42);
+            assert_eq!(new.dimensions.get(&3),2 * 65536);
+            assert!(new.skips.get(&2) == state.skips.get(&2));
+            assert_eq!(new.toks.get(&5).len(),3);
+            assert_eq!(new.lccodes.get(&b'A'),b'b');
+            assert_eq!(new.pdfobjs.values().map(|s| s.to_string()).collect::<Vec<_>>(),vec!("obj"));
+            assert_eq!(new.commands.base().len(),state.commands.base().len());
+            assert!(matches!(new.commands.get(&"bar".into()).as_ref().map(|c| &*c.orig),Some(Primitive(p)) if p.name == "relax"));
+            // the undumped state works like the original
+            let new = run(new,"\\edef\\r{\\foo x.}\\edef\\s{\\the\\c}");
+            assert_eq!(body(&new,"r"),"[x]");
+            assert_eq!(body(&new,"s"),"7");
+        })
+    }
+
+    #[test]
+    fn undumpable_state_is_rejected() {
+        crate::utils::with_stack_size(|| {
+            let state = run(State::new(),"\\catcode`\\{=1 \\catcode`\\}=2 \\setbox3\\hbox{}");
+            assert!(state.dump(&mut vec!()).is_err());
+            let state = run(state,"\\setbox3\\box99");
+            assert!(state.dump(&mut vec!()).is_ok());
+            let mut data = vec!();
+            state.dump(&mut data).unwrap();
+            data.truncate(data.len() - 1);
+            assert!(State::undump(&mut data.as_slice(),&NoOutput::new(None)).is_err());
+        })
+    }
+
+    #[test]
+    fn dump_primitive() {
+        use crate::filesystem::MemoryFS;
+        use crate::interpreter::params::{DefaultParams, OutFiles, Verbosity};
+        crate::utils::with_stack_size(|| {
+            let mut p = DefaultParams::new(false,true,None);
+            p.verbosity = Verbosity::Quiet;
+            p.filesystem = Box::new(MemoryFS::new());
+            p.outfiles = OutFiles::Dir(std::path::PathBuf::from("/out"));
+            // \dump is not expandable
+            let (outcome,state,_) = Interpreter::do_string_with_state(Path::new("/test.tex"),State::new(),
+                "\\catcode`\\{=1 \\catcode`\\}=2 \\edef\\x{\\dump}\\count1=6 ",NoColon::new(),&p);
+            assert!(outcome.success());
+            assert_eq!(state.registers.get(&1),6);
+            assert!(p.filesystem.read(Path::new("/out/test.fmt")).is_err());
+            // and ends the job
+            let (outcome,state,_) = Interpreter::do_string_with_state(Path::new("/test.tex"),State::new(),
+                "\\count1=5 \\dump\\count1=6 ",NoColon::new(),&p);
+            assert!(outcome.success());
+            assert_eq!(state.registers.get(&1),5);
+            let data = p.filesystem.read(Path::new("/out/test.fmt")).unwrap();
+            let new = State::undump(&mut data.as_slice(),&NoOutput::new(None)).unwrap();
+            assert_eq!(new.registers.get(&1),5);
+            // nothing can be written if output files are kept in memory
+            p.outfiles = OutFiles::Memory;
+            p.filesystem = Box::new(MemoryFS::new());
+            let (outcome,state,_) = Interpreter::do_string_with_state(Path::new("/test.tex"),State::new(),
+                "\\nonstopmode\\count1=5 \\dump\\count1=6 ",NoColon::new(),&p);
+            assert!(!outcome.success());
+            assert_eq!(state.registers.get(&1),5);
+            assert!(p.filesystem.read(Path::new("/out/test.fmt")).is_err());
+        })
+    }
+
+    struct Dummy;
+    impl ExternalCommand for Dummy {
+        fn expandable(&self) -> bool { false }
+        fn assignable(&self) -> bool { false }
+        fn has_num(&self) -> bool { false }
+        fn has_whatsit(&self) -> bool { false }
+        fn name(&self) -> String { "ext".to_string() }
+        fn execute(&self,_int:&Interpreter) -> Result<(),TeXError> { Ok(()) }
+        fn expand(&self,_exp:&mut Expansion,_int:&Interpreter) -> Result<(),TeXError> { Ok(()) }
+        fn assign(&self,_int:&Interpreter,_global:bool) -> Result<(),TeXError> { Ok(()) }
+        fn get_num(&self,_int:&Interpreter) -> Result<Numeric,TeXError> { TeXErr!("no number") }
+    }
+
+    #[test]
+    fn external_commands() {
+        let mut state = State::new();
+        state.commands.set_locally("ext".into(),Some(Ext(Arc::new(Dummy)).as_command()));
+        let mut data = vec!();
+        state.dump(&mut data).unwrap();
+        assert!(State::undump(&mut data.as_slice(),&NoOutput::new(None)).is_err());
+        let new = State::undump_with(&mut data.as_slice(),&NoOutput::new(None),vec!(Arc::new(Dummy))).unwrap();
+        assert!(matches!(new.commands.get(&"ext".into()).as_ref().map(|c| &*c.orig),Some(Ext(e)) if e.name() == "ext"));
+    }
+}
@@ -39,6 +39,8 @@ impl<K:Eq+Hash+Clone,V:Default+PartialEq+Clone,R:RegisterLike<K,V>> StateStore<K
         }
     }
     pub fn destroy(self) -> R {self.store}
+    /// the current values (including those that are local to the current group)
+    pub fn base(&self) -> &R {&self.store}
     pub fn get(&self,k:&K) -> V {
         match self.store.get_value(k) {
             Some(v) => v.clone(),
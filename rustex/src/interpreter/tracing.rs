@@ -0,0 +1,270 @@
+/*! The TeX tracing parameters `\tracingmacros`, `\tracingcommands`, `\tracingassigns`,
+`\tracinggroups`, `\tracingifs` and `\tracingonline`.
+
+Traces are formatted like pdfTeX's, so that the logs of both can be compared line by line, and
+are emitted via [`InterpreterParams::log`](crate::interpreter::params::InterpreterParams::log).
+*/
+
+use crate::catcodes::CategoryCode;
+use crate::commands::{PrimitiveTeXCommand, RegisterReference};
+use crate::commands::conditionals::{ELSE, FI, OR};
+use crate::commands::primitives::show_name;
+use crate::commands::registers::{TRACINGCOMMANDS, TRACINGIFS, TRACINGMACROS, TRACINGONLINE};
+use crate::interpreter::state::GroupType;
+use crate::interpreter::{tokens_to_string, Interpreter, TeXMode};
+use crate::ontology::Token;
+use crate::stomach::boxes::BoxMode;
+use crate::utils::TeXString;
+
+/// The name of a group of type `tp` entered in `mode`, as used by `\tracinggroups` and
+/// `\showgroups`; an `\hbox` begun in vertical mode is an "adjusted hbox", since its
+/// `\vadjust`s and inserts migrate to the enclosing list
+pub fn group_name(tp:&GroupType,mode:TeXMode) -> &'static str {
+    let vertical = matches!(mode,TeXMode::Vertical | TeXMode::InternalVertical);
+    let math = matches!(mode,TeXMode::Math | TeXMode::Displaymath);
+    match tp {
+        GroupType::Token | GroupType::Box(BoxMode::M) if math => "math",
+        GroupType::Token => "simple",
+        GroupType::Begingroup => "semi simple",
+        // void box groups are never opened
+        GroupType::Box(BoxMode::H | BoxMode::Void) if vertical => "adjusted hbox",
+        GroupType::Box(BoxMode::H | BoxMode::Void) => "hbox",
+        GroupType::Box(BoxMode::V) => "vbox",
+        GroupType::Box(BoxMode::M | BoxMode::DM) => "math shift",
+        GroupType::Box(BoxMode::LeftRight) => "math left"
+    }
+}
+
+/// What opened a group of type `tp` entered in `mode`, as shown in parentheses by `\showgroups`
+pub fn group_opener(tp:&GroupType,mode:TeXMode) -> &'static str {
+    match tp {
+        GroupType::Token => "{",
+        GroupType::Box(BoxMode::M) if matches!(mode,TeXMode::Math | TeXMode::Displaymath) => "{",
+        GroupType::Begingroup => "\\begingroup",
+        GroupType::Box(BoxMode::H | BoxMode::Void) => "\\hbox{",
+        GroupType::Box(BoxMode::V) => "\\vbox{",
+        GroupType::Box(BoxMode::M) => "$",
+        GroupType::Box(BoxMode::DM) => "$$",
+        GroupType::Box(BoxMode::LeftRight) => "\\left"
+    }
+}
+
+fn is_fi_or_else(cmd:&PrimitiveTeXCommand) -> bool {
+    match cmd {
+        PrimitiveTeXCommand::Primitive(p) => **p == FI || **p == ELSE || **p == OR,
+        _ => false
+    }
+}
+
+impl Interpreter<'_> {
+    /// Whether the integer parameter `reg` (e.g. [`TRACINGMACROS`]) is greater than `level`
+    pub fn tracing(&self,reg:&RegisterReference,level:i32) -> bool {
+        self.state.registers_prim.get(&(reg.index - 1)) > level
+    }
+    /// Writes `s` to the log and, if `\tracingonline` is positive, to the terminal
+    pub fn trace(&self,s:&str) {
+        self.params.log(s);
+        // if the log is printed anyway, there is no point in printing everything twice
+        if self.tracing(&TRACINGONLINE,0) && !self.params.do_log() {
+            self.params.message(&(s.to_string() + "\n"))
+        }
+    }
+    fn esc(&self,name:&str) -> String {
+        match self.state.catcodes.get_scheme().escapechar {
+            255 => name.to_string(),
+            c => TeXString(vec!(c)).to_string() + name
+        }
+    }
+    /// a control sequence as printed by TeX's `print_cs`, i.e. followed by a space if it is
+    /// a control word
+    fn print_cs(&self,tk:&Token) -> String {
+        let name = show_name(tk,self);
+        match tk.catcode {
+            CategoryCode::Escape if tk.name().len() > 1 => name + " ",
+            CategoryCode::Escape => match tk.name().head() {
+                Some(c) if self.state.catcodes.get_scheme().get_code(*c) == CategoryCode::Letter => name + " ",
+                _ => name
+            },
+            _ => name
+        }
+    }
+    /// `{mode: s}`, where the mode is only printed if it changed since the last such line
+    fn show_cur_cmd(&mut self,s:&str) {
+        let mode = self.state.mode;
+        if self.shown_mode == Some(mode) {
+            self.trace(&format!("{{{}}}",s))
+        } else {
+            self.shown_mode = Some(mode);
+            self.trace(&format!("{{{} mode: {}}}",mode,s))
+        }
+    }
+    /// The innermost conditional as printed after `\fi`, `\else` and `\or` by `\tracingifs`
+    fn if_info(&self) -> String {
+        match self.state.condition_types.last() {
+            None => String::new(),
+            Some((name,unless,line)) => {
+                let mut ret = format!(": {}{} (level {})",
+                    if *unless {self.esc("unless")} else {String::new()},
+                    self.esc(name),self.state.conditions.len());
+                if *line != 0 { ret += &format!(" entered on line {}",line) }
+                ret
+            }
+        }
+    }
+    /// `\tracingcommands`: shows the command `tk` (with meaning `cmd`, unless it is a character)
+    /// that is about to be executed
+    pub(crate) fn trace_command(&mut self,tk:&Token,cmd:Option<&PrimitiveTeXCommand>) {
+        let scheme = self.state.catcodes.get_scheme();
+        let mut s = match (cmd,tk.catcode) {
+            (Some(c),_) => c.meaning(scheme).to_string(),
+            (None,CategoryCode::Space | CategoryCode::EOL) => "blank space  ".to_string(),
+            (None,CategoryCode::Escape | CategoryCode::Active | CategoryCode::Comment |
+                CategoryCode::Ignored | CategoryCode::Invalid) => show_name(tk,self),
+            (None,_) => PrimitiveTeXCommand::Char(tk.clone()).meaning(scheme).to_string()
+        };
+        if let Some(c) = cmd {
+            if is_fi_or_else(c) && self.tracing(&TRACINGIFS,0) { s += &self.if_info() }
+        }
+        self.show_cur_cmd(&s)
+    }
+    /// Shows the expandable primitive `cmd` if `\tracingcommands > 1` (or `\tracingifs > 0` for
+    /// conditionals); macros are traced by [`Interpreter::trace_macro`] instead
+    pub(crate) fn trace_expansion(&mut self,tk:&Token,cmd:&PrimitiveTeXCommand) {
+        match cmd {
+            PrimitiveTeXCommand::Def(_) => (),
+            PrimitiveTeXCommand::Cond(c) => self.trace_if(c.name,false),
+            c if self.tracing(&TRACINGCOMMANDS,1) || (is_fi_or_else(c) && self.tracing(&TRACINGIFS,0)) =>
+                self.trace_command(tk,Some(c)),
+            _ => ()
+        }
+    }
+    /// Shows the conditional `name` that is about to be evaluated, with its level and line
+    /// if `\tracingifs > 0`
+    pub(crate) fn trace_if(&mut self,name:&str,unless:bool) {
+        let ifs = self.tracing(&TRACINGIFS,0);
+        if !ifs && !self.tracing(&TRACINGCOMMANDS,1) { return }
+        let mut s = if unless {self.esc("unless")} else {String::new()} + &self.esc(name);
+        if ifs {
+            s += &format!(": (level {}) entered on line {}",self.state.conditions.len() + 1,self.mouths.line_no().0)
+        }
+        self.show_cur_cmd(&s)
+    }
+    /// `\tracingmacros`: the macro `tk` with meaning `cmd` and the arguments it was called with
+    pub(crate) fn trace_macro(&self,tk:&Token,cmd:&PrimitiveTeXCommand,args:&[Vec<Token>]) {
+        let scheme = self.state.catcodes.get_scheme();
+        let meaning = cmd.meaning(scheme).to_string();
+        let body = match meaning.find("macro:") {
+            Some(i) => &meaning[i + 6..],
+            None => &meaning
+        };
+        let mut s = format!("\n{}{}",self.print_cs(tk),body);
+        for (i,a) in args.iter().enumerate() {
+            s += &format!("\n#{}<-{}",i + 1,tokens_to_string(a,scheme))
+        }
+        self.trace(&s)
+    }
+    /// `\tracingmacros > 1`: the token list `\name` that is inserted (e.g. `\everypar`)
+    pub(crate) fn trace_token_list(&self,name:&str,tks:&Vec<Token>) {
+        if !tks.is_empty() && self.tracing(&TRACINGMACROS,1) {
+            self.trace(&format!("{}->{}",self.esc(name),tokens_to_string(tks,self.state.catcodes.get_scheme())))
+        }
+    }
+    /// `\tracingassigns`: the assignment of `new` to `name` (without escape character), whose
+    /// previous value is `old`
+    pub(crate) fn trace_assign(&self,name:&str,old:String,new:String,global:bool) {
+        let name = self.esc(name);
+        if old == new {
+            self.trace(&format!("{{reassigning {}={}}}",name,new))
+        } else {
+            self.trace(&format!("{{{}changing {}={}}}",if global {"globally "} else {""},name,old));
+            self.trace(&format!("{{into {}={}}}",name,new))
+        }
+    }
+    /// Only the second line of [`Interpreter::trace_assign`]; used when `\tracingassigns`
+    /// itself is switched on, since TeX checks it again after the assignment
+    pub(crate) fn trace_assign_into(&self,name:&str,new:String) {
+        self.trace(&format!("{{into {}={}}}",self.esc(name),new))
+    }
+    /// `\tracinggroups`: entering or leaving the innermost group
+    pub(crate) fn trace_group(&self,entering:bool) {
+        let level = self.state.stack_depth();
+        let tp = match self.state.tp.ls.front() {
+            Some(tp) => tp,
+            None => return
+        };
+        let (line,mode) = self.state.group_lines.last().copied().unwrap_or((0,TeXMode::Vertical));
+        let mut s = format!("{{{} {} group (level {})",if entering {"entering"} else {"leaving"},group_name(tp,mode),level);
+        if line != 0 {
+            s += &format!(" {} {}",if entering {"at line"} else {"entered at line"},line)
+        }
+        self.trace(&(s + "}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::interpreter::Interpreter;
+    use crate::interpreter::params::Recording;
+    use crate::interpreter::state::State;
+    use crate::stomach::colon::NoColon;
+
+    /// Runs `text` and returns the log
+    fn log(text:&str) -> String {
+        let text = "\\catcode`\\{=1 \\catcode`\\}=2 \\catcode`\\#=6 ".to_string() + text;
+        crate::utils::with_stack_size(move || {
+            let p = Recording::new();
+            let (outcome,_,()) = Interpreter::do_string_with_state(Path::new("/test.tex"),State::new(),&text,NoColon::new(),&p);
+            assert!(outcome.success());
+            p.log()
+        })
+    }
+
+    #[test]
+    fn macros() {
+        assert_eq!(log("\\def\\a#1{x#1}\\tracingmacros=2 \\a b\\everypar{y}\\par a\\par"),
+            "\n\\a #1->x#1\n#1<-b\n\\everypar->y\n");
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(log("\\tracingcommands=3 \\relax\\expandafter\\relax\\relax a\\par"),
+            "{vertical mode: \\relax}\n{\\expandafter}\n{\\relax}\n{\\relax}\n\
+            {the letter a}\n{horizontal mode: the letter a}\n{\\par}\n");
+    }
+
+    #[test]
+    fn assigns() {
+        assert_eq!(log("\\tracingassigns=1 \\count1=5 \\count1=5 \\global\\count1=6 \\def\\a{x}"),
+            "{into \\tracingassigns=1}\n{changing \\count1=0}\n{into \\count1=5}\n{reassigning \\count1=5}\n\
+            {globally changing \\count1=5}\n{into \\count1=6}\n{changing \\a=undefined}\n{into \\a=macro:->x}\n");
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(log("\\tracinggroups=1 \\begingroup\\endgroup\n{\\hbox{}}a\\hbox{}\\par"),
+            "{entering semi simple group (level 1) at line 1}\n\
+            {leaving semi simple group (level 1) entered at line 1}\n\
+            {entering simple group (level 1) at line 2}\n\
+            {entering adjusted hbox group (level 2) at line 2}\n\
+            {leaving adjusted hbox group (level 2) entered at line 2}\n\
+            {leaving simple group (level 1) entered at line 2}\n\
+            {entering hbox group (level 1) at line 2}\n\
+            {leaving hbox group (level 1) entered at line 2}\n");
+        assert_eq!(log("\\catcode`\\$=3 \\tracinggroups=1 $x{y}$\\par"),
+            "{entering math shift group (level 1) at line 1}\n\
+            {entering math group (level 2) at line 1}\n\
+            {leaving math group (level 2) entered at line 1}\n\
+            {leaving math shift group (level 1) entered at line 1}\n");
+    }
+
+    #[test]
+    fn ifs() {
+        assert_eq!(log("\\tracingifs=1 \\iftrue\\fi\n\\unless\\iffalse\\else\\fi"),
+            "{vertical mode: \\iftrue: (level 1) entered on line 1}\n\
+            {\\fi: \\iftrue (level 1) entered on line 1}\n\
+            {\\unless\\iffalse: (level 1) entered on line 2}\n\
+            {\\else: \\unless\\iffalse (level 1) entered on line 2}\n\
+            {\\fi: \\unless\\iffalse (level 1) entered on line 2}\n");
+    }
+}
@@ -1,10 +1,27 @@
+/*! A pure Rust implementation of the parts of kpathsea that RusTeX needs, i.e. finding files in a
+TeX installation without running `kpsewhich`.
+
+The `texmf.cnf` files are looked up along `$TEXMFCNF` (or kpathsea's default list relative to the
+location of the TeX binaries, or some well-known locations if there are none). Search paths such
+as `TEXINPUTS` are read from them for the program name `pdflatex`, with environment variables
+taking precedence (including `VAR.pdflatex` and `VAR_pdflatex`, and an empty path element in an
+environment variable standing for the value from `texmf.cnf`). Path elements may use
+`$VAR`/`${VAR}`, `{a,b}`, `~`, a trailing `//` (search subdirectories) and a leading `!!` (only
+search the `ls-R` database). Trees listed in `TEXMFDBS` that have an `ls-R` file are indexed from
+that file instead of from the disk.
+*/
+
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
-static mut KPATHSEA : Option<Kpathsea> = None;
+static KPATHSEA : std::sync::OnceLock<Result<Kpathsea,String>> = std::sync::OnceLock::new();
+
+/// The program name used to select `VAR.progname` variants
+const PROGNAME : &str = "pdflatex";
 
-pub static mut LOG : bool = false;
+/// The search path variables the file map is built from, in order of priority
+const PATH_VARS : [&str;4] = ["VARTEXFONTS","VFFONTS","TFMFONTS","TEXINPUTS"];
 
 fn check(pb : &PathBuf) -> Option<PathBuf> {
     match pb.parent() {
@@ -20,6 +37,24 @@ fn check(pb : &PathBuf) -> Option<PathBuf> {
     }
 }
 
+/// Locates and indexes the TeX installation, unless that already happened; returns why that
+/// failed, if it did. Without an installation, [`kpsewhich`] only finds files relative to the
+/// given directory.
+pub fn check_installation() -> Result<(),String> {
+    match KPATHSEA.get_or_init(Kpathsea::init) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.clone())
+    }
+}
+
+/// The `texmf.cnf` files read and the directories searched, one per line
+pub fn installation_summary() -> Result<String,String> {
+    let k = KPATHSEA.get_or_init(Kpathsea::init).as_ref().map_err(|e| e.clone())?;
+    let mut ret = String::new();
+    for c in &k.cnfs { ret += &format!("texmf.cnf: {}\n",c.display()) }
+    for s in &k.searched { ret += &format!("{}\n",s) }
+    Ok(ret)
+}
 
 pub fn kpsewhich(s : &str, indir : &Path) -> Option<(PathBuf,bool)> {
     if s.starts_with("nul:") && cfg!(target_os = "windows") {
@@ -37,12 +72,9 @@ pub fn kpsewhich(s : &str, indir : &Path) -> Option<(PathBuf,bool)> {
         Some(p) => return Some((p,false)),
         _ => ()
     }
-    let kpathsea = match unsafe { &KPATHSEA } {
-        None => unsafe {
-            KPATHSEA = Some(Kpathsea::init());
-            KPATHSEA.as_ref().unwrap()
-        }
-        Some(k) => k
+    let kpathsea = match KPATHSEA.get_or_init(Kpathsea::init) {
+        Ok(k) => k,
+        Err(_) => return Some((default,false))
     };
     let split : Vec<&str> = s.split(".").collect();
     let (file,ext) = match split.len() {
@@ -61,7 +93,7 @@ pub fn kpsewhich(s : &str, indir : &Path) -> Option<(PathBuf,bool)> {
     match kpathsea.map.get(&ext) {
         Some(m) => match m.get(&file) {
             Some(f) => Some((f.clone(),true)),
-            _ => match kpathsea.map.get("TEX").unwrap().get( &(if ext.is_empty() {file} else {file + "." + &ext})) {
+            _ => match kpathsea.map.get("TEX").and_then(|m| m.get( &(if ext.is_empty() {file} else {file + "." + &ext}))) {
                 Some(f) => Some((f.clone(),true)),
                 _ => Some((default,false))
             }
@@ -69,6 +101,45 @@ pub fn kpsewhich(s : &str, indir : &Path) -> Option<(PathBuf,bool)> {
         _ => Some((default,false))
     }
 }
+
+/// The output of the command line `kpsewhich args` (as in `\input|"kpsewhich args"`): the paths
+/// of the files found in `fs` and the values of the variables asked for with `-var-value`, one
+/// per line
+pub fn kpsewhich_command(args : &str, indir : &Path, fs : &dyn crate::filesystem::FileSystem) -> String {
+    // options taking an argument, which may be given as the next word
+    const WITH_ARG : [&str;9] = ["var-value","expand-var","expand-path","show-path","format",
+        "progname","engine","dpi","path"];
+    let mut ret : Vec<String> = vec!();
+    let mut args = args.split_whitespace().map(|a| a.trim_matches('"'));
+    while let Some(a) = args.next() {
+        if a.starts_with('-') {
+            let opt = a.trim_start_matches('-');
+            let (opt,arg) = match opt.split_once('=') {
+                Some((o,v)) => (o,Some(v.to_string())),
+                None if WITH_ARG.contains(&opt) => (opt,args.next().map(|s| s.to_string())),
+                None => (opt,None)
+            };
+            match (opt,arg,KPATHSEA.get_or_init(Kpathsea::init)) {
+                ("var-value",Some(v),Ok(k)) => {
+                    let value = k.vars.expand_vars(&format!("${{{}}}",v),0);
+                    ret.push(match value.strip_prefix('~') {
+                        Some(rest) => home() + rest,
+                        None => value
+                    })
+                }
+                ("expand-var",Some(v),Ok(k)) => ret.push(k.vars.expand_vars(&v,0)),
+                _ => ()
+            }
+        } else {
+            match fs.resolve(a,indir) {
+                Some((p,_)) if fs.is_file(&p) => ret.push(p.display().to_string()),
+                _ => ()
+            }
+        }
+    }
+    ret.join("\n")
+}
+
 fn recurse_dot(file : &str, ext:&str, indir : &Path) -> Option<(PathBuf,bool)> {
     //let mut dirs : Vec<PathBuf> = vec!();
     for entry in std::fs::read_dir(indir).unwrap() {
@@ -92,261 +163,473 @@ fn recurse_dot(file : &str, ext:&str, indir : &Path) -> Option<(PathBuf,bool)> {
     None
 }
 
+fn env_sep(chars:&[char],i:usize) -> bool {
+    match chars[i] {
+        ';' => true,
+        ':' => !cfg!(target_os = "windows") || !matches!(chars.get(i+1),Some('/') | Some('\\')),
+        _ => false
+    }
+}
+
+/// Splits a search path at the path separators outside of braces
+fn split_path(s:&str) -> Vec<String> {
+    let chars : Vec<char> = s.chars().collect();
+    let mut ret = vec!(String::new());
+    let mut depth : usize = 0;
+    for i in 0..chars.len() {
+        match chars[i] {
+            '{' => { depth += 1; ret.last_mut().unwrap().push('{') }
+            '}' => { depth = depth.saturating_sub(1); ret.last_mut().unwrap().push('}') }
+            _ if depth == 0 && env_sep(&chars,i) => ret.push(String::new()),
+            c => ret.last_mut().unwrap().push(c)
+        }
+    }
+    ret
+}
+
+/// `a{b,c{d,e}}f` => `abf`, `acdf`, `acef`
+fn expand_braces(s:&str) -> Vec<String> {
+    let start = match s.find('{') {
+        Some(i) => i,
+        None => return vec!(s.to_string())
+    };
+    let mut depth : usize = 0;
+    let mut alternatives = vec!(String::new());
+    let mut end = None;
+    for (i,c) in s[start + 1..].char_indices() {
+        match c {
+            '}' if depth == 0 => { end = Some(start + 1 + i); break }
+            ',' if depth == 0 => { alternatives.push(String::new()); continue }
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => ()
+        }
+        alternatives.last_mut().unwrap().push(c)
+    }
+    let end = match end {
+        Some(e) => e,
+        // unbalanced; taken literally
+        None => return vec!(s.to_string())
+    };
+    let (prefix,suffix) = (&s[..start],&s[end + 1..]);
+    let mut ret = vec!();
+    for a in alternatives {
+        for e in expand_braces(&(prefix.to_string() + &a + suffix)) { ret.push(e) }
+    }
+    ret
+}
+
+/// The variables from the `texmf.cnf` files, and those determined by the location of the TeX
+/// binaries
+struct Vars {
+    cnf:HashMap<String,String>
+}
+impl Vars {
+    fn env(name:&str) -> Option<String> {
+        [name.to_string() + "." + PROGNAME,name.to_string() + "_" + PROGNAME,name.to_string()].iter()
+            .find_map(|n| std::env::var(n).ok())
+    }
+    fn cnf(&self,name:&str) -> Option<String> {
+        self.cnf.get(&(name.to_string() + "." + PROGNAME)).or(self.cnf.get(name)).cloned()
+    }
+    /// The unexpanded value of the variable `name`; empty elements of a search path from an
+    /// environment variable are replaced by the value from `texmf.cnf`
+    fn value(&self,name:&str) -> Option<String> {
+        match (Vars::env(name),self.cnf(name)) {
+            (Some(e),Some(c)) if split_path(&e).iter().any(|s| s.is_empty()) => {
+                let sep = if cfg!(target_os = "windows") {";"} else {":"};
+                Some(split_path(&e).into_iter().map(|s| if s.is_empty() {c.clone()} else {s})
+                    .collect::<Vec<String>>().join(sep))
+            }
+            (Some(e),_) => Some(e),
+            (None,c) => c
+        }
+    }
+    /// Replaces `$VAR` and `${VAR}` in `s`; undefined variables are empty
+    fn expand_vars(&self,s:&str,depth:u8) -> String {
+        let mut ret = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '$' { ret.push(c); continue }
+            let mut name = String::new();
+            if chars.peek() == Some(&'{') {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    if c == '}' { break }
+                    name.push(c)
+                }
+            } else {
+                while let Some(c) = chars.peek() {
+                    if !c.is_ascii_alphanumeric() && *c != '_' { break }
+                    name.push(*c);
+                    chars.next();
+                }
+            }
+            // guards against cyclic definitions
+            if depth < 32 {
+                if let Some(v) = self.value(&name) { ret += &self.expand_vars(&v,depth + 1) }
+            }
+        }
+        ret
+    }
+    /// The elements of the search path `name`, with variables and braces expanded
+    fn path(&self,name:&str) -> Vec<String> {
+        let value = match self.value(name) {
+            Some(v) => v.replace('\\',"/"),
+            None => return vec!()
+        };
+        let mut ret = vec!();
+        for e in split_path(&self.expand_vars(&value,0)) {
+            for e in expand_braces(&e) {
+                for e in split_path(&e) {
+                    if !e.is_empty() { ret.push(e) }
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Parses the `texmf.cnf` file `file` into `vars`; earlier definitions take precedence over later ones
+fn read_cnf(file:&Path,vars:&mut HashMap<String,String>) {
+    if let Ok(c) = std::fs::read(file) { parse_cnf(&String::from_utf8_lossy(&c),vars) }
+}
+
+/// Parses the content of a `texmf.cnf` file into `vars`; earlier definitions take precedence
+/// over later ones
+fn parse_cnf(content:&str,vars:&mut HashMap<String,String>) {
+    let mut lines = content.lines();
+    while let Some(l) = lines.next() {
+        let mut line = l.trim().to_string();
+        while line.ends_with('\\') {
+            line.pop();
+            match lines.next() {
+                Some(n) => line += n.trim(),
+                None => break
+            }
+        }
+        if line.is_empty() || line.starts_with('%') || line.starts_with('#') { continue }
+        // comments start with % or # after whitespace
+        let comment = line.char_indices().zip(line.chars().skip(1))
+            .find(|((_,c),n)| c.is_whitespace() && (*n == '%' || *n == '#')).map(|((i,_),_)| i);
+        if let Some(i) = comment { line.truncate(i) }
+        let (key,value) = match line.find(|c:char| c == '=' || c.is_whitespace()) {
+            Some(i) => (line[..i].trim(),line[i..].trim_start().trim_start_matches('=').trim()),
+            None => continue
+        };
+        if key.is_empty() { continue }
+        match vars.entry(key.to_string()) {
+            Entry::Occupied(_) => (),
+            Entry::Vacant(e) => { e.insert(value.to_string()); }
+        }
+    }
+}
+
+/// The directory containing the TeX binaries, if they are on the `PATH`
+fn selfautoloc() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    let exe = if cfg!(target_os = "windows") {".exe"} else {""};
+    for dir in std::env::split_paths(&path) {
+        for bin in ["kpsewhich","pdftex"] {
+            let p = dir.join(bin.to_string() + exe);
+            if p.is_file() {
+                return std::fs::canonicalize(&p).unwrap_or(p).parent().map(|p| p.to_path_buf())
+            }
+        }
+    }
+    None
+}
+
+fn insert_selfauto(vars:&mut HashMap<String,String>,loc:&Path) {
+    let dir = loc.parent().unwrap_or(loc);
+    let parent = dir.parent().unwrap_or(dir);
+    let grandparent = parent.parent().unwrap_or(parent);
+    for (k,v) in [("SELFAUTOLOC",loc),("SELFAUTODIR",dir),("SELFAUTOPARENT",parent),("SELFAUTOGRANDPARENT",grandparent)] {
+        vars.insert(k.to_string(),v.display().to_string().replace('\\',"/"));
+    }
+}
+
+/// kpathsea's default `TEXMFCNF`, relative to the location of the binaries
+const DEFAULT_TEXMFCNF : &str = "{$SELFAUTOLOC,$SELFAUTOLOC/share/texmf-local/web2c,$SELFAUTOLOC/share/texmf-dist/web2c,\
+$SELFAUTOLOC/share/texmf/web2c,$SELFAUTOLOC/texmf-local/web2c,$SELFAUTOLOC/texmf-dist/web2c,$SELFAUTOLOC/texmf/web2c,\
+$SELFAUTODIR,$SELFAUTODIR/share/texmf-local/web2c,$SELFAUTODIR/share/texmf-dist/web2c,$SELFAUTODIR/share/texmf/web2c,\
+$SELFAUTODIR/texmf-local/web2c,$SELFAUTODIR/texmf-dist/web2c,$SELFAUTODIR/texmf/web2c,\
+$SELFAUTOGRANDPARENT/texmf-local/web2c,$SELFAUTOPARENT,\
+$SELFAUTOPARENT/share/texmf-local/web2c,$SELFAUTOPARENT/share/texmf-dist/web2c,$SELFAUTOPARENT/share/texmf/web2c,\
+$SELFAUTOPARENT/texmf-local/web2c,$SELFAUTOPARENT/texmf-dist/web2c,$SELFAUTOPARENT/texmf/web2c}";
+
+/// Where TeX distributions usually put their `texmf.cnf`, for when no binaries are installed
+fn well_known_cnf_dirs() -> Vec<PathBuf> {
+    let mut ret = vec!();
+    for base in ["/usr/local/texlive","/opt/texlive"] {
+        if let Ok(rd) = std::fs::read_dir(base) {
+            let mut years : Vec<PathBuf> = rd.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect();
+            years.sort();
+            for y in years.into_iter().rev() { ret.push(y.join("texmf-dist").join("web2c")) }
+        }
+    }
+    for d in ["/usr/share/texlive/texmf-dist/web2c","/usr/share/texmf/web2c","/etc/texmf/web2c"] {
+        ret.push(PathBuf::from(d))
+    }
+    ret
+}
+
+/// An `ls-R` database: the directories (in the order listed) with the names of their entries
+struct LsR {
+    root:PathBuf,
+    dirs:Vec<(PathBuf,Vec<String>)>,
+    dirset:HashSet<PathBuf>
+}
+impl LsR {
+    fn read(root:&Path) -> Option<LsR> {
+        let file = ["ls-R","ls-r"].iter().map(|f| root.join(f)).find(|f| f.is_file())?;
+        Some(LsR::parse(root,&String::from_utf8_lossy(&std::fs::read(file).ok()?)))
+    }
+    /// The database with content `content` of the tree `root`
+    fn parse(root:&Path,content:&str) -> LsR {
+        let mut dirs : Vec<(PathBuf,Vec<String>)> = vec!();
+        for line in content.lines() {
+            if line.is_empty() || line.starts_with('%') { continue }
+            if let Some(d) = line.strip_suffix(':') {
+                let d = d.strip_prefix("./").unwrap_or(d);
+                let dir = if d == "." { root.to_path_buf() } else { root.join(d) };
+                dirs.push((dir,vec!()))
+            } else if let Some((_,files)) = dirs.last_mut() {
+                files.push(line.to_string())
+            }
+        }
+        let dirset = dirs.iter().map(|(d,_)| d.clone()).collect();
+        LsR { root:root.to_path_buf(),dirs,dirset }
+    }
+    /// Adds the files in `dir` (and its subdirectories, if `recurse`) to `map`
+    fn fill_map(&self,map: &mut HashMap<String,HashMap<String,PathBuf>>,dir:&Path,recurse:bool) {
+        for (d,files) in &self.dirs {
+            if d == dir || (recurse && d.starts_with(dir)) {
+                for f in files {
+                    let p = d.join(f);
+                    if !self.dirset.contains(&p) { insert(map,p) }
+                }
+            }
+        }
+    }
+}
+
+fn insert(map: &mut HashMap<String,HashMap<String,PathBuf>>,p:PathBuf) {
+    let ext = match p.extension() {
+        Some(s) => s.to_ascii_uppercase().to_str().unwrap().to_string(),
+        _ => "".to_string()
+    };
+    let filename = match p.file_stem() {
+        Some(s) => s.to_ascii_uppercase().to_str().unwrap().to_string(),
+        _ => "".to_string()
+    };
+    match map.entry(ext) {
+        Entry::Occupied(mut v) => match v.get_mut().entry(filename) {
+            Entry::Vacant(v) => {v.insert(p);}
+            _ => ()
+        },
+        Entry::Vacant(v) => {
+            v.insert(HashMap::new()).insert(filename,p);
+        }
+    }
+}
+
 struct Kpathsea {
     recdot: bool,
+    vars: Vars,
+    /// the `texmf.cnf` files read
+    cnfs: Vec<PathBuf>,
+    /// the elements of the search paths, as in `texmf.cnf`
+    searched: Vec<String>,
     pub map : HashMap<String,HashMap<String,PathBuf>>
 }
 impl Kpathsea {
-    pub fn init() -> Kpathsea {
-        let loc = std::str::from_utf8(std::process::Command::new("kpsewhich")
-            .args(vec!("-var-value","SELFAUTOLOC")).output().expect("kpsewhich not found!")
-            .stdout.as_slice()).unwrap().trim().to_string();
-        let rs : Vec<String> = std::str::from_utf8(std::process::Command::new("kpsewhich")
-            .args(vec!("-a","texmf.cnf")).output().expect("kpsewhich not found!")
-            .stdout.as_slice()).unwrap().split(|c| c=='\r' || c == '\n').map(|x| x.trim().to_string()).filter(|s| !s.is_empty()).collect();
-        if rs.is_empty() && loc.contains("miktex") { Self::miktex(loc) } else {Self::texlive(rs,loc)}
-    }
-
-    fn texlive(rs: Vec<String>,loc:String) -> Kpathsea {
-        let mut vars = HashMap::<String,String>::new();
-        {
-            let selfautoloc = PathBuf::from(loc);
-            match selfautoloc.parent() {
-                Some(p) => {
-                    vars.insert("SELFAUTODIR".to_string(),p.to_str().unwrap().to_string());
-                    match p.parent() {
-                        Some(pp) => {
-                            vars.insert("SELFAUTOPARENT".to_string(),pp.to_str().unwrap().to_string());
-                            match pp.parent() {
-                                Some(ppp) => {vars.insert("SELFAUTOGRANDPARENT".to_string(),ppp.to_str().unwrap().to_string());},
-                                _ => {vars.insert("SELFAUTOGRANDPARENT".to_string(),pp.to_str().unwrap().to_string());}
-                            }
-                        }
-                        None => {
-                            vars.insert("SELFAUTOPARENT".to_string(),p.to_str().unwrap().to_string());
-                            vars.insert("SELFAUTOGRANDPARENT".to_string(),p.to_str().unwrap().to_string());
-                        }
-                    }
-                },
-                _ => {
-                    vars.insert("SELFAUTODIR".to_string(),selfautoloc.to_str().unwrap().to_string());
-                    vars.insert("SELFAUTOPARENT".to_string(),selfautoloc.to_str().unwrap().to_string());
-                    vars.insert("SELFAUTOGRANDPARENT".to_string(),selfautoloc.to_str().unwrap().to_string());
-                }
-            }
-            vars.insert("SELFAUTOLOC".to_string(),selfautoloc.to_str().unwrap().to_string());
+    pub fn init() -> Result<Kpathsea,String> {
+        let loc = selfautoloc();
+        let mut vars = HashMap::new();
+        if let Some(l) = &loc { insert_selfauto(&mut vars,l) }
+        let mut search = Vars { cnf:vars.clone() };
+        if loc.is_some() { search.cnf.insert("TEXMFCNF".to_string(),DEFAULT_TEXMFCNF.to_string()); }
+        let mut dirs : Vec<PathBuf> = search.path("TEXMFCNF").into_iter().map(PathBuf::from).collect();
+        if loc.is_none() && Vars::env("TEXMFCNF").is_none() { dirs.append(&mut well_known_cnf_dirs()) }
+        let mut cnfs : Vec<PathBuf> = vec!();
+        for d in dirs {
+            let f = d.join("texmf.cnf");
+            if f.is_file() && !cnfs.contains(&f) { cnfs.push(f) }
         }
-        for r in rs {
-            let p = PathBuf::from(r);
-            if p.exists() {
-                let lines : Vec<String> = std::str::from_utf8(std::fs::read(p).unwrap().as_slice()).unwrap().split(|c| c=='\r' || c == '\n').map(|x| x.trim().to_string()).collect();
-                for l in lines {
-                    if !l.starts_with("%") && !l.is_empty() {
-                        let mut kb : Vec<String> = l.split("=").map(|x| x.trim().to_string()).collect();
-                        if kb.len() == 2 {
-                            let v = kb.pop().unwrap();
-                            let k = kb.pop().unwrap();
-                            match vars.entry(k) {
-                                Entry::Occupied(_) => (),
-                                Entry::Vacant(e) => { e.insert(v); }
-                            }
-                        }
-                    }
-                }
+        match (cnfs.first(),&loc) {
+            (None,Some(l)) if l.display().to_string().to_lowercase().contains("miktex") => return Ok(Self::miktex(l)),
+            (None,_) => return Err("No TeX installation found: no texmf.cnf in $TEXMFCNF, next to the TeX binaries or in the usual locations. \
+                Set TEXMFCNF to the directory containing texmf.cnf (e.g. <texlive>/texmf-dist/web2c).".to_string()),
+            // no binaries; assume <root>/texmf-dist/web2c/texmf.cnf and <root>/bin/<platform>
+            (Some(c),None) => {
+                let root = c.parent().and_then(|p| p.parent()).and_then(|p| p.parent()).unwrap_or(Path::new("/"));
+                insert_selfauto(&mut vars,&root.join("bin").join("unknown"))
             }
+            _ => ()
         }
-        let filestrs : Vec<String> = vec!(
-            vars.get("VARTEXFONTS").map(|x| x.replace("\\","/")),
-            vars.get("VFFONTS").map(|x| x.replace("\\","/")),
-            vars.get("TFMFONTS").map(|x| x.replace("\\","/")),
-            std::env::vars().find(|a| a.0 == "TEXINPUTS").map(|x| x.1.replace("\\","/")),
-            vars.get("TEXINPUTS").map(|x| x.clone())
-        ).into_iter().flatten().collect();
-        vars.insert("progname".to_string(),"pdflatex".to_string());
-        Self::finalize(filestrs,vars)
-    }
-
-    fn miktex(loc:String) -> Kpathsea {
-        let pdftex_map = std::str::from_utf8(std::process::Command::new("kpsewhich")
-            .args(vec!("pdftex.map")).output().expect("kpsewhich not found!")
-            .stdout.as_slice()).unwrap().trim().to_string();
-        let miktex = Path::new(&loc).parent().unwrap().parent().unwrap().parent().unwrap();
-        let appdata = Path::new(&pdftex_map.split("MiKTeX").next().unwrap()).join("MiKTeX");
+        for c in &cnfs { read_cnf(c,&mut vars) }
+        let vars = Vars { cnf:vars };
+        let filestrs : Vec<String> = PATH_VARS.iter().map(|v| vars.path(v)).flatten().collect();
+        let dbs : Vec<LsR> = {
+            let mut roots = vars.path("TEXMFDBS");
+            if roots.is_empty() { roots = vars.path("TEXMF") }
+            roots.iter().map(|r| Kpathsea::element(r).0).filter_map(|r| LsR::read(&r)).collect()
+        };
+        Ok(Self::finalize(cnfs,filestrs,vars,dbs))
+    }
+
+    fn miktex(loc:&Path) -> Kpathsea {
+        let miktex = loc.parent().and_then(|p| p.parent()).and_then(|p| p.parent()).unwrap_or(loc);
+        let appdata = std::env::var("LOCALAPPDATA").or(std::env::var("APPDATA")).map(|s| Path::new(&s).join("MiKTeX"));
         let mut filestrs: Vec<String> = vec!(
             miktex.join("tex").join("generic").display().to_string().replace("\\","/") + "//",
             miktex.join("tex").join("latex").display().to_string().replace("\\","/") + "//",
-            miktex.join("fonts").display().to_string().replace("\\","/") + "//",
-            appdata.join("fonts").display().to_string().replace("\\","/") + "//"
+            miktex.join("fonts").display().to_string().replace("\\","/") + "//"
         );
+        if let Ok(a) = appdata {
+            filestrs.push(a.join("fonts").display().to_string().replace("\\","/") + "//")
+        }
         if let Some((_,tip)) = std::env::vars().find(|a| a.0 == "TEXINPUTS") {
             filestrs.insert(0,tip.replace("\\","/"))
         }
-        Self::finalize(filestrs,HashMap::default())
+        Self::finalize(vec!(),filestrs,Vars { cnf:HashMap::new() },vec!())
     }
 
-    fn finalize(filestrs : Vec<String>,vars: HashMap::<String,String>) -> Kpathsea {
-        unsafe {
-            if LOG {
-                for s in &filestrs {
-                    println!("{s}");
-                }
-            }
-        }
-        let home = if cfg!(target_os = "windows") {
-            std::env::vars().find(|x| x.0 == "HOMEDRIVE").unwrap().1 +
-                &std::env::vars().find(|x| x.0 == "HOMEPATH").unwrap().1
-        } else {
-            std::env::vars().find(|x| x.0 == "HOME").unwrap().1
+    /// The directory of a path element, and whether to search it recursively and only in
+    /// the `ls-R` database
+    fn element(e:&str) -> (PathBuf,bool,bool) {
+        let mut d = e.trim();
+        let dbonly = d.starts_with("!!");
+        if dbonly { d = &d[2..] }
+        let recurse = d.ends_with("//");
+        let d = d.trim_end_matches('/');
+        let d = match d.strip_prefix('~') {
+            Some(rest) => home() + rest,
+            None => d.to_string()
         };
+        (PathBuf::from(d),recurse,dbonly)
+    }
+
+    fn finalize(cnfs:Vec<PathBuf>,filestrs : Vec<String>,vars:Vars,dbs:Vec<LsR>) -> Kpathsea {
         let mut recdot = false;
-        let dirs : Vec<String> = filestrs.into_iter().map(|x| Kpathsea::parse_string(x,&vars)).flatten().collect();
-        unsafe {
-            if LOG {
-                println!("--------------------------------------");
-                for s in &dirs {
-                    println!("{s}");
-                }
-            }
-        }
-        let mut paths : Vec<(PathBuf,bool)> = vec!();
-        for mut d in dirs {
+        let mut paths : Vec<(PathBuf,bool,bool)> = vec!();
+        for d in filestrs.iter().cloned() {
             if d.starts_with(".") {
                 if d == ".//" {
                     recdot = true
                 }
-            }
-            else {
-                let mut recurse : bool = false;
-                if d.starts_with("~") {
-                    d = home.clone() + &d[1..]
-                }
-                if d.ends_with("//") {
-                    d.pop();d.pop();
-                    recurse = true
-                }
-                if !d.trim().is_empty() {
-                    let pb = PathBuf::from(d.trim());
-                    if pb.exists() && ! paths.contains(&(pb.clone(),recurse)) {
-                        paths.push((pb,recurse))
-                    }
-                }
+            } else if !d.trim().is_empty() {
+                let e = Kpathsea::element(&d);
+                if !paths.contains(&e) { paths.push(e) }
             }
         }
         let mut map : HashMap<String,HashMap<String,PathBuf>> = HashMap::new();
-        for (path,recurse) in paths { Kpathsea::fill_map(&mut map,path,recurse) }
-        Kpathsea { map,recdot }
+        for (path,recurse,dbonly) in paths {
+            match dbs.iter().filter(|db| path.starts_with(&db.root)).max_by_key(|db| db.root.components().count()) {
+                Some(db) => db.fill_map(&mut map,&path,recurse),
+                None if dbonly => (),
+                None if path.exists() => Kpathsea::fill_map(&mut map,path,recurse),
+                None => ()
+            }
+        }
+        Kpathsea { map,recdot,vars,cnfs,searched:filestrs }
     }
 
 
     fn fill_map(map: &mut HashMap<String,HashMap<String,PathBuf>>, path : PathBuf, recurse: bool) {
-        for entry in std::fs::read_dir(path).unwrap() {
-            let p = entry.unwrap().path();
+        let entries = match std::fs::read_dir(path) {
+            Ok(e) => e,
+            _ => return
+        };
+        for entry in entries.flatten() {
+            let p = entry.path();
             if p.is_dir() && recurse {
                 Kpathsea::fill_map(map,p,recurse)
-            } else {
-                if p.is_file() {
-                    let ext = match p.extension() {
-                        Some(s) => s.to_ascii_uppercase().to_str().unwrap().to_string(),
-                        _ => "".to_string()
-                    };
-                    let filename = match p.file_stem() {
-                        Some(s) => s.to_ascii_uppercase().to_str().unwrap().to_string(),
-                        _ => "".to_string()
-                    };
-                    match map.entry(ext) {
-                        Entry::Occupied(mut v) => match v.get_mut().entry(filename) {
-                            Entry::Vacant(v) => {v.insert(p);}
-                            _ => ()
-                        },
-                        Entry::Vacant(v) => {
-                            v.insert(HashMap::new()).insert(filename,p);
-                        }
-                    }
-                }
+            } else if p.is_file() {
+                insert(map,p)
             }
         }
     }
-    fn parse_string(s : String,vars:&HashMap<String,String>) -> Vec<String> {
-        let mut fin : Vec<String> = vec!();
-        let mut ret : Vec<String> = vec!("".to_string());
-        let mut i : usize = 0;
-        let chars : Vec<char> = s.chars().collect();
-        loop {
-            match chars.get(i) {
-                None => {
-                    fin.append(&mut ret);
-                    return fin
-                },
-                Some(';') => {
-                    i += 1;
-                    fin.append(&mut std::mem::take(&mut ret));
-                    ret.push("".to_string())
-                },
-                Some(':') if !cfg!(target_os = "windows") || chars.get(i+1) != Some(&'/') => {
-                    i += 1;
-                    fin.append(&mut std::mem::take(&mut ret));
-                    ret.push("".to_string())
-                },
-                Some('$') => {
-                    i += 1;
-                    let mut varname : String = "".to_string();
-                    loop {
-                        match chars.get(i) {
-                            Some(c) if c.is_ascii_alphabetic() => {
-                                i += 1;
-                                varname.push(*c)
-                            },
-                            _ => break
-                        }
-                    }
-                    match vars.get(&varname) {
-                        None => panic!("unknown variable name"),
-                        Some(s) => {
-                            let rets = Kpathsea::parse_string(s.clone(),vars);
-                            let nrets = std::mem::take(&mut ret);
-                            for o in nrets { for r in &rets { ret.push(o.clone() + r) }}
-                        }
-                    }
-                },
-                Some('!') => i += 1,
-                Some('{') => {
-                    i += 1;
-                    let mut rets : Vec<String> = vec!("".to_string());
-                    let mut inbracks : u8 = 0;
-                    loop {
-                        match chars.get(i) {
-                            Some(',') if inbracks == 0 => {
-                                i += 1;
-                                rets.push("".to_string())
-                            },
-                            Some('{') => {
-                                i += 1;
-                                inbracks += 1
-                            },
-                            Some('}') if inbracks == 0 => {
-                                i += 1;
-                                break
-                            }
-                            Some('}') => {
-                                i += 1;
-                                inbracks -= 1
-                            }
-                            Some(c) => {
-                                i += 1;
-                                rets.last_mut().unwrap().push(*c)
-                            }
-                            None => panic!("Syntax error in texmf.cnf")
-                        }
-                    }
-                    let allrets : Vec<String> = rets.into_iter().map(|x| Kpathsea::parse_string(x,vars)).flatten().collect();
-                    let nrets = std::mem::take(&mut ret);
-                    for o in nrets { for r in &allrets { ret.push(o.clone() + r) }}
-                },
-                Some(c) => {
-                    i += 1;
-                    for s in &mut ret { s.push(*c) }
-                }
-            }
-        }
+}
+
+fn home() -> String {
+    if cfg!(target_os = "windows") {
+        std::env::var("HOMEDRIVE").unwrap_or_default() + &std::env::var("HOMEPATH").unwrap_or_default()
+    } else {
+        std::env::var("HOME").unwrap_or_default()
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(cnf:&str) -> Vars {
+        let mut vars = HashMap::new();
+        parse_cnf(cnf,&mut vars);
+        Vars { cnf:vars }
+    }
+
+    #[test]
+    fn cnf_first_definition_wins() {
+        let v = vars("% comment\nRUSTEX_A = first\nRUSTEX_A = second\n# also a comment\nRUSTEX_B=b");
+        assert_eq!(v.cnf("RUSTEX_A").as_deref(),Some("first"));
+        assert_eq!(v.cnf("RUSTEX_B").as_deref(),Some("b"));
+    }
+    #[test]
+    fn cnf_continuations_comments_and_progname() {
+        let v = vars("RUSTEX_A = a,\\\n  b % trailing comment\nRUSTEX_B.pdflatex = pdf\nRUSTEX_B = plain\nRUSTEX_C x");
+        assert_eq!(v.cnf("RUSTEX_A").as_deref(),Some("a,b"));
+        assert_eq!(v.cnf("RUSTEX_B").as_deref(),Some("pdf"));
+        assert_eq!(v.cnf("RUSTEX_C").as_deref(),Some("x"));
+    }
+    #[test]
+    fn braces() {
+        assert_eq!(expand_braces("a{b,c{d,e}}f"),vec!("abf","acdf","acef"));
+        assert_eq!(expand_braces("{a,b}{c,d}"),vec!("ac","ad","bc","bd"));
+        assert_eq!(expand_braces("x{,y}"),vec!("x","xy"));
+        assert_eq!(expand_braces("no{braces"),vec!("no{braces"));
+        assert_eq!(expand_braces("plain"),vec!("plain"));
+    }
+    #[test]
+    fn vars_are_expanded() {
+        let v = vars("RUSTEX_ROOT = /tex\nRUSTEX_DIST = $RUSTEX_ROOT/dist\nRUSTEX_PATH = {${RUSTEX_DIST},$RUSTEX_ROOT/local}//\nRUSTEX_LOOP = $RUSTEX_LOOP");
+        assert_eq!(v.expand_vars("$RUSTEX_DIST/x",0),"/tex/dist/x");
+        assert_eq!(v.expand_vars("${RUSTEX_ROOT}y",0),"/texy");
+        assert_eq!(v.expand_vars("$RUSTEX_UNDEFINED.",0),".");
+        assert_eq!(v.expand_vars("$RUSTEX_LOOP",0),"");
+        assert_eq!(v.path("RUSTEX_PATH"),vec!("/tex/dist//","/tex/local//"));
+    }
+    #[test]
+    fn paths_are_split_outside_braces() {
+        let sep = if cfg!(target_os = "windows") {";"} else {":"};
+        assert_eq!(split_path(&std::format!("a{}{{b{}c}}{}",sep,sep,sep)),vec!("a",&std::format!("{{b{}c}}",sep),""));
+        assert_eq!(split_path("a;b"),vec!("a","b"));
+        assert_eq!(split_path(""),vec!(""));
+    }
+    #[test]
+    fn kpsewhich_command_uses_the_file_system() {
+        let fs = crate::filesystem::MemoryFS::new();
+        fs.insert("/project/sub/mystuff.sty","");
+        fs.insert("/project/main.tex","");
+        assert_eq!(kpsewhich_command("mystuff.sty main.tex missing.sty",Path::new("/project"),&fs),"/project/sub/mystuff.sty\n/project/main.tex");
+    }
+    #[test]
+    fn ls_r() {
+        let root = Path::new("/texmf");
+        let db = LsR::parse(root,"% ls-R -- filename database\n./:\nls-R\ntex\n\n./tex:\nlatex\nplain.tex\n\n./tex/latex:\nbase\n\n./tex/latex/base:\nlatex.ltx\narticle.cls\n");
+        assert!(db.dirset.contains(&root.join("tex/latex")));
+        let mut map = HashMap::new();
+        db.fill_map(&mut map,&root.join("tex"),false);
+        assert_eq!(map["TEX"].get("PLAIN"),Some(&root.join("tex/plain.tex")));
+        assert!(map.get("LTX").is_none());
+        // directories are not files
+        assert!(map.get("").map(|m| m.get("LATEX").is_none()).unwrap_or(true));
+        let mut map = HashMap::new();
+        db.fill_map(&mut map,&root.join("tex"),true);
+        assert_eq!(map["LTX"].get("LATEX"),Some(&root.join("tex/latex/base/latex.ltx")));
+        assert_eq!(map["CLS"].get("ARTICLE"),Some(&root.join("tex/latex/base/article.cls")));
+    }
+}
@@ -7,29 +7,14 @@ pub mod catcodes;
 pub mod stomach;
 pub mod fonts;
 pub mod kpathsea;
+pub mod filesystem;
+pub mod pdfsvg;
 //pub mod new_state;
 //mod new_mouth;
 
 pub const STACK_SIZE : usize = 16 * 1024 * 1024;
 
-pub static mut LOG : bool = false;
-static STORE_IN_FILE : bool = false;
 static COPY_TOKENS_FULL : bool = true;
-static INSERT_RUSTEX_ATTRS: bool = false;
-
-#[macro_export]
-macro_rules! log {
-    () => ();
-    ($arg:tt) => ( if unsafe{crate::LOG} {println!($arg);} );
-    ($head:tt,$($tl:expr),*) => ( if unsafe {crate::LOG} {
-        //println!($head,$($tl),*);
-        let retstr = std::format!("{}",std::format_args!($head,$($tl),*));
-        println!("{} {}",ansi_term::Colour::Red.bold().paint("Log:"),retstr);
-        //println!($head,$(ansi_term::Colour::Yellow.bold().paint($tl)),*);
-    })
-}
-
-
 
 #[macro_export]
 macro_rules! TeXErr {
@@ -65,7 +50,7 @@ pub static PGFSYS_RUST: &str = include_str!("resources/pgfsys-rust.def");
 mod tests {
     use std::fmt::Display;
     use crate::interpreter::state::State;
-    use crate::stomach::NoShipoutRoutine;
+    use crate::stomach::stomach_for;
     use crate::interpreter::params::DefaultParams;
     use crate::stomach::html::HTMLColon;
     use crate::Interpreter;
@@ -90,14 +75,15 @@ mod tests {
         let path = Path::new(&infile);
 
         let state = State::pdf_latex();
-        let mut stomach = NoShipoutRoutine::new();
+        let colon = HTMLColon::new(true);
+        let mut stomach = stomach_for(&colon);
         let p = DefaultParams::new(false, false, None);
-        let mut int = Interpreter::with_state(state.clone(), &mut stomach, &p);
-        let (success, s) = int.do_file(&path, HTMLColon::new(true));
+        let mut int = Interpreter::with_state(state.clone(), stomach.as_mut(), &p);
+        let (outcome, s) = int.do_file(&path, colon);
         let outfile = pathstr.to_string() + "test.xhtml";
         let mut file = std::fs::File::create(&outfile).unwrap();
         file.write_all(s.as_bytes()).expect("");
-        assert!(success)
+        assert!(outcome.success())
     }
 
     #[test]
@@ -134,12 +120,13 @@ mod tests {
                         if *done < max {
                             *done += 1;
                             println!("------------\n\nDoing {}\n\n---------------\n", path.to_str().unwrap());
-                            let mut stomach = NoShipoutRoutine::new();
+                            let colon = HTMLColon::new(true);
+                            let mut stomach = stomach_for(&colon);
                             let p = DefaultParams::new(false, false, None);
-                            let mut int = Interpreter::with_state(st.clone(), &mut stomach, &p);
-                            let (success, s) = int.do_file(&path, HTMLColon::new(true));
-                            assert!(success);
-                            if success {
+                            let mut int = Interpreter::with_state(st.clone(), stomach.as_mut(), &p);
+                            let (outcome, s) = int.do_file(&path, colon);
+                            assert!(outcome.success());
+                            if outcome.success() {
                                 let mut topcommands = int.state.commands.destroy();
                                 for (n,cmd) in topcommands.into_iter() {
                                     if n.to_string().starts_with("c_stex_module") {
@@ -154,12 +141,13 @@ mod tests {
             st
         }
         state = do_dir(d.clone(),d,state,Some(output.to_string()),&mut done,max);
-        let mut stomach = NoShipoutRoutine::new();
+        let colon = HTMLColon::new(true);
+        let mut stomach = stomach_for(&colon);
         let p = DefaultParams::new(false, false, None);
-        let mut int = Interpreter::with_state(state, &mut stomach, &p);
+        let mut int = Interpreter::with_state(state, stomach.as_mut(), &p);
         let path = Path::new("/home/jazzpirate/work/MathHub/sTeX/DemoExamples/source/quickstart.tex");
-        let (success, s) = int.do_file(path, HTMLColon::new(true));
-        assert!(success);
+        let (outcome, s) = int.do_file(path, colon);
+        assert!(outcome.success());
     }
 }
 
@@ -203,72 +191,90 @@ pub fn pdfium() -> Option<&'static Pdfium> {
         }
     }
 }
-pub fn pdf_to_img(path:&str) -> Option<image::DynamicImage> {
-    match pdfium() {
-        Some(pdfium) => {
-            match pdfium.load_pdf_from_file(&path,None) {
-                Ok(doc) => {
-                    let cfg = PdfRenderConfig::new().scale_page_by_factor(5.0);
-                    match doc.pages().iter().next().unwrap().render_with_config(&cfg) {
-                        Ok(mut bmp) => Some(bmp.as_image()),
-                        _ => None
-                    }
-                }
-                _ => None
-            }
+lazy_static! {
+    /// pdfium is not thread safe; serializes its initialization and use
+    static ref PDFIUM_LOCK : std::sync::Mutex<()> = std::sync::Mutex::new(());
+}
+/// Renders the page `page` (starting at 1) of the pdf file with content `bytes`, restricted to
+/// the page box `boxspec` (see [`pdfsvg::pdf_to_svg`])
+pub fn pdf_to_img(bytes:&[u8],page:i32,boxspec:Option<&str>) -> Option<image::DynamicImage> {
+    let _lock = PDFIUM_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let doc = pdfium()?.load_pdf_from_byte_slice(bytes,None).ok()?;
+    let page = doc.pages().get((page.max(1) - 1) as u16).ok()?;
+    let scale = 5.0;
+    let cfg = PdfRenderConfig::new().scale_page_by_factor(scale);
+    let img = page.render_with_config(&cfg).ok()?.as_image();
+    // pdfium renders the crop box
+    match (pdfsvg::page_box(&page,None),pdfsvg::page_box(&page,boxspec)) {
+        (Some(c),Some(b)) if c != b => {
+            let x = ((b.left().value - c.left().value).max(0.0) * scale) as u32;
+            let y = ((c.top().value - b.top().value).max(0.0) * scale) as u32;
+            let w = (b.width().value * scale) as u32;
+            let h = (b.height().value * scale) as u32;
+            Some(img.crop_imm(x,y,w.min(img.width().saturating_sub(x)),h.min(img.height().saturating_sub(y))))
         }
-        None => None
+        _ => Some(img)
     }
 }
 
 lazy_static! {
 
+    /// The versions of the installed pdfTeX if there is one, otherwise those of pdfTeX 1.40.25
     pub static ref VERSION_INFO : VersionInfo = {
         use std::process::Command;
-        use std::str;
 
-        let rs : Vec<u8> = Command::new("pdftex")
-            .arg("--version").output().expect("pdftex not found!")
-            .stdout;
-        let mut retstr = match str::from_utf8(rs.as_slice()) {
-            Ok(v) => v,
-            Err(_) => panic!("utils.rs 68")
-        };
+        match Command::new("pdftex").arg("--version").output() {
+            Ok(out) => std::str::from_utf8(out.stdout.as_slice()).ok()
+                .and_then(VersionInfo::parse).unwrap_or_default(),
+            Err(_) => VersionInfo::default()
+        }
+    };
+}
+impl Default for VersionInfo {
+    fn default() -> Self {
+        VersionInfo{texversion:"3.141592653".into(),etexversion:"2".into(),
+            etexrevision:".6".into(),pdftexversion:"140".into(),
+            pdftexrevision:"25".into()
+        }
+    }
+}
+impl VersionInfo {
+    /// Parses the output of `pdftex --version`
+    fn parse(mut retstr:&str) -> Option<VersionInfo> {
         if retstr.starts_with("MiKTeX") {
             // TODO better
-            VersionInfo{texversion:"0".into(),etexversion:"2".into(),
+            return Some(VersionInfo{texversion:"0".into(),etexversion:"2".into(),
                 etexrevision:".6".into(),pdftexversion:"140".into(),
                 pdftexrevision:"22".into()
-            }
-        } else {
-            retstr = retstr.strip_prefix("pdfTeX ").expect("Unknown TeX engine");
-            let mut pos = retstr.find("-").expect("TeX version string malformed");
-            let texversion = &retstr[0..pos];
-            retstr = &retstr[pos+1..];
-            pos = retstr.find(".").expect("TeX version string malformed");
-            let etexversion = &retstr[0..pos];
-            retstr = &retstr[pos..];
-            pos = retstr.find("-").expect("TeX version string malformed");
-            let etexrevision = &retstr[0..pos];
-            retstr = &retstr[pos+1..];
-            pos = retstr.find(".").expect("TeX version string malformed");
-            let pdftexversion1 = &retstr[0..pos];
-            retstr = &retstr[pos+1..];
-            pos = retstr.find(".").expect("TeX version string malformed");
-            let pdftexversion2 = &retstr[0..pos];
-            retstr = &retstr[pos+1..];
-            let pdftexversion = pdftexversion1.to_owned() + pdftexversion2;
-            pos = retstr.find(|x:char| !x.is_ascii_digit()).expect("TeX version string malformed");
-            let pdftexrevision = &retstr[0..pos];
-
-            VersionInfo{
-                texversion:texversion.into(),
-                etexversion:etexversion.into(),
-                etexrevision:etexrevision.into(),
-                pdftexversion:pdftexversion.into(),
-                pdftexrevision:pdftexrevision.into()}
+            })
         }
-    };
+        retstr = retstr.strip_prefix("pdfTeX ")?;
+        let mut pos = retstr.find("-")?;
+        let texversion = &retstr[0..pos];
+        retstr = &retstr[pos+1..];
+        pos = retstr.find(".")?;
+        let etexversion = &retstr[0..pos];
+        retstr = &retstr[pos..];
+        pos = retstr.find("-")?;
+        let etexrevision = &retstr[0..pos];
+        retstr = &retstr[pos+1..];
+        pos = retstr.find(".")?;
+        let pdftexversion1 = &retstr[0..pos];
+        retstr = &retstr[pos+1..];
+        pos = retstr.find(".")?;
+        let pdftexversion2 = &retstr[0..pos];
+        retstr = &retstr[pos+1..];
+        let pdftexversion = pdftexversion1.to_owned() + pdftexversion2;
+        pos = retstr.find(|x:char| !x.is_ascii_digit())?;
+        let pdftexrevision = &retstr[0..pos];
+
+        Some(VersionInfo{
+            texversion:texversion.into(),
+            etexversion:etexversion.into(),
+            etexrevision:etexrevision.into(),
+            pdftexversion:pdftexversion.into(),
+            pdftexrevision:pdftexrevision.into()})
+    }
 }
 
 static DEBUG : bool = true;
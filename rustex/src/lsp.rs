@@ -0,0 +1,421 @@
+//! A Language Server Protocol server for LaTeX documents, communicating over stdio.
+//!
+//! Keeps a warm [`State`] (`latex.ltx`, or a format file) and reprocesses a document on
+//! every change, publishing the resulting errors and warnings. Hover shows the `\meaning`
+//! of the control sequence under the cursor, go-to-definition jumps to where the document
+//! (or a package it loads) defined it, and completion offers all defined control sequences.
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use clap::Parser;
+use serde_json::{json, Value};
+use rustex::catcodes::CategoryCode;
+use rustex::interpreter::Interpreter;
+use rustex::interpreter::diagnostics::{Diagnostic, Severity};
+use rustex::interpreter::params::{DefaultParams, NoOutput, Verbosity};
+use rustex::interpreter::state::State;
+use rustex::ontology::LaTeXObject;
+use rustex::stomach::colon::NoColon;
+
+#[derive(Parser,Debug)]
+#[clap(author, version, about = "RusTeX language server (LSP over stdio)", long_about = None)]
+struct Parameters {
+    /// load the initial state from this format file instead of processing latex.ltx
+    #[clap(long)]
+    format: Option<String>,
+
+    /// ignored; stdio is the only transport
+    #[clap(long)]
+    stdio:bool
+}
+
+fn main() {
+    rustex::utils::with_stack_size(run)
+}
+fn run() {
+    let params : Parameters = Parameters::parse();
+    // without a state, the server stays up and reports why on every document
+    let state = match &params.format {
+        Some(f) => State::from_format_file(Path::new(f),&NoOutput::new(None))
+            .map_err(|e| std::format!("Could not load format {}: {}",f,e.msg)),
+        None => State::try_pdf_latex().map_err(|e| e.msg)
+    };
+    let mut server = Server { state, docs:HashMap::new() };
+    let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    while let Some(msg) = read_message(&mut stdin) {
+        let id = msg.get("id").cloned();
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| server.handle(msg))) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(_) => if let Some(id) = id {
+                send(json!({"jsonrpc":"2.0","id":id,"error":{"code":-32603,"message":"RusTeX panicked"}}))
+            }
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+fn read_message(r:&mut dyn BufRead) -> Option<Value> {
+    let mut len : Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line).ok()? == 0 { return None }
+        let line = line.trim_end();
+        if line.is_empty() { break }
+        if let Some((k,v)) = line.split_once(':') {
+            if k.eq_ignore_ascii_case("Content-Length") { len = v.trim().parse().ok() }
+        }
+    }
+    let mut buf = vec!(0u8;len?);
+    r.read_exact(&mut buf).ok()?;
+    serde_json::from_slice(&buf).ok()
+}
+fn send(v:Value) {
+    let s = v.to_string();
+    let mut out = std::io::stdout().lock();
+    write!(out,"Content-Length: {}\r\n\r\n{}",s.len(),s).unwrap();
+    out.flush().unwrap()
+}
+fn notify(method:&str,params:Value) {
+    send(json!({"jsonrpc":"2.0","method":method,"params":params}))
+}
+
+fn uri_to_path(uri:&str) -> PathBuf {
+    let s = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = vec!();
+    let mut it = s.bytes();
+    while let Some(b) = it.next() {
+        if b == b'%' {
+            let h : String = it.by_ref().take(2).map(|b| b as char).collect();
+            match u8::from_str_radix(&h,16) {
+                Ok(c) => bytes.push(c),
+                Err(_) => { bytes.push(b'%'); bytes.extend(h.bytes()) }
+            }
+        } else { bytes.push(b) }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).to_string())
+}
+fn path_to_uri(path:&str) -> String {
+    "file://".to_string() + &path.replace('%',"%25").replace(' ',"%20")
+}
+
+/// LSP positions count UTF-16 code units, TeX columns bytes
+fn utf16_to_byte(line:&str,character:usize) -> usize {
+    let mut units = 0;
+    for (i,c) in line.char_indices() {
+        if units >= character { return i }
+        units += c.len_utf16()
+    }
+    line.len()
+}
+fn byte_to_utf16(line:&str,byte:usize) -> usize {
+    line.char_indices().take_while(|(i,_)| *i < byte).map(|(_,c)| c.len_utf16()).sum()
+}
+
+fn is_letter(b:u8) -> bool { b.is_ascii_alphabetic() || b == b'@' }
+
+/// The name of the control sequence in `line` at byte `col`, assuming LaTeX's internal catcodes
+fn cs_in_line(line:&str,col:usize) -> Option<&str> {
+    let bytes = line.as_bytes();
+    let mut start = col.min(bytes.len());
+    // the cursor may be on the escape character itself
+    if start < bytes.len() && bytes[start] == b'\\' { start += 1 }
+    while start > 0 && is_letter(bytes[start - 1]) { start -= 1 }
+    if start == 0 || bytes[start - 1] != b'\\' { return None }
+    let mut end = start;
+    while end < bytes.len() && is_letter(bytes[end]) { end += 1 }
+    // control symbols like \,
+    if end == start && end < bytes.len() && bytes[end].is_ascii() { end += 1 }
+    if end == start { None } else { Some(&line[start..end]) }
+}
+
+// -------------------------------------------------------------------------------------------------
+
+struct Document {
+    path:PathBuf,
+    text:String,
+    /// the state after processing the document
+    state:Option<State>,
+    /// other files the last check published diagnostics for
+    published:Vec<String>
+}
+
+struct Server {
+    state:Result<State,String>,
+    docs:HashMap<String,Document>
+}
+
+impl Server {
+    /// returns false on `exit`
+    fn handle(&mut self,msg:Value) -> bool {
+        let method = msg["method"].as_str().unwrap_or("").to_string();
+        let id = msg.get("id").cloned();
+        let params = &msg["params"];
+        let result = match method.as_str() {
+            "initialize" => json!({
+                "capabilities":{
+                    "textDocumentSync":1,
+                    "hoverProvider":true,
+                    "definitionProvider":true,
+                    "completionProvider":{"triggerCharacters":["\\"]}
+                },
+                "serverInfo":{"name":"rustex-lsp","version":env!("CARGO_PKG_VERSION")}
+            }),
+            "initialized" => {
+                if let Err(e) = &self.state {
+                    notify("window/showMessage",json!({"type":1,"message":"RusTeX: ".to_string() + e}))
+                }
+                return true
+            }
+            "shutdown" => Value::Null,
+            "exit" => return false,
+            "textDocument/didOpen" => {
+                let doc = &params["textDocument"];
+                let uri = doc["uri"].as_str().unwrap_or("").to_string();
+                self.docs.insert(uri.clone(),Document {
+                    path:uri_to_path(&uri),
+                    text:doc["text"].as_str().unwrap_or("").to_string(),
+                    state:None,
+                    published:vec!()
+                });
+                self.check(&uri);
+                return true
+            }
+            "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+                // full synchronization: the last change is the whole document
+                if let (Some(doc),Some(text)) = (self.docs.get_mut(&uri),params["contentChanges"].as_array().and_then(|a| a.last()).and_then(|c| c["text"].as_str())) {
+                    doc.text = text.to_string()
+                }
+                self.check(&uri);
+                return true
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+                let published = self.docs.remove(&uri).map(|d| d.published).unwrap_or_default();
+                for u in published.iter().chain(std::iter::once(&uri)) {
+                    notify("textDocument/publishDiagnostics",json!({"uri":u,"diagnostics":[]}))
+                }
+                return true
+            }
+            "textDocument/hover" => self.hover(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/completion" => self.completion(params),
+            _ => match id {
+                Some(id) => {
+                    send(json!({"jsonrpc":"2.0","id":id,"error":{"code":-32601,"message":std::format!("Method not found: {}",method)}}));
+                    return true
+                }
+                None => return true
+            }
+        };
+        if let Some(id) = id { send(json!({"jsonrpc":"2.0","id":id,"result":result})) }
+        true
+    }
+
+    /// Reprocesses the document and publishes its diagnostics
+    fn check(&mut self,uri:&str) {
+        let doc = match self.docs.get_mut(uri) {
+            Some(d) => d,
+            None => return
+        };
+        let mut state = match &self.state {
+            Ok(s) => s.clone(),
+            Err(e) => {
+                notify("textDocument/publishDiagnostics",json!({"uri":uri,"diagnostics":[{
+                    "range":{"start":{"line":0,"character":0},"end":{"line":0,"character":0}},
+                    "severity":1,"source":"rustex","message":e
+                }]}));
+                return
+            }
+        };
+        let mut p = DefaultParams::new(false,true,Some(DefaultParams::default_listeners()));
+        p.verbosity = Verbosity::Quiet;
+        state.set_store_sources(true);
+        state.set_store_sites(true);
+        let (path,text) = (doc.path.clone(),doc.text.clone());
+        let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(||
+            Interpreter::do_string_with_state(&path,state,&text,NoColon::new(),&p)
+        ));
+        let mut diagnostics = p.diagnostics.take();
+        doc.state = match ret {
+            Ok((_,s,())) => Some(s),
+            Err(e) => {
+                let msg = e.downcast_ref::<String>().cloned().or(e.downcast_ref::<&str>().map(|s| s.to_string())).unwrap_or_default();
+                diagnostics.push(Diagnostic {
+                    severity:Severity::Error,message:"RusTeX panicked: ".to_string() + &msg,source:None,
+                    file:None,line:None,column:None,textrace:vec!(),backtrace:None
+                });
+                None
+            }
+        };
+        let docpath = doc.path.to_string_lossy().to_string();
+        // diagnostics from other files (packages, \input files) go to those files
+        let mut by_uri : HashMap<String,Vec<Value>> = HashMap::new();
+        by_uri.insert(uri.to_string(),vec!());
+        for d in diagnostics.iter() {
+            let (target,text) = match &d.file {
+                Some(f) if *f != docpath => (path_to_uri(f),std::fs::read_to_string(f).ok()),
+                _ => (uri.to_string(),None)
+            };
+            let (line,col) = match d.line {
+                Some(l) if l > 0 => {
+                    let text = match &text {
+                        Some(t) => t.lines().nth(l - 1).unwrap_or(""),
+                        None if target == uri => doc.text.lines().nth(l - 1).unwrap_or(""),
+                        None => ""
+                    };
+                    (l - 1,byte_to_utf16(text,d.column.unwrap_or(1).saturating_sub(1)))
+                }
+                _ => (0,0)
+            };
+            by_uri.entry(target).or_default().push(json!({
+                "range":{"start":{"line":line,"character":col},"end":{"line":line,"character":col + 1}},
+                "severity":match d.severity { Severity::Error => 1, Severity::Warning => 2, Severity::Info => 3 },
+                "source":d.source.clone().unwrap_or("rustex".to_string()),
+                "message":d.message.trim_end()
+            }))
+        }
+        // clear the files that had diagnostics from the last run but have none now
+        for old in std::mem::take(&mut doc.published) {
+            by_uri.entry(old).or_default();
+        }
+        doc.published = by_uri.keys().filter(|u| *u != uri).cloned().collect();
+        for (target,ds) in by_uri {
+            notify("textDocument/publishDiagnostics",json!({"uri":target,"diagnostics":ds}))
+        }
+    }
+
+    /// The name of the control sequence at the given position: from the source model of the
+    /// document if it was read there, otherwise from the text
+    fn cs_at(&self,params:&Value) -> Option<(&Document,String)> {
+        let doc = self.docs.get(params["textDocument"]["uri"].as_str()?)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let text = doc.text.lines().nth(line).unwrap_or("");
+        let col = utf16_to_byte(text,params["position"]["character"].as_u64()? as usize);
+        let from_sources = doc.state.as_ref().and_then(|s| s.sources()).and_then(|f|
+            match f.object_at(&doc.path.to_string_lossy(),line + 1,col + 1) {
+                Some(LaTeXObject::Token(t)) if t.catcode == CategoryCode::Escape => Some(t.name().to_string()),
+                _ => None
+            }
+        );
+        match from_sources {
+            Some(n) => Some((doc,n)),
+            None => cs_in_line(text,col).map(|n| (doc,n.to_string()))
+        }
+    }
+    fn hover(&self,params:&Value) -> Value {
+        let (doc,name) = match self.cs_at(params) {
+            Some(r) => r,
+            None => return Value::Null
+        };
+        let state = match doc.state.as_ref().or(self.state.as_ref().ok()) {
+            Some(s) => s,
+            None => return Value::Null
+        };
+        let cmd = state.commands.get(&name.as_str().into());
+        let meaning = match &cmd {
+            Some(cmd) => cmd.meaning(state.catcodes.get_scheme()).to_string(),
+            None => "undefined".to_string()
+        };
+        let mut value = std::format!("```latex\n\\{}={}\n```",name,meaning);
+        if let Some(site) = cmd.and_then(|c| c.defined_at) {
+            value += &std::format!("\n\nDefined by {}",site)
+        }
+        json!({"contents":{"kind":"markdown","value":value}})
+    }
+    fn definition(&self,params:&Value) -> Value {
+        let (doc,name) = match self.cs_at(params) {
+            Some(r) => r,
+            None => return Value::Null
+        };
+        let state = match doc.state.as_ref().or(self.state.as_ref().ok()) {
+            Some(s) => s,
+            None => return Value::Null
+        };
+        match state.defined_at(&name.as_str().into()) {
+            Some(site) if site.file.len() > 0 => {
+                let line = site.line.saturating_sub(1);
+                let col = site.column.saturating_sub(1);
+                json!({"uri":path_to_uri(&site.file.to_string()),"range":{"start":{"line":line,"character":col},"end":{"line":line,"character":col}}})
+            }
+            _ => Value::Null
+        }
+    }
+    fn completion(&self,params:&Value) -> Value {
+        let doc = match params["textDocument"]["uri"].as_str().and_then(|u| self.docs.get(u)) {
+            Some(d) => d,
+            None => return Value::Null
+        };
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let text = doc.text.lines().nth(line).unwrap_or("");
+        let col = utf16_to_byte(text,params["position"]["character"].as_u64().unwrap_or(0) as usize).min(text.len());
+        let bytes = text.as_bytes();
+        let mut start = col;
+        while start > 0 && is_letter(bytes[start - 1]) { start -= 1 }
+        if start == 0 || bytes[start - 1] != b'\\' { return Value::Null }
+        let prefix = &text[start..col];
+        let state = match doc.state.as_ref().or(self.state.as_ref().ok()) {
+            Some(s) => s,
+            None => return Value::Null
+        };
+        // the store holds the current meaning of every command, including group-local ones
+        let mut names : Vec<String> = state.commands.base().iter()
+            .filter(|(_,c)| c.is_some())
+            .map(|(n,_)| n.to_string())
+            .filter(|n| n.starts_with(prefix) && !n.is_empty() && !n.chars().any(|c| c.is_control()))
+            .collect();
+        names.sort();
+        const MAX : usize = 500;
+        let incomplete = names.len() > MAX;
+        let items : Vec<Value> = names.into_iter().take(MAX).map(|n| {
+            let detail : String = match state.commands.get(&n.as_str().into()) {
+                Some(c) => c.meaning(state.catcodes.get_scheme()).to_string().chars().take(80).collect(),
+                None => String::new()
+            };
+            json!({"label":n,"kind":3,"detail":detail})
+        }).collect();
+        json!({"isIncomplete":incomplete,"items":items})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uris() {
+        assert_eq!(uri_to_path("file:///home/a%20b/c.tex"),PathBuf::from("/home/a b/c.tex"));
+        assert_eq!(uri_to_path("file:///%C3%A4.tex"),PathBuf::from("/ä.tex"));
+        assert_eq!(uri_to_path("file:///a%zz.tex"),PathBuf::from("/a%zz.tex"));
+        assert_eq!(uri_to_path("/plain.tex"),PathBuf::from("/plain.tex"));
+        assert_eq!(uri_to_path(&path_to_uri("/a b/100%.tex")),PathBuf::from("/a b/100%.tex"));
+    }
+
+    #[test]
+    fn utf16_columns() {
+        assert_eq!(utf16_to_byte("abc",2),2);
+        assert_eq!(utf16_to_byte("äbc",1),2);
+        // 𝔸 is two UTF-16 code units and four bytes
+        assert_eq!(utf16_to_byte("𝔸x",2),4);
+        assert_eq!(utf16_to_byte("ab",10),2);
+        assert_eq!(byte_to_utf16("𝔸x",4),2);
+        assert_eq!(byte_to_utf16("äbc",2),1);
+    }
+
+    #[test]
+    fn control_sequence_under_cursor() {
+        let line = r"a \textbf{x} \@ifnextchar\, y";
+        assert_eq!(cs_in_line(line,2),Some("textbf"));
+        assert_eq!(cs_in_line(line,5),Some("textbf"));
+        assert_eq!(cs_in_line(line,9),Some("textbf"));
+        assert_eq!(cs_in_line(line,10),None);
+        assert_eq!(cs_in_line(line,14),Some("@ifnextchar"));
+        assert_eq!(cs_in_line(line,25),Some(","));
+        assert_eq!(cs_in_line(line,0),None);
+        assert_eq!(cs_in_line(line,100),None);
+        assert_eq!(cs_in_line(r"\\",0),Some(r"\"));
+    }
+}
@@ -1,18 +1,23 @@
-use std::borrow::BorrowMut;
-use std::collections::HashMap;
 use std::env;
-use std::fmt::Display;
-use std::io::Write;
-use std::ops::Deref;
-use std::path::Path;
-use rustex::interpreter::Interpreter;
-use rustex::interpreter::params::{DefaultParams, NoOutput};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rustex::filesystem::{FileSystem, OverlayFS, PhysicalFS};
+use rustex::filesystem::archive::{is_archive, ArchiveFS};
+use rustex::interpreter::{Interpreter, Outcome};
+use rustex::interpreter::deps::Dependencies;
+use rustex::interpreter::diagnostics::{Diagnostic, Severity};
+use rustex::interpreter::debugger::{Breakpoint, Debugger};
+use rustex::interpreter::profiler::Profiler;
+use rustex::interpreter::params::{DefaultParams, NoOutput, OutFiles, Verbosity};
+use rustex::interpreter::state::State;
+use rustex::references::SourceMap;
+use rustex::stomach::assets::Assets;
 use rustex::stomach::html::HTMLColon;
-use rustex::stomach::NoShipoutRoutine;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use regex::Regex;
 use rustex::commands::pdftex::pdftex_commands;
-use rustex::commands::pgfsvg::pgf_commands;
 use rustex::commands::rustex_specials::rustex_special_commands;
 use rustex::stomach::colon::NoColon;
 use rustex::utils::PWD;
@@ -20,11 +25,12 @@ use rustex::utils::PWD;
 #[derive(Parser,Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Parameters {
-    /// Input file (tex)
+    /// Input file (tex); may also be a zip or tar(.gz) archive, whose main file (the one with
+    /// \documentclass) is processed, or a file in one, e.g. paper.zip/main.tex
     #[clap(short, long)]
     input: Option<String>,
 
-    /// Input dir (tex)
+    /// Input dir (tex); all files matching --include (and not --exclude) are processed
     #[clap(short, long)]
     dir: Option<String>,
 
@@ -36,34 +42,263 @@ struct Parameters {
     #[clap(short, long)]
     output: Option<String>,
 
-    /// use only one thread
+    /// Directory for the output (and the files written via \openout); in --dir mode, the
+    /// structure of the input directory is mirrored
+    #[clap(long)]
+    output_dir: Option<String>,
+
+    /// In --dir mode: only process files whose path (relative to the input dir) matches one of
+    /// these globs [default: **/*.tex]. Globs without / match the file name.
+    #[clap(long)]
+    include: Vec<String>,
+
+    /// In --dir mode: skip files and directories whose path (relative to the input dir) matches
+    /// one of these globs. Globs without / match the file or directory name.
+    #[clap(long)]
+    exclude: Vec<String>,
+
+    /// Rerun until the files written via \openout (.aux, .toc, ...) stabilize, at most this many times
+    #[clap(short, long)]
+    passes: Option<usize>,
+
+    /// Process the input file (if any) on top of latex.ltx and write the resulting state to this format file
+    #[clap(long)]
+    dump: Option<String>,
+
+    /// A zip or tar(.gz) archive (e.g. a packed texmf tree) whose files are found by name, like
+    /// the files of the TeX installation; may be given several times
+    #[clap(long, value_name = "ARCHIVE")]
+    root: Vec<String>,
+
+    /// Load the initial state from this format file instead of processing latex.ltx
+    #[clap(long)]
+    format: Option<String>,
+
+    /// Load the initial state from this format file if it exists; otherwise, process latex.ltx
+    /// and write the resulting state to it
+    #[clap(long)]
+    state_cache: Option<String>,
+
+    /// Break the output into pages of height \vsize, as built by the output routine ("print layout")
+    #[clap(long)]
+    paginate:bool,
+
+    /// With --input: write a source map (JSON) from the ids of the generated HTML elements to
+    /// the ranges in the .tex files they come from to this file
+    #[clap(long)]
+    sourcemap: Option<String>,
+
+    /// Write images to this directory, named after the hash of their content, and reference them
+    /// from the HTML instead of embedding them
+    #[clap(long)]
+    assets: Option<String>,
+
+    /// Add rustex:width, rustex:font etc. attributes to the HTML, for debugging
+    #[clap(long)]
+    rustex_attrs:bool,
+
+    /// With --input: pause before the first command and read debugger commands from stdin
+    /// (type help at the prompt)
+    #[clap(long)]
+    debug:bool,
+
+    /// With --input: pause in the debugger before the control sequence \cs or at file:line;
+    /// may be given several times
+    #[clap(long = "break", value_name = "\\cs|file:line")]
+    breakpoints: Vec<String>,
+
+    /// With --input: write a table of the expansions, executions and time per control sequence
+    /// and file to this file
+    #[clap(long)]
+    profile: Option<String>,
+
+    /// With --input: write the time spent per stack of macro expansions to this file, in the
+    /// collapsed stack format of flamegraph tools
+    #[clap(long)]
+    profile_stacks: Option<String>,
+
+    /// Abort processing a file after this many seconds
+    #[clap(long)]
+    timeout: Option<f64>,
+
+    /// Abort processing a file after this many expansions
+    #[clap(long)]
+    max_expansions: Option<usize>,
+
+    /// \jobname (default: the name of the input file); not allowed with --dir, since every
+    /// file needs a job name of its own
+    #[clap(long, conflicts_with = "dir")]
+    jobname: Option<String>,
+
+    /// Let \write18 execute shell commands
+    #[clap(long)]
+    shell_escape:bool,
+
+    /// Interaction mode; in all but errorstopmode, processing continues after errors
+    #[clap(long, value_enum)]
+    interaction: Option<Interaction>,
+
+    /// What to print while processing [default: normal, or quiet with --json]
+    #[clap(long, value_enum)]
+    verbosity: Option<Level>,
+
+    /// Print a machine-readable summary (input, output, success, passes, diagnostics) as JSON
+    #[clap(long)]
+    json:bool,
+
+    /// Write all errors and warnings as JSON to this file
+    #[clap(long)]
+    diagnostics: Option<String>,
+
+    /// In --dir mode: process this many files concurrently (files are then independent of each
+    /// other, i.e. sTeX modules are not shared between them)
+    #[clap(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    /// In --dir mode: skip files whose output is up to date, i.e. neither RusTeX nor any of the
+    /// files read while producing it (recorded in <output>.deps) have changed since
+    #[clap(long)]
+    incremental:bool,
+
+    /// Use only one thread
     #[clap(short, long)]
     singlethreaded:bool,
 
-    /// process latex.ltx verbosely
+    /// Process latex.ltx verbosely
     #[clap(long)]
     test:bool
 
 }
-static mut SKIP : bool = false;
-static SKIP_UNTIL : &str = "source/mmt.tex"; //integernumbers.en.tex
-static DOMAX: usize = 50;
-static mut DONE:usize = 0;
+
+#[derive(ValueEnum,Clone,Copy,Debug)]
+enum Interaction { Batchmode, Nonstopmode, Scrollmode, Errorstopmode }
+impl Interaction {
+    /// The value of `\interactionmode`
+    fn mode(&self) -> i32 {
+        match self {
+            Interaction::Batchmode => 0,
+            Interaction::Nonstopmode => 1,
+            Interaction::Scrollmode => 2,
+            Interaction::Errorstopmode => 3
+        }
+    }
+}
+
+#[derive(ValueEnum,Clone,Copy,Debug)]
+enum Level { Quiet, Errors, Normal, Verbose }
+impl Level {
+    fn verbosity(&self) -> Verbosity {
+        match self {
+            Level::Quiet => Verbosity::Quiet,
+            Level::Errors => Verbosity::Errors,
+            Level::Normal => Verbosity::Normal,
+            Level::Verbose => Verbosity::Verbose
+        }
+    }
+}
+
+/// A glob for --include/--exclude: `**` matches any number of directories, `*` and `?` anything
+/// but `/`
+struct Glob {
+    regex:Regex,
+    name_only:bool
+}
+impl Glob {
+    fn new(pattern:&str) -> Glob {
+        let mut re = "^".to_string();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        re.push_str("(?:.*/)?")
+                    } else { re.push_str(".*") }
+                }
+                '*' => re.push_str("[^/]*"),
+                '?' => re.push_str("[^/]"),
+                c => re.push_str(&regex::escape(&c.to_string()))
+            }
+        }
+        re.push('$');
+        Glob { regex:Regex::new(&re).unwrap(), name_only:!pattern.contains('/') }
+    }
+    fn matches(&self,rel:&Path) -> bool {
+        let s = if self.name_only {
+            rel.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default()
+        } else {
+            rel.to_string_lossy().replace('\\',"/")
+        };
+        self.regex.is_match(&s)
+    }
+}
+
+fn collect_files(root:&Path,dir:&Path,include:&[Glob],exclude:&[Glob],ret:&mut Vec<PathBuf>) {
+    let mut entries : Vec<PathBuf> = std::fs::read_dir(dir).unwrap().map(|f| f.unwrap().path()).collect();
+    entries.sort();
+    for path in entries {
+        let rel = path.strip_prefix(root).unwrap();
+        if exclude.iter().any(|g| g.matches(rel)) { continue }
+        if path.is_dir() {
+            collect_files(root,&path,include,exclude,ret)
+        } else if include.iter().any(|g| g.matches(rel)) {
+            ret.push(path)
+        }
+    }
+}
+
+fn absolute(p:&str) -> PathBuf {
+    let path = Path::new(p).to_path_buf();
+    if path.is_absolute() { path } else { env::current_dir().unwrap().join(path) }
+}
+
+fn summary_json(input:&Path,output:Option<&Path>,outcome:Outcome,passes:usize,diagnostics:&[Diagnostic]) -> serde_json::Value {
+    let count = |s:Severity| diagnostics.iter().filter(|d| d.severity == s).count();
+    serde_json::json!({
+        "input":input.display().to_string(),
+        "output":output.map(|o| o.display().to_string()),
+        "success":outcome.success(),
+        "cancelled":outcome == Outcome::Cancelled,
+        "passes":passes,
+        "errors":count(Severity::Error),
+        "warnings":count(Severity::Warning),
+        "diagnostics":diagnostics.iter().map(|d| d.json()).collect::<Vec<_>>()
+    })
+}
+
+/// The exit status if a file could not be processed (or processing it failed)
+const EXIT_FAILED : i32 = 1;
+/// The exit status if a run was stopped via its cancellation token
+const EXIT_CANCELLED : i32 = 2;
+
+fn exit_status(outcome:Outcome) -> i32 {
+    match outcome {
+        Outcome::Success => 0,
+        Outcome::Failure => EXIT_FAILED,
+        Outcome::Cancelled => EXIT_CANCELLED
+    }
+}
+
+/// Prints `msg` to stderr and exits with a non-zero status
+fn fail(msg:&str) -> ! {
+    eprintln!("{}",msg);
+    std::process::exit(EXIT_FAILED)
+}
 
 fn main() {
     rustex::utils::with_stack_size(run)
 }
 fn run() {
     let params : Parameters = Parameters::parse();
-    use rustex::interpreter::state::State;
-    //use magick_rust::{MagickWand, magick_wand_genesis};
-    use rustex::fonts::convert::*;
 
     if params.test {
         let state = rustex::utils::with_stack_size(|| {
-            use rustex::interpreter::params::DefaultParams;
             println!("Testing latex.ltx...");
-            unsafe {rustex::kpathsea::LOG = true};
+            match rustex::kpathsea::installation_summary() {
+                Ok(s) => print!("{}",s),
+                Err(e) => println!("{}",e)
+            }
             let mut state = State::new();
             let p = DefaultParams::new(false, false, None);
 
@@ -92,124 +327,345 @@ fn run() {
         return
     }
 
-    match params.input {
-        None => {
-            match params.dir {
+    let verbosity = match params.verbosity {
+        Some(l) => l.verbosity(),
+        None if params.json => Verbosity::Quiet,
+        None => Verbosity::Normal
+    };
+    let chatty = verbosity >= Verbosity::Normal;
+    let mut archives : Vec<Arc<ArchiveFS>> = vec!();
+    for r in &params.root {
+        match ArchiveFS::open(&absolute(r)) {
+            Ok(a) => archives.push(Arc::new(a)),
+            Err(e) => {
+                println!("Could not read {}: {}",r,e);
+                return
+            }
+        }
+    }
+    let mut input = params.input.as_ref().map(|i| absolute(i));
+    // an input in an archive, e.g. paper.zip or paper.zip/main.tex
+    let input_archive = input.as_ref().and_then(|i| i.ancestors().find(|a| a.is_file() && is_archive(a)).map(|a| a.to_path_buf()));
+    if let Some(a) = &input_archive {
+        let fs = match ArchiveFS::open(a) {
+            Ok(fs) => fs,
+            Err(e) => {
+                println!("Could not read {}: {}",a.display(),e);
+                return
+            }
+        };
+        if input.as_ref() == Some(a) {
+            match fs.main_file() {
+                Some(m) => input = Some(m),
                 None => {
-                    println!("No file given. Testing latex.ltx...");
-                    let state = State::pdf_latex();
-                    state.commands.get(&"eTeXversion".into()).expect("");
-                    println!("\n\nSuccess! \\o/")
+                    println!("No main file (with \\documentclass) found in {}",a.display());
+                    return
+                }
+            }
+        }
+        archives.insert(0,Arc::new(fs))
+    }
+    // the archives given via --root (and an archive input) on top of the disk, which files are
+    // written to
+    let fs : Arc<dyn FileSystem> = if archives.is_empty() { Arc::new(PhysicalFS) } else {
+        let mut layers : Vec<Box<dyn FileSystem>> = archives.iter().map(|a| Box::new(a.clone()) as Box<dyn FileSystem>).collect();
+        layers.push(Box::new(PhysicalFS));
+        Arc::new(OverlayFS::new(layers))
+    };
+    let make_params = |outfiles:OutFiles| {
+        let mut p = DefaultParams::new(verbosity == Verbosity::Verbose,params.singlethreaded,None);
+        p.verbosity = verbosity;
+        p.backtraces = params.diagnostics.is_some();
+        p.jobname = params.jobname.clone();
+        p.shell_escape = params.shell_escape;
+        p.outfiles = outfiles;
+        p.limits.timeout = params.timeout.map(Duration::from_secs_f64);
+        p.limits.expansions = params.max_expansions;
+        p.filesystem = Box::new(fs.clone());
+        p
+    };
+    let interaction = params.interaction.map(|i| i.mode());
+    let needs_latex = params.format.is_none() && !params.state_cache.as_ref().map(|c| Path::new(c).exists()).unwrap_or(false);
+    if needs_latex {
+        if let Err(e) = rustex::kpathsea::check_installation() { fail(&e) }
+    }
+    let initial_state = || {
+        let mut state = match (&params.format,&params.state_cache) {
+            (Some(f),_) => match State::from_format_file(Path::new(f),&NoOutput::new(None)) {
+                Ok(s) => s,
+                Err(e) => fail(&format!("Could not load format {}: {}",f,e.msg))
+            }
+            (None,Some(c)) if Path::new(c).exists() => match State::from_format_file(Path::new(c),&NoOutput::new(None)) {
+                Ok(s) => s,
+                Err(e) => {
+                    if chatty { println!("Ignoring state cache {}: {}",c,e.msg) }
+                    let s = State::try_pdf_latex().unwrap_or_else(|e| fail(&e.msg));
+                    if let Err(e) = s.dump_to_file(Path::new(c),&PhysicalFS) { eprintln!("Could not write state cache {}: {}",c,e.msg) }
+                    s
+                }
+            }
+            (None,Some(c)) => {
+                let s = State::try_pdf_latex().unwrap_or_else(|e| fail(&e.msg));
+                if let Err(e) = s.dump_to_file(Path::new(c),&PhysicalFS) { eprintln!("Could not write state cache {}: {}",c,e.msg) }
+                s
+            }
+            (None,None) => State::try_pdf_latex().unwrap_or_else(|e| fail(&e.msg))
+        };
+        if let Some(i) = interaction { state.set_interaction_mode(i,true) }
+        state
+    };
+    let paginate = params.paginate;
+    let rustex_attrs = params.rustex_attrs;
+    // the source map of the last colon created, i.e. of the last pass
+    let sourcemap : Mutex<Option<Arc<Mutex<SourceMap>>>> = Mutex::new(None);
+    let assets = params.assets.as_ref().map(|d| Assets::new(absolute(d),String::new(),fs.clone()));
+    // for an HTML file in html_dir
+    let colon = |html_dir:&Path| {
+        let mut c = HTMLColon::new(true);
+        c.paginate = paginate;
+        c.rustex_attrs = rustex_attrs;
+        if let Some(a) = &assets {
+            let url = pathdiff::diff_paths(&a.dir,html_dir).unwrap_or(a.dir.clone());
+            c.assets = Some(a.with_url(url.to_string_lossy().replace('\\',"/")))
+        }
+        if params.sourcemap.is_some() && params.input.is_some() {
+            *sourcemap.lock().unwrap() = Some(c.sourcemap())
+        }
+        c
+    };
+
+    if let Some(fmt) = &params.dump {
+        let mut state = initial_state();
+        if let Some(i) = &input {
+            let p = make_params(OutFiles::Memory);
+            let (outcome,s,_) = Interpreter::do_file_with_state(i,state,NoColon::new(),&p);
+            if !outcome.success() {
+                fail(&format!("\n\nFailed processing {}",i.display()))
+            }
+            state = s
+        }
+        match state.dump_to_file(Path::new(fmt),&PhysicalFS) {
+            Ok(_) => println!("\n\nSuccess! \\o/\nFormat written to {}",fmt),
+            Err(e) => fail(&format!("\n\nFailed\n{}",e.msg))
+        }
+        return
+    }
+
+    let status;
+    let mut summaries : Vec<serde_json::Value> = vec!();
+    let mut diagnostics : Vec<Diagnostic> = vec!();
+    match (&input,&params.dir) {
+        (None,None) => {
+            println!("No file given. Testing latex.ltx...");
+            let state = initial_state();
+            state.commands.get(&"eTeXversion".into()).expect("");
+            println!("\n\nSuccess! \\o/");
+            return
+        }
+        (None,Some(d)) => {
+            let root = absolute(d);
+            let include : Vec<Glob> = if params.include.is_empty() { vec!(Glob::new("**/*.tex")) } else {
+                params.include.iter().map(|g| Glob::new(g)).collect()
+            };
+            let exclude : Vec<Glob> = params.exclude.iter().map(|g| Glob::new(g)).collect();
+            let mut files = vec!();
+            collect_files(&root,&root,&include,&exclude,&mut files);
+            let outdir = params.output_dir.as_ref().map(|d| absolute(d));
+            let out_path = |path:&Path| -> PathBuf {
+                match &outdir {
+                    Some(o) => o.join(path.strip_prefix(&root).unwrap()).with_extension("html"),
+                    None => path.with_extension("html")
                 }
-                Some(d) => {
-                    let max = 10;
-                    let mut done = 0;
-                    let mut state = State::pdf_latex();
-                    fn do_dir<P: AsRef<Path>,D:Display>(s : P,d:D,mut st : State,out:Option<String>) -> State {
-                        //println!("{}",d);
-                        for f in std::fs::read_dir(s).unwrap() {
-                            let f = f.unwrap();
-                            let path = f.path();
-                            if std::fs::metadata(&path).unwrap().is_dir() {
-                                let init = path.to_str().unwrap();
-                                if !init.ends_with(".git") &&
-                                    !init.ends_with("content") &&
-                                    !init.ends_with("errors") &&
-                                    !init.ends_with("narration") &&
-                                    !init.ends_with("relational") &&
-                                    !init.ends_with("buildresults") &&
-                                    !init.ends_with("xhtml") &&
-                                    !init.ends_with("export") &&
-                                    !init.ends_with("lib")
-                                    {
-                                    st = do_dir(path.clone(),path.display(),st.clone(),out.clone())
-                                }
-                            } else {
-                                if path.to_str().unwrap().ends_with(".tex") {
-                                    if unsafe{!SKIP} && path.to_str().unwrap().ends_with(SKIP_UNTIL) {
-                                        unsafe {SKIP = true}
-                                    };
-                                    if unsafe{SKIP && (DONE < DOMAX)} {
-                                        unsafe {DONE += 1};
-                                        println!("------------\n\nDoing {}\n\n---------------\n", path.to_str().unwrap());
-                                        let mut stomach = NoShipoutRoutine::new();
-                                        let p = DefaultParams::new(false, false, None);
-                                        let mut int = Interpreter::with_state(st.clone(), stomach.borrow_mut(), &p);
-                                        let (success, s) = int.do_file(&path, HTMLColon::new(true));
-                                        if success {
-                                            let mut topcommands = int.state.commands.destroy();
-                                            for (n,cmd) in topcommands.into_iter() {
-                                                if n.to_string().starts_with("c_stex_module") {
-                                                    st.commands.set(n,cmd.map(|x| x.clean()),true);
-                                                }
-                                            }
-                                        }
-                                        match out {
-                                            None => if success { println!("\n\nSuccess!\n{}", s) } else { println!("\n\nFailed\n{}", s) },
-                                            Some(ref f) => {
-                                                let mut file = std::fs::File::create(&f).unwrap();
-                                                file.write_all(s.as_bytes()).expect("");
-                                                if success {
-                                                    println!("\n\nSuccess! \\o/\nResult written to {}", f)
-                                                } else {
-                                                    println!("\n\nFailed\nPartial result written to {}", f)
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
+            };
+            let deps_path = |out:&Path| -> PathBuf {
+                let mut s = out.as_os_str().to_owned();
+                s.push(".deps");
+                PathBuf::from(s)
+            };
+            let mut up_to_date = vec!();
+            if params.incremental {
+                files.retain(|path| {
+                    let out = out_path(path);
+                    let current = out.exists() && match Dependencies::read_from(&deps_path(&out)) {
+                        Some(d) => d.up_to_date(fs.as_ref()),
+                        None => false
+                    };
+                    if current { up_to_date.push(path.clone()) }
+                    !current
+                });
+            }
+            let format = params.format.as_ref().or(params.state_cache.as_ref()).map(|f| absolute(f));
+            let target = |path:&Path| -> (PathBuf,OutFiles) {
+                let target = out_path(path);
+                match &outdir {
+                    Some(_) => {
+                        let dir = target.parent().unwrap().to_path_buf();
+                        std::fs::create_dir_all(&dir).expect("Could not create output directory");
+                        (target,OutFiles::Dir(dir))
+                    }
+                    None => (target,OutFiles::JobDir)
+                }
+            };
+            let results : Mutex<Vec<(PathBuf,Outcome,serde_json::Value,Vec<Diagnostic>)>> = Mutex::new(vec!());
+            let done = |path:&Path,outcome:Outcome,passes:usize,ret:Result<String,String>,mut deps:Dependencies,p:&DefaultParams| {
+                let (out,_) = target(path);
+                let outcome = match ret {
+                    Ok(s) => {
+                        std::fs::write(&out,s.as_bytes()).expect("Could not write output");
+                        if chatty {
+                            match outcome {
+                                Outcome::Success => println!("\n\nSuccess! \\o/\n{} written to {}", path.display(), out.display()),
+                                Outcome::Failure => println!("\n\nFailed\nPartial result of {} written to {}", path.display(), out.display()),
+                                Outcome::Cancelled => println!("\n\nCancelled\nPartial result of {} written to {}", path.display(), out.display())
                             }
                         }
-                        st
+                        outcome
+                    }
+                    Err(msg) => {
+                        if verbosity >= Verbosity::Errors { eprintln!("\n\nFailed\n{} panicked: {}",path.display(),msg) }
+                        Outcome::Failure
+                    }
+                };
+                let success = outcome.success();
+                if params.incremental {
+                    let deps_file = deps_path(&out);
+                    if success {
+                        if let Some(f) = &format { deps.add_file(f,fs.as_ref()) }
+                        deps.write_to(&deps_file).expect("Could not write dependencies");
+                    } else if deps_file.exists() {
+                        std::fs::remove_file(&deps_file).expect("Could not remove dependencies");
                     }
-                    state = do_dir(d.clone(),d,state,params.output.clone());
-                    let mut stomach = NoShipoutRoutine::new();
-                    let p = DefaultParams::new(false, false, None);
-                    let mut int = Interpreter::with_state(state, stomach.borrow_mut(), &p);
-                    let path = Path::new("/home/jazzpirate/work/MathHub/sTeX/DemoExamples/source/quickstart.tex");
-                    int.do_file(path, HTMLColon::new(true));
                 }
+                let ds = p.diagnostics.take();
+                let summary = summary_json(path,if success || out.exists() {Some(&out)} else {None},outcome,passes,&ds);
+                results.lock().unwrap().push((path.to_path_buf(),outcome,summary,ds));
+            };
+            let state = initial_state();
+            if params.jobs > 1 {
+                Interpreter::do_files_parallel(&files,&state,params.jobs,params.passes.unwrap_or(1),
+                    |path| colon(out_path(path).parent().unwrap()),
+                    |path| make_params(target(path).1),
+                    |r,p| done(&r.path,r.outcome,r.passes,r.result,r.dependencies,p)
+                )
+            } else {
+                let mut state = state;
+                for path in &files {
+                    if chatty { println!("------------\n\nDoing {}\n\n---------------\n", path.display()) }
+                    let p = make_params(target(path).1);
+                    let (outcome,s,ret,passes) = Interpreter::do_file_with_state_multipass(path,state.clone(),|| colon(out_path(path).parent().unwrap()),params.passes.unwrap_or(1),&p);
+                    let deps = s.dependencies();
+                    if outcome.success() {
+                        // keep sTeX modules for later files
+                        for (n,cmd) in s.commands.destroy().into_iter() {
+                            if n.to_string().starts_with("c_stex_module") {
+                                state.commands.set(n,cmd.map(|x| x.clean()),true);
+                            }
+                        }
+                    }
+                    done(path,outcome,passes,Ok(ret),deps,&p)
+                }
+            }
+            let mut results = results.into_inner().unwrap();
+            results.sort_by(|a,b| a.0.cmp(&b.0));
+            let failed : Vec<&PathBuf> = results.iter().filter(|r| r.1 == Outcome::Failure).map(|r| &r.0).collect();
+            let cancelled : Vec<&PathBuf> = results.iter().filter(|r| r.1 == Outcome::Cancelled).map(|r| &r.0).collect();
+            if chatty {
+                println!("\n\nProcessed {} files: {} succeeded, {} failed",results.len(),results.len() - failed.len() - cancelled.len(),failed.len());
+                if !cancelled.is_empty() { println!("{} files cancelled",cancelled.len()) }
+                if !up_to_date.is_empty() { println!("{} files skipped (up to date)",up_to_date.len()) }
+                for f in &failed { println!("  failed: {}",f.display()) }
+                for f in &cancelled { println!("  cancelled: {}",f.display()) }
             }
+            // failures take precedence over cancellations
+            status = results.iter().map(|r| exit_status(r.1)).filter(|s| *s != 0).min().unwrap_or(0);
+            for (_,_,summary,ds) in results {
+                summaries.push(summary);
+                diagnostics.extend(ds);
+            }
+            if params.json { println!("{}",serde_json::Value::from(summaries)) }
         }
-        Some(i) => {
-            let mut path = Path::new(&i).to_path_buf();
-            if !path.is_absolute() {
-                path = env::current_dir().unwrap().join(path);
-            }
-            let mut stomach = NoShipoutRoutine::new();
-            let p = DefaultParams::new(false,params.singlethreaded,None);
-            let state = State::pdf_latex();
-            let mut int = Interpreter::with_state(state,stomach.borrow_mut(),&p);
-            let (success,s) = match params.text {
-                Some(s) =>
-                    int.do_string(&*path,s.as_str(),HTMLColon::new(true)),
+        (Some(path),_) => {
+            let outdir = params.output_dir.as_ref().map(|d| absolute(d));
+            let out = match (&params.output,&outdir) {
+                (Some(f),_) => Some(absolute(f)),
+                (None,Some(o)) => Some(o.join(path.file_name().unwrap()).with_extension("html")),
+                (None,None) => None
+            };
+            let outfiles = match &outdir {
+                Some(o) => {
+                    std::fs::create_dir_all(o).expect("Could not create output directory");
+                    OutFiles::Dir(o.clone())
+                }
+                // archives are read-only
+                None if input_archive.is_some() => OutFiles::Memory,
+                None => OutFiles::JobDir
+            };
+            let html_dir = match &out {
+                Some(o) => o.parent().unwrap().to_path_buf(),
+                None => env::current_dir().unwrap()
+            };
+            let mut p = make_params(outfiles);
+            if params.debug || !params.breakpoints.is_empty() {
+                let breakpoints = params.breakpoints.iter().map(|b| Breakpoint::parse(b)).collect();
+                p.debugger = Some(Debugger::stdio(breakpoints,params.debug))
+            }
+            if params.profile.is_some() || params.profile_stacks.is_some() {
+                p.profiler = Some(Profiler::new())
+            }
+            let state = initial_state();
+            let (outcome,s,passes) = match &params.text {
+                Some(t) => {
+                    let (outcome,_,s) = Interpreter::do_string_with_state(path,state,t.as_str(),colon(&html_dir),&p);
+                    (outcome,s,1)
+                }
                 None => {
-                    if !path.exists() {
-                        println!("File {} not found", i)
+                    if !p.filesystem.is_file(path) {
+                        fail(&format!("File {} not found", path.display()))
                     }
-                    int.do_file(&*path, HTMLColon::new(true))
+                    let (outcome,_,s,passes) = Interpreter::do_file_with_state_multipass(path,state,|| colon(&html_dir),params.passes.unwrap_or(1),&p);
+                    if chatty && passes > 1 { println!("\n\nDone after {} passes",passes) }
+                    (outcome,s,passes)
                 }
             };
-            match params.output {
-                None => if success {println!("\n\nSuccess!\n{}",s)} else {println!("\n\nFailed\n{}",s)},
-                Some(f) => {
-                   /* let f = match f.strip_prefix("\"").and_then(|f| f.strip_suffix("\"")) {
-                        Some(s) => s.to_string(),
-                        _ => f
-                    };*/
-                    let mut path = Path::new(&f).to_path_buf();
-                    if !path.is_absolute() {
-                        path = env::current_dir().unwrap().join(path);
-                    }
-                    std::fs::write(path,s.as_bytes()).expect("");
-                    if success {
-                        println!("\n\nSuccess! \\o/\nResult written to {}", f)
-                    } else {
-                        println!("\n\nFailed\nPartial result written to {}",f)
+            match (&out,outcome) {
+                (None,_) if params.json => (),
+                (None,Outcome::Success) => println!("\n\nSuccess!\n{}",s),
+                (None,Outcome::Failure) => println!("\n\nFailed\n{}",s),
+                (None,Outcome::Cancelled) => println!("\n\nCancelled\n{}",s),
+                (Some(f),_) => {
+                    std::fs::write(f,s.as_bytes()).expect("Could not write output");
+                    if chatty {
+                        match outcome {
+                            Outcome::Success => println!("\n\nSuccess! \\o/\nResult written to {}", f.display()),
+                            Outcome::Failure => println!("\n\nFailed\nPartial result written to {}",f.display()),
+                            Outcome::Cancelled => println!("\n\nCancelled\nPartial result written to {}",f.display())
+                        }
                     }
                 }
             }
-
+            status = exit_status(outcome);
+            if let (Some(f),Some(sm)) = (&params.sourcemap,sourcemap.lock().unwrap().as_ref()) {
+                std::fs::write(f,sm.lock().unwrap().to_json().as_bytes()).expect("Could not write source map");
+                if chatty { println!("Source map written to {}",f) }
+            }
+            if let Some(pr) = &p.profiler {
+                if let Some(f) = &params.profile {
+                    std::fs::write(f,pr.report().as_bytes()).expect("Could not write profile");
+                    if chatty { println!("Profile written to {}",f) }
+                }
+                if let Some(f) = &params.profile_stacks {
+                    std::fs::write(f,pr.collapsed_stacks().as_bytes()).expect("Could not write profile");
+                    if chatty { println!("Collapsed stacks written to {}",f) }
+                }
+            }
+            diagnostics = p.diagnostics.take();
+            if params.json { println!("{}",summary_json(path,out.as_deref(),outcome,passes,&diagnostics)) }
         }
     }
-}
\ No newline at end of file
+    if let Some(f) = &params.diagnostics {
+        let ds = serde_json::Value::from_iter(diagnostics.iter().map(|d| d.json()));
+        std::fs::write(f,ds.to_string().as_bytes()).expect("Could not write diagnostics");
+        if chatty { println!("Diagnostics written to {}",f) }
+    }
+    if status != 0 { std::process::exit(status) }
+}
@@ -209,6 +209,53 @@ impl LaTeXFile {
     pub(crate) fn add(&mut self,tk : LaTeXObject) {
         self.ch.push(tk)
     }
+    /// The tokens, comments and nested (`\input`) files of this file, in source order
+    pub fn children(&self) -> &[LaTeXObject] {
+        &self.ch
+    }
+    fn walk<'a>(&'a self,f:&mut dyn FnMut(&'a LaTeXFile,&'a LaTeXObject)) {
+        for o in &self.ch {
+            f(self,o);
+            if let LaTeXObject::File(lf) = o { lf.walk(f) }
+        }
+    }
+    /// This file and all files nested in it, depth-first
+    pub fn files(&self) -> Vec<&LaTeXFile> {
+        let mut ret = vec!(self);
+        self.walk(&mut |_,o| if let LaTeXObject::File(f) = o { ret.push(f) });
+        ret
+    }
+    /// All tokens of this file and the files nested in it, in source order
+    pub fn tokens(&self) -> Vec<&Token> {
+        let mut ret = vec!();
+        self.walk(&mut |_,o| if let LaTeXObject::Token(t) = o { ret.push(t) });
+        ret
+    }
+    /// All comments of this file and the files nested in it, in source order
+    pub fn comments(&self) -> Vec<&Comment> {
+        let mut ret = vec!();
+        self.walk(&mut |_,o| if let LaTeXObject::Comment(c) = o { ret.push(c) });
+        ret
+    }
+    /// All occurrences of the control sequence `\name` (as read, i.e. before expansion)
+    pub fn uses(&self,name:&str) -> Vec<&Token> {
+        let mut ret = vec!();
+        self.walk(&mut |_,o| match o {
+            LaTeXObject::Token(t) if t.catcode == CategoryCode::Escape && t.name().to_string() == name => ret.push(t),
+            _ => ()
+        });
+        ret
+    }
+    /// The token (or comment) at `(line,column)` in the file with the given path
+    pub fn object_at(&self,file:&str,line:usize,column:usize) -> Option<&LaTeXObject> {
+        let mut ret = None;
+        self.walk(&mut |lf,o| if ret.is_none() && lf.path.as_ref().map(|p| p.to_string() == file).unwrap_or(false) {
+            if let Some(SourceReference::File(_,start,end)) = o.reference() {
+                if *start <= (line,column) && (line,column) < *end { ret = Some(o) }
+            }
+        });
+        ret
+    }
 }
 
 #[derive(Clone)]
@@ -222,4 +269,67 @@ pub enum LaTeXObject {
     Comment(Comment),
     Token(Token),
     File(LaTeXFile)
-}
\ No newline at end of file
+}
+impl LaTeXObject {
+    pub fn reference(&self) -> Option<&SourceReference> {
+        match self {
+            LaTeXObject::Comment(c) => Some(&c.reference),
+            LaTeXObject::Token(t) => t.reference.as_deref(),
+            LaTeXObject::File(_) => None
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::filesystem::MemoryFS;
+    use crate::interpreter::Interpreter;
+    use crate::interpreter::params::{DefaultParams, Verbosity};
+    use crate::interpreter::state::State;
+    use crate::references::SourceReference;
+    use crate::stomach::colon::NoColon;
+    use super::LaTeXObject;
+
+    #[test]
+    fn sources() {
+        crate::utils::with_stack_size(|| {
+            let mut p = DefaultParams::new(false,true,None);
+            p.verbosity = Verbosity::Quiet;
+            let fs = MemoryFS::new();
+            fs.insert("/foo.tex","\\def\\foo{x}% in foo\n");
+            p.filesystem = Box::new(fs);
+            let mut state = State::new();
+            state.set_store_sources(true);
+            let (outcome,state,_) = Interpreter::do_string_with_state(Path::new("/test.tex"),state,
+                "\\catcode`\\{=1 \\catcode`\\}=2 \\input foo\n% main\n\\foo a\\foo",NoColon::new(),&p);
+            assert!(outcome.success());
+            let src = state.sources().unwrap();
+            let files = src.files();
+            assert_eq!(files.len(),2);
+            assert_eq!(files[1].path.as_ref().unwrap().to_string(),"/foo.tex");
+            assert!(src.children().iter().any(|o| matches!(o,LaTeXObject::File(f) if f.path == files[1].path)));
+            // the definition in foo.tex and both uses in the main file
+            assert_eq!(src.uses("foo").len(),3);
+            assert_eq!(files[1].uses("foo").len(),1);
+            let comments : Vec<String> = src.comments().iter().map(|c| c.text.clone()).collect();
+            assert_eq!(comments,vec!("% in foo".to_string(),"% main".to_string()));
+            // line 3 is "\foo a\foo"; columns start at 1 and ends are exclusive
+            let at = |col| match src.object_at("/test.tex",3,col) {
+                Some(LaTeXObject::Token(t)) => t.name().to_string(),
+                Some(LaTeXObject::Comment(c)) => c.text.clone(),
+                _ => String::new()
+            };
+            assert_eq!(at(1),"foo");
+            assert_eq!(at(4),"foo");
+            assert_eq!(at(6),"a");
+            assert_eq!(at(7),"foo");
+            assert_eq!(at(10),"foo");
+            assert_eq!(at(11),"");
+            assert_eq!(src.object_at("/test.tex",2,3).map(|o| matches!(o,LaTeXObject::Comment(_))),Some(true));
+            match src.uses("foo")[2].reference.as_deref() {
+                Some(SourceReference::File(_,start,end)) => assert_eq!((*start,*end),((3,7),(3,11))),
+                _ => panic!("no source reference")
+            }
+        })
+    }
+}
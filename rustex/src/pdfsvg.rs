@@ -0,0 +1,316 @@
+/*! Conversion of pages of PDF files included via `\pdfximage` to SVG, so that vector figures
+stay sharp, small and searchable in the HTML output.
+
+The paths, text and images of the page are translated one by one using [pdfium](crate::pdfium),
+clip paths becoming `<clipPath>` elements; text is drawn in a generic font of the same style,
+stretched to the width it has in the PDF. Raster images are kept apart as PNG files (see
+[`PdfSvg::content_with`]), so that they can be stored as assets like other images. If
+the page contains anything that can not be translated (e.g. shadings), [`pdf_to_svg`] returns
+`None` and the page is rasterized instead (see [`pdf_to_img`](crate::pdf_to_img)).
+*/
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::io::Cursor;
+use pdfium_render::prelude::*;
+
+/// A page of a PDF file as SVG
+#[derive(Clone)]
+pub struct PdfSvg {
+    /// the SVG elements of the page, with the origin at the top left corner of the page box
+    /// and in units of bp; images refer to [`PdfSvg::images`] by placeholders
+    content:String,
+    /// the raster images of the page as PNG files
+    pub images:Vec<Vec<u8>>,
+    /// the width of the page box in bp
+    pub width:f32,
+    /// the height of the page box in bp
+    pub height:f32
+}
+
+/// The page box `boxspec` (`mediabox`, `cropbox`, `bleedbox`, `trimbox` or `artbox`) of `page`;
+/// like pdfTeX, the crop box if none is given and the media box if the page lacks the one requested
+pub(crate) fn page_box(page:&PdfPage,boxspec:Option<&str>) -> Option<PdfRect> {
+    let b = page.boundaries();
+    let requested = match boxspec {
+        Some("mediabox") => b.media(),
+        Some("bleedbox") => b.bleed(),
+        Some("trimbox") => b.trim(),
+        Some("artbox") => b.art(),
+        _ => b.crop()
+    };
+    requested.or_else(|_| b.crop()).or_else(|_| b.media()).ok().map(|b| b.bounds)
+}
+
+/// The page `page` (starting at 1, as in `\pdfximage page`) of the PDF file with content
+/// `bytes`, restricted to the page box `boxspec`
+pub fn pdf_to_svg(bytes:&[u8],page:i32,boxspec:Option<&str>) -> Option<PdfSvg> {
+    let _lock = crate::PDFIUM_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let pdfium = crate::pdfium()?;
+    let doc = pdfium.load_pdf_from_byte_slice(bytes,None).ok()?;
+    let page = doc.pages().get((page.max(1) - 1) as u16).ok()?;
+    let bx = page_box(&page,boxspec)?;
+    let mut svg = SvgWriter { out:String::new(),images:vec!(),clips:HashSet::new() };
+    for o in page.objects().iter() {
+        object_to_svg(&o,&mut svg)?
+    }
+    // PDF coordinates grow upwards from the bottom left corner
+    Some(PdfSvg {
+        content:format!("<g transform=\"matrix(1 0 0 -1 {} {})\">{}</g>",num(-bx.left().value),num(bx.top().value),svg.out),
+        images:svg.images,
+        width:bx.width().value,
+        height:bx.height().value
+    })
+}
+
+const IMAGE_PLACEHOLDER : &str = "href=\"rustex-image:";
+
+impl PdfSvg {
+    /// The SVG elements of the page, with the origin at the top left corner of the page box and
+    /// in units of bp; `href` returns the URL of each of the [`PdfSvg::images`]
+    pub fn content_with(&self,mut href:impl FnMut(&[u8]) -> String) -> String {
+        let mut ret = String::new();
+        let mut rest = self.content.as_str();
+        while let Some(i) = rest.find(IMAGE_PLACEHOLDER) {
+            ret.push_str(&rest[..i]);
+            rest = &rest[i + IMAGE_PLACEHOLDER.len()..];
+            let end = rest.find('"').unwrap_or(rest.len());
+            let url = rest[..end].parse::<usize>().ok().and_then(|n| self.images.get(n)).map(|img| href(img)).unwrap_or_default();
+            write!(ret,"href=\"{}",escape(&url)).unwrap();
+            rest = &rest[end..];
+        }
+        ret.push_str(rest);
+        ret
+    }
+}
+
+/// The SVG of a page while it is being translated
+struct SvgWriter {
+    out:String,
+    images:Vec<Vec<u8>>,
+    /// the ids of the `<clipPath>` elements written so far
+    clips:HashSet<String>
+}
+
+/// `bp` in scaled points
+pub(crate) fn bp_to_sp(bp:f32) -> i32 { (bp * 65536.0 * 72.27 / 72.0).round() as i32 }
+
+lazy_static! {
+    static ref SVG_SIZE_ATTR : regex::Regex = regex::Regex::new(r#"\s(width|height|viewBox)\s*=\s*["']([^"']*)["']"#).unwrap();
+}
+
+/// The size in bp of an SVG file with content `content`, from the `width`, `height` and
+/// `viewBox` attributes of its root element; like in browsers, 300x150px if they are missing
+pub(crate) fn svg_size(content:&[u8]) -> (f32,f32) {
+    let s = String::from_utf8_lossy(content);
+    let root = match s.find("<svg") {
+        Some(i) => &s[i..i + s[i..].find('>').unwrap_or(s.len() - i)],
+        None => return (225.0,112.5)
+    };
+    let attr = |name:&str| -> Option<String> {
+        SVG_SIZE_ATTR.captures_iter(root).find(|c| &c[1] == name).map(|c| c[2].trim().to_string())
+    };
+    // in bp; None for percentages
+    let length = |v:String| -> Option<f32> {
+        let i = v.find(|c:char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+')).unwrap_or(v.len());
+        let n : f32 = v[..i].parse().ok()?;
+        match v[i..].trim() {
+            "" | "px" => Some(n * 0.75),
+            "pt" => Some(n),
+            "pc" => Some(n * 12.0),
+            "in" => Some(n * 72.0),
+            "cm" => Some(n * 72.0 / 2.54),
+            "mm" => Some(n * 72.0 / 25.4),
+            _ => None
+        }
+    };
+    let viewbox = attr("viewBox").and_then(|v| {
+        let vs : Vec<f32> = v.split(|c:char| c == ',' || c.is_whitespace()).filter(|s| !s.is_empty()).filter_map(|s| s.parse().ok()).collect();
+        if vs.len() == 4 && vs[2] > 0.0 && vs[3] > 0.0 { Some((vs[2] * 0.75,vs[3] * 0.75)) } else { None }
+    });
+    match (attr("width").and_then(length),attr("height").and_then(length),viewbox) {
+        (Some(w),Some(h),_) => (w,h),
+        (Some(w),None,Some((vw,vh))) => (w,w * vh / vw),
+        (None,Some(h),Some((vw,vh))) => (h * vw / vh,h),
+        (None,None,Some(vb)) => vb,
+        _ => (225.0,112.5)
+    }
+}
+
+fn num(f:f32) -> String {
+    let s = format!("{:.3}",f);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+fn matrix(m:&PdfMatrix) -> String {
+    format!("matrix({} {} {} {} {} {})",num(m.a()),num(m.b()),num(m.c()),num(m.d()),num(m.e()),num(m.f()))
+}
+
+fn color(c:&PdfColor) -> String {
+    format!("rgb({},{},{})",c.red(),c.green(),c.blue())
+}
+
+fn escape(s:&str) -> String {
+    s.replace('&',"&amp;").replace('<',"&lt;").replace('>',"&gt;").replace('"',"&quot;")
+}
+
+/// Appends `o` as SVG; `None` if `o` can not be translated
+fn object_to_svg(o:&PdfPageObject,svg:&mut SvgWriter) -> Option<()> {
+    let clips = clip_to_svg(o,svg)?;
+    let out = &mut svg.out;
+    match o {
+        PdfPageObject::Path(p) => path_to_svg(p,out)?,
+        PdfPageObject::Text(t) => text_to_svg(t,out)?,
+        PdfPageObject::Image(i) => {
+            let img = i.get_raw_image().ok()?;
+            let mut buf = Cursor::new(vec!());
+            img.write_to(&mut buf,image::ImageFormat::Png).ok()?;
+            // images fill the unit square, top row at y=1
+            write!(out,"<image width=\"1\" height=\"1\" preserveAspectRatio=\"none\" transform=\"{} matrix(1 0 0 -1 0 1)\" {}{}\"/>",
+                matrix(&i.matrix().ok()?),IMAGE_PLACEHOLDER,svg.images.len()).ok()?;
+            svg.images.push(buf.into_inner())
+        }
+        PdfPageObject::XObjectForm(f) => {
+            write!(out,"<g transform=\"{}\">",matrix(&f.matrix().ok()?)).ok()?;
+            for c in f.iter() { object_to_svg(&c,svg)? }
+            svg.out.push_str("</g>")
+        }
+        _ => return None
+    }
+    for _ in 0..clips { svg.out.push_str("</g>") }
+    Some(())
+}
+
+/// Writes the clip path of `o` (in the coordinates of its container, not of `o` itself) and opens
+/// a `<g>` for each of its paths, since the clipped region is their intersection; returns their number
+fn clip_to_svg(o:&PdfPageObject,svg:&mut SvgWriter) -> Option<usize> {
+    let clip = match o.get_clip_path() {
+        Some(c) => c,
+        None => return Some(0)
+    };
+    let mut groups = 0;
+    for segments in clip.iter() {
+        let d = path_data(&segments)?;
+        if d.is_empty() { continue }
+        let id = format!("rustex-clip-{:x}",md5::compute(&d));
+        if svg.clips.insert(id.clone()) {
+            write!(svg.out,"<clipPath id=\"{}\"><path d=\"{}\"/></clipPath>",id,d).ok()?
+        }
+        write!(svg.out,"<g clip-path=\"url(#{})\">",id).ok()?;
+        groups += 1
+    }
+    Some(groups)
+}
+
+/// The `d` attribute of an SVG path with the given segments
+fn path_data<'a>(segments:&'a impl PdfPathSegments<'a>) -> Option<String> {
+    let mut d = String::new();
+    // the points of a Bézier curve come as three consecutive segments
+    let mut bezier : Vec<(f32,f32)> = vec!();
+    for s in segments.iter() {
+        let (x,y) = (num(s.x().value),num(s.y().value));
+        match s.segment_type() {
+            PdfPathSegmentType::MoveTo => write!(d,"M{} {}",x,y).ok()?,
+            PdfPathSegmentType::LineTo => write!(d,"L{} {}",x,y).ok()?,
+            PdfPathSegmentType::BezierTo => {
+                bezier.push((s.x().value,s.y().value));
+                if bezier.len() == 3 {
+                    d.push('C');
+                    d.push_str(&bezier.iter().map(|(x,y)| num(*x) + " " + &num(*y)).collect::<Vec<_>>().join(" "));
+                    bezier.clear()
+                }
+            }
+            PdfPathSegmentType::Unknown => return None
+        }
+        if s.is_close() { d.push('Z') }
+    }
+    Some(d)
+}
+
+fn path_to_svg(p:&PdfPagePathObject,out:&mut String) -> Option<()> {
+    let d = path_data(&p.segments())?;
+    let fill = match p.fill_mode().ok()? {
+        PdfPathFillMode::None => "fill=\"none\"".to_string(),
+        mode => {
+            let c = p.fill_color().ok()?;
+            let mut f = format!("fill=\"{}\"",color(&c));
+            if c.alpha() < 255 { write!(f," fill-opacity=\"{}\"",num(c.alpha() as f32 / 255.0)).ok()? }
+            if mode == PdfPathFillMode::EvenOdd { f.push_str(" fill-rule=\"evenodd\"") }
+            f
+        }
+    };
+    write!(out,"<path d=\"{}\" transform=\"{}\" {}",d,matrix(&p.matrix().ok()?),fill).ok()?;
+    if p.is_stroked().ok()? {
+        let c = p.stroke_color().ok()?;
+        write!(out," stroke=\"{}\" stroke-width=\"{}\"",color(&c),num(p.stroke_width().ok()?.value)).ok()?;
+        if c.alpha() < 255 { write!(out," stroke-opacity=\"{}\"",num(c.alpha() as f32 / 255.0)).ok()? }
+        match p.line_join() {
+            Ok(PdfPageObjectLineJoin::Round) => out.push_str(" stroke-linejoin=\"round\""),
+            Ok(PdfPageObjectLineJoin::Bevel) => out.push_str(" stroke-linejoin=\"bevel\""),
+            _ => ()
+        }
+        match p.line_cap() {
+            Ok(PdfPageObjectLineCap::Round) => out.push_str(" stroke-linecap=\"round\""),
+            Ok(PdfPageObjectLineCap::Square) => out.push_str(" stroke-linecap=\"square\""),
+            _ => ()
+        }
+        if let Ok(dashes) = p.dash_array() {
+            if !dashes.is_empty() {
+                write!(out," stroke-dasharray=\"{}\"",dashes.iter().map(|d| num(d.value)).collect::<Vec<_>>().join(" ")).ok()?
+            }
+        }
+    }
+    out.push_str("/>");
+    Some(())
+}
+
+fn text_to_svg(t:&PdfPageTextObject,out:&mut String) -> Option<()> {
+    let text = t.text();
+    if text.trim().is_empty() { return Some(()) }
+    let m = t.matrix().ok()?;
+    let font = t.font();
+    let family = if font.is_fixed_pitch() { "monospace" } else if font.is_sans_serif() { "sans-serif" } else { "serif" };
+    let fill = match t.render_mode() {
+        // keep invisible text (e.g. from OCR) searchable
+        PdfPageTextRenderMode::Invisible | PdfPageTextRenderMode::InvisibleClipping => "fill-opacity=\"0\"".to_string(),
+        _ => format!("fill=\"{}\"",color(&t.fill_color().ok()?))
+    };
+    // text grows downwards in SVG; the width in text space makes the generic font match the original
+    write!(out,"<text transform=\"{} scale(1 -1)\" font-size=\"{}\" font-family=\"{}\" {}",
+        matrix(&m),num(t.unscaled_font_size().value),family,fill).ok()?;
+    if matches!(font.weight(),Ok(PdfFontWeight::Weight600 | PdfFontWeight::Weight700Bold | PdfFontWeight::Weight800 | PdfFontWeight::Weight900)) {
+        out.push_str(" font-weight=\"bold\"")
+    }
+    if font.italic_angle().map(|a| a != 0).unwrap_or(false) { out.push_str(" font-style=\"italic\"") }
+    let scale = (m.a() * m.a() + m.b() * m.b()).sqrt();
+    if let Ok(w) = t.width() {
+        if scale > 0.0 && w.value > 0.0 {
+            write!(out," textLength=\"{}\" lengthAdjust=\"spacingAndGlyphs\"",num(w.value / scale)).ok()?
+        }
+    }
+    write!(out," style=\"white-space:pre\">{}</text>",escape(&text)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_resolved_by_the_caller() {
+        let svg = PdfSvg {
+            content:format!("<image {}0\"/><text>{}1</text><image {}1\"/>",IMAGE_PLACEHOLDER,escape(IMAGE_PLACEHOLDER),IMAGE_PLACEHOLDER),
+            images:vec!(b"a".to_vec(),b"b&".to_vec()),
+            width:1.0,height:1.0
+        };
+        let content = svg.content_with(|img| format!("assets/{}.png",String::from_utf8_lossy(img)));
+        assert_eq!(content,"<image href=\"assets/a.png\"/><text>href=&quot;rustex-image:1</text><image href=\"assets/b&amp;.png\"/>");
+    }
+
+    #[test]
+    fn svg_sizes() {
+        assert_eq!(svg_size(b"<?xml?><svg width=\"100\" height='2in' stroke-width=\"3\">"),(75.0,144.0));
+        assert_eq!(svg_size(b"<svg viewBox=\"0 0 200 100\" width=\"10pt\">"),(10.0,5.0));
+        assert_eq!(svg_size(b"<svg width=\"50%\">"),(225.0,112.5));
+    }
+}
@@ -11,6 +11,25 @@ pub enum SourceReference {
     File(TeXStr,(usize,usize),(usize,usize)),
     Exp(Token,Arc<PrimitiveTeXCommand>)
 }
+impl Drop for SourceReference {
+    /// Unlinks expansion chains iteratively; dropping them recursively would overflow the stack
+    /// for long (e.g. tail recursive) chains
+    fn drop(&mut self) {
+        let mut next = match self {
+            SourceReference::Exp(tk,_) => tk.reference.take(),
+            _ => return
+        };
+        while let Some(r) = next {
+            next = match Arc::try_unwrap(r) {
+                Ok(mut sr) => match &mut sr {
+                    SourceReference::Exp(tk,_) => tk.reference.take(),
+                    _ => None
+                },
+                Err(_) => None
+            }
+        }
+    }
+}
 
 #[derive(PartialEq,Clone)]
 pub struct SourceFileReference {
@@ -30,12 +49,25 @@ impl SourceFileReference {
         self.file.to_string() + "#(" + &self.start.0.to_string() + ";" + &self.start.1.to_string() +
             ")(" + &self.end.0.to_string() + ";" + &self.end.1.to_string() + ")"
     }
+    /// The union of this range and `other`, if they are in the same file and `other` starts
+    /// within this range or right after its end (ends are inclusive)
     pub fn merge(&self,other:&SourceFileReference) -> Option<SourceFileReference> {
-        if self.file == other.file {
+        if self.file == other.file && self.start <= other.start && other.start <= (self.end.0,self.end.1 + 1) {
+            Some(SourceFileReference {
+                file:self.file.clone(),
+                start:self.start,
+                end:self.end.max(other.end)
+            })
+        } else { None }
+    }
+    /// The range from the start of this range to the end of `other`, including anything in
+    /// between; `None` if `other` is in a different file or ends before this range starts
+    pub fn span(&self,other:&SourceFileReference) -> Option<SourceFileReference> {
+        if self.file == other.file && self.start <= other.end {
             Some(SourceFileReference {
                 file:self.file.clone(),
                 start:self.start,
-                end:other.end
+                end:self.end.max(other.end)
             })
         } else { None }
     }
@@ -43,7 +75,7 @@ impl SourceFileReference {
         match ls.iter().find_map(|w| w.iter().find_map(|w| w.get_ref())) {
             Some(start) => {
                 match ls.iter().rev().find_map(|w| w.iter().rev().find_map(|w| w.get_ref())) {
-                    Some(end) => start.merge(&end),
+                    Some(end) => start.span(&end),
                     _ => None
                 }
             }
@@ -54,11 +86,90 @@ impl SourceFileReference {
         match ls.iter().find_map(|w| w.get_ref()) {
             Some(start) => {
                 match ls.iter().rev().find_map(|w| w.get_ref()) {
-                    Some(end) => start.merge(&end),
+                    Some(end) => start.span(&end),
                     _ => None
                 }
             }
             _ => None
         }
     }
-}
\ No newline at end of file
+}
+/// Maps the ids of HTML elements to the source ranges they were generated from, see
+/// [`HTMLColon::sourcemap`](crate::stomach::html::HTMLColon::sourcemap)
+#[derive(Clone,Default)]
+pub struct SourceMap {
+    /// (element id, source range), in document order
+    pub elements:Vec<(String,SourceFileReference)>,
+    next:usize
+}
+impl SourceMap {
+    pub fn new() -> SourceMap { SourceMap::default() }
+    /// A fresh element id
+    pub fn next_id(&mut self) -> String {
+        self.next += 1;
+        "rustex-s".to_string() + &self.next.to_string()
+    }
+    pub fn add(&mut self,id:String,rf:SourceFileReference) {
+        self.elements.push((id,rf))
+    }
+    /// "jump to source": the source range of the element with id `id`
+    pub fn source(&self,id:&str) -> Option<&SourceFileReference> {
+        self.elements.iter().find(|(i,_)| i == id).map(|(_,r)| r)
+    }
+    /// "jump to output": the ids of all elements whose source range contains `(line,column)`
+    /// in `file`, innermost first
+    pub fn elements_at(&self,file:&str,line:usize,column:usize) -> Vec<&str> {
+        let mut ret : Vec<&(String,SourceFileReference)> = self.elements.iter().filter(|(_,r)|
+            r.file.to_string() == file && r.start <= (line,column) && (line,column) <= r.end
+        ).collect();
+        ret.sort_by(|(_,a),(_,b)| b.start.cmp(&a.start).then(a.end.cmp(&b.end)));
+        ret.into_iter().map(|(i,_)| i.as_str()).collect()
+    }
+    /// `{"version":1,"elements":{id:{"file":...,"start":[line,col],"end":[line,col]},...}}`
+    pub fn to_json(&self) -> String {
+        let elems : serde_json::Map<String,serde_json::Value> = self.elements.iter().map(|(i,r)| (i.clone(),serde_json::json!({
+            "file":r.file.to_string(),
+            "start":[r.start.0,r.start.1],
+            "end":[r.end.0,r.end.1]
+        }))).collect();
+        serde_json::json!({"version":1,"elements":elems}).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rf(file:&str,start:(usize,usize),end:(usize,usize)) -> SourceFileReference {
+        SourceFileReference { file:file.into(),start,end }
+    }
+
+    #[test]
+    fn merge_and_span() {
+        let a = rf("a.tex",(3,4),(3,5));
+        // touching and overlapping
+        assert!(a.merge(&rf("a.tex",(3,6),(3,6))) == Some(rf("a.tex",(3,4),(3,6))));
+        assert!(a.merge(&rf("a.tex",(3,5),(3,6))) == Some(rf("a.tex",(3,4),(3,6))));
+        assert!(a.merge(&rf("a.tex",(3,4),(3,9))) == Some(rf("a.tex",(3,4),(3,9))));
+        assert!(a.merge(&rf("a.tex",(3,4),(3,4))) == Some(a.clone()));
+        // a gap, backwards or another file
+        assert!(a.merge(&rf("a.tex",(3,7),(3,8))).is_none());
+        assert!(a.merge(&rf("a.tex",(3,2),(3,5))).is_none());
+        assert!(a.merge(&rf("b.tex",(3,5),(3,6))).is_none());
+        // spans cover gaps, but not backwards
+        assert!(a.span(&rf("a.tex",(7,0),(7,2))) == Some(rf("a.tex",(3,4),(7,2))));
+        assert!(a.span(&rf("a.tex",(1,0),(2,0))).is_none());
+        assert!(a.span(&rf("b.tex",(7,0),(7,2))).is_none());
+    }
+
+    #[test]
+    fn source_map_json() {
+        let mut map = SourceMap::new();
+        let id = map.next_id();
+        map.add(id.clone(),rf("/d/a \"b\".tex",(1,0),(2,3)));
+        let json : serde_json::Value = serde_json::from_str(&map.to_json()).unwrap();
+        assert_eq!(json["version"],1);
+        assert_eq!(json["elements"][&id]["file"],"/d/a \"b\".tex");
+        assert_eq!(json["elements"][&id]["end"],serde_json::json!([2,3]));
+    }
+}
@@ -1,6 +1,6 @@
 use std::borrow::BorrowMut;
 pub use crate::stomach::whatsits::Whatsit;
-use crate::{Interpreter, log, TeXErr};
+use crate::{Interpreter, TeXErr};
 use crate::commands::{DefMacro, PrimitiveTeXCommand, registers, Signature, TokenList};
 use crate::fonts::{ArcFont, Font};
 use crate::interpreter::state::{GroupType, State};
@@ -13,6 +13,7 @@ use std::sync::{Arc, mpsc};
 use std::sync::mpsc::{Receiver, Sender};
 use crate::commands::registers::PREVGRAF;
 use crate::interpreter::params::InterpreterParams;
+use crate::interpreter::dimensions::MAX_DIMEN;
 use crate::ontology::Token;
 use crate::stomach::boxes::{HBox, TeXBox};
 
@@ -24,8 +25,10 @@ pub mod groups;
 pub mod simple;
 pub mod colon;
 pub mod html;
+pub mod assets;
+pub mod show;
 
-pub fn split_vertical(vlist:Vec<Whatsit>,target:i32,int:&Interpreter) -> (Vec<Whatsit>,Vec<Whatsit>) {
+pub fn split_vertical(vlist:Vec<Whatsit>,target:i32) -> (Vec<Whatsit>,Vec<Whatsit>) {
     let mut currentheight : i32 = 0;
     //let mut marks: Vec<(Vec<Token>,Option<SourceFileReference>)> = vec!();
     let mut presplit : Vec<StomachGroup> = vec!(StomachGroup::Top(vec!()));
@@ -51,9 +54,6 @@ pub fn split_vertical(vlist:Vec<Whatsit>,target:i32,int:&Interpreter) -> (Vec<Wh
             Some(sg) => {
                 let next = sg.get_mut().remove(0);
                 match next {
-                    Whatsit::Simple(SimpleWI::Mark(_)) => {
-                        ()//TeXErr!("TODO")
-                    },
                     Whatsit::Grouped(wg) => {
                         presplit.push(StomachGroup::Other(wg.new_from()));
                         input.push(StomachGroup::Other(wg))
@@ -64,7 +64,7 @@ pub fn split_vertical(vlist:Vec<Whatsit>,target:i32,int:&Interpreter) -> (Vec<Wh
                             if currentheight + p.lineheight.unwrap() > target {
                                 break Some(Whatsit::Par(p))
                             }
-                            let (f,s) = p.split(target - currentheight,&int.state);
+                            let (f,s) = p.split(target - currentheight);
                             presplit.last_mut().unwrap().push(Whatsit::Par(f));
                             break Some(Whatsit::Par(s))
                         } else {
@@ -93,7 +93,7 @@ pub fn split_vertical(vlist:Vec<Whatsit>,target:i32,int:&Interpreter) -> (Vec<Wh
                 _ => unreachable!()//TeXErr!("Should be unreachable!")
             }
         }
-        Some(_) => {
+        Some(first) => {
             while match presplit.last() {
                 Some(StomachGroup::Top(_)) => false,
                 _ => true
@@ -108,6 +108,7 @@ pub fn split_vertical(vlist:Vec<Whatsit>,target:i32,int:&Interpreter) -> (Vec<Wh
             for g in &input[1..] {
                 second.push(g.new_from())
             }
+            second.last_mut().unwrap().push(first);
             loop {
                 match input.last_mut() {
                     None => break,
@@ -128,17 +129,10 @@ pub fn split_vertical(vlist:Vec<Whatsit>,target:i32,int:&Interpreter) -> (Vec<Wh
                     }
                     Some(sg) => {
                         let next = sg.get_mut().remove(0);
-                        match next {
-                            Whatsit::Simple(SimpleWI::Mark(_)) => {
-                                ()//TeXErr!("TODO")
-                            },
-                            next => {
-                                second.last_mut().unwrap().push(next)
-                                }
-                            }
-                        }
+                        second.last_mut().unwrap().push(next)
                     }
                 }
+            }
             let sec = match second.pop() {
                 Some(StomachGroup::Top(v)) => v,
                 _ => unreachable!()//TeXErr!("Should be unreachable!")
@@ -219,7 +213,8 @@ impl StomachGroup {
 
 pub enum StomachMessage {
     End,
-    WI(Whatsit)
+    WI(Whatsit),
+    Page(TeXBox)
 }
 
 pub trait Stomach : Send {
@@ -230,6 +225,24 @@ pub trait Stomach : Send {
 
     // ---------------------------------------------------------------------------------------------
 
+    fn set_sender(&mut self,sender:Sender<StomachMessage>) {
+        self.base_mut().sender = Some(sender)
+    }
+    /// Called by the interpreter in vertical mode. Returns `true` if a page has been put into
+    /// `\box255`, in which case the interpreter runs `\output` and calls [`Stomach::output_done`].
+    /// If `last`, the current page should be finished even if it isn't full yet.
+    fn build_page(&mut self,_state:&mut State,_last:bool) -> Result<bool,TeXError> { Ok(false) }
+    /// Called with whatever `\output` left on the vertical list
+    fn output_done(&mut self,_state:&mut State,leftover:Vec<Whatsit>) -> Result<(),TeXError> {
+        for w in leftover { self.add_inner_actually(w)? }
+        Ok(())
+    }
+    /// `\shipout`
+    fn ship_page(&mut self,_state:&mut State,bx:TeXBox) -> Result<(),TeXError> {
+        self.flush()?;
+        self.add_inner_actually(bx.as_whatsit())
+    }
+
     fn start_paragraph(&mut self,parskip:i32) {
         self.flush().unwrap();
         self.base_mut().stomachgroups.push(StomachGroup::Par(Paragraph::new(parskip)))
@@ -240,10 +253,6 @@ pub trait Stomach : Send {
         let hangindent = state.hangindent.get();
         let hangafter = state.hangafter.get();
         let parshape = state.parshape.get();
-        /*if !parshape.is_empty() {
-            unsafe{ crate::LOG = true }
-            println!("here")
-        }*/
         p.close(state,hangindent,hangafter,parshape);
         state.registers_prim.set((PREVGRAF.index - 1),p.finallines as i32,true);
         self.add_inner_actually(Whatsit::Par(p))?;
@@ -685,6 +694,7 @@ pub trait Stomach : Send {
         state.indocument = true;
         let base = self.base_mut();
         base.indocument = true;
+        base.vsize = state.dimensions_prim.get(&(registers::VSIZE.index - 1));
         let mut basefont: Option<ArcFont> = None;
         let mut basecolor: TeXStr = "000000".into();
         let mut rets : Vec<Whatsit> = vec!();
@@ -718,7 +728,7 @@ pub trait Stomach : Send {
         }
         self.on_begin_document_inner(state);
         let (sender,receiver) = mpsc::channel::<StomachMessage>();
-        self.base_mut().sender = Some(sender);
+        self.set_sender(sender);
         (receiver,basefont.unwrap(),basecolor)
     }
 
@@ -792,6 +802,8 @@ pub struct StomachBase {
     pub buffer:Vec<Whatsit>,
     pub indocument:bool,
     pub pageheight:i32,
+    /// `\vsize` at the beginning of the document (which [`NoShipoutRoutine`] sets to "infinite")
+    pub vsize:i32,
     pub sender:Option<Sender<StomachMessage>>
 }
 impl StomachBase {
@@ -801,12 +813,19 @@ impl StomachBase {
             buffer:vec!(),
             indocument: false,
             pageheight: 0,
+            vsize: 0,
             sender:None,
             //sender:None
         }
     }
 }
 
+/// The [`Stomach`] for processing a document with `colon`: a [`ShipoutRoutine`] if the colon
+/// wants pages from the output routine, a [`NoShipoutRoutine`] otherwise
+pub fn stomach_for<A>(colon:&dyn colon::Colon<A>) -> Box<dyn Stomach> {
+    if colon.output_routine() { Box::new(ShipoutRoutine::new()) } else { Box::new(NoShipoutRoutine::new()) }
+}
+
 pub struct NoShipoutRoutine {
     base: StomachBase,
     floatlist: Vec<(TeXStr,i32)>,
@@ -929,8 +948,6 @@ impl Stomach for NoShipoutRoutine {
         &self.base
     }
     fn add(&mut self,state:&mut State,params:&dyn InterpreterParams, wi: Whatsit) -> Result<(),TeXError> {
-        /*log!("HERE: {} -- {}",self.base.stomachgroups.len(),self.base.buffer.len());
-        print!("");*/
         match wi {
             Whatsit::Simple(SimpleWI::Penalty(ref p)) if p.penalty <= -1000 && self.is_top() && self.base().indocument => {
                 self.add_inner(state,params,wi)?;
@@ -948,8 +965,250 @@ impl Stomach for NoShipoutRoutine {
     }
 }
 
+/// A [`Stomach`] with an actual page builder: The main vertical list is broken into pages of
+/// height `\vsize` (or at penalties <= -10000), which are put into `\box255` for `\output`.
+/// Boxes passed to `\shipout` are sent to the [`Colon`](colon::Colon) as pages.
+pub struct ShipoutRoutine {
+    base: StomachBase,
+    sender:Option<Sender<StomachMessage>>,
+    inoutput:bool,
+    /// the current page so far, i.e. how many entries of the main vertical list
+    /// [`Stomach::build_page`] has already looked at, their total height and whether any of
+    /// them is not discardable
+    page:(usize,i32,bool),
+    /// the space for inserts already subtracted from `\pagegoal`
+    inserts:i32
+}
+impl ShipoutRoutine {
+    pub fn new() -> ShipoutRoutine {
+        ShipoutRoutine {
+            base: StomachBase::new(),
+            sender:None,
+            inoutput:false,
+            page:(0,0,false),
+            inserts:0
+        }
+    }
+    fn discardable(wi:&Whatsit) -> bool {
+        match wi {
+            Whatsit::Simple(SimpleWI::Penalty(_) | SimpleWI::VSkip(_) | SimpleWI::VFil(_) |
+                            SimpleWI::VFill(_) | SimpleWI::Vss(_) | SimpleWI::VKern(_)) => true,
+            _ => false
+        }
+    }
+    /// The space the pending inserts take up on the page: for every class `n`, `\skip n` plus
+    /// the height of the material times `\count n`/1000
+    fn insert_space(state:&State) -> i32 {
+        let mut ret = 0;
+        for (n,v) in state.inserts.iter() {
+            let height : i32 = v.iter().map(|w| w.height() + w.depth()).sum();
+            ret += state.skips.get(n).base + ((height as i64) * (state.registers.get(n) as i64) / 1000) as i32
+        }
+        ret
+    }
+    fn get_marks(v:&Vec<Whatsit>,marks:&mut Vec<Vec<Token>>) {
+        for w in v { match w {
+            Whatsit::Simple(SimpleWI::Mark(m)) => marks.push(m.toks.clone()),
+            Whatsit::Grouped(g) => ShipoutRoutine::get_marks(g.children(),marks),
+            _ => ()
+        }}
+    }
+    fn fire(&mut self,state:&mut State,page:Vec<Whatsit>,penalty:i32) -> Result<bool,TeXError> {
+        use crate::stomach::boxes::{VBox, VBoxType};
+        let mut marks : Vec<Vec<Token>> = vec!();
+        ShipoutRoutine::get_marks(&page,&mut marks);
+        state.topmark = std::mem::take(&mut state.botmark);
+        state.firstmark = marks.first().cloned().unwrap_or(state.topmark.clone());
+        state.botmark = marks.pop().unwrap_or(state.topmark.clone());
+        for (i,mut v) in std::mem::take(&mut state.inserts) {
+            let mut ch = match state.boxes.take(i) {
+                TeXBox::V(vb) => vb.children,
+                TeXBox::Void => vec!(),
+                bx => vec!(bx.as_whatsit())
+            };
+            ch.append(&mut v);
+            state.boxes.set(i,TeXBox::V(VBox {
+                children:ch,tp:VBoxType::V,spread:0,_width:None,_height:None,_depth:None,_to:None,rf:None
+            }),true);
+        }
+        state.registers_prim.set(registers::OUTPUTPENALTY.index - 1,penalty,true);
+        state.dimensions_prim.set(registers::PAGETOTAL.index - 1,0,true);
+        state.pagegoal = MAX_DIMEN;
+        self.inserts = 0;
+        let bx = TeXBox::V(VBox {
+            children:page,tp:VBoxType::V,spread:0,_width:None,_height:None,_depth:None,
+            _to:Some(state.dimensions_prim.get(&(registers::VSIZE.index - 1))),rf:None
+        });
+        if state.toks_prim.get(&(registers::OUTPUT.index - 1)).is_empty() {
+            self.ship_page(state,bx)?;
+            Ok(false)
+        } else {
+            state.boxes.set(255,bx,true);
+            self.inoutput = true;
+            Ok(true)
+        }
+    }
+}
+
+impl Stomach for ShipoutRoutine {
+    fn base_mut(&mut self) -> &mut StomachBase {
+        self.base.borrow_mut()
+    }
+    fn base(&self) -> &StomachBase {
+        &self.base
+    }
+    fn add(&mut self,state:&mut State,params:&dyn InterpreterParams, wi: Whatsit) -> Result<(),TeXError> {
+        match wi {
+            Whatsit::Exec(e) => (std::sync::Arc::try_unwrap(e).ok().unwrap()._apply)(state,params),
+            _ => self.add_inner(state,params,wi)
+        }
+    }
+    fn on_begin_document_inner(&mut self, _state: &mut State) {}
+    fn set_sender(&mut self,sender:Sender<StomachMessage>) {
+        // everything else stays on the main vertical list until it is shipped out
+        self.sender = Some(sender)
+    }
+    fn build_page(&mut self,state:&mut State,last:bool) -> Result<bool,TeXError> {
+        if self.inoutput || !self.base.indocument || !self.is_top() { return Ok(false) }
+        self.flush()?;
+        let vsize = state.dimensions_prim.get(&(registers::VSIZE.index - 1));
+        let top = match self.base.stomachgroups.first_mut() {
+            Some(StomachGroup::Top(v)) => v,
+            _ => return Ok(false)
+        };
+        // only the contributions since the last call are new; unless entries were removed since
+        let (mut i,mut height,mut nonempty) = if self.page.0 <= top.len() { self.page } else { (0,0,false) };
+        // like TeX, the goal is fixed once the page gets contents (boxes or inserts), and
+        // reduced by the space for every insert that arrives afterwards
+        let inserts = ShipoutRoutine::insert_space(state);
+        if inserts != self.inserts {
+            if state.pagegoal == MAX_DIMEN { state.pagegoal = vsize }
+            state.pagegoal -= inserts - self.inserts;
+            self.inserts = inserts
+        }
+        while i < top.len() {
+            match &top[i] {
+                w if !nonempty && ShipoutRoutine::discardable(w) => {
+                    top.remove(i);
+                    continue
+                }
+                Whatsit::Simple(SimpleWI::Penalty(p)) if p.penalty <= -10000 => {
+                    let penalty = p.penalty;
+                    let rest = top.split_off(i + 1);
+                    top.pop();
+                    let page = std::mem::replace(top,rest);
+                    self.page = (0,0,false);
+                    return self.fire(state,page,penalty)
+                }
+                Whatsit::Simple(SimpleWI::Mark(_)) => (),
+                w => {
+                    if !nonempty && state.pagegoal == MAX_DIMEN { state.pagegoal = vsize }
+                    nonempty = true;
+                    height += w.height();
+                    if height > state.pagegoal {
+                        let (page,rest) = split_vertical(std::mem::take(top),state.pagegoal);
+                        *top = rest;
+                        self.page = (0,0,false);
+                        return self.fire(state,page,10000)
+                    }
+                }
+            }
+            i += 1
+        }
+        if last && nonempty {
+            let page = std::mem::take(top);
+            self.page = (0,0,false);
+            self.fire(state,page,10000)
+        } else {
+            self.page = (i,height,nonempty);
+            state.dimensions_prim.set(registers::PAGETOTAL.index - 1,height,true);
+            Ok(false)
+        }
+    }
+    fn output_done(&mut self,_state:&mut State,mut leftover:Vec<Whatsit>) -> Result<(),TeXError> {
+        self.inoutput = false;
+        self.page = (0,0,false);
+        match self.base.stomachgroups.first_mut() {
+            Some(StomachGroup::Top(v)) => {
+                leftover.append(v);
+                *v = leftover;
+                Ok(())
+            }
+            _ => TeXErr!("Main vertical list missing after \\output")
+        }
+    }
+    fn ship_page(&mut self,_state:&mut State,bx:TeXBox) -> Result<(),TeXError> {
+        match &self.sender {
+            Some(s) => {
+                s.send(StomachMessage::Page(bx)).unwrap();
+                Ok(())
+            }
+            None => Ok(()) // before \begin{document}
+        }
+    }
+    fn finish(&mut self,state:&mut State) {
+        let wis = self.close_all(state);
+        match self.sender.as_ref() {
+            Some(sender) => {
+                match wis {
+                    Ok(v) if v.iter().any(|w| !ShipoutRoutine::discardable(w) && !matches!(w,Whatsit::Simple(SimpleWI::Mark(_)))) => sender.send(StomachMessage::Page(TeXBox::V(crate::stomach::boxes::VBox {
+                        children:v,tp:crate::stomach::boxes::VBoxType::V,spread:0,_width:None,_height:None,
+                        _depth:None,_to:None,rf:None
+                    }))).unwrap(),
+                    _ => ()
+                }
+                sender.send(StomachMessage::End).unwrap();
+            }
+            _ => ()
+        }
+    }
+}
+
 impl Interpreter<'_> {
     pub fn stomach_add(&mut self,wi:Whatsit) -> Result<(),TeXError> {
         self.stomach.add(&mut self.state,self.params,wi)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::interpreter::Interpreter;
+    use crate::interpreter::params::DefaultParams;
+    use crate::interpreter::state::State;
+    use crate::stomach::Whatsit;
+    use crate::stomach::boxes::TeXBox;
+    use crate::stomach::colon::{Colon, ColonBase};
+
+    /// Collects the pages shipped out
+    struct Pages(ColonBase,Vec<TeXBox>);
+    impl Colon<Vec<TeXBox>> for Pages {
+        fn base_mut(&mut self) -> &mut ColonBase { &mut self.0 }
+        fn base(&self) -> &ColonBase { &self.0 }
+        fn ship_whatsit(&mut self, _:Whatsit) {}
+        fn close(&mut self) -> Vec<TeXBox> { std::mem::take(&mut self.1) }
+        fn ship_page(&mut self, bx:TeXBox) { self.1.push(bx) }
+        fn output_routine(&self) -> bool { true }
+    }
+
+    #[test]
+    fn output_routine() {
+        let text = "\\catcode`\\{=1 \\catcode`\\}=2 \\catcode`\\#=6 \\def\\document{}\\document\n\
+            \\vsize=100pt \\count100=1000 \\skip100=10pt \\def\\pages{}\\xdef\\goals{\\the\\pagegoal}\
+            \\output={\\xdef\\pages{\\pages[\\the\\outputpenalty,\\topmark,\\firstmark,\\botmark,\\the\\ht100]}\\setbox0\\box100\\shipout\\box255}\
+            \\hrule height 40pt\\mark{a}\\hrule height 40pt\\mark{b}\\hrule height 40pt\\mark{c}\\penalty-10000 \
+            \\hrule height 10pt\\xdef\\goals{\\goals,\\the\\pagegoal}\\insert100{\\hrule height 50pt}\
+            \\xdef\\goals{\\goals,\\the\\pagegoal}\\hrule height 30pt\\hrule height 30pt\n";
+        let (outcome,state,pages) = crate::utils::with_stack_size(move || {
+            let p = DefaultParams::new(false,true,None);
+            Interpreter::do_string_with_state(Path::new("/test.tex"),State::new(),text,Pages(ColonBase::new(),vec!()),&p)
+        });
+        assert!(outcome.success());
+        let mac = |name:&str| {
+            let cmd = state.commands.get(&name.into()).unwrap();
+            cmd.meaning(state.catcodes.get_scheme()).to_string()
+        };
+        assert_eq!(mac("goals"),"macro:->16383.99998pt,100.0pt,40.0pt");
+        assert_eq!(mac("pages"),"macro:->[10000,,a,b,0.0pt][-10000,b,c,c,0.0pt][10000,c,c,c,50.0pt][10000,c,c,c,0.0pt]");
+        assert_eq!(pages.len(),4);
+    }
+}
@@ -0,0 +1,131 @@
+/*! Images written to files next to the HTML instead of being embedded as data URIs; see
+[`HTMLColon::assets`](crate::stomach::html::HTMLColon::assets).
+
+Files are named after the md5 hash of their content, so identical images are written (and
+downloaded by browsers) only once, even across the files of a batch sharing the same [`Assets`].
+*/
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use image::ImageFormat;
+use crate::filesystem::FileSystem;
+
+#[derive(Clone)]
+pub struct Assets {
+    /// the directory the files are written to
+    pub dir:PathBuf,
+    /// the URL of [`Assets::dir`] as seen from the HTML file, e.g. `assets/`
+    pub url:String,
+    /// the names of the files already written, shared between clones
+    written:Arc<Mutex<HashSet<String>>>,
+    /// where the files are written, i.e. the [`FileSystem`] of the
+    /// [`InterpreterParams`](crate::interpreter::params::InterpreterParams) producing the HTML
+    fs:Arc<dyn FileSystem>
+}
+
+impl Assets {
+    pub fn new(dir:PathBuf,url:String,fs:Arc<dyn FileSystem>) -> Assets {
+        Assets { dir,url,written:Arc::new(Mutex::new(HashSet::new())),fs }
+    }
+    /// The same assets directory, as seen from an HTML file elsewhere
+    pub fn with_url(&self,url:String) -> Assets {
+        Assets { dir:self.dir.clone(),url,written:self.written.clone(),fs:self.fs.clone() }
+    }
+    /// Writes `content` to a file with extension `ext` (unless an identical one exists) and
+    /// returns its URL
+    pub fn store(&self,content:&[u8],ext:&str) -> std::io::Result<String> {
+        let name = self.write(content,ext)?;
+        let sep = if self.url.is_empty() || self.url.ends_with('/') { "" } else { "/" };
+        Ok(format!("{}{}{}",self.url,sep,name))
+    }
+    /// Like [`Assets::store`], but returns the file name, i.e. the URL as seen from other assets
+    pub fn write(&self,content:&[u8],ext:&str) -> std::io::Result<String> {
+        let name = format!("{:x}.{}",md5::compute(content),ext);
+        let mut written = self.written.lock().unwrap();
+        if !written.contains(&name) {
+            let path = self.dir.join(&name);
+            if !self.fs.is_file(&path) {
+                self.fs.write(&path,content)?;
+            }
+            written.insert(name.clone());
+        }
+        Ok(name)
+    }
+}
+
+/// The file extension and MIME type of `content` if browsers can display it as is, i.e. it need
+/// not be transcoded to PNG
+pub fn web_format(content:&[u8],filename:&std::path::Path) -> Option<(&'static str,&'static str)> {
+    if filename.extension().map(|e| e.eq_ignore_ascii_case("svg")).unwrap_or(false) {
+        return Some(("svg","image/svg+xml"))
+    }
+    match image::guess_format(content).ok()? {
+        ImageFormat::Png => Some(("png","image/png")),
+        ImageFormat::Jpeg => Some(("jpg","image/jpeg")),
+        ImageFormat::Gif => Some(("gif","image/gif")),
+        ImageFormat::WebP => Some(("webp","image/webp")),
+        ImageFormat::Avif => Some(("avif","image/avif")),
+        ImageFormat::Bmp => Some(("bmp","image/bmp")),
+        ImageFormat::Ico => Some(("ico","image/x-icon")),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::filesystem::{FileSystem, MemoryFS, Metadata};
+    use super::{Assets, web_format};
+
+    /// Counts the files looked at and written
+    #[derive(Default)]
+    struct Counting(MemoryFS,AtomicUsize,AtomicUsize);
+    impl FileSystem for Counting {
+        fn resolve(&self,name:&str,indir:&Path) -> Option<(PathBuf,bool)> { self.0.resolve(name,indir) }
+        fn read(&self,path:&Path) -> std::io::Result<Vec<u8>> { self.0.read(path) }
+        fn write(&self,path:&Path,content:&[u8]) -> std::io::Result<()> {
+            self.2.fetch_add(1,Ordering::SeqCst);
+            self.0.write(path,content)
+        }
+        fn metadata(&self,path:&Path) -> Option<Metadata> {
+            self.1.fetch_add(1,Ordering::SeqCst);
+            self.0.metadata(path)
+        }
+    }
+
+    #[test]
+    fn content_addressed() {
+        let fs = Arc::new(Counting::default());
+        let assets = Assets::new(PathBuf::from("/out/assets"),"assets".to_string(),fs.clone());
+        let name = assets.write(b"abc","png").unwrap();
+        assert_eq!(name,"900150983cd24fb0d6963f7d28e17f72.png");
+        assert_eq!(fs.read(Path::new("/out/assets/900150983cd24fb0d6963f7d28e17f72.png")).unwrap(),b"abc");
+        assert_eq!(assets.store(b"abc","png").unwrap(),"assets/900150983cd24fb0d6963f7d28e17f72.png");
+        // clones for HTML files elsewhere know what has been written already
+        let other = assets.with_url("../assets/".to_string());
+        assert_eq!(other.store(b"abc","png").unwrap(),"../assets/900150983cd24fb0d6963f7d28e17f72.png");
+        assert_eq!((fs.1.load(Ordering::SeqCst),fs.2.load(Ordering::SeqCst)),(1,1));
+        // a new one finds the file
+        let fresh = Assets::new(PathBuf::from("/out/assets"),String::new(),fs.clone());
+        assert_eq!(fresh.store(b"abc","png").unwrap(),"900150983cd24fb0d6963f7d28e17f72.png");
+        assert_eq!(fs.2.load(Ordering::SeqCst),1);
+        other.write(b"abd","png").unwrap();
+        assert_eq!(fs.2.load(Ordering::SeqCst),2);
+    }
+
+    #[test]
+    fn web_formats() {
+        let jpeg = [0xFF,0xD8,0xFF,0xE0,0,0x10,b'J',b'F',b'I',b'F',0];
+        let png = [0x89,b'P',b'N',b'G',0x0D,0x0A,0x1A,0x0A,0,0];
+        let tiff = [b'I',b'I',0x2A,0,8,0,0,0];
+        assert_eq!(web_format(&jpeg,Path::new("photo.jpeg")),Some(("jpg","image/jpeg")));
+        assert_eq!(web_format(&png,Path::new("plot.PNG")),Some(("png","image/png")));
+        // SVGs are recognized by their name, since they are text
+        assert_eq!(web_format(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>",Path::new("fig.SVG")),Some(("svg","image/svg+xml")));
+        // TIFF needs to be transcoded
+        assert_eq!(web_format(&tiff,Path::new("scan.tif")),None);
+    }
+}
@@ -459,7 +459,7 @@ impl HBox {
         match (self._to,self.spread) {
             (None,0) => htmlnode!(colon,div,self.get_ref(),"rustex-hbox",node_top,node => {
                         linescalebox!(colon,self.lineheight,node,{
-                if crate::INSERT_RUSTEX_ATTRS {
+                if colon.rustex_attrs {
                     node.attr("rustex:width".into(),dimtohtml(self.width()));
                     node.attr("rustex:height".into(),dimtohtml(self.height()));
                 }
@@ -474,7 +474,7 @@ impl HBox {
             (Some(to),_) => htmlnode!(colon,div,self.get_ref(),"rustex-hbox",node_top,node => {
                 withwidth!(colon,to,node,nnode => {
                         linescalebox!(colon,self.lineheight,nnode,{
-                    if crate::INSERT_RUSTEX_ATTRS {
+                    if colon.rustex_attrs {
                         nnode.attr("rustex:width".into(),dimtohtml(self.width()));
                         nnode.attr("rustex:height".into(),dimtohtml(self.height()));
                         nnode.attr("rustex:to".into(),dimtohtml(to));
@@ -488,7 +488,7 @@ impl HBox {
             (_,spread) => htmlnode!(colon,div,self.get_ref(),"rustex-hbox",node_top,node => {
                 withwidth!(colon,self.width(),node,nnode => {
                         linescalebox!(colon,self.lineheight,nnode,{
-                    if crate::INSERT_RUSTEX_ATTRS {
+                    if colon.rustex_attrs {
                         nnode.attr("rustex:width".into(),dimtohtml(self.width()));
                         nnode.attr("rustex:height".into(),dimtohtml(self.height()));
                         nnode.attr("rustex:spread".into(),dimtohtml(spread));
@@ -660,7 +660,7 @@ impl WhatsitTrait for VBox {
                         if let Some(dp) = self._depth {
                             div.style("margin-bottom".into(),dimtohtml(dp))
                         }
-                        if crate::INSERT_RUSTEX_ATTRS {
+                        if colon.rustex_attrs {
                             node.attr("rustex:width".into(),dimtohtml(self.width()));
                             node.attr("rustex:height".into(),dimtohtml(self.height()));
                         }
@@ -731,7 +731,7 @@ impl WhatsitTrait for VBox {
                 };
                 //htmlnode!(colon,div,None,outercls,node_top,conta => {
                     htmlnode!(colon,div,None,innercls,node_top,cont => {
-                    if crate::INSERT_RUSTEX_ATTRS {
+                    if colon.rustex_attrs {
                         cont.attr("rustex:width".into(),dimtohtml(self.width()));
                         cont.attr("rustex:height".into(),dimtohtml(self.height()));
                     }
@@ -832,13 +832,13 @@ impl VBox {
     fn html_t_inner_i(self, colon: &mut HTMLColon, node_top: &mut Option<HTMLParent>, withwidth:bool) {
         htmlnode!(colon,div,self.get_ref(),"rustex-vtop",node_top,node => {
             if withwidth {node.style("width".into(),"100%".into())}
-            if crate::INSERT_RUSTEX_ATTRS {
+            if colon.rustex_attrs {
                 node.attr("rustex:width".into(),dimtohtml(self.width()));
                 node.attr("rustex:height".into(),dimtohtml(self.height()));
                 node.attr("rustex:depth".into(),dimtohtml(self.depth()));
             }
             if let Some(ht) = self._to {
-                if crate::INSERT_RUSTEX_ATTRS {
+                if colon.rustex_attrs {
                     node.attr("rustex:to".into(),dimtohtml(ht));
                 }
                 if ht < 0 {
@@ -870,13 +870,13 @@ impl VBox {
     fn html_v_inner_i(self, colon: &mut HTMLColon, node_top: &mut Option<HTMLParent>, withwidth:bool) {
         htmlnode!(colon,div,self.get_ref(),"rustex-vbox",node_top,node => {
             if withwidth {node.style("width".into(),"100%".into())}
-            if crate::INSERT_RUSTEX_ATTRS {
+            if colon.rustex_attrs {
                 node.attr("rustex:width".into(),dimtohtml(self.width()));
                 node.attr("rustex:height".into(),dimtohtml(self.height()));
                 node.attr("rustex:depth".into(),dimtohtml(self.depth()));
             }
             if let Some(ht) = self._to {
-                if crate::INSERT_RUSTEX_ATTRS {
+                if colon.rustex_attrs {
                     node.attr("rustex:to".into(),dimtohtml(ht));
                 }
                 if ht < 0 {
@@ -2,7 +2,9 @@ use std::sync::Arc;
 use std::sync::mpsc::Receiver;
 use crate::fonts::{ArcFont, Font};
 use crate::Interpreter;
+use crate::interpreter::diagnostics::Diagnostics;
 use crate::stomach::{StomachMessage, Whatsit};
+use crate::stomach::boxes::TeXBox;
 use crate::stomach::whatsits::WhatsitTrait;
 use crate::utils::TeXStr;
 
@@ -12,14 +14,18 @@ pub enum ColonMode { V,H,P,M,External(TeXStr) }
 pub struct ColonBase {
     pub basefont:Option<ArcFont>,
     pub basecolor:Option<TeXStr>,
-    pub receiver:Option<Receiver<StomachMessage>>
+    pub receiver:Option<Receiver<StomachMessage>>,
+    /// problems found while rendering (the colon may run in its own thread); passed on to the
+    /// [`InterpreterParams`](crate::interpreter::params::InterpreterParams) after the document
+    pub diagnostics:Arc<Diagnostics>
 }
 impl ColonBase {
     pub fn new() -> ColonBase {
         ColonBase {
             basefont:None,
             basecolor:None,
-            receiver:None
+            receiver:None,
+            diagnostics:Arc::new(Diagnostics::new())
         }
     }
 }
@@ -29,6 +35,13 @@ pub trait Colon<A> : Send {
     fn base(&self) -> &ColonBase;
     fn ship_whatsit(&mut self, wi:Whatsit);
     fn close(&mut self) -> A;
+    /// A box shipped out via `\shipout`; by default just treated like any other whatsit
+    fn ship_page(&mut self, bx:TeXBox) {
+        self.ship_whatsit(bx.as_whatsit())
+    }
+    /// Whether the document should be broken into pages by the page builder and `\output`, i.e.
+    /// processed with a [`ShipoutRoutine`](crate::stomach::ShipoutRoutine)
+    fn output_routine(&self) -> bool { false }
 
     fn normalize_whatsit(&self, wi:Whatsit) -> Vec<Whatsit> {
         let mut top : Vec<Whatsit> = vec!();
@@ -207,7 +207,7 @@ impl WhatsitTrait for FontChange {
         match &self.font.file.chartable {
             Some(_) => {
                 htmlannotate!(colon,span,self.get_ref(),node_top,a => {
-                    if crate::INSERT_RUSTEX_ATTRS {
+                    if colon.rustex_attrs {
                         a.attr("rustex:font".into(),(&self.font.file.name).into());
                         a.attr("rustex:fontfile".into(),(&self.font.file.filepath).into());
                     }
@@ -3,16 +3,19 @@ use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::{Add, AddAssign};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use itertools::Itertools;
 use crate::fonts::{ArcFont, Font, NULL_FONT};
 use crate::fonts::fontchars::FontTableParam;
 use crate::Interpreter;
 use crate::interpreter::dimensions::{numtostr, Skip};
-use crate::references::SourceFileReference;
+use crate::references::{SourceFileReference, SourceMap};
+use crate::stomach::assets::Assets;
 use crate::stomach::colon::{Colon, ColonBase, ColonMode};
-use crate::stomach::Whatsit;
-use crate::stomach::whatsits::{lineheight, WhatsitTrait};
+use crate::stomach::{split_vertical, Whatsit};
+use crate::stomach::boxes::TeXBox;
+use crate::stomach::simple::{MoveRight, SimpleWI};
+use crate::stomach::whatsits::{Insert, lineheight, WhatsitTrait};
 use crate::utils::TeXStr;
 
 pub static HTMLSCALE : f32 = 1.5;
@@ -272,7 +275,18 @@ pub struct HTMLColon {
     doheader:bool,
     pub state:HTMLState,
     pub namespaces : HashMap<String,String>,
-    pagewidth:i32,pub textwidth:i32
+    pagewidth:i32,pub textwidth:i32,
+    /// break the output into pages of height `\vsize`, each in its own `<div class="rustex-page">`;
+    /// the document is then processed with a [`ShipoutRoutine`](crate::stomach::ShipoutRoutine),
+    /// i.e. pages are built by `\output`; their headers and footers get `<div>`s of their own
+    pub paginate:bool,
+    pageheight:i32,vsize:i32,
+    page:Vec<Whatsit>,pageinserts:Vec<Whatsit>,pagecount:usize,
+    /// add `rustex:width`, `rustex:font` etc. attributes, for debugging
+    pub rustex_attrs:bool,
+    /// write images to files instead of embedding them as data URIs
+    pub assets:Option<Assets>,
+    sourcemap:Option<Arc<Mutex<SourceMap>>>
 }
 //unsafe impl Send for HTMLColon {}
 
@@ -280,16 +294,28 @@ impl Colon<String> for HTMLColon {
     fn base(&self) -> &ColonBase { &self.base }
     fn base_mut(&mut self) -> &mut ColonBase { &mut self.base }
     fn ship_whatsit(&mut self, w:Whatsit) {
-        for w in self.normalize_whatsit(w) { w.as_html(&ColonMode::V,self,&mut None) }
-        let fi = match self.base.basefont.as_ref() {
-            None => NULL_FONT.try_with(|x| FontInfo::new(x)).unwrap(),
-            Some(f) => FontInfo::new(f)
-        };
-        for n in std::mem::take(&mut self.state.top) {
-            self.ret += &n.make_string("  ".into(),&HTML_NS,&fi).to_string()
-        }
+        if self.paginate { return self.add_to_page(w) }
+        let ret = self.render(vec!(w));
+        self.ret += &ret
     } //}
+    fn ship_page(&mut self, bx: TeXBox) {
+        if !self.paginate { return self.ship_whatsit(bx.as_whatsit()) }
+        self.close_page();
+        // shipped out by an output routine, so headers, footers and footnotes are already there
+        let (header,body,footer) = match bx {
+            TeXBox::V(vb) => page_parts(vb.children),
+            bx => (vec!(),vec!(bx.as_whatsit()),vec!())
+        };
+        let header = self.render(header);
+        let footer = self.render(footer);
+        self.write_page(header,body,vec!(),footer)
+    }
+    fn output_routine(&self) -> bool { self.paginate }
     fn initialize(&mut self, basefont: ArcFont, basecolor: TeXStr, int: &Interpreter) {
+        self.pagewidth = int.state.dimensions_prim.get(&(crate::commands::registers::PDFPAGEWIDTH.index - 1));
+        self.pageheight = int.state.dimensions_prim.get(&(crate::commands::registers::PDFPAGEHEIGHT.index - 1));
+        self.vsize = int.stomach.base().vsize;
+        self.textwidth = int.state.dimensions_prim.get(&(crate::commands::registers::HSIZE.index - 1));
         if self.doheader {
             self.state.currsize =  int.state.dimensions_prim.get(&(crate::commands::registers::HSIZE.index - 1));
             self.state.currcolor = match &basecolor {
@@ -303,8 +329,6 @@ impl Colon<String> for HTMLColon {
                 }
                 None => 655360
             };
-            self.pagewidth = int.state.dimensions_prim.get(&(crate::commands::registers::PDFPAGEWIDTH.index - 1));
-            self.textwidth = int.state.dimensions_prim.get(&(crate::commands::registers::HSIZE.index - 1));
             self.state.line_height = lineheight(&int.state);//int.state.skips_prim.get(&(crate::commands::registers::BASELINESKIP.index - 1));
             self.state.line_scale = self.state.lineheight();
 
@@ -314,12 +338,147 @@ impl Colon<String> for HTMLColon {
         }
     }
     fn close(&mut self) -> String {
+        if self.paginate { self.close_page() }
         if self.doheader {
             self.header() + &std::mem::take(&mut self.ret) + "\n    </div>\n  </body>\n</html>"
         } else { std::mem::take(&mut self.ret) }
     }
 }
+/// Splits a page shipped out by `\output` into header, body and footer. Like plain TeX's
+/// `\makeheadline\pagebody\makefootline` and LaTeX's `\@outputpage`, output routines usually ship
+/// a (possibly nested) vbox whose tallest box is the text; the boxes above it make up the header,
+/// the ones below it the footer. Anything else (e.g. a list of lines or paragraphs) is all body.
+fn page_parts(mut page:Vec<Whatsit>) -> (Vec<Whatsit>,Vec<Whatsit>,Vec<Whatsit>) {
+    fn as_box(w:&Whatsit) -> Option<&TeXBox> {
+        match w {
+            Whatsit::Box(bx) => Some(bx),
+            Whatsit::Simple(SimpleWI::MoveRight(m)) => Some(&m.content),
+            _ => None
+        }
+    }
+    fn spacing(w:&Whatsit) -> bool {
+        matches!(w,Whatsit::Simple(SimpleWI::VSkip(_) | SimpleWI::VKern(_) | SimpleWI::VFil(_) |
+            SimpleWI::VFill(_) | SimpleWI::Vss(_) | SimpleWI::Penalty(_) | SimpleWI::Mark(_)))
+    }
+    loop {
+        let boxes : Vec<usize> = page.iter().enumerate().filter(|(_,w)| as_box(w).is_some()).map(|(i,_)| i).collect();
+        if boxes.len() > 3 || !page.iter().all(|w| as_box(w).is_some() || spacing(w)) {
+            return (vec!(),page,vec!())
+        }
+        match boxes.as_slice() {
+            [] => return (vec!(),page,vec!()),
+            [i] => match page.remove(*i) {
+                Whatsit::Box(TeXBox::V(vb)) |
+                Whatsit::Simple(SimpleWI::MoveRight(MoveRight { content:TeXBox::V(vb),.. })) => page = vb.children,
+                w => {
+                    page.insert(*i,w);
+                    return (vec!(),page,vec!())
+                }
+            },
+            _ => {
+                let body = boxes.into_iter().rev().max_by_key(|i| {
+                    let bx = as_box(&page[*i]).unwrap();
+                    bx.height() + bx.depth()
+                }).unwrap();
+                if !matches!(as_box(&page[body]),Some(TeXBox::V(_))) { return (vec!(),page,vec!()) }
+                let footer = page.split_off(body + 1);
+                let body = page.pop().unwrap();
+                return (page,vec!(body),footer)
+            }
+        }
+    }
+}
+
 impl HTMLColon {
+    /// Enables the source map: every element with a source reference gets an `id`
+    /// (instead of a `rustex:sourceref` attribute) and an entry in the returned [`SourceMap`],
+    /// which is complete once the colon is closed. Text runs are wrapped in `<span>`s for this.
+    pub fn sourcemap(&mut self) -> Arc<Mutex<SourceMap>> {
+        self.sourcemap.get_or_insert_with(|| Arc::new(Mutex::new(SourceMap::new()))).clone()
+    }
+    pub fn has_sourcemap(&self) -> bool { self.sourcemap.is_some() }
+    fn render(&mut self,wis:Vec<Whatsit>) -> String {
+        for w in wis {
+            for w in self.normalize_whatsit(w) { w.as_html(&ColonMode::V,self,&mut None) }
+        }
+        if let Some(sm) = &self.sourcemap {
+            let mut sm = sm.lock().unwrap();
+            for c in self.state.top.iter_mut() { c.add_ids(&mut sm) }
+        }
+        let fi = match self.base.basefont.as_ref() {
+            None => NULL_FONT.try_with(|x| FontInfo::new(x)).unwrap(),
+            Some(f) => FontInfo::new(f)
+        };
+        let mut ret = "".to_string();
+        for n in std::mem::take(&mut self.state.top) {
+            ret += &n.make_string("  ".into(),&HTML_NS,&fi).to_string()
+        }
+        ret
+    }
+    fn add_to_page(&mut self,w:Whatsit) {
+        match w {
+            Whatsit::Inserts(_) => self.pageinserts.push(w),
+            Whatsit::Simple(SimpleWI::Penalty(p)) if p.penalty <= -10000 => return self.close_page(),
+            w => self.page.push(w)
+        }
+        loop {
+            let insertheight : i32 = self.pageinserts.iter().map(|w| match w {
+                Whatsit::Inserts(Insert(v)) => v.iter().flatten().map(|w| w.height() + w.depth()).sum(),
+                _ => 0
+            }).sum();
+            let target = self.vsize - insertheight;
+            let height : i32 = self.page.iter().map(|w| w.height()).sum();
+            if height <= target || self.page.is_empty() { return }
+            let (first,mut rest) = split_vertical(std::mem::take(&mut self.page),target);
+            // a single item larger than a page gets a page of its own
+            self.page = if first.is_empty() { vec!(rest.remove(0)) } else { first };
+            self.close_page();
+            self.page = rest;
+        }
+    }
+    /// Finishes a page built by [`add_to_page`](HTMLColon::add_to_page), i.e. without an output
+    /// routine; there is nothing to take a header from, so it only gets a footer with its number
+    fn close_page(&mut self) {
+        if self.page.is_empty() && self.pageinserts.is_empty() { return }
+        let page = std::mem::take(&mut self.page);
+        let inserts = std::mem::take(&mut self.pageinserts);
+        let footer = (self.pagecount + 1).to_string();
+        self.write_page(String::new(),page,inserts,footer)
+    }
+    fn write_page(&mut self,header:String,body:Vec<Whatsit>,footnotes:Vec<Whatsit>,footer:String) {
+        self.pagecount += 1;
+        self.ret += "\n<div class=\"rustex-page\" style=\"width:";
+        self.ret += &dimtohtml(self.pagewidth).to_string();
+        self.ret += ";min-height:";
+        self.ret += &dimtohtml(self.pageheight).to_string();
+        self.ret += ";padding-left:";
+        let padding = dimtohtml((self.pagewidth - self.textwidth) / 2).to_string();
+        self.ret += padding.as_str();
+        self.ret += ";padding-right:";
+        self.ret += padding.as_str();
+        self.ret += "\">";
+        if !header.is_empty() {
+            self.ret += "\n<div class=\"rustex-page-header\">";
+            self.ret += &header;
+            self.ret += "</div>";
+        }
+        self.ret += "\n<div class=\"rustex-page-body\">";
+        let body = self.render(body);
+        self.ret += &body;
+        self.ret += "</div>";
+        if !footnotes.is_empty() {
+            self.ret += "\n<div class=\"rustex-page-footnotes\">";
+            let footnotes = self.render(footnotes);
+            self.ret += &footnotes;
+            self.ret += "</div>";
+        }
+        if !footer.is_empty() {
+            self.ret += "\n<div class=\"rustex-page-footer\">";
+            self.ret += &footer;
+            self.ret += "</div>";
+        }
+        self.ret += "\n</div>"
+    }
     fn header(&self) -> String {
         let mut ret : String = "".to_string();
         if self.doheader {
@@ -353,7 +512,10 @@ impl HTMLColon {
             //ret += ";--document-width:min(100vw,";
             //ret += &dimtohtml(self.textwidth).to_string();
             ret += ");padding-left:";
-            let padding = ((self.pagewidth - self.textwidth) as f32 / (2.0 * (self.pagewidth as f32)) * 100.0).to_string() + "%";
+            // in paginated mode, the pages have the margins instead
+            let padding = if self.paginate { "0".to_string() } else {
+                ((self.pagewidth - self.textwidth) as f32 / (2.0 * (self.pagewidth as f32)) * 100.0).to_string() + "%"
+            };
             ret += padding.as_str();
             //ret += &dimtohtml(((self.pagewidth - self.textwidth) as f32 / 2.0).round() as i32).to_string();
             ret += ";padding-right:";
@@ -361,7 +523,7 @@ impl HTMLColon {
             //ret += &dimtohtml(((self.pagewidth - self.textwidth) as f32 / 2.0).round() as i32).to_string();
             ret += ";line-height:";
             ret += &(self.state.line_scale).to_string();
-            if crate::INSERT_RUSTEX_ATTRS {
+            if self.rustex_attrs {
                 ret += ";\"";
                 ret += " rustex:font=\"";
                 match self.base.basefont.as_ref() {
@@ -381,7 +543,13 @@ impl HTMLColon {
             doheader,
             namespaces:HashMap::new(),
             pagewidth: 0,
-            textwidth: 0
+            textwidth: 0,
+            paginate:false,
+            pageheight:0,vsize:0,
+            page:vec!(),pageinserts:vec!(),pagecount:0,
+            rustex_attrs:false,
+            assets:None,
+            sourcemap:None
         };
         ret.namespaces.insert("xhtml".into(),HTML_NS.into());
         ret.namespaces.insert("mml".into(),MATHML_NS.into());
@@ -662,6 +830,25 @@ pub enum HTMLChild {
     Annot(HTMLAnnotation)
 }
 impl HTMLChild {
+    fn add_ids(&mut self,sm:&mut SourceMap) {
+        let (attributes,sourceref,children) = match self {
+            HTMLChild::Str(_) => return,
+            HTMLChild::Node(n) => (&mut n.attributes,&mut n.sourceref,&mut n.children),
+            HTMLChild::Annot(a) => (&mut a.attributes,&mut a.sourceref,&mut a.children)
+        };
+        if let Some(rf) = sourceref.take() {
+            let id = match attributes.get(&"id".into()) {
+                Some(id) => id.clone().to_string(),
+                None => {
+                    let id = sm.next_id();
+                    attributes.insert("id".into(),id.clone().into());
+                    id
+                }
+            };
+            sm.add(id,rf)
+        }
+        for c in children.iter_mut() { c.add_ids(sm) }
+    }
     pub fn make_string(self,prefix:HTMLStr,namespace:&str,fi:&FontInfo) -> HTMLStr {
         match self {
             HTMLChild::Str(s) => s,
@@ -901,4 +1088,32 @@ impl From<&HTMLStr> for String {
             HTMLStr::Imm(s) => s.to_string()
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::stomach::Whatsit;
+    use crate::stomach::boxes::{HBox, TeXBox, VBox, VBoxType};
+    use crate::stomach::whatsits::WhatsitTrait;
+    use super::page_parts;
+
+    fn vbox(children:Vec<Whatsit>,height:i32) -> Whatsit {
+        Whatsit::Box(TeXBox::V(VBox { children,tp:VBoxType::V,spread:0,_width:None,_height:Some(height),_depth:None,_to:None,rf:None }))
+    }
+    fn hbox(height:i32) -> Whatsit {
+        let mut bx = HBox::new_trivial(vec!());
+        bx._height = Some(height);
+        Whatsit::Box(TeXBox::H(bx))
+    }
+
+    #[test]
+    fn headers_and_footers() {
+        // plain TeX: \vbox{\makeheadline\pagebody\makefootline}
+        let page = vec!(vbox(vec!(hbox(10)),0),vbox(vec!(hbox(10),hbox(10)),500),hbox(10));
+        let (header,body,footer) = page_parts(vec!(vbox(page,520)));
+        assert_eq!((header.len(),body.len(),footer.len()),(1,1,1));
+        assert_eq!(body[0].height(),500);
+        // a list of lines has neither
+        let (header,body,footer) = page_parts(vec!(hbox(10),hbox(20),hbox(10)));
+        assert_eq!((header.len(),body.len(),footer.len()),(0,3,0));
+    }
+}
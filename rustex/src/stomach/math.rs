@@ -150,7 +150,7 @@ impl WhatsitTrait for MathGroup {
         match mode {
             ColonMode::H | ColonMode::P | ColonMode::V if self.limits => htmlnode!(colon,div,None,"rustex-display-math-container",node_top,div =>{
                 htmlnode!(colon,MATHML_NS:math,self.get_ref(),"",htmlparent!(div),node=> {
-                    if crate::INSERT_RUSTEX_ATTRS {
+                    if colon.rustex_attrs {
                         node.attr("rustex:width".into(),dimtohtml(self.width()));
                         node.attr("rustex:height".into(),dimtohtml(self.height()));
                     }
@@ -167,7 +167,7 @@ impl WhatsitTrait for MathGroup {
                 })
             }),
             ColonMode::H | ColonMode::P | ColonMode::V => htmlnode!(colon,MATHML_NS:math,self.get_ref(),"",node_top,node=> {
-                if crate::INSERT_RUSTEX_ATTRS {
+                if colon.rustex_attrs {
                     node.attr("rustex:width".into(),dimtohtml(self.width()));
                     node.attr("rustex:height".into(),dimtohtml(self.height()));
                 }
@@ -188,7 +188,7 @@ impl WhatsitTrait for MathGroup {
                 match (self.subscript,self.superscript) {
                     (None,None) => self.kernel.as_html(mode,colon,node_top),
                     (None,Some(ss)) if self.limits && self.kernel.is_largeop() => htmlnode!(colon,mover,rf,"",node_top,msup => {
-                        if crate::INSERT_RUSTEX_ATTRS {
+                        if colon.rustex_attrs {
                         msup.attr("rustex:width".into(),dimtohtml(width));
                         msup.attr("rustex:height".into(),dimtohtml(height));
                     }
@@ -200,7 +200,7 @@ impl WhatsitTrait for MathGroup {
                     if msup.children.len() < 3 { htmlnode!(colon,mrow,None,"",htmlparent!(msup)) }
                 }),
                     (None,Some(ss)) => htmlnode!(colon,msup,rf,"",node_top,msup => {
-                        if crate::INSERT_RUSTEX_ATTRS {
+                        if colon.rustex_attrs {
                         msup.attr("rustex:width".into(),dimtohtml(width));
                         msup.attr("rustex:height".into(),dimtohtml(height));
                     }
@@ -212,7 +212,7 @@ impl WhatsitTrait for MathGroup {
                     if msup.children.len() < 3 { htmlnode!(colon,mrow,None,"",htmlparent!(msup)) }
                 }),
                     (Some(ss),None) if self.limits && self.kernel.is_largeop() => htmlnode!(colon,munder,rf,"",node_top,msup => {
-                        if crate::INSERT_RUSTEX_ATTRS {
+                        if colon.rustex_attrs {
                         msup.attr("rustex:width".into(),dimtohtml(width));
                         msup.attr("rustex:height".into(),dimtohtml(height));
                     }
@@ -224,7 +224,7 @@ impl WhatsitTrait for MathGroup {
                     if msup.children.len() < 3 { htmlnode!(colon,mrow,None,"",htmlparent!(msup)) }
                 }),
                     (Some(ss),None) => htmlnode!(colon,msub,rf,"",node_top,msup => {
-                        if crate::INSERT_RUSTEX_ATTRS {
+                        if colon.rustex_attrs {
                         msup.attr("rustex:width".into(),dimtohtml(width));
                         msup.attr("rustex:height".into(),dimtohtml(height));
                     }
@@ -236,7 +236,7 @@ impl WhatsitTrait for MathGroup {
                     if msup.children.len() < 3 { htmlnode!(colon,mrow,None,"",htmlparent!(msup)) }
                 }),
                     (Some(subk),Some(supk)) if self.limits && is_large_op(&self.kernel) => htmlnode!(colon,munderover,rf,"",node_top,msub => {
-                        if crate::INSERT_RUSTEX_ATTRS {
+                        if colon.rustex_attrs {
                         msub.attr("rustex:width".into(),dimtohtml(width));
                         msub.attr("rustex:height".into(),dimtohtml(height));
                     }
@@ -251,7 +251,7 @@ impl WhatsitTrait for MathGroup {
                     if msub.children.len() < 5 { htmlnode!(colon,mrow,None,"",htmlparent!(msub)) }
                 }),
                     (Some(subk),Some(supk)) => htmlnode!(colon,msubsup,rf,"",node_top,msub => {
-                        if crate::INSERT_RUSTEX_ATTRS {
+                        if colon.rustex_attrs {
                         msub.attr("rustex:width".into(),dimtohtml(width));
                         msub.attr("rustex:height".into(),dimtohtml(height));
                     }
@@ -587,7 +587,7 @@ impl WhatsitTrait for CustomMathChar {
                         _ => {}
                     };
                     a.fontinfo = Some(mimoinfo);
-                    if crate::INSERT_RUSTEX_ATTRS { a.attr("rustex:font".into(),(&self.font.file.name).into()) }
+                    if colon.rustex_attrs { a.attr("rustex:font".into(),(&self.font.file.name).into()) }
                     htmlliteral!(colon,htmlparent!(a),>charstr<)
                 })
             }
@@ -640,7 +640,7 @@ impl MathChar {
         if (self.class == 0 || self.class == 7) {
             htmlnode!(colon,mi,self.sourceref,"",node_top,a => {
                 a.fontinfo = Some(mimoinfo);
-                if crate::INSERT_RUSTEX_ATTRS {
+                if colon.rustex_attrs {
                     a.attr("rustex:font".into(),(&self.font.file.name).into()) ;
                     a.attr("rustex:charpos".into(),self.position.to_string().into());
                 }
@@ -661,7 +661,7 @@ impl MathChar {
                 } else {
                     a.attr("stretchy".into(),"false".into());
                 }
-                if crate::INSERT_RUSTEX_ATTRS {
+                if colon.rustex_attrs {
                     a.attr("rustex:font".into(),(&self.font.file.name).into()) ;
                     a.attr("rustex:charpos".into(),self.position.to_string().into());
                 }
@@ -685,7 +685,7 @@ impl MathChar {
                     } else {
                         a.attr("stretchy".into(),"false".into());
                     }
-                    if crate::INSERT_RUSTEX_ATTRS {
+                    if colon.rustex_attrs {
                         a.attr("rustex:font".into(),(&self.font.file.name).into()) ;
                         a.attr("rustex:charpos".into(),self.position.to_string().into());
                     }
@@ -22,7 +22,9 @@ pub struct Paragraph {
     pub _height:i32,
     pub _depth:i32,
     lines : Option<Vec<(i32,i32)>>,
-    pub finallines:usize
+    pub finallines:usize,
+    /// the line the paragraph was started on (for `\showlists`)
+    pub line:usize
 }
 
 impl WhatsitTrait for Paragraph {
@@ -70,7 +72,7 @@ impl WhatsitTrait for Paragraph {
                 }
                 _ => ()
             }
-            if crate::INSERT_RUSTEX_ATTRS {
+            if colon.rustex_attrs {
                 node.attr("rustex:width".into(),dimtohtml(self.width()));
                 node.attr("rustex:height".into(),dimtohtml(self.height()));
             }
@@ -168,11 +170,11 @@ impl Paragraph {
             _width:self._width,
             _height:self._height,
             _depth:self._depth,
-            lines:self.lines,finallines:self.finallines
+            lines:self.lines,finallines:self.finallines,line:self.line
         };
         (np,self.children)
     }
-    pub fn split(self,target:i32,state:&State) -> (Paragraph,Paragraph) {
+    pub fn split(self,target:i32) -> (Paragraph,Paragraph) {
         let mut presplit : Vec<StomachGroup> = vec!(StomachGroup::Top(vec!()));
         let mut currentwidth : i32 = 0;
         let mut currentheight : i32 = 0;
@@ -222,6 +224,7 @@ impl Paragraph {
                             currentdepth = 0;
                             currline += 1;
                             hgoal = lines.get(currline).unwrap_or(lines.last().unwrap()).1;
+                            presplit.last_mut().unwrap().push(next)
                         }
                         Whatsit::Math(mg) if mg.limits => {
                             currentwidth = 0;
@@ -281,7 +284,7 @@ impl Paragraph {
                     _ => ()//TeXErr!("Should be unreachable!")
                 }
             }
-            Some(_) => {
+            Some(first) => {
                 while match presplit.last() {
                     Some(StomachGroup::Top(_)) => false,
                     _ => true
@@ -296,6 +299,12 @@ impl Paragraph {
                 for g in &input[1..] {
                     second.push(g.new_from())
                 }
+                match first {
+                    // a forced line break where the paragraph is split: the second part starts a
+                    // new line anyway, so keeping it would make its first line empty
+                    Whatsit::Simple(SimpleWI::Penalty(_)) => (),
+                    first => second.last_mut().unwrap().push(first)
+                }
                 loop {
                     match input.last_mut() {
                         None => break,
@@ -338,7 +347,7 @@ impl Paragraph {
                 }
             }
         }
-        p2.close(state,0,0,vec!());
+        p2.layout(0,0,vec!());
         (p1,p2)
     }
     pub fn close(&mut self,state:&State,hangindent:i32,hangafter:usize,parshape:Vec<(i32,i32)>) {
@@ -346,6 +355,10 @@ impl Paragraph {
         self.leftskip.get_or_insert(state.skips_prim.get(&(crate::commands::registers::LEFTSKIP.index - 1)));
         self.hsize.get_or_insert(state.dimensions_prim.get(&(crate::commands::registers::HSIZE.index - 1)));
         self.lineheight.get_or_insert(lineheight(state));
+        self.layout(hangindent,hangafter,parshape)
+    }
+    /// computes the lines and dimensions of a paragraph whose hsize, skips and lineheight are known
+    fn layout(&mut self,hangindent:i32,hangafter:usize,parshape:Vec<(i32,i32)>) {
         self._width = self.hsize.unwrap() - (self.leftskip.unwrap().base  + self.rightskip.unwrap().base);
 
         self.lines.get_or_insert(if !parshape.is_empty() {
@@ -416,6 +429,6 @@ impl Paragraph {
     pub fn new(parskip:i32) -> Paragraph { Paragraph {
         parskip,children:vec!(),
         leftskip:None,rightskip:None,hsize:None,lineheight:None,
-        _width:0,_height:0,_depth:0,lines:None,finallines:0
+        _width:0,_height:0,_depth:0,lines:None,finallines:0,line:0
     }}
 }
\ No newline at end of file
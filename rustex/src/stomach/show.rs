@@ -0,0 +1,204 @@
+/*! TeX-style listings of [`Whatsit`] lists, as printed by `\showbox` and `\showlists`.
+
+Every node is printed on its own line, prefixed by one `.` per nesting level. Lists nested deeper
+than `\showboxdepth` are abbreviated as ` []`, and lists longer than `\showboxbreadth` are cut off
+with `etc.`. Since RusTeX does not break paragraphs into lines, paragraphs are listed as a single
+`\paragraph` node containing their horizontal material.
+*/
+
+use crate::interpreter::dimensions::{numtostr, Skip, SkipDim};
+use crate::stomach::boxes::TeXBox;
+use crate::stomach::groups::WIGroupTrait;
+use crate::stomach::simple::SimpleWI;
+use crate::stomach::whatsits::{Whatsit, WhatsitTrait};
+
+/// Lists `ls` with the given `\showboxdepth` and `\showboxbreadth`
+pub fn show_list(ls:&[Whatsit],depth:i32,breadth:i32) -> String {
+    let mut lister = Lister::new(depth,breadth);
+    lister.list(ls);
+    lister.out
+}
+
+/// Lists the box `bx` (which is itself a node at depth 0); void boxes are listed as `void`
+pub fn show_box(bx:&TeXBox,depth:i32,breadth:i32) -> String {
+    let mut lister = Lister::new(depth,breadth);
+    match bx {
+        TeXBox::Void => lister.out.push_str("void"),
+        _ => lister.tbox(bx,0)
+    }
+    lister.out
+}
+
+fn dim(d:i32) -> String { numtostr(d,"") }
+
+fn skipdim(d:&SkipDim) -> String {
+    match d {
+        SkipDim::Pt(i) => dim(*i),
+        o => o.to_string()
+    }
+}
+
+fn spec(s:&Skip) -> String {
+    let mut ret = dim(s.base);
+    if let Some(st) = &s.stretch { ret += " plus "; ret += &skipdim(st) }
+    if let Some(sh) = &s.shrink { ret += " minus "; ret += &skipdim(sh) }
+    ret
+}
+
+fn printable(c:u8) -> String {
+    if c >= 32 && c < 127 { (c as char).to_string() } else { format!("^^{:02x}",c) }
+}
+
+/// The glue spec of glue-like nodes
+fn glue(wi:&Whatsit) -> Option<String> {
+    let fil = SkipDim::Fil(65536);
+    match wi {
+        Whatsit::Simple(SimpleWI::VSkip(s)) => Some(spec(&s.skip)),
+        Whatsit::Simple(SimpleWI::HSkip(s)) => Some(spec(&s.skip)),
+        Whatsit::Simple(SimpleWI::VFil(_) | SimpleWI::HFil(_)) =>
+            Some(spec(&Skip { base:0, stretch:Some(fil), shrink:None })),
+        Whatsit::Simple(SimpleWI::VFill(_) | SimpleWI::HFill(_)) =>
+            Some(spec(&Skip { base:0, stretch:Some(SkipDim::Fill(65536)), shrink:None })),
+        Whatsit::Simple(SimpleWI::Vss(_) | SimpleWI::Hss(_)) =>
+            Some(spec(&Skip { base:0, stretch:Some(fil), shrink:Some(fil) })),
+        Whatsit::Space(s) => Some(dim(s.width())),
+        _ => None
+    }
+}
+
+struct Lister {
+    depth:usize,
+    breadth:usize,
+    prefix:String,
+    out:String
+}
+impl Lister {
+    fn new(depth:i32,breadth:i32) -> Lister {
+        Lister {
+            depth:if depth < 0 {0} else {depth as usize},
+            breadth:if breadth <= 0 {5} else {breadth as usize},
+            prefix:String::new(),
+            out:String::new()
+        }
+    }
+    fn line(&mut self,s:&str) {
+        if !self.out.is_empty() { self.out.push('\n') }
+        self.out.push_str(&self.prefix);
+        self.out.push_str(s)
+    }
+    /// Lists the children of the current node one level deeper
+    fn nested(&mut self,ls:&[Whatsit]) {
+        if ls.is_empty() { return }
+        if self.prefix.len() >= self.depth {
+            self.out.push_str(" []");
+            return
+        }
+        self.prefix.push('.');
+        self.list(ls);
+        self.prefix.pop();
+    }
+    fn list(&mut self,ls:&[Whatsit]) {
+        let mut flat : Vec<&Whatsit> = vec!();
+        flatten(ls,&mut flat);
+        for (i,wi) in flat.into_iter().enumerate() {
+            if i >= self.breadth {
+                self.line("etc.");
+                return
+            }
+            self.node(wi)
+        }
+    }
+    fn tbox(&mut self,bx:&TeXBox,shift:i32) {
+        let shifted = if shift != 0 { format!(", shifted {}",dim(shift)) } else { String::new() };
+        let dims = format!("({}+{})x{}{}",dim(bx.height()),dim(bx.depth()),dim(bx.width()),shifted);
+        match bx {
+            TeXBox::Void => (),
+            TeXBox::H(hb) => {
+                self.line(&("\\hbox".to_string() + &dims));
+                self.nested(&hb.children)
+            }
+            TeXBox::V(vb) => {
+                self.line(&("\\vbox".to_string() + &dims));
+                self.nested(&vb.children)
+            }
+            TeXBox::M(_) | TeXBox::DM(_) | TeXBox::LeftRight(..) => {
+                self.line("\\mathon");
+                self.line("\\mathoff")
+            }
+        }
+    }
+    fn node(&mut self,wi:&Whatsit) {
+        use SimpleWI::*;
+        if let Some(g) = glue(wi) {
+            return self.line(&("\\glue ".to_string() + &g))
+        }
+        match wi {
+            Whatsit::Box(bx) => self.tbox(bx,0),
+            Whatsit::Float(bx) => {
+                self.line("\\insert");
+                self.prefix.push('.');
+                self.tbox(bx,0);
+                self.prefix.pop();
+            }
+            Whatsit::Char(c) => self.line(&format!("\\{} {}",c.font.name,printable(c.char))),
+            Whatsit::Accent(a) => self.line(&format!("\\accent\\{} {}{}",a.font.name,printable(a.acc as u8),printable(a.char.char))),
+            Whatsit::Par(p) => {
+                self.line(&format!("\\paragraph({}+{})x{}",dim(p.height()),dim(p.depth()),dim(p.width())));
+                self.nested(&p.children)
+            }
+            Whatsit::Math(_) => {
+                self.line("\\mathon");
+                self.line("\\mathoff")
+            }
+            Whatsit::Above(_) => self.line("\\fraction"),
+            Whatsit::Inserts(_) => self.line("\\insert"),
+            Whatsit::Exec(_) => self.line("\\whatsit"),
+            Whatsit::Simple(VRule(r)) => self.line(&format!("\\rule({}+{})x{}",
+                r.height.map(dim).unwrap_or("*".to_string()),
+                r.depth.map(dim).unwrap_or("*".to_string()),
+                dim(r.width()))),
+            Whatsit::Simple(HRule(r)) => self.line(&format!("\\rule({}+{})x{}",
+                dim(r.height()),dim(r.depth()),
+                r.width.map(dim).unwrap_or("*".to_string()))),
+            Whatsit::Simple(MSkip(s)) => self.line(&("\\mskip ".to_string() + &s.skip.to_string())),
+            Whatsit::Simple(Penalty(p)) => self.line(&format!("\\penalty {}",p.penalty)),
+            Whatsit::Simple(VKern(k)) => self.line(&format!("\\kern {}",dim(k.dim))),
+            Whatsit::Simple(HKern(k)) => self.line(&format!("\\kern {}",dim(k.dim))),
+            Whatsit::Simple(Indent(i)) => self.line(&format!("\\hbox(0.0+0.0)x{}",dim(i.dim))),
+            Whatsit::Simple(Raise(r)) => self.tbox(&r.content,-r.dim),
+            Whatsit::Simple(MoveRight(m)) => self.tbox(&m.content,m.dim),
+            Whatsit::Simple(Mark(m)) =>
+                self.line(&format!("\\mark{{{}}}",crate::interpreter::tokens_to_string_default(&m.toks))),
+            Whatsit::Simple(Leaders(l)) => {
+                self.line(&("\\leaders ".to_string() + &glue(&l.glue).unwrap_or(dim(l.glue.width()))));
+                self.prefix.push('.');
+                self.node(&l.bx);
+                self.prefix.pop();
+            }
+            Whatsit::Simple(HAlign(_)) => self.line("\\halign"),
+            Whatsit::Simple(VAlign(_)) => self.line("\\valign"),
+            Whatsit::Simple(PDFXImage(_)) => self.line("\\pdfrefximage"),
+            Whatsit::Simple(PDFXForm(_)) => self.line("\\pdfrefxform"),
+            Whatsit::Simple(PDFLiteral(_)) => self.line("\\pdfliteral"),
+            Whatsit::Simple(PDFDest(_)) => self.line("\\pdfdest"),
+            Whatsit::Simple(PDFInfo(_)) => self.line("\\pdfinfo"),
+            Whatsit::Simple(PDFMatrix(_)) => self.line("\\pdfsetmatrix"),
+            Whatsit::Simple(Left(_)) => self.line("\\left"),
+            Whatsit::Simple(Middle(_)) => self.line("\\middle"),
+            Whatsit::Simple(Right(_)) => self.line("\\right"),
+            _ => self.line("\\whatsit")
+        }
+    }
+}
+
+/// Font, color and link groups are not nodes in TeX; their children are listed in their place
+fn flatten<'a>(ls:&'a [Whatsit],ret:&mut Vec<&'a Whatsit>) {
+    for wi in ls {
+        match wi {
+            Whatsit::Grouped(g) | Whatsit::GroupOpen(g) => flatten(g.children(),ret),
+            Whatsit::Ls(v) => flatten(v,ret),
+            Whatsit::GroupClose(_) => (),
+            o => ret.push(o)
+        }
+    }
+}
@@ -1,15 +1,20 @@
 use std::any::Any;
+use std::borrow::Cow;
 use std::cmp::{max, min};
 use std::io::{Cursor, Write};
 use std::path::PathBuf;
+use std::sync::Arc;
 use base64::Engine;
 use image::DynamicImage;
 use image::imageops::FilterType;
 use crate::interpreter::dimensions::{dimtostr, MuSkip, numtostr, round, Skip, SkipDim};
+use crate::interpreter::diagnostics::Diagnostic;
 use crate::references::SourceFileReference;
 use crate::stomach::boxes::{Alignment, FilLevel, HBox, TeXBox, VBox};
 use crate::stomach::colon::ColonMode;
-use crate::stomach::html::{dimtohtml, HTML_NS, HTMLChild, HTMLColon, HTMLNode, HTMLParent, HTMLSCALE, HTMLStr};
+use crate::stomach::html::{dimtohtml, HTML_NS, HTMLChild, HTMLColon, HTMLNode, HTMLParent, HTMLSCALE, HTMLStr, SVG_NS};
+use crate::pdfsvg::{bp_to_sp, PdfSvg};
+use crate::stomach::assets::{Assets, web_format};
 use crate::stomach::math::{GroupedMath, MathChar};
 use crate::stomach::Whatsit;
 use crate::stomach::whatsits::{HasWhatsitIter, WhatsitIter, WhatsitTrait};
@@ -206,6 +211,10 @@ pub struct PDFXImage{
     pub boxspec:Option<TeXStr>,
     pub filename:PathBuf,
     pub image:Option<DynamicImage>,
+    /// the page of a PDF file, if it could be converted to SVG (then `image` is `None`)
+    pub svg:Option<PdfSvg>,
+    /// the content of the image file, unless it is a PDF
+    pub original:Option<Arc<Vec<u8>>>,
     pub sourceref:Option<SourceFileReference>,
     pub _width:Option<i32>,
     pub _height:Option<i32>
@@ -220,8 +229,9 @@ impl WhatsitTrait for PDFXImage {
     fn width(&self) -> i32 {
         match self._width {
             Some(w) => w,
-            None => match &self.image {
-                Some(img) => img.width() as i32 * 65536,
+            None => match (&self.svg,&self.image) {
+                (Some(svg),_) => bp_to_sp(svg.width),
+                (_,Some(img)) => img.width() as i32 * 65536,
                 _ => 65536
             }
         }
@@ -229,8 +239,9 @@ impl WhatsitTrait for PDFXImage {
     fn height(&self) -> i32 {
         match self._height {
             Some(h) => h,
-            None => match &self.image {
-                Some(img) => img.height() as i32 * 65536,
+            None => match (&self.svg,&self.image) {
+                (Some(svg),_) => bp_to_sp(svg.height),
+                (_,Some(img)) => img.height() as i32 * 65536,
                 _ => 65536
             }
         }
@@ -300,38 +311,115 @@ impl WhatsitTrait for PDFXImage {
                 lineheight:None
             }.as_html(mode, colon, node_top)
         }
-        match self.image {
-            Some(ref img) => {
-                let target_width = 5 * ((HTMLSCALE as f64) * round(self.width())).round() as u32;
-                let target_height = 5 * ((HTMLSCALE as f64) * round(self.height())).round() as u32;
-                /*println!("Original width: {}\nOriginal height:{}\nthis.width:{}\nthis.height:{}\ntarget_width:{}\ntarget_height:{}",
-                    img.width(),img.height(),dimtohtml(self.width()),dimtohtml(self.height()),target_width,target_height
-                );*/
-                let nimg = if img.width() > target_width || img.height() > target_height {
-                    image::imageops::resize(
-                        &img.clone().into_rgba8(),
-                        target_width,
-                        target_height,
-                        FilterType::Gaussian
-                    )
-                } else { img.clone().into_rgba8() };
-                let mut buf = Cursor::new(vec!());//Vec<u8> = vec!();
-                match nimg.write_to(&mut buf, image::ImageFormat::Png/*Jpeg(254)*/) {
-                    Ok(_) => {
-                        let res_base64 = "data:image/png;base64,".to_string() + &base64::engine::general_purpose::STANDARD.encode(&buf.into_inner());
-                        htmlnode!(colon,img,self.sourceref.clone(),"",node_top,i => {
-                            i.attr("src".into(),res_base64.into());
-                            i.attr("width".into(),dimtohtml(self.width()));
-                            i.attr("height".into(),dimtohtml(self.height()));
+        if let Some(svg) = &self.svg {
+            let (w,h) = (svg.width,svg.height);
+            match &colon.assets {
+                // an SVG shown by <img> can not load other files, but one in an <object> can;
+                // its images are then assets next to it
+                Some(assets) => {
+                    let mut object = false;
+                    let content = svg.content_with(|png| {
+                        object = true;
+                        match assets.write(png,"png") {
+                            Ok(name) => name,
+                            Err(e) => {
+                                self.asset_warning(colon,assets,e);
+                                data_uri(png,"image/png")
+                            }
+                        }
+                    });
+                    let file = format!("<svg xmlns=\"{}\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" preserveAspectRatio=\"none\">{}</svg>",SVG_NS,w,h,w,h,content);
+                    let src = self.asset_url(colon,assets,file.as_bytes(),"svg","image/svg+xml");
+                    if object {
+                        return htmlnode!(colon,object,self.sourceref.clone(),"",node_top,o => {
+                            o.attr("type".into(),"image/svg+xml".into());
+                            o.attr("data".into(),src.into());
+                            o.attr("width".into(),dimtohtml(self.width()));
+                            o.attr("height".into(),dimtohtml(self.height()));
                         })
                     }
-                    Err(e) => ()
-                        //println!("{}",e)
+                    return htmlnode!(colon,img,self.sourceref.clone(),"",node_top,i => {
+                        i.attr("src".into(),src.into());
+                        i.attr("width".into(),dimtohtml(self.width()));
+                        i.attr("height".into(),dimtohtml(self.height()));
+                    })
+                }
+                None => {
+                    let content = svg.content_with(|png| data_uri(png,"image/png"));
+                    return htmlnode!(colon,SVG_NS:svg,self.sourceref.clone(),"",node_top,node => {
+                        node.attr("width".into(),dimtohtml(self.width()));
+                        node.attr("height".into(),dimtohtml(self.height()));
+                        node.attr("viewBox".into(),format!("0 0 {} {}",w,h).into());
+                        node.attr("preserveAspectRatio".into(),"none".into());
+                        htmlliteral!(colon,htmlparent!(node),content)
+                    })
                 }
             }
-            _ => ()
+        }
+        let src = match &colon.assets {
+            Some(assets) => {
+                let file = match &self.original {
+                    Some(o) => match web_format(o,&self.filename) {
+                        Some((ext,mime)) => Some((Cow::Borrowed(o.as_slice()),ext,mime)),
+                        // transcode e.g. TIFF
+                        None => self.image.as_ref().and_then(|img| to_png(img,None)).map(|png| (Cow::Owned(png),"png","image/png"))
+                    }
+                    None => self.image.as_ref().and_then(|img| to_png(img,None)).map(|png| (Cow::Owned(png),"png","image/png"))
+                };
+                file.map(|(content,ext,mime)| self.asset_url(colon,assets,&content,ext,mime))
+            }
+            None => match &self.original {
+                Some(o) if self.image.is_none() => web_format(o,&self.filename).map(|(_,mime)| data_uri(o,mime)),
+                _ => self.image.as_ref().and_then(|img| {
+                    let target_width = 5 * ((HTMLSCALE as f64) * round(self.width())).round() as u32;
+                    let target_height = 5 * ((HTMLSCALE as f64) * round(self.height())).round() as u32;
+                    to_png(img,Some((target_width,target_height))).map(|png| data_uri(&png,"image/png"))
+                })
+            }
+        };
+        if let Some(src) = src {
+            htmlnode!(colon,img,self.sourceref.clone(),"",node_top,i => {
+                i.attr("src".into(),src.into());
+                i.attr("width".into(),dimtohtml(self.width()));
+                i.attr("height".into(),dimtohtml(self.height()));
+            })
+        }
+    }
+}
+
+impl PDFXImage {
+    /// The URL of `content` stored in `assets`, or a data URI if that fails
+    fn asset_url(&self,colon:&HTMLColon,assets:&Assets,content:&[u8],ext:&str,mime:&str) -> String {
+        match assets.store(content,ext) {
+            Ok(url) => url,
+            Err(e) => {
+                self.asset_warning(colon,assets,e);
+                data_uri(content,mime)
+            }
         }
     }
+    fn asset_warning(&self,colon:&HTMLColon,assets:&Assets,e:std::io::Error) {
+        colon.base.diagnostics.push(Diagnostic::warning(
+            format!("Could not write image {} to {}: {}; embedding it instead",self.filename.display(),assets.dir.display(),e),
+            self.sourceref.as_ref()
+        ))
+    }
+}
+
+fn data_uri(content:&[u8],mime:&str) -> String {
+    format!("data:{};base64,{}",mime,base64::engine::general_purpose::STANDARD.encode(content))
+}
+
+/// `img` as PNG, shrunk to at most `max` (width, height)
+fn to_png(img:&DynamicImage,max:Option<(u32,u32)>) -> Option<Vec<u8>> {
+    let nimg = match max {
+        Some((w,h)) if img.width() > w || img.height() > h =>
+            image::imageops::resize(&img.clone().into_rgba8(),w,h,FilterType::Gaussian),
+        _ => img.clone().into_rgba8()
+    };
+    let mut buf = Cursor::new(vec!());
+    nimg.write_to(&mut buf,image::ImageFormat::Png).ok()?;
+    Some(buf.into_inner())
 }
 
 #[derive(Clone)]
@@ -1294,7 +1382,7 @@ impl WhatsitTrait for HAlign {
                         table.style("height".into(),dimtohtml(colon.state.fontsize));
                     }
                     table.style("grid-template-columns".into(),std::format_args!("repeat({},1fr)",colnums).to_string().into());
-                    if crate::INSERT_RUSTEX_ATTRS {
+                    if colon.rustex_attrs {
                         table.attr("rustex:width".into(),dimtohtml(width));
                         table.attr("rustex:height".into(),dimtohtml(height));
                     }
@@ -1307,7 +1395,7 @@ impl WhatsitTrait for HAlign {
                 if self.lineheight == Some(0) {
                     table.style("height".into(),dimtohtml(colon.state.fontsize));
                 }
-                if crate::INSERT_RUSTEX_ATTRS {
+                if colon.rustex_attrs {
                     table.attr("rustex:width".into(),dimtohtml(self.width()));
                     table.attr("rustex:height".into(),dimtohtml(self.height()));
                 }
@@ -390,8 +390,11 @@ impl WhatsitTrait for PrintChar {
     }
     fn as_html(self, mode: &ColonMode, colon: &mut HTMLColon, node_top: &mut Option<HTMLParent>) {
         let str: HTMLStr = HTMLStr::from(self.charstr).html_escape();
+        // with a source map, text runs get a span (and hence an id) in all modes
+        let sourcemap = colon.has_sourcemap() && node_top.is_some() && *mode != ColonMode::M;
         match mode {
-            ColonMode::H => {
+            _ if sourcemap || *mode == ColonMode::H => {
+                let sourceref = if sourcemap { self.get_ref() } else { None };
                 let maybetext = match match node_top {
                     Some(HTMLParent::N(n)) => n.children.last_mut(),
                     Some(HTMLParent::A(n)) => n.children.last_mut(),
@@ -399,15 +402,22 @@ impl WhatsitTrait for PrintChar {
                 } {
                     Some(HTMLChild::Node(n)) => Some(n),
                     _ => None
-                };;
-                match maybetext {
-                    Some(n) if n.classes.contains(&"rustex-text".into()) =>{
+                };
+                // the run continues if its source range can be extended by this character's
+                let merged = match (&maybetext,&sourceref) {
+                    (Some(n),Some(r)) => n.sourceref.as_ref().and_then(|nr| nr.merge(r)).map(Some),
+                    (Some(n),None) => Some(n.sourceref.clone()),
+                    _ => None
+                };
+                match (maybetext,merged) {
+                    (Some(n),Some(rf)) if n.classes.contains(&"rustex-text".into()) =>{
+                        n.sourceref = rf;
                         n.children.push(HTMLChild::Str(str.into()));
                         /*n.style("height".into(),"".into());
                         n.style("width".into(),"".into());
                         n.style("line-height".into(),"".into());*/
                     }
-                    _ => htmlnode!(colon,span,None,"rustex-text",node_top,span => {
+                    _ => htmlnode!(colon,span,sourceref,"rustex-text",node_top,span => {
                         let h = self.font.get_height(self.char as u16);
                         /*span.ht = h;
                         span.style("height".into(),dimtohtml(h));
@@ -265,7 +265,11 @@ pub struct TeXError {
     backtrace : Backtrace,
     tk:Option<Token>,
     pub textrace:Vec<(String,String)>,
-    pub toplinepos:Vec<(String,usize,usize)>
+    pub toplinepos:Vec<(String,usize,usize)>,
+    /// tokens to insert when recovering from this error in `\nonstopmode` etc.
+    pub(in crate) recovery:Vec<Token>,
+    /// never recovered from, not even in `\nonstopmode` etc.
+    pub(in crate) fatal:bool
 }
 
 impl TeXError {
@@ -279,10 +283,22 @@ impl TeXError {
         Backtrace::from(frames)
     }
     pub (in crate) fn new(msg:String,tk:Option<Token>) -> TeXError {
-        TeXError {msg,source:Box::new(None),backtrace:TeXError::backtrace(),tk,textrace:vec!(),toplinepos:vec!()}
+        TeXError {msg,source:Box::new(None),backtrace:TeXError::backtrace(),tk,textrace:vec!(),toplinepos:vec!(),recovery:vec!(),fatal:false}
     }
-    pub fn derive(self,msg:String) -> TeXError {
-        TeXError {msg,source:Box::new(Some(self)),backtrace:TeXError::backtrace(),tk:None,textrace:vec!(),toplinepos:vec!()}
+    pub fn derive(mut self,msg:String) -> TeXError {
+        let recovery = std::mem::take(&mut self.recovery);
+        let fatal = self.fatal;
+        TeXError {msg,source:Box::new(Some(self)),backtrace:TeXError::backtrace(),tk:None,textrace:vec!(),toplinepos:vec!(),recovery,fatal}
+    }
+    /// Makes this error abort processing regardless of the interaction mode
+    pub(in crate) fn fatal(mut self) -> TeXError {
+        self.fatal = true;
+        self
+    }
+    /// When recovering, insert `tks` (like TeX's "Missing $ inserted")
+    pub(in crate) fn recover_with(mut self,tks:Vec<Token>) -> TeXError {
+        self.recovery = tks;
+        self
     }
     pub fn throw(&mut self, int: &mut Interpreter) {
         self.backtrace.resolve();
@@ -303,6 +319,12 @@ impl TeXError {
             }
         }
     }
+    /// The Rust backtrace of where this error was created
+    pub fn rust_backtrace(&self) -> String {
+        let mut bt = self.backtrace.clone();
+        bt.resolve();
+        std::format!("{:?}",bt)
+    }
     pub fn priant(&mut self) {
         self.backtrace.resolve();
         //println!("{}",self)
@@ -367,9 +389,13 @@ use crate::interpreter::mouth::{Mouth, StringMouthSource};
     }
 }*/
 
+/// Expansions beyond this many are omitted from [`stacktrace`]s
+const MAX_STACKTRACE : usize = 100;
+
 pub fn stacktrace(tk : Token) -> Vec<(String,String)> {
     let mut currtk = tk;
     let mut ret : Vec<(String,String)> = vec!();
+    let mut omitted : usize = 0;
     let mut currtkstr = "".to_string();
     let mut currline = "".to_string();
     loop {
@@ -402,6 +428,11 @@ pub fn stacktrace(tk : Token) -> Vec<(String,String)> {
                     ret.push((std::mem::take(&mut currtkstr), std::mem::take(&mut currline)));
                     break
                 }
+                SourceReference::Exp(ntk, _) if ret.len() >= MAX_STACKTRACE => {
+                    omitted += 1;
+                    currtkstr.clear();
+                    currtk = ntk.clone()
+                }
                 SourceReference::Exp(ntk, cmd) => {
                     currline += "Expanded from ";
                     match ntk.catcode {
@@ -419,6 +450,9 @@ pub fn stacktrace(tk : Token) -> Vec<(String,String)> {
             }
         }
     }
+    if omitted > 0 {
+        ret.insert(ret.len() - 1,("...".to_string(),format!("({} more expansions)",omitted)))
+    }
     ret
 }
 /*
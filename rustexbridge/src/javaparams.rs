@@ -1,7 +1,14 @@
-use rustex::interpreter::params::{CommandListener, DefaultParams, InterpreterParams};
+use rustex::interpreter::debugger::Debugger;
+use rustex::interpreter::profiler::Profiler;
+use rustex::interpreter::limits::{CancellationToken, Limits};
+use rustex::filesystem::{FileSystem, PhysicalFS};
+use rustex::interpreter::params::{CommandListener, DefaultParams, InterpreterParams, OutFiles};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use jni::JNIEnv;
-use jni::objects::{JObject,JValue};
+use jni::objects::{GlobalRef, JObject, JValue};
 use rustex::utils::TeXError;
+use rustex::interpreter::diagnostics::Diagnostic;
 
 pub (in crate) struct JavaParams<'borrow,'env> {
     env:&'borrow JNIEnv<'env>,
@@ -11,11 +18,52 @@ pub (in crate) struct JavaParams<'borrow,'env> {
     store_in_file:bool,
     copy_tokens_full:bool,
     copy_commands_full:bool,
+    limits:Limits,
+    cancellation:CancellationToken,
+    /// the key of [`JavaParams::cancellation`] in [`RUNNING`]
+    running:usize,
     pub listeners: Vec<Box<dyn CommandListener>>
 }
 
+/// The cancellation tokens of the conversions in progress, with the Java params objects they run with
+static RUNNING : Mutex<Vec<(usize,GlobalRef,CancellationToken)>> = Mutex::new(Vec::new());
+static NEXT_RUNNING : AtomicUsize = AtomicUsize::new(0);
+
+/// Cancels the conversions running with the Java params object `params`; false if there are none
+pub(in crate) fn cancel(env:&JNIEnv,params:JObject) -> bool {
+    let running = RUNNING.lock().unwrap();
+    let mut found = false;
+    for (_,p,token) in running.iter() {
+        if env.is_same_object(p.as_obj(),params).unwrap_or(false) {
+            token.cancel();
+            found = true
+        }
+    }
+    found
+}
+
+/// The limits set in the Java params object `params`, where 0 stands for the default (or no limit)
+fn limits(env:&JNIEnv,params:JObject) -> Limits {
+    // Params classes compiled before the limits existed lack the fields
+    let field = |name:&str,tp:&str| env.get_field(params,name,tp).map_err(|_| env.exception_clear()).ok();
+    let int = |name:&str| field(name,"I").and_then(|v| v.i().ok()).filter(|i| *i > 0).map(|i| i as usize);
+    let long = |name:&str| field(name,"J").and_then(|v| v.j().ok()).filter(|i| *i > 0).map(|i| i as u64);
+    let default = Limits::default();
+    Limits {
+        input_stack:int("input_stack").unwrap_or(default.input_stack),
+        group_depth:int("group_depth").unwrap_or(default.group_depth),
+        expansion_depth:int("expansion_depth").unwrap_or(default.expansion_depth),
+        expansion_tokens:int("expansion_tokens").unwrap_or(default.expansion_tokens),
+        expansions:long("max_expansions").map(|i| i as usize),
+        timeout:long("timeout_ms").map(std::time::Duration::from_millis)
+    }
+}
+
 impl<'borrow,'env> JavaParams<'borrow,'env> {
     pub fn new(env:&'borrow JNIEnv<'env>,params:JObject<'env>) -> JavaParams<'borrow,'env> {
+        let cancellation = CancellationToken::new();
+        let running = NEXT_RUNNING.fetch_add(1,Ordering::Relaxed);
+        RUNNING.lock().unwrap().push((running,env.new_global_ref(params).unwrap(),cancellation.clone()));
         JavaParams {
             env,params,
             singlethreaded:env.get_field(params,"singlethreaded","Z").unwrap().z().unwrap(),
@@ -23,10 +71,17 @@ impl<'borrow,'env> JavaParams<'borrow,'env> {
             store_in_file:env.get_field(params,"store_in_file","Z").unwrap().z().unwrap(),
             copy_tokens_full:env.get_field(params,"copy_tokens_full","Z").unwrap().z().unwrap(),
             copy_commands_full:env.get_field(params,"copy_commands_full","Z").unwrap().z().unwrap(),
+            limits:limits(env,params),
+            cancellation,running,
             listeners: DefaultParams::default_listeners()
         }
     }
 }
+impl Drop for JavaParams<'_,'_> {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().retain(|(i,_,_)| *i != self.running)
+    }
+}
 
 use crate::{javastring,jobj,jarray};
 
@@ -72,6 +127,15 @@ impl<'borrow,'env> InterpreterParams for JavaParams<'borrow,'env> {
         let output = jobj!(javastring!(self.env,s));
         self.env.call_method(self.params,"message","(Ljava/lang/String;)V",&[output]).unwrap();
     }
+    fn debugger(&self) -> Option<&Debugger> { None }
+    fn profiler(&self) -> Option<&Profiler> { None }
+    fn limits(&self) -> Limits { self.limits }
+    fn cancellation(&self) -> Option<&CancellationToken> { Some(&self.cancellation) }
+    fn filesystem(&self) -> &dyn FileSystem { &PhysicalFS }
+    fn show(&self, s: &str) {
+        let output = jobj!(javastring!(self.env,s));
+        self.env.call_method(self.params,"message","(Ljava/lang/String;)V",&[output]).unwrap();
+    }
     fn file_close(&self) {
         self.env.call_method(self.params,"file_close","()V",&[]).unwrap();
     }
@@ -94,7 +158,21 @@ impl<'borrow,'env> InterpreterParams for JavaParams<'borrow,'env> {
         self.env.call_method(self.params,"error_i","(Ljava/lang/String;[[Ljava/lang/String;[[Ljava/lang/String;)V",
                              &[a1,JValue::Object(JObject::from(retstr)),JValue::Object(JObject::from(filestr))]).unwrap();
     }
+    fn diagnostic(&self, d: Diagnostic) {
+        let opt_str = |s:Option<String>| match s {
+            Some(s) => jobj!(javastring!(self.env,s)),
+            None => jobj!(JObject::null())
+        };
+        let opt_int = |i:Option<usize>| JValue::Int(i.map(|i| i as i32).unwrap_or(-1));
+        self.env.call_method(self.params,"diagnostic_i","(Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;Ljava/lang/String;II)V",
+            &[jobj!(javastring!(self.env,d.severity.as_str())),jobj!(javastring!(self.env,d.message)),
+                opt_str(d.source),opt_str(d.file),opt_int(d.line),opt_int(d.column)]).unwrap();
+    }
     fn command_listeners(&self) -> &Vec<Box<dyn CommandListener>> {
         &self.listeners
     }
+    fn outfiles(&self) -> OutFiles { OutFiles::Memory }
+    fn jobname(&self) -> Option<String> { None }
+    fn shell_escape(&self) -> bool { false }
+    fn begin_pass(&self, _pass: usize) {}
 }
\ No newline at end of file
@@ -53,15 +53,21 @@ pub extern "system" fn Java_info_kwarc_rustex_RusTeXBridge_initializeMain(
 ) -> jboolean {
     main_state!(st => {
         match st {
-            Some(_) => (),
-            None => {
-                let state = State::pdf_latex();
-                *st = Some(state);
-                unsafe{ rustex::PDFIUM_PATH = Some(env.get_string(path).expect("Couldn't get java string!").into()) }
+            Some(_) => jboolean::from(true),
+            // e.g. no TeX installation
+            None => match State::try_pdf_latex() {
+                Ok(state) => {
+                    *st = Some(state);
+                    unsafe{ rustex::PDFIUM_PATH = Some(env.get_string(path).expect("Couldn't get java string!").into()) }
+                    jboolean::from(true)
+                }
+                Err(e) => {
+                    eprintln!("RusTeX could not be initialized: {}",e.msg);
+                    jboolean::from(false)
+                }
             }
         }
-    });
-    jboolean::from(true)
+    })
 }
 
 struct Sandbox(State);
@@ -96,6 +102,17 @@ pub extern "system" fn Java_info_kwarc_rustex_RusTeXBridge_newsb(
 
 use crate::javaparams::JavaParams;
 
+/// Stops the conversions running with the params object `p` (from another thread); they return
+/// the output produced so far. False if none is running.
+#[no_mangle]
+pub extern "system" fn Java_info_kwarc_rustex_RusTeXBridge_cancel(
+    env: JNIEnv,
+    _class: JClass,
+    p:JObject
+) -> jboolean {
+    jboolean::from(javaparams::cancel(&env,p))
+}
+
 #[no_mangle]
 pub extern "system" fn Java_info_kwarc_rustex_RusTeXBridge_parseI(
     env: JNIEnv,
@@ -105,7 +122,7 @@ pub extern "system" fn Java_info_kwarc_rustex_RusTeXBridge_parseI(
     if use_main == 1 {
         let st = main_state!();
         let (b,s,ret) = util::do_file(env, file, st, &JavaParams::new(&env, p));
-        if b {
+        if b.success() {
             main_state!(st => {
                 util::do_memories(st.as_mut().unwrap(), s, &memories)
             })
@@ -115,7 +132,7 @@ pub extern "system" fn Java_info_kwarc_rustex_RusTeXBridge_parseI(
         let Sandbox(mut state) = Sandbox::from_pointer(ptr);
         let st = state.clone();
         let (b,s,ret) = util::do_file(env, file, st, &JavaParams::new(&env, p));
-        if b {
+        if b.success() {
             main_state!(st => {
                 util::do_memories(st.as_mut().unwrap(), s, &memories)
             })
@@ -134,7 +151,7 @@ pub extern "system" fn Java_info_kwarc_rustex_RusTeXBridge_parseStringI(
     if use_main == 1 {
         let st = main_state!();
         let (b,s,ret) = util::do_string(env, file, text, st, &JavaParams::new(&env, p));
-        if b {
+        if b.success() {
             main_state!(st => {
                 util::do_memories(st.as_mut().unwrap(), s, &memories)
             });
@@ -144,7 +161,7 @@ pub extern "system" fn Java_info_kwarc_rustex_RusTeXBridge_parseStringI(
         let Sandbox(mut state) = Sandbox::from_pointer(ptr);
         let st = state.clone();
         let (b,s,ret) = util::do_string(env, file, text, st, &JavaParams::new(&env, p));
-        if b {
+        if b.success() {
             util::do_memories(&mut state, s, &memories)
         }
         Sandbox(state).set_pointer(env,cls);
@@ -24,14 +24,14 @@ use jni::objects::{JList, JMap, JObject, JString};
 use jni::sys::jstring;
 
 use rustex::interpreter::state::State;
-use rustex::interpreter::Interpreter;
+use rustex::interpreter::{Interpreter, Outcome};
 use rustex::stomach::html::HTMLColon;
 use std::path::Path;
 
 use crate::javaparams::JavaParams;
 
 pub(in crate) fn do_file<'borrow,'env>(env:JNIEnv, file:JString, s:State, params:&JavaParams<'borrow,'env>)
-                                       -> (bool,State,jstring) {
+                                       -> (Outcome,State,jstring) {
     let filename : String = env
         .get_string(file)
         .expect("Couldn't get java string!")
@@ -43,7 +43,7 @@ pub(in crate) fn do_file<'borrow,'env>(env:JNIEnv, file:JString, s:State, params
 }
 
 pub(in crate) fn do_string<'borrow,'env>(env:JNIEnv, file:JString, text:JString, s:State, params:&JavaParams<'borrow,'env>)
-                                         -> (bool,State,jstring) {
+                                         -> (Outcome,State,jstring) {
     let filename : String = env
         .get_string(file)
         .expect("Couldn't get java string!")
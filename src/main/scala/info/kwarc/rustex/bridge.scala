@@ -30,6 +30,14 @@ abstract class Params {
   var store_in_file = false
   var copy_tokens_full = true
   var copy_commands_full = true
+  // resource limits; 0 for the default
+  var input_stack = 0
+  var group_depth = 0
+  var expansion_depth = 0
+  var expansion_tokens = 0
+  // 0 for no limit
+  var max_expansions = 0L
+  var timeout_ms = 0L
   def log(s:String)
   def write_16(s:String)
   def write_17(s:String)
@@ -43,6 +51,11 @@ abstract class Params {
     error(msg,stacktrace.toList.map(s => (s(0),s(1))),files.toList.map(s => (s(0),s(1).toInt,s(2).toInt)))
   }
   def error(msg:String,stacktrace:List[(String,String)],files:List[(String,Int,Int)])
+  private def diagnostic_i(severity:String,message:String,source:String,file:String,line:Int,column:Int) = {
+    diagnostic(severity,message,Option(source),Option(file),Some(line).filter(_ >= 0),Some(column).filter(_ >= 0))
+  }
+  // LaTeX/package warnings and over-/underfull boxes found in the output; severity is "error", "warning" or "info"
+  def diagnostic(severity:String,message:String,source:Option[String],file:Option[String],line:Option[Int],column:Option[Int]): Unit = {}
 }
 
 object RusTeXBridge {
@@ -220,6 +233,8 @@ class RusTeXBridge(private[rustex] var params: Params = RusTeXBridge.noParams, p
   @native private[rustex] def parseI(ptr: Long, p: Params, file: String, memories: util.ArrayList[String], envs:util.Map[String,String] ,use_main: Boolean): String
   @native private[rustex] def parseStringI(ptr: Long, text: String, p: Params, file: String, memories: util.ArrayList[String], envs:util.Map[String,String], use_main: Boolean): String
   @native private[rustex] def initializeMain(path: String): Boolean
+  // stops the conversions currently running with p; false if there are none
+  @native def cancel(p: Params): Boolean
 
   def setEnvs(env:(String,String)*) = envs = env.toList
   def setParams(p: Params) = params = p